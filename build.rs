@@ -3,10 +3,217 @@ use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
+/// Size of the packet header (linked list node) on the target.
+const TL_PACKET_HEADER_SIZE: usize = 8;
+/// Packet type, event code and parameter length.
+const TL_EVT_HEADER_SIZE: usize = 3;
+/// Packet header, packet type, opcode, parameter length and parameters.
+const CMD_PACKET_SIZE: usize = TL_PACKET_HEADER_SIZE + 4 + 255;
+/// Packet header and event with the largest payload.
+const EVT_BUFFER_SIZE: usize = TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255;
+
+/// Mailbox buffer sizes, configured with environment variables at build time.
+struct Config {
+    /// Number of BLE events of the most common size that fit into the event pool.
+    ble_evt_queue_length: usize,
+    /// Most common BLE event payload size the event pool is sized for.
+    ble_most_event_payload_size: usize,
+    /// Maximum length of ACL data in a single packet.
+    acl_data_len: usize,
+    /// Number of trace packets that CPU2 can have in flight.
+    traces_evt_pool_length: usize,
+    /// Size of the BLE NVM data buffer in bytes, zero if BLE NVM data is kept in flash by CPU2.
+    ble_nvm_sram_size: usize,
+}
+
+impl Config {
+    fn from_env() -> Self {
+        Config {
+            ble_evt_queue_length: env_usize("STM32WB_TL_BLE_EVT_QUEUE_LENGTH", 5, 1..=64),
+            ble_most_event_payload_size: env_usize(
+                "STM32WB_TL_BLE_MOST_EVENT_PAYLOAD_SIZE",
+                255,
+                27..=255,
+            ),
+            acl_data_len: env_usize("STM32WB_TL_ACL_DATA_LEN", 251, 27..=251),
+            traces_evt_pool_length: env_usize("STM32WB_TL_TRACES_EVT_POOL_LENGTH", 4, 1..=16),
+            ble_nvm_sram_size: env_usize("STM32WB_TL_BLE_NVM_SRAM_SIZE", 0, 0..=4096),
+        }
+    }
+
+    fn evt_pool_size(&self) -> usize {
+        self.ble_evt_queue_length
+            * 4
+            * ((TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + self.ble_most_event_payload_size + 3)
+                / 4)
+    }
+
+    fn traces_evt_pool_size(&self) -> usize {
+        self.traces_evt_pool_length * 4 * ((EVT_BUFFER_SIZE + 3) / 4)
+    }
+
+    /// Shared RAM sections in the order they're placed, with their sizes.
+    fn sections(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("TL_REF_TABLE", 8 * 4),
+            ("TL_DEVICE_INFO_TABLE", 32),
+            ("TL_BLE_TABLE", 4 * 4),
+            ("TL_THREAD_TABLE", 3 * 4),
+            ("TL_SYS_TABLE", 2 * 4),
+            ("TL_MEM_MANAGER_TABLE", 7 * 4),
+            ("TL_TRACES_TABLE", 4),
+            ("TL_MAC_802_15_4_TABLE", 3 * 4),
+            ("TL_ZIGBEE_TABLE", 3 * 4),
+            ("FREE_BUF_QUEUE", TL_PACKET_HEADER_SIZE),
+            ("TRACES_EVT_QUEUE", TL_PACKET_HEADER_SIZE),
+            ("EVT_QUEUE", TL_PACKET_HEADER_SIZE),
+            ("SYSTEM_EVT_QUEUE", TL_PACKET_HEADER_SIZE),
+            ("SYS_CMD_BUF", CMD_PACKET_SIZE),
+            ("BLE_CMD_BUFFER", CMD_PACKET_SIZE),
+            ("CS_BUFFER", TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 4),
+            (
+                "HCI_ACL_DATA_BUFFER",
+                TL_PACKET_HEADER_SIZE + 5 + self.acl_data_len,
+            ),
+            ("BLE_SPARE_EVT_BUF", EVT_BUFFER_SIZE),
+            ("SYS_SPARE_EVT_BUF", EVT_BUFFER_SIZE),
+            ("EVT_POOL", self.evt_pool_size()),
+            ("TRACES_EVT_POOL", self.traces_evt_pool_size()),
+            ("THREAD_OT_CMD_RSP_BUFFER", CMD_PACKET_SIZE),
+            ("THREAD_NOTIF_ACK_BUFFER", EVT_BUFFER_SIZE),
+            ("THREAD_CLI_CMD_RSP_BUFFER", CMD_PACKET_SIZE),
+            ("MAC_802_15_4_CMD_RSP_BUFFER", CMD_PACKET_SIZE),
+            ("MAC_802_15_4_NOTIF_ACK_BUFFER", EVT_BUFFER_SIZE),
+            ("ZIGBEE_APPLI_CMD_BUFFER", CMD_PACKET_SIZE),
+            ("ZIGBEE_NOTIF_ACK_BUFFER", EVT_BUFFER_SIZE),
+            ("ZIGBEE_REQUEST_BUFFER", EVT_BUFFER_SIZE),
+            ("BLE_NVM_SRAM", self.ble_nvm_sram_size),
+        ]
+    }
+
+    /// Constants for `tl_mbox`.
+    fn rust(&self) -> String {
+        format!(
+            "// Generated by build.rs from `STM32WB_TL_*` environment variables.\n\
+             const CFG_TLBLE_EVT_QUEUE_LENGTH: usize = {};\n\
+             const CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE: usize = {};\n\
+             const CFG_TL_ACL_DATA_LEN: usize = {};\n\
+             const CFG_TL_TRACES_EVT_POOL_LEN: usize = {};\n\
+             const CFG_BLE_NVM_SRAM_SIZE: usize = {};\n",
+            self.ble_evt_queue_length,
+            self.ble_most_event_payload_size,
+            self.acl_data_len,
+            self.traces_evt_pool_length,
+            self.ble_nvm_sram_size,
+        )
+    }
+
+    /// Section sizes for `tl_mbox` to check against its statics.
+    fn rust_sections(&self) -> String {
+        let mut out = String::from("// Generated by build.rs, sizes of the shared RAM sections.\n");
+        for (name, size) in self.sections() {
+            out.push_str(&format!("const SECTION_SIZE_{}: usize = {};\n", name, size));
+        }
+
+        out
+    }
+}
+
+fn env_usize(name: &str, default: usize, range: std::ops::RangeInclusive<usize>) -> usize {
+    println!("cargo:rerun-if-env-changed={}", name);
+
+    let value = match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("\n\n{} must be a number, got '{}'\n\n", name, value)),
+        Err(_) => default,
+    };
+
+    if !range.contains(&value) {
+        panic!(
+            "\n\n{} must be in {}..={}, got {}\n\n",
+            name,
+            range.start(),
+            range.end(),
+            value
+        );
+    }
+
+    value
+}
+
+/// Parses `ORIGIN` and `LENGTH` of the `RAM_SHARED` region from the memory layout.
+fn ram_shared(memory: &str) -> (usize, usize) {
+    let line = memory
+        .lines()
+        .find(|line| line.trim_start().starts_with("RAM_SHARED"))
+        .expect("RAM_SHARED region is missing from the memory layout");
+
+    let field = |name: &str| {
+        let value = line
+            .split(name)
+            .nth(1)
+            .unwrap()
+            .trim_start_matches([' ', '=']);
+        value.split([',', ' ']).next().unwrap().to_string()
+    };
+
+    let origin = field("ORIGIN");
+    let origin = usize::from_str_radix(origin.trim_start_matches("0x"), 16).unwrap();
+
+    let length = field("LENGTH");
+    let length = match length.strip_suffix('K') {
+        Some(kib) => kib.parse::<usize>().unwrap() * 1024,
+        None => length.parse().unwrap(),
+    };
+
+    (origin, length)
+}
+
+/// Scatters the mailbox sections in the shared RAM, one after another.
+fn sections(config: &Config, origin: usize, length: usize) -> String {
+    let mut out = String::from(
+        "\n/*\n * Scatter the mailbox interface memory sections in shared memory\n * \
+         Generated by build.rs\n */\nSECTIONS {\n",
+    );
+
+    let mut addr = origin;
+    for (name, size) in config.sections() {
+        out.push_str(&format!(
+            "    {:<32} 0x{:08x} (NOLOAD) : {{ *({}) }} >RAM_SHARED\n",
+            name, addr, name
+        ));
+
+        addr += (size + 3) / 4 * 4;
+    }
+
+    out.push_str("}\n");
+
+    if addr > origin + length {
+        let usage: String = config
+            .sections()
+            .iter()
+            .map(|(name, size)| format!("    {:<32} {}\n", name, size))
+            .collect();
+
+        panic!(
+            "\n\nMailbox buffers take {} bytes, but RAM_SHARED is only {} bytes:\n{}\n\
+             Reduce STM32WB_TL_BLE_EVT_QUEUE_LENGTH, STM32WB_TL_BLE_MOST_EVENT_PAYLOAD_SIZE,\n\
+             STM32WB_TL_ACL_DATA_LEN, STM32WB_TL_TRACES_EVT_POOL_LENGTH\n\
+             or STM32WB_TL_BLE_NVM_SRAM_SIZE\n\n",
+            addr - origin,
+            length,
+            usage
+        );
+    }
+
+    out
+}
+
 fn main() {
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
 
-    let linker = match (
+    let memory = match (
         cfg!(feature = "xC-package"),
         cfg!(feature = "xE-package"),
         cfg!(feature = "xG-package"),
@@ -19,17 +226,38 @@ fn main() {
             panic!("\n\nMust select exactly one package for linker script generation!\nChoices: 'xC-package', 'xE-package' or 'xG-package'\n\n");
         }
 
-        (true, false, false) => include_bytes!("memory_xC.x"),
-        (false, true, false) => include_bytes!("memory_xE.x"),
-        (false, false, true) => include_bytes!("memory_xG.x"),
+        (true, false, false) => include_str!("memory_xC.x"),
+        (false, true, false) => include_str!("memory_xE.x"),
+        (false, false, true) => include_str!("memory_xG.x"),
     };
 
-    File::create(out.join("memory.x"))
-        .unwrap()
-        .write_all(linker)
+    let config = Config::from_env();
+    if config.ble_nvm_sram_size & 3 != 0 {
+        panic!(
+            "\n\nSTM32WB_TL_BLE_NVM_SRAM_SIZE must be a multiple of 4, got {}\n\n",
+            config.ble_nvm_sram_size
+        );
+    }
+
+    let (origin, length) = ram_shared(memory);
+
+    let mut linker = File::create(out.join("memory.x")).unwrap();
+    linker.write_all(memory.as_bytes()).unwrap();
+    linker
+        .write_all(sections(&config, origin, length).as_bytes())
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
+    File::create(out.join("tl_mbox_config.rs"))
+        .unwrap()
+        .write_all(config.rust().as_bytes())
+        .unwrap();
+
+    File::create(out.join("tl_mbox_sections.rs"))
+        .unwrap()
+        .write_all(config.rust_sections().as_bytes())
+        .unwrap();
+
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=memory_xC.x");
     println!("cargo:rerun-if-changed=memory_xE.x");
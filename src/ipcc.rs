@@ -236,3 +236,60 @@ impl IpccExt for IPCC {
         Ipcc { rb: self }
     }
 }
+
+/// CPU1 side of the IPCC channels, as used by the mailbox transport (`tl_mbox`).
+///
+/// Implemented by `Ipcc` for the hardware and by `tl_mbox::sim::SimIpcc` when the transport is
+/// tested on the host with the `host-sim` feature.
+pub trait IpccChannels {
+    fn c1_set_rx_channel(&mut self, channel: IpccChannel, enabled: bool);
+    fn c1_get_rx_channel(&self, channel: IpccChannel) -> bool;
+    fn c1_set_tx_channel(&mut self, channel: IpccChannel, enabled: bool);
+    fn c1_get_tx_channel(&self, channel: IpccChannel) -> bool;
+    fn c1_clear_flag_channel(&mut self, channel: IpccChannel);
+    fn c1_set_flag_channel(&mut self, channel: IpccChannel);
+    fn c1_is_active_flag(&self, channel: IpccChannel) -> bool;
+    fn c2_is_active_flag(&self, channel: IpccChannel) -> bool;
+
+    fn is_tx_pending(&self, channel: IpccChannel) -> bool {
+        !self.c1_is_active_flag(channel) && self.c1_get_tx_channel(channel)
+    }
+
+    fn is_rx_pending(&self, channel: IpccChannel) -> bool {
+        self.c2_is_active_flag(channel) && self.c1_get_rx_channel(channel)
+    }
+}
+
+impl IpccChannels for Ipcc {
+    fn c1_set_rx_channel(&mut self, channel: IpccChannel, enabled: bool) {
+        Ipcc::c1_set_rx_channel(self, channel, enabled)
+    }
+
+    fn c1_get_rx_channel(&self, channel: IpccChannel) -> bool {
+        Ipcc::c1_get_rx_channel(self, channel)
+    }
+
+    fn c1_set_tx_channel(&mut self, channel: IpccChannel, enabled: bool) {
+        Ipcc::c1_set_tx_channel(self, channel, enabled)
+    }
+
+    fn c1_get_tx_channel(&self, channel: IpccChannel) -> bool {
+        Ipcc::c1_get_tx_channel(self, channel)
+    }
+
+    fn c1_clear_flag_channel(&mut self, channel: IpccChannel) {
+        Ipcc::c1_clear_flag_channel(self, channel)
+    }
+
+    fn c1_set_flag_channel(&mut self, channel: IpccChannel) {
+        Ipcc::c1_set_flag_channel(self, channel)
+    }
+
+    fn c1_is_active_flag(&self, channel: IpccChannel) -> bool {
+        Ipcc::c1_is_active_flag(self, channel)
+    }
+
+    fn c2_is_active_flag(&self, channel: IpccChannel) -> bool {
+        Ipcc::c2_is_active_flag(self, channel)
+    }
+}
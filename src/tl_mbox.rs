@@ -1,22 +1,45 @@
+use core::convert::Infallible;
 use core::mem::MaybeUninit;
 
 use bit_field::BitField;
-use heapless::spsc;
 
+pub mod asynch;
 pub mod ble;
+pub mod bytes;
 mod channels;
 pub mod cmd;
 pub mod consts;
 pub mod evt;
+pub mod fus;
+pub mod info;
 pub mod lhci;
+mod linked_list;
+pub mod mac802154;
 pub mod mm;
+pub mod nvm;
+pub mod queue;
 pub mod shci;
+#[cfg(feature = "host-sim")]
+pub mod sim;
+
+// The simulator replaces IPCC and critical sections, so firmware built with it wouldn't work
+#[cfg(all(feature = "host-sim", target_os = "none"))]
+compile_error!("`host-sim` feature is for host tests only and can't be enabled for the target");
 pub mod sys;
-mod unsafe_linked_list;
+pub mod thread;
+pub mod traces;
+pub mod zigbee;
 
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::ble::acl::{self, AclData};
+use crate::tl_mbox::ble::command::{self as ble_command, Command, PendingCommand};
 use crate::tl_mbox::cmd::{AclDataPacket, CmdPacket};
 use crate::tl_mbox::evt::EvtBox;
-use unsafe_linked_list::LinkedListNode;
+pub use crate::tl_mbox::queue::HeaplessEvtQueue;
+use crate::tl_mbox::queue::{EvtQueue, OverflowPolicy};
+use heapless::consts::{U32, U8};
+use heapless::ArrayLength;
+use linked_list::LinkedListNode;
 
 #[derive(Debug, Copy, Clone)]
 #[repr(C, packed)]
@@ -24,6 +47,13 @@ pub struct SafeBootInfoTable {
     version: u32,
 }
 
+impl SafeBootInfoTable {
+    pub fn version(&self) -> info::Version {
+        info::Version::from_raw(self.version)
+    }
+}
+
+/// FUS (formerly Root Security Service) information.
 #[derive(Debug, Copy, Clone)]
 #[repr(C, packed)]
 pub struct RssInfoTable {
@@ -32,61 +62,95 @@ pub struct RssInfoTable {
     rss_info: u32,
 }
 
+impl RssInfoTable {
+    /// FUS version.
+    pub fn version(&self) -> info::Version {
+        info::Version::from_raw(self.version)
+    }
+
+    /// Memory taken by FUS.
+    pub fn memory_size(&self) -> info::MemorySize {
+        info::MemorySize::from_raw(self.memory_size)
+    }
+}
+
 /**
- * Version
- * [0:3]   = Build - 0: Untracked - 15:Released - x: Tracked version
- * [4:7]   = branch - 0: Mass Market - x: ...
- * [8:15]  = Subversion
- * [16:23] = Version minor
- * [24:31] = Version major
+ * Version, see `info::Version`
  *
- * Memory Size
- * [0:7]   = Flash ( Number of 4k sector)
- * [8:15]  = Reserved ( Shall be set to 0 - may be used as flash extension )
- * [16:23] = SRAM2b ( Number of 1k sector)
- * [24:31] = SRAM2a ( Number of 1k sector)
+ * Memory Size, see `info::MemorySize`
+ *
+ * Stack Info (Thread info in older firmware)
+ * [0:7]   = Stack type
+ *
+ * BLE info in older firmware, reserved now
  */
 #[derive(Debug, Copy, Clone)]
 #[repr(C, packed)]
 pub struct WirelessFwInfoTable {
     version: u32,
     memory_size: u32,
-    thread_info: u32,
-    ble_info: u32,
+    stack_info: u32,
+    reserved: u32,
 }
 
 impl WirelessFwInfoTable {
+    pub fn version(&self) -> info::Version {
+        info::Version::from_raw(self.version)
+    }
+
+    pub fn memory_size(&self) -> info::MemorySize {
+        info::MemorySize::from_raw(self.memory_size)
+    }
+
+    pub fn stack_type(&self) -> info::StackType {
+        let stack_info = self.stack_info;
+        info::StackType::from(stack_info.get_bits(0..8) as u8)
+    }
+
     pub fn version_major(&self) -> u8 {
-        let version = self.clone().version;
-        (version.get_bits(24..31) & 0xff) as u8
+        self.version().major
     }
 
     pub fn version_minor(&self) -> u8 {
-        let version = self.clone().version;
-        (version.clone().get_bits(16..23) & 0xff) as u8
+        self.version().minor
     }
 
     pub fn subversion(&self) -> u8 {
-        let version = self.clone().version;
-        (version.clone().get_bits(8..15) & 0xff) as u8
+        self.version().sub
     }
 
     /// Size of FLASH, expressed in number of 4K sectors.
     pub fn flash_size(&self) -> u8 {
-        let memory_size = self.clone().memory_size;
-        (memory_size.clone().get_bits(0..7) & 0xff) as u8
+        self.memory_size().flash_sectors
     }
 
     /// Size of SRAM2a, expressed in number of 1K sectors.
     pub fn sram2a_size(&self) -> u8 {
-        let memory_size = self.clone().memory_size;
-        (memory_size.clone().get_bits(24..31) & 0xff) as u8
+        self.memory_size().sram2a_sectors
     }
 
     /// Size of SRAM2b, expressed in number of 1K sectors.
     pub fn sram2b_size(&self) -> u8 {
-        let memory_size = self.clone().memory_size;
-        (memory_size.clone().get_bits(16..23) & 0xff) as u8
+        self.memory_size().sram2b_sectors
+    }
+
+    /// Checks that the stack is `min_version` or newer and supports `feature`.
+    pub fn check(
+        &self,
+        min_version: info::Version,
+        feature: info::StackFeature,
+    ) -> Result<(), info::Error> {
+        let version = self.version();
+        if !version.is_at_least(min_version) {
+            return Err(info::Error::TooOld(version));
+        }
+
+        let stack_type = self.stack_type();
+        if !stack_type.supports(feature) {
+            return Err(info::Error::Unsupported(stack_type));
+        }
+
+        Ok(())
     }
 }
 
@@ -151,6 +215,14 @@ struct Mac802154Table {
     evt_queue: *const u8,
 }
 
+#[derive(Debug)]
+#[repr(C, align(4))]
+struct ZigbeeTable {
+    notack_buffer: *const u8,
+    appli_cmd_m4_to_m0_buffer: *const u8,
+    request_m0_to_m4_buffer: *const u8,
+}
+
 /// Reference table. Contains pointers to all other tables.
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
@@ -162,6 +234,7 @@ pub struct RefTable {
     mem_manager_table: *const MemManagerTable,
     traces_table: *const TracesTable,
     mac_802_15_4_table: *const Mac802154Table,
+    zigbee_table: *const ZigbeeTable,
 }
 
 #[link_section = "TL_REF_TABLE"]
@@ -188,22 +261,29 @@ static mut TL_TRACES_TABLE: MaybeUninit<TracesTable> = MaybeUninit::uninit();
 #[link_section = "TL_MAC_802_15_4_TABLE"]
 static mut TL_MAC_802_15_4_TABLE: MaybeUninit<Mac802154Table> = MaybeUninit::uninit();
 
+#[link_section = "TL_ZIGBEE_TABLE"]
+static mut TL_ZIGBEE_TABLE: MaybeUninit<ZigbeeTable> = MaybeUninit::uninit();
+
 #[link_section = "FREE_BUF_QUEUE"]
 static mut FREE_BUF_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit();
 
 // Not in shared RAM
 static mut LOCAL_FREE_BUF_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit();
 
-#[allow(dead_code)] // Not used currently but reserved
 #[link_section = "TRACES_EVT_QUEUE"]
 static mut TRACES_EVT_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit();
 
-type PacketHeader = unsafe_linked_list::LinkedListNode;
+type PacketHeader = linked_list::LinkedListNode;
 
 const TL_PACKET_HEADER_SIZE: usize = core::mem::size_of::<PacketHeader>();
 const TL_EVT_HEADER_SIZE: usize = 3;
 const TL_CS_EVT_SIZE: usize = core::mem::size_of::<evt::CsEvt>();
 
+// Buffer sizes that `build.rs` lays out the shared RAM with
+const _: [(); TL_PACKET_HEADER_SIZE + 4 + 255] = [(); core::mem::size_of::<CmdPacket>()];
+const _: [(); TL_PACKET_HEADER_SIZE + 5 + CFG_TL_ACL_DATA_LEN] =
+    [(); core::mem::size_of::<AclDataPacket>()];
+
 #[link_section = "CS_BUFFER"]
 static mut CS_BUFFER: MaybeUninit<
     [u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + TL_CS_EVT_SIZE],
@@ -218,7 +298,7 @@ static mut SYSTEM_EVT_QUEUE: MaybeUninit<LinkedListNode> = MaybeUninit::uninit()
 #[link_section = "SYS_CMD_BUF"]
 pub static mut SYS_CMD_BUF: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
 
-/**
+/*
  * Queue length of BLE Event
  * This parameter defines the number of asynchronous events that can be stored in the HCI layer before
  * being reported to the application. When a command is sent to the BLE core coprocessor, the HCI layer
@@ -230,11 +310,21 @@ pub static mut SYS_CMD_BUF: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
  * the system may hang if the queue is full with asynchronous events and the HCI layer is still waiting
  * for a CC/CS event, In that case, the notification TL_BLE_HCI_ToNot() is called to indicate
  * to the application a HCI command did not receive its command event within 30s (Default HCI Timeout).
+ *
+ * Buffer sizes are configured at build time with environment variables read by `build.rs`:
+ * `STM32WB_TL_BLE_EVT_QUEUE_LENGTH`, `STM32WB_TL_BLE_MOST_EVENT_PAYLOAD_SIZE`,
+ * `STM32WB_TL_ACL_DATA_LEN`, `STM32WB_TL_TRACES_EVT_POOL_LENGTH` and
+ * `STM32WB_TL_BLE_NVM_SRAM_SIZE` (see `nvm`). `build.rs` also lays out
+ * the shared RAM sections for the chosen sizes and checks that they fit into `RAM_SHARED`.
  */
-const CFG_TLBLE_EVT_QUEUE_LENGTH: usize = 5;
-const CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE: usize = 255;
+include!(concat!(env!("OUT_DIR"), "/tl_mbox_config.rs"));
+
 const TL_BLE_EVENT_FRAME_SIZE: usize = TL_EVT_HEADER_SIZE + CFG_TLBLE_MOST_EVENT_PAYLOAD_SIZE;
 
+/// Longest received ACL data that fits into a BLE event buffer after the packet type, handle
+/// and data length.
+const TL_BLE_EVT_ACL_DATA_LEN: usize = TL_BLE_EVENT_FRAME_SIZE - 5;
+
 const fn divc(x: usize, y: usize) -> usize {
     ((x) + (y) - 1) / (y)
 }
@@ -242,8 +332,16 @@ const fn divc(x: usize, y: usize) -> usize {
 const POOL_SIZE: usize =
     CFG_TLBLE_EVT_QUEUE_LENGTH * 4 * divc(TL_PACKET_HEADER_SIZE + TL_BLE_EVENT_FRAME_SIZE, 4);
 
+// Pools are word arrays, so that packet headers carved out of them are aligned (the sections
+// are aligned by `build.rs` on the target, but not on the host)
 #[link_section = "EVT_POOL"]
-static mut EVT_POOL: MaybeUninit<[u8; POOL_SIZE]> = MaybeUninit::uninit();
+static mut EVT_POOL: MaybeUninit<[u32; POOL_SIZE / 4]> = MaybeUninit::uninit();
+
+const TRACES_POOL_SIZE: usize =
+    CFG_TL_TRACES_EVT_POOL_LEN * 4 * divc(TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255, 4);
+
+#[link_section = "TRACES_EVT_POOL"]
+static mut TRACES_EVT_POOL: MaybeUninit<[u32; TRACES_POOL_SIZE / 4]> = MaybeUninit::uninit();
 
 #[link_section = "SYS_SPARE_EVT_BUF"]
 static mut SYS_SPARE_EVT_BUF: MaybeUninit<[u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255]> =
@@ -257,19 +355,111 @@ static mut BLE_SPARE_EVT_BUF: MaybeUninit<[u8; TL_PACKET_HEADER_SIZE + TL_EVT_HE
 static mut BLE_CMD_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
 
 #[link_section = "HCI_ACL_DATA_BUFFER"]
-//                                 fuck these "magic" numbers from ST ---v---v
-static mut HCI_ACL_DATA_BUFFER: MaybeUninit<[u8; TL_PACKET_HEADER_SIZE + 5 + 251]> =
-    MaybeUninit::uninit();
+static mut HCI_ACL_DATA_BUFFER: MaybeUninit<AclDataPacket> = MaybeUninit::uninit();
+
+#[link_section = "THREAD_OT_CMD_RSP_BUFFER"]
+static mut THREAD_OT_CMD_RSP_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
+
+#[link_section = "THREAD_NOTIF_ACK_BUFFER"]
+static mut THREAD_NOTIF_ACK_BUFFER: MaybeUninit<
+    [u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255],
+> = MaybeUninit::uninit();
+
+#[link_section = "THREAD_CLI_CMD_RSP_BUFFER"]
+static mut THREAD_CLI_CMD_RSP_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
 
-pub type HeaplessEvtQueue = spsc::Queue<EvtBox, heapless::consts::U32, u8, spsc::SingleCore>;
+#[link_section = "MAC_802_15_4_CMD_RSP_BUFFER"]
+static mut MAC_802_15_4_CMD_RSP_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
 
-pub struct TlMbox {
+#[link_section = "MAC_802_15_4_NOTIF_ACK_BUFFER"]
+static mut MAC_802_15_4_NOTIF_ACK_BUFFER: MaybeUninit<
+    [u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255],
+> = MaybeUninit::uninit();
+
+#[link_section = "ZIGBEE_APPLI_CMD_BUFFER"]
+static mut ZIGBEE_APPLI_CMD_BUFFER: MaybeUninit<CmdPacket> = MaybeUninit::uninit();
+
+#[link_section = "ZIGBEE_NOTIF_ACK_BUFFER"]
+static mut ZIGBEE_NOTIF_ACK_BUFFER: MaybeUninit<
+    [u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255],
+> = MaybeUninit::uninit();
+
+#[link_section = "ZIGBEE_REQUEST_BUFFER"]
+static mut ZIGBEE_REQUEST_BUFFER: MaybeUninit<
+    [u8; TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255],
+> = MaybeUninit::uninit();
+
+#[link_section = "BLE_NVM_SRAM"]
+static mut BLE_NVM_SRAM: MaybeUninit<[u32; CFG_BLE_NVM_SRAM_SIZE / 4]> = MaybeUninit::uninit();
+
+/// Checks that every section laid out by `build.rs` has the size of the static placed into it.
+/// Sizes are computed for the 32-bit target.
+#[cfg(target_pointer_width = "32")]
+mod section_sizes {
+    use super::*;
+    use core::mem::size_of;
+
+    include!(concat!(env!("OUT_DIR"), "/tl_mbox_sections.rs"));
+
+    const EVT_BUF: usize = TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255;
+    const CS_BUF: usize = TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + TL_CS_EVT_SIZE;
+
+    const _: [(); SECTION_SIZE_TL_REF_TABLE] = [(); size_of::<RefTable>()];
+    const _: [(); SECTION_SIZE_TL_DEVICE_INFO_TABLE] = [(); size_of::<DeviceInfoTable>()];
+    const _: [(); SECTION_SIZE_TL_BLE_TABLE] = [(); size_of::<BleTable>()];
+    const _: [(); SECTION_SIZE_TL_THREAD_TABLE] = [(); size_of::<ThreadTable>()];
+    const _: [(); SECTION_SIZE_TL_SYS_TABLE] = [(); size_of::<SysTable>()];
+    const _: [(); SECTION_SIZE_TL_MEM_MANAGER_TABLE] = [(); size_of::<MemManagerTable>()];
+    const _: [(); SECTION_SIZE_TL_TRACES_TABLE] = [(); size_of::<TracesTable>()];
+    const _: [(); SECTION_SIZE_TL_MAC_802_15_4_TABLE] = [(); size_of::<Mac802154Table>()];
+    const _: [(); SECTION_SIZE_TL_ZIGBEE_TABLE] = [(); size_of::<ZigbeeTable>()];
+    const _: [(); SECTION_SIZE_FREE_BUF_QUEUE] = [(); size_of::<LinkedListNode>()];
+    const _: [(); SECTION_SIZE_TRACES_EVT_QUEUE] = [(); size_of::<LinkedListNode>()];
+    const _: [(); SECTION_SIZE_EVT_QUEUE] = [(); size_of::<LinkedListNode>()];
+    const _: [(); SECTION_SIZE_SYSTEM_EVT_QUEUE] = [(); size_of::<LinkedListNode>()];
+    const _: [(); SECTION_SIZE_SYS_CMD_BUF] = [(); size_of::<CmdPacket>()];
+    const _: [(); SECTION_SIZE_BLE_CMD_BUFFER] = [(); size_of::<CmdPacket>()];
+    const _: [(); SECTION_SIZE_CS_BUFFER] = [(); CS_BUF];
+    const _: [(); SECTION_SIZE_HCI_ACL_DATA_BUFFER] = [(); size_of::<AclDataPacket>()];
+    const _: [(); SECTION_SIZE_BLE_SPARE_EVT_BUF] = [(); EVT_BUF];
+    const _: [(); SECTION_SIZE_SYS_SPARE_EVT_BUF] = [(); EVT_BUF];
+    const _: [(); SECTION_SIZE_EVT_POOL] = [(); POOL_SIZE];
+    const _: [(); SECTION_SIZE_TRACES_EVT_POOL] = [(); TRACES_POOL_SIZE];
+    const _: [(); SECTION_SIZE_THREAD_OT_CMD_RSP_BUFFER] = [(); size_of::<CmdPacket>()];
+    const _: [(); SECTION_SIZE_THREAD_NOTIF_ACK_BUFFER] = [(); EVT_BUF];
+    const _: [(); SECTION_SIZE_THREAD_CLI_CMD_RSP_BUFFER] = [(); size_of::<CmdPacket>()];
+    const _: [(); SECTION_SIZE_MAC_802_15_4_CMD_RSP_BUFFER] = [(); size_of::<CmdPacket>()];
+    const _: [(); SECTION_SIZE_MAC_802_15_4_NOTIF_ACK_BUFFER] = [(); EVT_BUF];
+    const _: [(); SECTION_SIZE_ZIGBEE_APPLI_CMD_BUFFER] = [(); size_of::<CmdPacket>()];
+    const _: [(); SECTION_SIZE_ZIGBEE_NOTIF_ACK_BUFFER] = [(); EVT_BUF];
+    const _: [(); SECTION_SIZE_ZIGBEE_REQUEST_BUFFER] = [(); EVT_BUF];
+    const _: [(); SECTION_SIZE_BLE_NVM_SRAM] = [(); size_of::<[u32; CFG_BLE_NVM_SRAM_SIZE / 4]>()];
+}
+
+/// Mailbox between CPU1 and CPU2.
+///
+/// `SQ` and `BQ` are capacities of the system and BLE event queues.
+pub struct TlMbox<SQ = U8, BQ = U32>
+where
+    SQ: ArrayLength<EvtBox>,
+    BQ: ArrayLength<EvtBox>,
+{
     sys: sys::Sys,
     ble: ble::Ble,
+    thread: Option<thread::Thread>,
+    mac: Option<mac802154::Mac802154>,
+    zigbee: Option<zigbee::Zigbee>,
+    traces: traces::Traces,
     _mm: mm::MemoryManager,
 
-    /// Current event that is produced during IPCC IRQ handler execution on SYS channel
-    evt_queue: HeaplessEvtQueue,
+    /// Events received on SYS channel during IPCC IRQ handler execution
+    sys_queue: EvtQueue<SQ>,
+
+    /// Events received on BLE channel during IPCC IRQ handler execution
+    ble_queue: EvtQueue<BQ>,
+
+    /// Trace packets received on traces channel during IPCC IRQ handler execution
+    traces_queue: EvtQueue<U8>,
 
     /// Last received Command Complete event.
     last_cc_evt: Option<evt::CcEvt>,
@@ -277,7 +467,43 @@ pub struct TlMbox {
 
 impl TlMbox {
     /// Initializes low-level transport between CPU1 and BLE stack on CPU2.
+    ///
+    /// Events received while their queue is full are dropped.
     pub fn tl_init(rcc: &mut crate::rcc::Rcc, ipcc: &mut crate::ipcc::Ipcc) -> TlMbox {
+        Self::tl_init_with_policy(
+            rcc,
+            ipcc,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::DropNewest,
+        )
+    }
+}
+
+impl<SQ, BQ> TlMbox<SQ, BQ>
+where
+    SQ: ArrayLength<EvtBox>,
+    BQ: ArrayLength<EvtBox>,
+{
+    /// Initializes low-level transport with event queues of `SQ` and `BQ` capacity and
+    /// the given overflow policies of the system and BLE event queues.
+    pub fn tl_init_with_policy(
+        rcc: &mut crate::rcc::Rcc,
+        ipcc: &mut crate::ipcc::Ipcc,
+        sys_policy: OverflowPolicy,
+        ble_policy: OverflowPolicy,
+    ) -> Self {
+        ipcc.init(rcc);
+
+        Self::tl_init_channels(ipcc, sys_policy, ble_policy)
+    }
+
+    /// Initializes low-level transport over IPCC channels that are already set up,
+    /// e.g. the simulated ones of `sim::SimIpcc`.
+    pub fn tl_init_channels(
+        ipcc: &mut impl IpccChannels,
+        sys_policy: OverflowPolicy,
+        ble_policy: OverflowPolicy,
+    ) -> Self {
         // Populate reference table with pointers in the shared memory
         unsafe {
             TL_REF_TABLE = MaybeUninit::new(RefTable {
@@ -288,6 +514,7 @@ impl TlMbox {
                 mem_manager_table: TL_MEM_MANAGER_TABLE.as_ptr(),
                 traces_table: TL_TRACES_TABLE.as_ptr(),
                 mac_802_15_4_table: TL_MAC_802_15_4_TABLE.as_ptr(),
+                zigbee_table: TL_ZIGBEE_TABLE.as_ptr(),
             });
 
             TL_SYS_TABLE = MaybeUninit::zeroed();
@@ -297,8 +524,10 @@ impl TlMbox {
             TL_MEM_MANAGER_TABLE = MaybeUninit::zeroed();
             TL_TRACES_TABLE = MaybeUninit::zeroed();
             TL_MAC_802_15_4_TABLE = MaybeUninit::zeroed();
+            TL_ZIGBEE_TABLE = MaybeUninit::zeroed();
 
             EVT_POOL = MaybeUninit::zeroed();
+            TRACES_EVT_POOL = MaybeUninit::zeroed();
             SYS_SPARE_EVT_BUF = MaybeUninit::zeroed();
             BLE_SPARE_EVT_BUF = MaybeUninit::zeroed();
 
@@ -307,42 +536,65 @@ impl TlMbox {
             HCI_ACL_DATA_BUFFER = MaybeUninit::zeroed();
         }
 
-        ipcc.init(rcc);
-
         let sys = sys::Sys::new(ipcc);
         let ble = ble::Ble::new(ipcc);
+        let traces = traces::Traces::new(ipcc);
         let mm = mm::MemoryManager::new();
 
-        let evt_queue = unsafe { heapless::spsc::Queue::u8_sc() };
-
         TlMbox {
             sys,
             ble,
+            thread: None,
+            mac: None,
+            zigbee: None,
+            traces,
             _mm: mm,
-            evt_queue,
+            sys_queue: EvtQueue::new(sys_policy),
+            ble_queue: EvtQueue::new(ble_policy),
+            traces_queue: EvtQueue::new(OverflowPolicy::DropNewest),
             last_cc_evt: None,
         }
     }
 
-    pub fn interrupt_ipcc_rx_handler(&mut self, ipcc: &mut crate::ipcc::Ipcc) {
+    pub fn interrupt_ipcc_rx_handler(&mut self, ipcc: &mut impl IpccChannels) {
         if ipcc.is_rx_pending(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL) {
-            self.sys.evt_handler(ipcc, &mut self.evt_queue);
+            if self.sys.evt_handler(ipcc, &mut self.sys_queue) {
+                // CPU2 won't answer commands sent before it restarted
+                self.ble.cancel_pending();
+            }
         } else if ipcc.is_rx_pending(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL) {
-            todo!()
+            // Channel is shared by Thread, 802.15.4 MAC and Zigbee, only one of them is initialized
+            if let Some(thread) = &mut self.thread {
+                thread.notification_handler(ipcc);
+            } else if let Some(mac) = &mut self.mac {
+                mac.notification_handler(ipcc);
+            } else if let Some(zigbee) = &mut self.zigbee {
+                zigbee.notification_handler(ipcc);
+            }
         } else if ipcc.is_rx_pending(channels::cpu2::IPCC_BLE_EVENT_CHANNEL) {
-            self.ble.evt_handler(ipcc, &mut self.evt_queue);
+            self.ble.evt_handler(ipcc, &mut self.ble_queue);
         } else if ipcc.is_rx_pending(channels::cpu2::IPCC_TRACES_CHANNEL) {
-            todo!()
+            self.traces.evt_handler(ipcc, &mut self.traces_queue);
         } else if ipcc.is_rx_pending(channels::cpu2::IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL) {
-            todo!()
+            if let Some(thread) = &mut self.thread {
+                thread.cli_notification_handler(ipcc);
+            } else if let Some(zigbee) = &mut self.zigbee {
+                zigbee.request_handler(ipcc);
+            }
         }
     }
 
-    pub fn interrupt_ipcc_tx_handler(&mut self, ipcc: &mut crate::ipcc::Ipcc) {
+    pub fn interrupt_ipcc_tx_handler(&mut self, ipcc: &mut impl IpccChannels) {
         if ipcc.is_tx_pending(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL) {
             self.last_cc_evt = Some(self.sys.cmd_evt_handler(ipcc));
         } else if ipcc.is_tx_pending(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL) {
-            todo!()
+            if let Some(thread) = &mut self.thread {
+                thread.ot_cmd_rsp_handler(ipcc);
+            } else if let Some(mac) = &mut self.mac {
+                mac.cmd_rsp_handler(ipcc);
+            } else if let Some(zigbee) = &mut self.zigbee {
+                zigbee.cmd_rsp_handler(ipcc);
+            }
         } else if ipcc.is_tx_pending(channels::cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL) {
             mm::free_buf_handler(ipcc);
         } else if ipcc.is_tx_pending(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL) {
@@ -351,22 +603,403 @@ impl TlMbox {
     }
 
     /// Returns CPU2 wireless firmware information (if present).
+    ///
+    /// FUS fills the device information table differently, so `None` is returned while it runs.
     pub fn wireless_fw_info(&self) -> Option<WirelessFwInfoTable> {
+        if self.cpu2_firmware() != fus::Cpu2Firmware::WirelessStack {
+            return None;
+        }
+
         let info = unsafe { &(*(*TL_REF_TABLE.as_ptr()).device_info_table).wireless_fw_info_table };
+        Some(*info)
+    }
 
-        // Zero version indicates that CPU2 wasn't active and didn't fill the information table
-        if info.version != 0 {
-            Some(info.clone())
+    /// Returns FUS information reported by the wireless stack (if present).
+    pub fn fus_info(&self) -> Option<RssInfoTable> {
+        if self.cpu2_firmware() != fus::Cpu2Firmware::WirelessStack {
+            return None;
+        }
+
+        let info = unsafe { &(*(*TL_REF_TABLE.as_ptr()).device_info_table).rss_info_table };
+        Some(*info)
+    }
+
+    /// Checks that CPU2 runs wireless stack `min_version` or newer that supports `feature`.
+    pub fn check_wireless_fw(
+        &self,
+        min_version: info::Version,
+        feature: info::StackFeature,
+    ) -> Result<WirelessFwInfoTable, info::Error> {
+        let info = self.wireless_fw_info().ok_or(info::Error::NotRunning)?;
+        info.check(min_version, feature)?;
+
+        Ok(info)
+    }
+
+    /// Returns `Ok` once CPU2 has reported that it's ready with `C2Ready` system event,
+    /// i.e. system commands such as `shci_ble_init` can be sent. Use `nb::block!` to wait.
+    ///
+    /// Each `C2Ready` event is reported once, so this can be used again after CPU2 is restarted
+    /// (e.g. with `shci_c2_reinit` or by FUS). The event itself is still delivered through
+    /// the event queue.
+    pub fn poll_cpu2_ready(&mut self) -> nb::Result<shci::ReadyState, Infallible> {
+        self.sys.take_ready().ok_or(nb::Error::WouldBlock)
+    }
+
+    /// Returns firmware that CPU2 is running, as reported in the device information table.
+    pub fn cpu2_firmware(&self) -> fus::Cpu2Firmware {
+        let table = unsafe { &*(*TL_REF_TABLE.as_ptr()).device_info_table };
+
+        // FUS writes a validity keyword in place of the safe boot information
+        let keyword = table.safe_boot_info_table.version;
+        let ws_version = table.wireless_fw_info_table.version;
+
+        if keyword == fus::FUS_DEVICE_INFO_TABLE_VALIDITY_KEYWORD {
+            fus::Cpu2Firmware::Fus
+        } else if ws_version != 0 {
+            fus::Cpu2Firmware::WirelessStack
         } else {
-            None
+            fus::Cpu2Firmware::Unknown
         }
     }
 
-    /// Picks single `EvtBox` from internal event queue.
+    /// Picks single `EvtBox` from internal event queues, system events first.
     ///
-    /// Internal event queue is populated in IPCC RX IRQ handler.
+    /// Internal event queues are populated in IPCC RX IRQ handler.
     pub fn dequeue_event(&mut self) -> Option<EvtBox> {
-        self.evt_queue.dequeue()
+        self.dequeue_sys_event()
+            .or_else(|| self.dequeue_ble_event())
+    }
+
+    /// Picks single `EvtBox` from the system event queue.
+    pub fn dequeue_sys_event(&mut self) -> Option<EvtBox> {
+        let (event, resume) = self.sys_queue.pop();
+        if resume {
+            self.sys.resume_rx(&mut steal_ipcc());
+        }
+
+        event
+    }
+
+    /// Picks single `EvtBox` from the BLE event queue.
+    pub fn dequeue_ble_event(&mut self) -> Option<EvtBox> {
+        let (event, resume) = self.ble_queue.pop();
+        if resume {
+            self.ble.resume_rx(&mut steal_ipcc());
+        }
+
+        event
+    }
+
+    pub fn sys_queue(&self) -> &EvtQueue<SQ> {
+        &self.sys_queue
+    }
+
+    pub fn ble_queue(&self) -> &EvtQueue<BQ> {
+        &self.ble_queue
+    }
+
+    /// Number of system events dropped because the queue was full.
+    pub fn dropped_sys_events(&self) -> u32 {
+        self.sys_queue.dropped()
+    }
+
+    /// Number of BLE events dropped because the queue was full.
+    pub fn dropped_ble_events(&self) -> u32 {
+        self.ble_queue.dropped()
+    }
+
+    /// Picks single trace packet, see `traces::Trace`.
+    ///
+    /// Traces are not returned by `dequeue_event`. Dropping the packet gives its buffer back
+    /// to CPU2.
+    pub fn dequeue_trace_event(&mut self) -> Option<EvtBox> {
+        self.traces_queue.pop().0
+    }
+
+    /// Number of trace packets dropped because the queue was full.
+    pub fn dropped_trace_events(&self) -> u32 {
+        self.traces_queue.dropped()
+    }
+
+    /// Sends typed HCI command on the BLE channel.
+    ///
+    /// Only one command can be in flight, `Busy` is returned until the previous one is answered
+    /// or cancelled with `cancel_ble_cmd`.
+    pub fn send_ble_cmd<C: Command>(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        cmd: &C,
+    ) -> Result<PendingCommand<C>, ble_command::Error> {
+        self.ble.send_cmd(ipcc, cmd)
+    }
+
+    /// Returns response to the command sent with `send_ble_cmd` once its
+    /// Command Complete/Status event arrives. Use `nb::block!` for blocking behavior.
+    pub fn poll_ble_cmd<C: Command>(
+        &mut self,
+        pending: &PendingCommand<C>,
+    ) -> nb::Result<C::Response, ble_command::Error> {
+        self.ble.poll_cmd(pending)
+    }
+
+    /// Forgets the command sent with `send_ble_cmd`, so that a new one can be sent if its
+    /// Command Complete/Status event never arrives. This is done automatically when CPU2
+    /// reports `C2Ready`.
+    ///
+    /// Polling the cancelled command returns `Superseded`.
+    pub fn cancel_ble_cmd(&mut self) {
+        self.ble.cancel_pending();
+    }
+
+    /// Sends ACL data packet to CPU2.
+    ///
+    /// Only one packet can be handed to CPU2 at a time, `WouldBlock` is returned until CPU2
+    /// acknowledges the previous one in IPCC TX IRQ handler.
+    pub fn send_acl_data(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        acl: &AclData,
+    ) -> nb::Result<(), acl::Error> {
+        self.ble.send_acl_data(ipcc, acl)
+    }
+
+    /// Returns `true` if `send_acl_data` can take the next packet.
+    pub fn is_acl_data_buffer_free(&self) -> bool {
+        self.ble.is_acl_data_buffer_free()
+    }
+
+    /// Returns response to the system command once its Command Complete event arrives.
+    /// Use `nb::block!` for blocking behavior.
+    pub fn poll_shci_cmd<R: shci::ShciResponse>(
+        &mut self,
+        pending: &shci::PendingShciCmd<R>,
+    ) -> nb::Result<R, shci::ShciError> {
+        match self.pop_last_cc_evt() {
+            Some(cc) => {
+                let opcode = cc.cmd_code;
+                if opcode != pending.opcode {
+                    return Err(nb::Error::Other(shci::ShciError::UnexpectedResponse(
+                        opcode,
+                    )));
+                }
+
+                sys::Sys::with_cmd_rsp_params(R::from_rsp_params)
+                    .ok_or(nb::Error::Other(shci::ShciError::Malformed))
+            }
+
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    /// Initializes Thread transport: populates the Thread table and starts receiving
+    /// notifications and CLI output.
+    ///
+    /// Must be called before the Thread stack is started with `shci::shci_c2_thread_init`.
+    /// Thread channels are shared with 802.15.4 MAC and Zigbee, so only one of them can be used.
+    pub fn thread_init(&mut self, ipcc: &mut impl IpccChannels) -> Result<(), thread::Error> {
+        if self.radio_channels_in_use() {
+            return Err(thread::Error::ChannelsInUse);
+        }
+
+        self.thread = Some(thread::Thread::new(ipcc));
+        Ok(())
+    }
+
+    /// Sends OpenThread API call `id` with its arguments to CPU2.
+    ///
+    /// Returns `WouldBlock` while the previous call hasn't completed.
+    pub fn send_ot_cmd(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        id: u32,
+        args: &[u32],
+    ) -> nb::Result<(), thread::Error> {
+        self.thread_mut()?.send_ot_cmd(ipcc, id, args)
+    }
+
+    /// Returns response to the last OpenThread API call once CPU2 has completed it.
+    /// Use `nb::block!` for blocking behavior.
+    pub fn poll_ot_rsp(&mut self) -> nb::Result<thread::OtMessage<'_>, thread::Error> {
+        self.thread_mut()?.poll_ot_rsp()
+    }
+
+    /// Returns OpenThread notification waiting for `ack_ot_notification`, if any.
+    pub fn ot_notification(&self) -> Option<thread::OtMessage<'_>> {
+        self.thread
+            .as_ref()
+            .and_then(|thread| thread.notification())
+    }
+
+    /// Acknowledges OpenThread notification, optionally returning a value to CPU2.
+    /// The next notification can't be received until then.
+    pub fn ack_ot_notification(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        ret: Option<u32>,
+    ) -> Result<(), thread::Error> {
+        self.thread_mut()?.ack_notification(ipcc, ret);
+        Ok(())
+    }
+
+    /// Sends a command line to the OpenThread CLI.
+    ///
+    /// Returns `WouldBlock` while CPU2 hasn't taken the previous command.
+    pub fn send_cli_cmd(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        line: &[u8],
+    ) -> nb::Result<(), thread::Error> {
+        self.thread_mut()?.send_cli_cmd(ipcc, line)
+    }
+
+    /// Returns OpenThread CLI output waiting for `ack_cli_notification`, if any.
+    pub fn cli_notification(&self) -> Option<&[u8]> {
+        self.thread
+            .as_ref()
+            .and_then(|thread| thread.cli_notification())
+    }
+
+    /// Acknowledges OpenThread CLI output. The next output can't be received until then.
+    pub fn ack_cli_notification(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+    ) -> Result<(), thread::Error> {
+        self.thread_mut()?.ack_cli_notification(ipcc);
+        Ok(())
+    }
+
+    fn thread_mut(&mut self) -> Result<&mut thread::Thread, thread::Error> {
+        self.thread.as_mut().ok_or(thread::Error::NotInitialized)
+    }
+
+    /// Initializes 802.15.4 MAC transport: populates the MAC table and starts receiving
+    /// notifications.
+    ///
+    /// Must be called before the MAC is started with `shci::shci_c2_802_15_4_init`.
+    /// MAC channels are shared with Thread and Zigbee, so only one of them can be used.
+    pub fn mac_init(&mut self, ipcc: &mut impl IpccChannels) -> Result<(), mac802154::Error> {
+        if self.radio_channels_in_use() {
+            return Err(mac802154::Error::ChannelsInUse);
+        }
+
+        self.mac = Some(mac802154::Mac802154::new(ipcc));
+        Ok(())
+    }
+
+    /// Sends MLME/MCPS request to CPU2.
+    ///
+    /// Returns `WouldBlock` while CPU2 hasn't taken the previous request.
+    pub fn send_mac_request<R: mac802154::MacRequest>(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        req: &R,
+    ) -> nb::Result<(), mac802154::Error> {
+        self.mac_mut()?.send_request(ipcc, req)
+    }
+
+    /// Returns `Ok` once CPU2 has accepted the last request. Its outcome is reported by
+    /// a confirm notification. Use `nb::block!` for blocking behavior.
+    pub fn poll_mac_request(&mut self) -> nb::Result<(), mac802154::Error> {
+        self.mac_mut()?.poll_request()
+    }
+
+    /// Returns MAC confirm or indication waiting for `ack_mac_notification`.
+    pub fn poll_mac_notification(
+        &self,
+    ) -> nb::Result<mac802154::Notification<'_>, mac802154::Error> {
+        self.mac
+            .as_ref()
+            .ok_or(mac802154::Error::NotInitialized)?
+            .poll_notification()
+    }
+
+    /// Acknowledges MAC notification. The next notification can't be received until then.
+    pub fn ack_mac_notification(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+    ) -> Result<(), mac802154::Error> {
+        self.mac_mut()?.ack_notification(ipcc);
+        Ok(())
+    }
+
+    fn mac_mut(&mut self) -> Result<&mut mac802154::Mac802154, mac802154::Error> {
+        self.mac.as_mut().ok_or(mac802154::Error::NotInitialized)
+    }
+
+    /// Initializes Zigbee transport: populates the Zigbee table and starts receiving
+    /// notifications and requests.
+    ///
+    /// Must be called before the Zigbee stack is started with `shci::shci_c2_zigbee_init`.
+    /// Zigbee channels are shared with Thread and 802.15.4 MAC, so only one of them can be used.
+    pub fn zigbee_init(&mut self, ipcc: &mut impl IpccChannels) -> Result<(), zigbee::Error> {
+        if self.radio_channels_in_use() {
+            return Err(zigbee::Error::ChannelsInUse);
+        }
+
+        self.zigbee = Some(zigbee::Zigbee::new(ipcc));
+        Ok(())
+    }
+
+    /// Sends Zigbee stack API call `id` with its arguments to CPU2.
+    ///
+    /// Returns `WouldBlock` while the previous call hasn't completed.
+    pub fn send_zigbee_cmd(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        id: u32,
+        args: &[u32],
+    ) -> nb::Result<(), zigbee::Error> {
+        self.zigbee_mut()?.send_cmd(ipcc, id, args)
+    }
+
+    /// Returns response to the last Zigbee API call once CPU2 has completed it.
+    /// Use `nb::block!` for blocking behavior.
+    pub fn poll_zigbee_rsp(&mut self) -> nb::Result<zigbee::ZigbeeMessage<'_>, zigbee::Error> {
+        self.zigbee_mut()?.poll_rsp()
+    }
+
+    /// Returns Zigbee notification waiting for `ack_zigbee_notification`, if any.
+    pub fn zigbee_notification(&self) -> Option<zigbee::ZigbeeMessage<'_>> {
+        self.zigbee
+            .as_ref()
+            .and_then(|zigbee| zigbee.notification())
+    }
+
+    /// Acknowledges Zigbee notification, optionally returning a value to CPU2.
+    /// The next notification can't be received until then.
+    pub fn ack_zigbee_notification(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        ret: Option<u32>,
+    ) -> Result<(), zigbee::Error> {
+        self.zigbee_mut()?.ack_notification(ipcc, ret);
+        Ok(())
+    }
+
+    /// Returns request from the Zigbee stack waiting for `ack_zigbee_request`, if any.
+    pub fn zigbee_request(&self) -> Option<zigbee::ZigbeeMessage<'_>> {
+        self.zigbee.as_ref().and_then(|zigbee| zigbee.request())
+    }
+
+    /// Acknowledges request from the Zigbee stack, optionally returning a value to CPU2.
+    /// The next request can't be received until then.
+    pub fn ack_zigbee_request(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        ret: Option<u32>,
+    ) -> Result<(), zigbee::Error> {
+        self.zigbee_mut()?.ack_request(ipcc, ret);
+        Ok(())
+    }
+
+    fn zigbee_mut(&mut self) -> Result<&mut zigbee::Zigbee, zigbee::Error> {
+        self.zigbee.as_mut().ok_or(zigbee::Error::NotInitialized)
+    }
+
+    /// Thread, 802.15.4 MAC and Zigbee share IPCC channels.
+    fn radio_channels_in_use(&self) -> bool {
+        self.thread.is_some() || self.mac.is_some() || self.zigbee.is_some()
     }
 
     /// Retrieves last Command Complete event and removes it from mailbox.
@@ -377,3 +1010,19 @@ impl TlMbox {
         })
     }
 }
+
+/// Steals IPCC to unmask a channel outside of IPCC IRQ handler, like `EvtBox` does on `Drop`.
+#[cfg(not(feature = "host-sim"))]
+fn steal_ipcc() -> crate::ipcc::Ipcc {
+    use crate::ipcc::IpccExt;
+
+    unsafe { stm32wb_pac::Peripherals::steal() }
+        .IPCC
+        .constrain()
+}
+
+/// Simulated IPCC registers are global, so stealing is free.
+#[cfg(feature = "host-sim")]
+fn steal_ipcc() -> sim::SimIpcc {
+    sim::SimIpcc
+}
@@ -0,0 +1,432 @@
+//! Futures-based front-end for `TlMbox`.
+//!
+//! `AsyncMbox` owns the mailbox together with IPCC, so that tasks and IPCC interrupt handlers can
+//! share them. The interrupt handlers call `AsyncMbox::interrupt_ipcc_rx_handler` and
+//! `AsyncMbox::interrupt_ipcc_tx_handler`, which wake tasks that wait for command responses and
+//! events. Nothing is allocated, so the futures can be run by any `no_std` executor:
+//!
+//! ```ignore
+//! static MBOX: AsyncMbox<Ipcc> = AsyncMbox::new();
+//!
+//! // Once, before the IPCC interrupts are unmasked
+//! MBOX.init(TlMbox::tl_init(&mut rcc, &mut ipcc), ipcc);
+//!
+//! #[interrupt]
+//! fn IPCC_C1_RX_IT() {
+//!     MBOX.interrupt_ipcc_rx_handler();
+//! }
+//!
+//! #[interrupt]
+//! fn IPCC_C1_TX_IT() {
+//!     MBOX.interrupt_ipcc_tx_handler();
+//! }
+//!
+//! async fn ble_task() {
+//!     MBOX.shci_cmd(|ipcc| Ok(shci_ble_init(ipcc, param))).await.unwrap();
+//!     MBOX.send_ble_cmd(&hci::Reset).await.unwrap();
+//!
+//!     loop {
+//!         let evt = MBOX.next_event().await;
+//!         // ...
+//!     }
+//! }
+//! ```
+//!
+//! Only one task at a time can wait for a response on each command channel and for events.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Context, Poll, Waker};
+
+#[cfg(not(feature = "host-sim"))]
+use cortex_m::interrupt;
+use heapless::consts::{U32, U8};
+use heapless::ArrayLength;
+
+#[cfg(feature = "host-sim")]
+use crate::tl_mbox::sim::interrupt;
+
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::ble::command::{self as ble_command, Command};
+use crate::tl_mbox::channels;
+use crate::tl_mbox::evt::{CcEvt, EvtBox};
+use crate::tl_mbox::shci::{self, PendingShciCmd, ShciError, ShciResponse};
+use crate::tl_mbox::TlMbox;
+
+/// Maximum length of system command parameters.
+const MAX_SYS_CMD_PARAMS_LEN: usize = 255;
+
+struct Shared<I, SQ, BQ>
+where
+    SQ: ArrayLength<EvtBox>,
+    BQ: ArrayLength<EvtBox>,
+{
+    mbox: TlMbox<SQ, BQ>,
+    ipcc: I,
+
+    /// System command has been sent and CPU2 hasn't responded yet.
+    sys_cmd_in_flight: bool,
+
+    sys_cmd_waker: Option<Waker>,
+    ble_cmd_waker: Option<Waker>,
+    evt_waker: Option<Waker>,
+}
+
+/// `TlMbox` and IPCC shared between async tasks and IPCC interrupt handlers.
+///
+/// `SQ` and `BQ` are capacities of the system and BLE event queues of the `TlMbox`.
+pub struct AsyncMbox<I, SQ = U8, BQ = U32>
+where
+    SQ: ArrayLength<EvtBox>,
+    BQ: ArrayLength<EvtBox>,
+{
+    shared: RefCell<Option<Shared<I, SQ, BQ>>>,
+}
+
+// Shared state is only accessed in critical sections. Simulated critical sections don't exclude
+// other threads, so with `host-sim` the mailbox has to stay on a single thread.
+#[cfg(not(feature = "host-sim"))]
+unsafe impl<I, SQ, BQ> Sync for AsyncMbox<I, SQ, BQ>
+where
+    I: Send,
+    SQ: ArrayLength<EvtBox>,
+    BQ: ArrayLength<EvtBox>,
+{
+}
+
+impl<I, SQ, BQ> AsyncMbox<I, SQ, BQ>
+where
+    I: IpccChannels,
+    SQ: ArrayLength<EvtBox>,
+    BQ: ArrayLength<EvtBox>,
+{
+    /// Creates an uninitialized mailbox, suitable for a `static`.
+    pub const fn new() -> Self {
+        AsyncMbox {
+            shared: RefCell::new(None),
+        }
+    }
+
+    /// Hands over initialized mailbox and IPCC.
+    ///
+    /// Must be called before IPCC interrupts are unmasked and before any other method.
+    pub fn init(&self, mbox: TlMbox<SQ, BQ>, ipcc: I) {
+        interrupt::free(|_| {
+            *self.shared.borrow_mut() = Some(Shared {
+                mbox,
+                ipcc,
+                sys_cmd_in_flight: false,
+                sys_cmd_waker: None,
+                ble_cmd_waker: None,
+                evt_waker: None,
+            })
+        });
+    }
+
+    /// Runs `f` with exclusive access to the mailbox and IPCC, for the APIs that don't have an
+    /// async counterpart.
+    ///
+    /// System commands must be sent with `send_sys_cmd` or `shci_cmd` only, so that their
+    /// responses go to the right task.
+    pub fn with<R>(&self, f: impl FnOnce(&mut TlMbox<SQ, BQ>, &mut I) -> R) -> R {
+        self.with_shared(|shared| f(&mut shared.mbox, &mut shared.ipcc))
+    }
+
+    fn with_shared<R>(&self, f: impl FnOnce(&mut Shared<I, SQ, BQ>) -> R) -> R {
+        interrupt::free(|_| {
+            let mut shared = self.shared.borrow_mut();
+            f(shared.as_mut().expect("AsyncMbox is not initialized"))
+        })
+    }
+
+    /// Must be called from the IPCC RX occupied interrupt handler.
+    ///
+    /// Wakes the tasks that wait for BLE command responses and events.
+    pub fn interrupt_ipcc_rx_handler(&self) {
+        let (ble_cmd_waker, evt_waker) = self.with_shared(|shared| {
+            shared.mbox.interrupt_ipcc_rx_handler(&mut shared.ipcc);
+
+            (shared.ble_cmd_waker.take(), shared.evt_waker.take())
+        });
+
+        // Wakers are called outside of the critical section
+        for waker in ble_cmd_waker.into_iter().chain(evt_waker) {
+            waker.wake();
+        }
+    }
+
+    /// Must be called from the IPCC TX free interrupt handler.
+    ///
+    /// Wakes the task that waits for a system command response.
+    pub fn interrupt_ipcc_tx_handler(&self) {
+        let sys_cmd_waker = self.with_shared(|shared| {
+            let sys_cmd_rsp = shared
+                .ipcc
+                .is_tx_pending(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL);
+
+            shared.mbox.interrupt_ipcc_tx_handler(&mut shared.ipcc);
+
+            if sys_cmd_rsp {
+                shared.sys_cmd_in_flight = false;
+                shared.sys_cmd_waker.take()
+            } else {
+                None
+            }
+        });
+
+        if let Some(waker) = sys_cmd_waker {
+            waker.wake();
+        }
+    }
+
+    /// Sends system command with raw `params` and waits for its Command Complete event.
+    ///
+    /// Returns `Busy` if another system command hasn't been answered yet.
+    pub async fn send_sys_cmd(&self, opcode: u16, params: &[u8]) -> Result<CcEvt, ShciError> {
+        if params.len() > MAX_SYS_CMD_PARAMS_LEN {
+            return Err(ShciError::TooLong);
+        }
+
+        self.start_sys_cmd(|ipcc| Ok(shci::send_cmd::<()>(ipcc, opcode, params)))?;
+
+        poll_fn(|cx| {
+            self.with_shared(|shared| match shared.mbox.pop_last_cc_evt() {
+                Some(cc) => Poll::Ready(Ok(cc)),
+                None => {
+                    register(&mut shared.sys_cmd_waker, cx);
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Sends system command with `send` (one of the `shci` or `fus` functions) and waits for
+    /// its decoded response:
+    ///
+    /// ```ignore
+    /// let state = MBOX.shci_cmd(|ipcc| Ok(fus::fus_get_state(ipcc))).await?;
+    /// ```
+    ///
+    /// Returns `Busy` if another system command hasn't been answered yet.
+    pub async fn shci_cmd<R: ShciResponse>(
+        &self,
+        send: impl FnOnce(&mut I) -> Result<PendingShciCmd<R>, ShciError>,
+    ) -> Result<R, ShciError> {
+        let pending = self.start_sys_cmd(send)?;
+
+        poll_fn(|cx| {
+            self.with_shared(|shared| match shared.mbox.poll_shci_cmd(&pending) {
+                Ok(rsp) => Poll::Ready(Ok(rsp)),
+                Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+                Err(nb::Error::WouldBlock) => {
+                    register(&mut shared.sys_cmd_waker, cx);
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    fn start_sys_cmd<R>(
+        &self,
+        send: impl FnOnce(&mut I) -> Result<PendingShciCmd<R>, ShciError>,
+    ) -> Result<PendingShciCmd<R>, ShciError> {
+        self.with_shared(|shared| {
+            if shared.sys_cmd_in_flight {
+                return Err(ShciError::Busy);
+            }
+
+            // Response to a command whose future has been dropped
+            shared.mbox.pop_last_cc_evt();
+
+            let pending = send(&mut shared.ipcc)?;
+            shared.sys_cmd_in_flight = true;
+
+            Ok(pending)
+        })
+    }
+
+    /// Sends typed HCI command on the BLE channel and waits for its response.
+    ///
+    /// Returns `Busy` if another BLE command hasn't been answered yet.
+    pub async fn send_ble_cmd<C: Command>(
+        &self,
+        cmd: &C,
+    ) -> Result<C::Response, ble_command::Error> {
+        let pending = self.with_shared(|shared| shared.mbox.send_ble_cmd(&mut shared.ipcc, cmd))?;
+
+        poll_fn(|cx| {
+            self.with_shared(|shared| match shared.mbox.poll_ble_cmd(&pending) {
+                Ok(rsp) => Poll::Ready(Ok(rsp)),
+                Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+                Err(nb::Error::WouldBlock) => {
+                    register(&mut shared.ble_cmd_waker, cx);
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Waits for the next system or BLE event, system events first.
+    ///
+    /// Command Complete/Status events of the commands sent with `send_ble_cmd` are not
+    /// returned.
+    pub async fn next_event(&self) -> EvtBox {
+        poll_fn(|cx| {
+            self.with_shared(|shared| match shared.mbox.dequeue_event() {
+                Some(evt) => Poll::Ready(evt),
+                None => {
+                    register(&mut shared.evt_waker, cx);
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl<I, SQ, BQ> Default for AsyncMbox<I, SQ, BQ>
+where
+    I: IpccChannels,
+    SQ: ArrayLength<EvtBox>,
+    BQ: ArrayLength<EvtBox>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stores the waker of the task being polled in `slot`.
+fn register(slot: &mut Option<Waker>, cx: &Context) {
+    match slot {
+        Some(waker) if waker.will_wake(cx.waker()) => {}
+        _ => *slot = Some(cx.waker().clone()),
+    }
+}
+
+#[cfg(all(test, feature = "host-sim"))]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::ble::hci::Reset;
+    use crate::tl_mbox::queue::OverflowPolicy;
+    use crate::tl_mbox::shci::{ReadyState, ShciStatus, SysEvent};
+    use crate::tl_mbox::sim::{Cpu2Sim, SimIpcc};
+    use core::convert::TryFrom;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::{RawWaker, RawWakerVTable};
+
+    /// Number of times the test waker has been woken.
+    static WAKES: AtomicUsize = AtomicUsize::new(0);
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(core::ptr::null(), &VTABLE),
+        |_| {
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        },
+        |_| {
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        },
+        |_| {},
+    );
+
+    fn poll<F: Future>(fut: Pin<&mut F>) -> Poll<F::Output> {
+        let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+        fut.poll(&mut Context::from_waker(&waker))
+    }
+
+    fn wakes() -> usize {
+        WAKES.load(Ordering::SeqCst)
+    }
+
+    fn init() -> (Cpu2Sim, AsyncMbox<SimIpcc>) {
+        let sim = Cpu2Sim::new();
+        WAKES.store(0, Ordering::SeqCst);
+
+        let mbox = AsyncMbox::new();
+        mbox.init(
+            TlMbox::tl_init_channels(
+                &mut SimIpcc,
+                OverflowPolicy::DropNewest,
+                OverflowPolicy::DropNewest,
+            ),
+            SimIpcc,
+        );
+
+        (sim, mbox)
+    }
+
+    #[test]
+    fn event_wakes_task() {
+        let (mut sim, mbox) = init();
+
+        let mut fut = mbox.next_event();
+        let mut fut = unsafe { Pin::new_unchecked(&mut fut) };
+        assert!(poll(fut.as_mut()).is_pending());
+        assert_eq!(wakes(), 0);
+
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service_async(&mbox).unwrap();
+        assert_eq!(wakes(), 1);
+
+        match poll(fut.as_mut()) {
+            Poll::Ready(evt) => assert!(matches!(
+                SysEvent::try_from(&evt),
+                Ok(SysEvent::C2Ready(ReadyState::WirelessStack))
+            )),
+            Poll::Pending => panic!("event not ready"),
+        }
+        assert_eq!(
+            mbox.with(|mbox, _| mbox.poll_cpu2_ready()),
+            Ok(ReadyState::WirelessStack)
+        );
+    }
+
+    #[test]
+    fn sys_cmd_response_wakes_task() {
+        let (mut sim, mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service_async(&mbox).unwrap();
+
+        let mut fut = mbox.shci_cmd(|ipcc| Ok(shci::shci_c2_reinit(ipcc)));
+        let mut fut = unsafe { Pin::new_unchecked(&mut fut) };
+        assert!(poll(fut.as_mut()).is_pending());
+
+        // Only one system command at a time
+        let mut busy = mbox.shci_cmd(|ipcc| Ok(shci::shci_c2_reinit(ipcc)));
+        let busy = unsafe { Pin::new_unchecked(&mut busy) };
+        assert_eq!(poll(busy), Poll::Ready(Err(ShciError::Busy)));
+
+        let woken = wakes();
+        sim.service_async(&mbox).unwrap();
+        assert_eq!(wakes(), woken + 1);
+        assert_eq!(poll(fut.as_mut()), Poll::Ready(Ok(ShciStatus::Success)));
+    }
+
+    #[test]
+    fn ble_cmd_response_wakes_task() {
+        let (mut sim, mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service_async(&mbox).unwrap();
+        drop(mbox.with(|mbox, _| mbox.dequeue_event()));
+
+        let mut fut = mbox.send_ble_cmd(&Reset);
+        let mut fut = unsafe { Pin::new_unchecked(&mut fut) };
+        assert!(poll(fut.as_mut()).is_pending());
+
+        let mut busy = mbox.send_ble_cmd(&Reset);
+        let busy = unsafe { Pin::new_unchecked(&mut busy) };
+        assert_eq!(poll(busy), Poll::Ready(Err(ble_command::Error::Busy)));
+
+        let woken = wakes();
+        sim.service_async(&mbox).unwrap();
+        assert!(wakes() > woken);
+        assert_eq!(poll(fut.as_mut()), Poll::Ready(Ok(())));
+
+        // Response isn't delivered as an event
+        assert!(mbox.with(|mbox, _| mbox.dequeue_event()).is_none());
+    }
+}
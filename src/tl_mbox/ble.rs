@@ -1,24 +1,53 @@
-use crate::ipcc::Ipcc;
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::bytes::Writer;
 use crate::tl_mbox::channels;
-use crate::tl_mbox::cmd::{CmdPacket, CmdSerial};
+use crate::tl_mbox::cmd::{AclDataPacket, CmdPacket, CmdSerial};
 use crate::tl_mbox::consts::TlPacketType;
 use crate::tl_mbox::evt::EvtBox;
-use crate::tl_mbox::unsafe_linked_list::{
-    LST_init_head, LST_is_empty, LST_remove_head, LinkedListNode,
-};
+use crate::tl_mbox::linked_list::{LinkedList, LinkedListNode};
+use crate::tl_mbox::queue::EvtQueue;
 use crate::tl_mbox::{
-    evt, BleTable, HeaplessEvtQueue, BLE_CMD_BUFFER, CS_BUFFER, EVT_QUEUE, HCI_ACL_DATA_BUFFER,
-    TL_BLE_TABLE, TL_REF_TABLE,
+    evt, BleTable, BLE_CMD_BUFFER, CS_BUFFER, EVT_QUEUE, HCI_ACL_DATA_BUFFER, TL_BLE_TABLE,
+    TL_REF_TABLE,
 };
+use core::convert::TryFrom;
 use core::mem::MaybeUninit;
-
-pub struct Ble {}
+use heapless::ArrayLength;
+
+pub mod aci;
+pub mod acl;
+pub mod adv;
+pub mod central;
+pub mod command;
+pub mod event;
+pub mod gatt;
+pub mod hci;
+pub mod security;
+pub mod types;
+
+use acl::AclData;
+use command::{Command, PendingCommand};
+use event::Event;
+
+pub struct Ble {
+    /// Opcode of the command that waits for its Command Complete/Status event.
+    pending_opcode: Option<u16>,
+
+    /// Sequence number of the last command sent.
+    seq: u32,
+
+    /// Command Complete/Status event of the last command, with its sequence number.
+    response: Option<(u32, EvtBox)>,
+
+    /// ACL data buffer holds a packet that CPU2 hasn't acknowledged yet.
+    acl_data_pending: bool,
+}
 
 impl Ble {
-    pub(super) fn new(ipcc: &mut Ipcc) -> Self {
-        unsafe {
-            LST_init_head(EVT_QUEUE.as_mut_ptr());
+    pub(super) fn new(ipcc: &mut impl IpccChannels) -> Self {
+        evt_queue().init();
 
+        unsafe {
             TL_BLE_TABLE = MaybeUninit::new(BleTable {
                 pcmd_buffer: BLE_CMD_BUFFER.as_mut_ptr().cast(),
                 pcs_buffer: CS_BUFFER.as_ptr().cast(),
@@ -29,35 +58,176 @@ impl Ble {
 
         ipcc.c1_set_rx_channel(channels::cpu2::IPCC_BLE_EVENT_CHANNEL, true);
 
-        Ble {}
+        Ble {
+            pending_opcode: None,
+            seq: 0,
+            response: None,
+            acl_data_pending: false,
+        }
+    }
+
+    /// Moves events from the CPU2 queue into `queue`, except for the response to the pending
+    /// command.
+    ///
+    /// If `queue` is full with `OverflowPolicy::Backpressure`, the channel is masked and left
+    /// pending until `resume_rx` is called.
+    pub(super) fn evt_handler<N: ArrayLength<EvtBox>>(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        queue: &mut EvtQueue<N>,
+    ) {
+        let evt_queue = evt_queue();
+
+        while let Some(node) = evt_queue.pop_head() {
+            let node: *mut LinkedListNode = node;
+            let event: *mut evt::EvtPacket = node.cast();
+            let event = EvtBox::new(event);
+
+            if self.is_pending_response(&event) {
+                self.pending_opcode = None;
+                self.response = Some((self.seq, event));
+            } else if let Err(event) = queue.push(event) {
+                // Leave the event to CPU2 until there's room for it
+                evt_queue.push_head(unsafe { &mut *event.into_raw().cast() });
+                ipcc.c1_set_rx_channel(channels::cpu2::IPCC_BLE_EVENT_CHANNEL, false);
+
+                return;
+            }
+        }
+
+        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_BLE_EVENT_CHANNEL);
+    }
+
+    /// Unmasks the channel after it has been stopped by `OverflowPolicy::Backpressure`.
+    pub(super) fn resume_rx(&mut self, ipcc: &mut impl IpccChannels) {
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_BLE_EVENT_CHANNEL, true);
+    }
+
+    fn is_pending_response(&self, event: &EvtBox) -> bool {
+        match (self.pending_opcode, Event::try_from(event)) {
+            (Some(opcode), Ok(event)) => command::is_response_to(&event, opcode),
+
+            _ => false,
+        }
     }
 
-    pub(super) fn evt_handler(&self, ipcc: &mut Ipcc, queue: &mut HeaplessEvtQueue) {
+    /// Serializes `cmd` into the BLE command buffer and notifies CPU2.
+    pub(super) fn send_cmd<C: Command>(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        cmd: &C,
+    ) -> Result<PendingCommand<C>, command::Error> {
+        if self.pending_opcode.is_some() {
+            return Err(command::Error::Busy);
+        }
+
         unsafe {
-            let mut node_ptr: *mut LinkedListNode = core::ptr::null_mut();
-            let node_ptr_ptr: *mut *mut LinkedListNode = &mut node_ptr;
+            let pcmd_buffer: *mut CmdPacket = (&*TL_REF_TABLE.assume_init().ble_table).pcmd_buffer;
+            let cmd_serial = &mut (*pcmd_buffer).cmdserial;
+
+            let mut w = Writer::new(&mut cmd_serial.cmd.payload);
+            cmd.write_params(&mut w)?;
+            let payload_len = w.len() as u8;
+
+            cmd_serial.ty = TlPacketType::BleCmd as u8;
+            cmd_serial.cmd.cmd_code = C::OPCODE;
+            cmd_serial.cmd.payload_len = payload_len;
+        }
 
-            while !LST_is_empty(EVT_QUEUE.as_mut_ptr()) {
-                LST_remove_head(EVT_QUEUE.as_mut_ptr(), node_ptr_ptr);
+        // Response to the previous command (if not picked up) is not needed anymore
+        self.response = None;
+        self.pending_opcode = Some(C::OPCODE);
+        self.seq = self.seq.wrapping_add(1);
 
-                let event: *mut evt::EvtPacket = node_ptr.cast();
-                let event = EvtBox::new(event);
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_BLE_CMD_CHANNEL);
 
-                queue.enqueue(event).unwrap();
+        Ok(PendingCommand::new(self.seq))
+    }
+
+    /// Returns decoded response to the `pending` command, if it has arrived.
+    pub(super) fn poll_cmd<C: Command>(
+        &mut self,
+        pending: &PendingCommand<C>,
+    ) -> nb::Result<C::Response, command::Error> {
+        if pending.seq != self.seq {
+            return Err(nb::Error::Other(command::Error::Superseded));
+        }
+
+        match self.response.take() {
+            Some((_, evt)) => {
+                let event = Event::try_from(&evt).map_err(|_| command::Error::Malformed)?;
+                Ok(command::decode_response::<C>(&event)?)
             }
+
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    /// Forgets the pending command, so that the next one can be sent if its Command
+    /// Complete/Status event is lost. Polling the cancelled command returns `Superseded`.
+    pub(super) fn cancel_pending(&mut self) {
+        self.pending_opcode = None;
+        self.response = None;
+        self.seq = self.seq.wrapping_add(1);
+    }
+
+    /// Copies `acl` into the ACL data buffer and notifies CPU2.
+    ///
+    /// Returns `WouldBlock` while CPU2 hasn't acknowledged the previous packet.
+    pub(super) fn send_acl_data(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        acl: &AclData,
+    ) -> nb::Result<(), acl::Error> {
+        if acl.data.len() > acl::MAX_ACL_DATA_LEN {
+            return Err(nb::Error::Other(acl::Error::TooLong));
         }
 
-        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_BLE_EVENT_CHANNEL);
+        if self.acl_data_pending {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        unsafe {
+            let packet: *mut AclDataPacket =
+                (&*TL_REF_TABLE.assume_init().ble_table).phci_acl_data_buffer;
+            let acl_serial = &mut (*packet).acl_data_serial;
+
+            acl_serial.ty = TlPacketType::AclData as u8;
+            acl_serial.handle = acl.raw_handle();
+            acl_serial.length = acl.data.len() as u16;
+            acl_serial.acl_data[..acl.data.len()].copy_from_slice(acl.data);
+        }
+
+        self.acl_data_pending = true;
+
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL);
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL, true);
+
+        Ok(())
     }
 
-    pub(super) fn acl_data_handler(&self, ipcc: &mut Ipcc) {
+    /// Returns `true` if the ACL data buffer can take the next packet.
+    pub(super) fn is_acl_data_buffer_free(&self) -> bool {
+        !self.acl_data_pending
+    }
+
+    /// CPU2 has consumed the ACL data packet.
+    pub(super) fn acl_data_handler(&mut self, ipcc: &mut impl IpccChannels) {
         ipcc.c1_set_tx_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL, false);
 
-        // TODO: ACL data ack to the user
+        self.acl_data_pending = false;
     }
 }
 
-pub fn ble_send_cmd(ipcc: &mut Ipcc, buf: &[u8]) {
+/// Sends a raw HCI command packet (packet type, opcode, parameter length and parameters).
+///
+/// Returns an error if the packet doesn't fit into the command buffer.
+/// Its Command Complete/Status event is delivered through the event queue.
+pub fn ble_send_cmd(ipcc: &mut impl IpccChannels, buf: &[u8]) -> Result<(), ()> {
+    if buf.len() > core::mem::size_of::<CmdSerial>() {
+        return Err(());
+    }
+
     unsafe {
         let pcmd_buffer: *mut CmdPacket = (&*TL_REF_TABLE.assume_init().ble_table).pcmd_buffer;
         let pcmd_serial: *mut CmdSerial = &mut (*pcmd_buffer).cmdserial;
@@ -70,14 +240,11 @@ pub fn ble_send_cmd(ipcc: &mut Ipcc, buf: &[u8]) {
     }
 
     ipcc.c1_set_flag_channel(channels::cpu1::IPCC_BLE_CMD_CHANNEL);
-}
 
-#[allow(dead_code)] // Not used currently but reserved
-pub(super) fn ble_send_acl_data(ipcc: &mut Ipcc) {
-    let mut cmd_packet =
-        unsafe { &mut *(*TL_REF_TABLE.assume_init().ble_table).phci_acl_data_buffer };
-    cmd_packet.acl_data_serial.ty = TlPacketType::AclData as u8;
+    Ok(())
+}
 
-    ipcc.c1_set_flag_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL);
-    ipcc.c1_set_tx_channel(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL, true);
+/// BLE event queue that CPU2 fills.
+fn evt_queue() -> LinkedList {
+    unsafe { LinkedList::from_head(EVT_QUEUE.as_mut_ptr()) }
 }
@@ -0,0 +1,56 @@
+//! ST vendor-specific ACI commands and events of the BLE stack running on CPU2.
+//!
+//! ACI commands are regular HCI commands with the vendor OGF (0x3f) and are sent with
+//! `TlMbox::send_ble_cmd`. ACI events arrive as HCI vendor events (`event::Event::Vendor`)
+//! and can be decoded further with `AciEvent::try_from`.
+
+use core::convert::TryFrom;
+
+use super::event::{Error, VendorEvent};
+use crate::tl_mbox::bytes::Reader;
+
+pub mod gap;
+pub mod gatt;
+pub mod hal;
+pub mod l2cap;
+
+#[derive(Debug, Copy, Clone)]
+pub enum AciEvent<'a> {
+    Gap(gap::Event<'a>),
+    Gatt(gatt::Event<'a>),
+    Hal(hal::Event<'a>),
+    L2cap(l2cap::Event<'a>),
+
+    /// Vendor event that has no typed representation yet.
+    Unknown {
+        code: u16,
+        params: &'a [u8],
+    },
+}
+
+impl<'a> TryFrom<VendorEvent<'a>> for AciEvent<'a> {
+    type Error = Error;
+
+    fn try_from(evt: VendorEvent<'a>) -> Result<Self, Self::Error> {
+        let mut r = Reader::new(evt.params);
+
+        // Event group is encoded in the upper bits of the event code
+        let event = match evt.code >> 10 {
+            0x00 => hal::Event::parse(evt.code, &mut r).map(|e| e.map(AciEvent::Hal)),
+            0x01 => gap::Event::parse(evt.code, &mut r).map(|e| e.map(AciEvent::Gap)),
+            0x02 => l2cap::Event::parse(evt.code, &mut r).map(|e| e.map(AciEvent::L2cap)),
+            0x03 => gatt::Event::parse(evt.code, &mut r).map(|e| e.map(AciEvent::Gatt)),
+
+            _ => Some(None),
+        };
+
+        match event {
+            Some(Some(event)) => Ok(event),
+            Some(None) => Ok(AciEvent::Unknown {
+                code: evt.code,
+                params: evt.params,
+            }),
+            None => Err(Error::Malformed),
+        }
+    }
+}
@@ -0,0 +1,633 @@
+//! ACI GAP commands and events.
+
+use crate::tl_mbox::ble::command::{opcode, Command, Error, Response, OGF_VENDOR};
+use crate::tl_mbox::ble::hci::{AdvertisingType, MAX_ADV_DATA_LEN};
+use crate::tl_mbox::ble::types::{AddressType, BdAddr, ConnectionHandle, Status};
+use crate::tl_mbox::bytes::{Reader, Writer};
+
+pub const ROLE_PERIPHERAL: u8 = 0x01;
+pub const ROLE_BROADCASTER: u8 = 0x02;
+pub const ROLE_CENTRAL: u8 = 0x04;
+pub const ROLE_OBSERVER: u8 = 0x08;
+
+pub const PROC_LIMITED_DISCOVERY: u8 = 0x01;
+pub const PROC_GENERAL_DISCOVERY: u8 = 0x02;
+pub const PROC_NAME_DISCOVERY: u8 = 0x04;
+pub const PROC_AUTO_CONNECTION_ESTABLISHMENT: u8 = 0x08;
+pub const PROC_GENERAL_CONNECTION_ESTABLISHMENT: u8 = 0x10;
+pub const PROC_SELECTIVE_CONNECTION_ESTABLISHMENT: u8 = 0x20;
+pub const PROC_DIRECT_CONNECTION_ESTABLISHMENT: u8 = 0x40;
+pub const PROC_OBSERVATION: u8 = 0x80;
+
+/// Largest passkey that can be displayed or entered (six decimal digits).
+pub const MAX_PASS_KEY: u32 = 999_999;
+
+pub const EVT_LIMITED_DISCOVERABLE: u16 = 0x0400;
+pub const EVT_PAIRING_COMPLETE: u16 = 0x0401;
+pub const EVT_PASS_KEY_REQ: u16 = 0x0402;
+pub const EVT_AUTHORIZATION_REQ: u16 = 0x0403;
+pub const EVT_SLAVE_SECURITY_INITIATED: u16 = 0x0404;
+pub const EVT_BOND_LOST: u16 = 0x0405;
+pub const EVT_PROC_COMPLETE: u16 = 0x0407;
+pub const EVT_ADDR_NOT_RESOLVED: u16 = 0x0408;
+pub const EVT_NUMERIC_COMPARISON_VALUE: u16 = 0x0409;
+pub const EVT_KEYPRESS_NOTIFICATION: u16 = 0x040a;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Privacy {
+    Disabled = 0x00,
+    Host = 0x01,
+    Controller = 0x02,
+}
+
+/// Initializes GAP layer, registers GAP service and its characteristics.
+#[derive(Debug, Copy, Clone)]
+pub struct Init {
+    /// Bitmask of `ROLE_*` constants.
+    pub role: u8,
+    pub privacy: Privacy,
+    /// Length of the device name characteristic.
+    pub device_name_char_len: u8,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct InitResponse {
+    pub service_handle: u16,
+    pub dev_name_char_handle: u16,
+    pub appearance_char_handle: u16,
+}
+
+impl Response for InitResponse {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(params);
+
+        Some(InitResponse {
+            service_handle: r.u16()?,
+            dev_name_char_handle: r.u16()?,
+            appearance_char_handle: r.u16()?,
+        })
+    }
+}
+
+impl Command for Init {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x008a);
+    type Response = InitResponse;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.role)?;
+        w.u8(self.privacy as u8)?;
+        w.u8(self.device_name_char_len)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SetNonDiscoverable;
+
+impl Command for SetNonDiscoverable {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0081);
+    type Response = ();
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Puts the device into general discoverable mode and starts advertising.
+#[derive(Debug, Copy, Clone)]
+pub struct SetDiscoverable<'a> {
+    /// Only undirected advertising types are allowed.
+    pub advertising_type: AdvertisingType,
+    /// Minimum advertising interval, in units of 0.625 ms.
+    pub interval_min: u16,
+    /// Maximum advertising interval, in units of 0.625 ms.
+    pub interval_max: u16,
+    pub own_address_type: AddressType,
+    pub filter_policy: u8,
+    /// Local name AD structure without the length byte: AD type (0x08 or 0x09) followed by the
+    /// name. Empty to omit.
+    pub local_name: &'a [u8],
+    /// Service UUID list AD structure without the length byte: AD type followed by the UUIDs.
+    /// Empty to omit.
+    pub service_uuid_list: &'a [u8],
+    /// Minimum slave connection interval, in units of 1.25 ms. 0 to omit.
+    pub slave_conn_interval_min: u16,
+    /// Maximum slave connection interval, in units of 1.25 ms. 0 to omit.
+    pub slave_conn_interval_max: u16,
+}
+
+impl<'a> Command for SetDiscoverable<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0083);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        match self.advertising_type {
+            AdvertisingType::ConnectableUndirected
+            | AdvertisingType::ScannableUndirected
+            | AdvertisingType::NonConnectableUndirected => {}
+
+            _ => return Err(Error::InvalidParameter),
+        }
+
+        if self.local_name.len() + self.service_uuid_list.len() > MAX_ADV_DATA_LEN {
+            return Err(Error::TooLong);
+        }
+
+        w.u8(self.advertising_type as u8)?;
+        w.u16(self.interval_min)?;
+        w.u16(self.interval_max)?;
+        w.u8(self.own_address_type as u8)?;
+        w.u8(self.filter_policy)?;
+        w.u8(self.local_name.len() as u8)?;
+        w.bytes(self.local_name)?;
+        w.u8(self.service_uuid_list.len() as u8)?;
+        w.bytes(self.service_uuid_list)?;
+        w.u16(self.slave_conn_interval_min)?;
+        w.u16(self.slave_conn_interval_max)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IoCapability {
+    DisplayOnly = 0x00,
+    DisplayYesNo = 0x01,
+    KeyboardOnly = 0x02,
+    NoInputNoOutput = 0x03,
+    KeyboardDisplay = 0x04,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SetIoCapability {
+    pub io_capability: IoCapability,
+}
+
+impl Command for SetIoCapability {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0085);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.io_capability as u8)?;
+        Ok(())
+    }
+}
+
+/// LE Secure Connections pairing support.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ScSupport {
+    /// Legacy pairing only.
+    NotSupported = 0x00,
+    /// Secure Connections are used if the peer supports them, legacy pairing otherwise.
+    Optional = 0x01,
+    /// Pairing with peers that don't support Secure Connections fails.
+    Mandatory = 0x02,
+}
+
+/// Type of the identity address distributed during bonding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IdentityAddressType {
+    Public = 0x00,
+    RandomStatic = 0x01,
+}
+
+/// Sets the security requirements used for pairing.
+#[derive(Debug, Copy, Clone)]
+pub struct SetAuthenticationRequirement {
+    /// Store keys of the peer to reconnect without pairing.
+    pub bonding: bool,
+    /// Require protection against man-in-the-middle attacks (passkey or numeric comparison).
+    pub mitm: bool,
+    pub secure_connections: ScSupport,
+    pub keypress_notifications: bool,
+    /// Minimum encryption key size, 7..=16.
+    pub min_encryption_key_size: u8,
+    /// Maximum encryption key size, 7..=16.
+    pub max_encryption_key_size: u8,
+    /// Passkey used instead of the one requested with `Event::PassKeyRequest`,
+    /// up to `MAX_PASS_KEY`.
+    pub fixed_pin: Option<u32>,
+    pub identity_address_type: IdentityAddressType,
+}
+
+impl SetAuthenticationRequirement {
+    /// Requirements for authenticated LE Secure Connections pairing with 16-byte keys.
+    pub fn authenticated_secure_connections(bonding: bool) -> Self {
+        SetAuthenticationRequirement {
+            bonding,
+            mitm: true,
+            secure_connections: ScSupport::Mandatory,
+            keypress_notifications: false,
+            min_encryption_key_size: 16,
+            max_encryption_key_size: 16,
+            fixed_pin: None,
+            identity_address_type: IdentityAddressType::Public,
+        }
+    }
+}
+
+impl Command for SetAuthenticationRequirement {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0086);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        let key_sizes = 7..=16;
+        if !key_sizes.contains(&self.min_encryption_key_size)
+            || !key_sizes.contains(&self.max_encryption_key_size)
+            || self.min_encryption_key_size > self.max_encryption_key_size
+        {
+            return Err(Error::InvalidParameter);
+        }
+
+        if self.fixed_pin.map_or(false, |pin| pin > MAX_PASS_KEY) {
+            return Err(Error::InvalidParameter);
+        }
+
+        w.u8(self.bonding as u8)?;
+        w.u8(self.mitm as u8)?;
+        w.u8(self.secure_connections as u8)?;
+        w.u8(self.keypress_notifications as u8)?;
+        w.u8(self.min_encryption_key_size)?;
+        w.u8(self.max_encryption_key_size)?;
+        // 0x00 selects the fixed pin
+        w.u8(self.fixed_pin.is_none() as u8)?;
+        w.u32(self.fixed_pin.unwrap_or(0))?;
+        w.u8(self.identity_address_type as u8)?;
+        Ok(())
+    }
+}
+
+/// Answers `Event::PassKeyRequest`.
+#[derive(Debug, Copy, Clone)]
+pub struct PassKeyResp {
+    pub handle: ConnectionHandle,
+    /// Up to `MAX_PASS_KEY`.
+    pub pass_key: u32,
+}
+
+impl Command for PassKeyResp {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0088);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.pass_key > MAX_PASS_KEY {
+            return Err(Error::InvalidParameter);
+        }
+
+        w.u16(self.handle.0)?;
+        w.u32(self.pass_key)?;
+        Ok(())
+    }
+}
+
+/// Answers `Event::AuthorizationRequest`.
+#[derive(Debug, Copy, Clone)]
+pub struct AuthorizationResp {
+    pub handle: ConnectionHandle,
+    pub authorize: bool,
+}
+
+impl Command for AuthorizationResp {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0089);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        // 0x01: authorize, 0x02: reject
+        w.u8(if self.authorize { 0x01 } else { 0x02 })?;
+        Ok(())
+    }
+}
+
+/// Sends slave security request to the master. Responds with Command Status.
+#[derive(Debug, Copy, Clone)]
+pub struct SlaveSecurityReq {
+    pub handle: ConnectionHandle,
+}
+
+impl Command for SlaveSecurityReq {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x008d);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        Ok(())
+    }
+}
+
+/// Security level of a connection, as defined for LE security mode 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum SecurityLevel {
+    NoSecurity = 0x01,
+    /// Unauthenticated pairing with encryption.
+    Unauthenticated = 0x02,
+    /// Authenticated pairing with encryption.
+    Authenticated = 0x03,
+    /// Authenticated LE Secure Connections pairing with encryption.
+    AuthenticatedSecureConnections = 0x04,
+}
+
+impl SecurityLevel {
+    fn from_raw(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(SecurityLevel::NoSecurity),
+            0x02 => Some(SecurityLevel::Unauthenticated),
+            0x03 => Some(SecurityLevel::Authenticated),
+            0x04 => Some(SecurityLevel::AuthenticatedSecureConnections),
+
+            _ => None,
+        }
+    }
+}
+
+impl Response for SecurityLevel {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(params);
+
+        // Only LE security mode 1 is supported
+        let _mode = r.u8()?;
+        SecurityLevel::from_raw(r.u8()?)
+    }
+}
+
+/// Reads the security level of a connection. Responds with `SecurityLevel`.
+#[derive(Debug, Copy, Clone)]
+pub struct GetSecurityLevel {
+    pub handle: ConnectionHandle,
+}
+
+impl Command for GetSecurityLevel {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0090);
+    type Response = SecurityLevel;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        Ok(())
+    }
+}
+
+/// Removes all bonded devices.
+#[derive(Debug, Copy, Clone)]
+pub struct ClearSecurityDb;
+
+impl Command for ClearSecurityDb {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0094);
+    type Response = ();
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Allows pairing with a device whose bond has been lost, see `Event::BondLost`.
+#[derive(Debug, Copy, Clone)]
+pub struct AllowRebond {
+    pub handle: ConnectionHandle,
+}
+
+impl Command for AllowRebond {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0095);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        Ok(())
+    }
+}
+
+/// Starts pairing as a master. Responds with Command Status, followed by
+/// `Event::PairingComplete`.
+#[derive(Debug, Copy, Clone)]
+pub struct SendPairingReq {
+    pub handle: ConnectionHandle,
+    /// Pair again even if the device is already bonded.
+    pub force_rebond: bool,
+}
+
+impl Command for SendPairingReq {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x009f);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        w.u8(self.force_rebond as u8)?;
+        Ok(())
+    }
+}
+
+/// Resolves a resolvable private address with the IRKs of bonded devices.
+/// Responds with the identity address.
+#[derive(Debug, Copy, Clone)]
+pub struct ResolvePrivateAddr {
+    pub address: BdAddr,
+}
+
+impl Command for ResolvePrivateAddr {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x00a0);
+    type Response = BdAddr;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.bytes(&self.address.0)?;
+        Ok(())
+    }
+}
+
+/// Answers `Event::NumericComparisonValue`.
+#[derive(Debug, Copy, Clone)]
+pub struct NumericComparisonValueConfirm {
+    pub handle: ConnectionHandle,
+    /// Both devices display the same value.
+    pub confirm: bool,
+}
+
+impl Command for NumericComparisonValueConfirm {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x00a5);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        w.u8(self.confirm as u8)?;
+        Ok(())
+    }
+}
+
+/// Adds or replaces AD structures in the advertising data while advertising.
+#[derive(Debug, Copy, Clone)]
+pub struct UpdateAdvData<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> Command for UpdateAdvData<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x008e);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.data.len() > MAX_ADV_DATA_LEN {
+            return Err(Error::TooLong);
+        }
+
+        w.u8(self.data.len() as u8)?;
+        w.bytes(self.data)?;
+        Ok(())
+    }
+}
+
+/// Removes AD structure of the given type from the advertising data.
+#[derive(Debug, Copy, Clone)]
+pub struct DeleteAdType {
+    pub ad_type: u8,
+}
+
+impl Command for DeleteAdType {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x008f);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.ad_type)?;
+        Ok(())
+    }
+}
+
+/// Terminates a connection. Responds with Command Status, followed by Disconnection Complete event.
+#[derive(Debug, Copy, Clone)]
+pub struct Terminate {
+    pub handle: ConnectionHandle,
+    pub reason: u8,
+}
+
+impl Command for Terminate {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0093);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        w.u8(self.reason)?;
+        Ok(())
+    }
+}
+
+/// Terminates the specified GAP procedure (one of `PROC_*` constants).
+#[derive(Debug, Copy, Clone)]
+pub struct TerminateProc {
+    pub procedure_code: u8,
+}
+
+impl Command for TerminateProc {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x009d);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.procedure_code)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Event<'a> {
+    /// Limited discoverable mode ended because of timeout.
+    LimitedDiscoverable,
+
+    PairingComplete {
+        handle: ConnectionHandle,
+        /// 0x00: success, 0x01: timeout, 0x02: failed, 0x03: encryption failed (LTK missing).
+        status: u8,
+        /// SMP pairing failure reason, valid if `status` is 0x02.
+        reason: u8,
+    },
+
+    /// Passkey is requested by the stack to proceed with pairing.
+    PassKeyRequest {
+        handle: ConnectionHandle,
+    },
+
+    AuthorizationRequest {
+        handle: ConnectionHandle,
+    },
+
+    /// Slave security request has been sent to the master.
+    SlaveSecurityInitiated,
+
+    /// Pairing was requested by a previously bonded device whose bond has been lost.
+    BondLost,
+
+    ProcComplete {
+        /// One of `PROC_*` constants.
+        procedure_code: u8,
+        status: Status,
+        data: &'a [u8],
+    },
+
+    AddrNotResolved {
+        handle: ConnectionHandle,
+    },
+
+    /// Numeric comparison value to be confirmed by the user.
+    NumericComparisonValue {
+        handle: ConnectionHandle,
+        value: u32,
+    },
+
+    KeypressNotification {
+        handle: ConnectionHandle,
+        notification_type: u8,
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Returns `None` if the event is malformed and `Some(None)` if the code is unknown.
+    pub(super) fn parse(code: u16, r: &mut Reader<'a>) -> Option<Option<Self>> {
+        Some(Some(match code {
+            EVT_LIMITED_DISCOVERABLE => Event::LimitedDiscoverable,
+
+            EVT_PAIRING_COMPLETE => Event::PairingComplete {
+                handle: ConnectionHandle::from_raw(r.u16()?),
+                status: r.u8()?,
+                reason: r.u8()?,
+            },
+
+            EVT_PASS_KEY_REQ => Event::PassKeyRequest {
+                handle: ConnectionHandle::from_raw(r.u16()?),
+            },
+
+            EVT_AUTHORIZATION_REQ => Event::AuthorizationRequest {
+                handle: ConnectionHandle::from_raw(r.u16()?),
+            },
+
+            EVT_SLAVE_SECURITY_INITIATED => Event::SlaveSecurityInitiated,
+
+            EVT_BOND_LOST => Event::BondLost,
+
+            EVT_PROC_COMPLETE => {
+                let procedure_code = r.u8()?;
+                let status = Status(r.u8()?);
+                let len = r.u8()? as usize;
+
+                Event::ProcComplete {
+                    procedure_code,
+                    status,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            EVT_ADDR_NOT_RESOLVED => Event::AddrNotResolved {
+                handle: ConnectionHandle::from_raw(r.u16()?),
+            },
+
+            EVT_NUMERIC_COMPARISON_VALUE => Event::NumericComparisonValue {
+                handle: ConnectionHandle::from_raw(r.u16()?),
+                value: r.u32()?,
+            },
+
+            EVT_KEYPRESS_NOTIFICATION => Event::KeypressNotification {
+                handle: ConnectionHandle::from_raw(r.u16()?),
+                notification_type: r.u8()?,
+            },
+
+            _ => return Some(None),
+        }))
+    }
+}
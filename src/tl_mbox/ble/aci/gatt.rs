@@ -0,0 +1,899 @@
+//! ACI GATT commands and events.
+
+use crate::tl_mbox::ble::command::{opcode, Command, Error, Response, OGF_VENDOR};
+use crate::tl_mbox::ble::gatt::{CharacteristicHandle, DescriptorHandle, ServiceHandle};
+use crate::tl_mbox::ble::types::{ConnectionHandle, Uuid};
+use crate::tl_mbox::bytes::{Reader, Writer};
+
+pub const PROP_BROADCAST: u8 = 0x01;
+pub const PROP_READ: u8 = 0x02;
+pub const PROP_WRITE_WITHOUT_RESP: u8 = 0x04;
+pub const PROP_WRITE: u8 = 0x08;
+pub const PROP_NOTIFY: u8 = 0x10;
+pub const PROP_INDICATE: u8 = 0x20;
+pub const PROP_SIGNED_WRITE: u8 = 0x40;
+pub const PROP_EXT: u8 = 0x80;
+
+pub const PERM_NONE: u8 = 0x00;
+pub const PERM_AUTHEN_READ: u8 = 0x01;
+pub const PERM_AUTHOR_READ: u8 = 0x02;
+pub const PERM_ENCRY_READ: u8 = 0x04;
+pub const PERM_AUTHEN_WRITE: u8 = 0x08;
+pub const PERM_AUTHOR_WRITE: u8 = 0x10;
+pub const PERM_ENCRY_WRITE: u8 = 0x20;
+
+pub const ACCESS_NONE: u8 = 0x00;
+pub const ACCESS_READ: u8 = 0x01;
+pub const ACCESS_WRITE_REQ: u8 = 0x02;
+pub const ACCESS_READ_WRITE: u8 = 0x03;
+pub const ACCESS_WRITE_WITHOUT_RESP: u8 = 0x04;
+pub const ACCESS_SIGNED_WRITE: u8 = 0x08;
+
+pub const NOTIFY_NONE: u8 = 0x00;
+/// Report `AttributeModified` once a client wrote the attribute.
+pub const NOTIFY_ATTRIBUTE_WRITE: u8 = 0x01;
+/// Report `WritePermitRequest` and wait for `WriteResp` before accepting a write.
+pub const NOTIFY_WRITE_REQ_AND_WAIT_FOR_APPL_RESP: u8 = 0x02;
+/// Report `ReadPermitRequest` and wait for `AllowRead` before serving a read.
+pub const NOTIFY_READ_REQ_AND_WAIT_FOR_APPL_RESP: u8 = 0x04;
+
+pub const EVT_ATTRIBUTE_MODIFIED: u16 = 0x0c01;
+pub const EVT_PROC_TIMEOUT: u16 = 0x0c02;
+pub const EVT_EXCHANGE_MTU_RESP: u16 = 0x0c03;
+pub const EVT_FIND_INFO_RESP: u16 = 0x0c04;
+pub const EVT_FIND_BY_TYPE_VALUE_RESP: u16 = 0x0c05;
+pub const EVT_READ_BY_TYPE_RESP: u16 = 0x0c06;
+pub const EVT_READ_RESP: u16 = 0x0c07;
+pub const EVT_READ_BLOB_RESP: u16 = 0x0c08;
+pub const EVT_READ_BY_GROUP_TYPE_RESP: u16 = 0x0c0a;
+pub const EVT_INDICATION: u16 = 0x0c0e;
+pub const EVT_NOTIFICATION: u16 = 0x0c0f;
+pub const EVT_PROC_COMPLETE: u16 = 0x0c10;
+pub const EVT_ERROR_RESP: u16 = 0x0c11;
+pub const EVT_DISC_READ_CHAR_BY_UUID_RESP: u16 = 0x0c12;
+pub const EVT_WRITE_PERMIT_REQ: u16 = 0x0c13;
+pub const EVT_READ_PERMIT_REQ: u16 = 0x0c14;
+pub const EVT_TX_POOL_AVAILABLE: u16 = 0x0c16;
+pub const EVT_SERVER_CONFIRMATION: u16 = 0x0c17;
+
+/// Minimum and maximum encryption key size accepted by `AddChar` and `AddCharDesc`.
+pub const ENC_KEY_SIZE_MIN: u8 = 7;
+pub const ENC_KEY_SIZE_MAX: u8 = 16;
+
+impl Response for ServiceHandle {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        Reader::new(params).u16().map(ServiceHandle)
+    }
+}
+
+impl Response for CharacteristicHandle {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        Reader::new(params).u16().map(CharacteristicHandle)
+    }
+}
+
+impl Response for DescriptorHandle {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        Reader::new(params).u16().map(DescriptorHandle)
+    }
+}
+
+fn check_enc_key_size(enc_key_size: u8) -> Result<(), Error> {
+    if !(ENC_KEY_SIZE_MIN..=ENC_KEY_SIZE_MAX).contains(&enc_key_size) {
+        return Err(Error::InvalidParameter);
+    }
+
+    Ok(())
+}
+
+/// Initializes GATT layer, must be sent before `gap::Init`.
+#[derive(Debug, Copy, Clone)]
+pub struct Init;
+
+impl Command for Init {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0101);
+    type Response = ();
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ServiceType {
+    Primary = 0x01,
+    Secondary = 0x02,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AddService {
+    pub uuid: Uuid,
+    pub service_type: ServiceType,
+    /// Number of attribute records of the service, including the service declaration itself.
+    pub max_attribute_records: u8,
+}
+
+impl Command for AddService {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0102);
+    type Response = ServiceHandle;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        self.uuid.write_aci(w)?;
+        w.u8(self.service_type as u8)?;
+        w.u8(self.max_attribute_records)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AddChar {
+    pub service: ServiceHandle,
+    pub uuid: Uuid,
+    /// Maximum length of the characteristic value.
+    pub value_len: u16,
+    /// Bitmask of `PROP_*` constants.
+    pub properties: u8,
+    /// Bitmask of `PERM_*` constants.
+    pub security_permissions: u8,
+    /// Bitmask of `NOTIFY_*` constants.
+    pub event_mask: u8,
+    pub enc_key_size: u8,
+    /// Value length may change, up to `value_len`.
+    pub is_variable: bool,
+}
+
+impl Command for AddChar {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0104);
+    type Response = CharacteristicHandle;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        check_enc_key_size(self.enc_key_size)?;
+
+        w.u16(self.service.0)?;
+        self.uuid.write_aci(w)?;
+        w.u16(self.value_len)?;
+        w.u8(self.properties)?;
+        w.u8(self.security_permissions)?;
+        w.u8(self.event_mask)?;
+        w.u8(self.enc_key_size)?;
+        w.u8(self.is_variable as u8)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AddCharDesc<'a> {
+    pub service: ServiceHandle,
+    pub characteristic: CharacteristicHandle,
+    pub uuid: Uuid,
+    pub max_len: u8,
+    /// Initial value, not longer than `max_len`.
+    pub value: &'a [u8],
+    /// Bitmask of `PERM_*` constants.
+    pub security_permissions: u8,
+    /// One of `ACCESS_*` constants.
+    pub access_permissions: u8,
+    /// Bitmask of `NOTIFY_*` constants.
+    pub event_mask: u8,
+    pub enc_key_size: u8,
+    pub is_variable: bool,
+}
+
+impl<'a> Command for AddCharDesc<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0105);
+    type Response = DescriptorHandle;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        check_enc_key_size(self.enc_key_size)?;
+        if self.value.len() > self.max_len as usize {
+            return Err(Error::TooLong);
+        }
+
+        w.u16(self.service.0)?;
+        w.u16(self.characteristic.0)?;
+        self.uuid.write_aci(w)?;
+        w.u8(self.max_len)?;
+        w.u8(self.value.len() as u8)?;
+        w.bytes(self.value)?;
+        w.u8(self.security_permissions)?;
+        w.u8(self.access_permissions)?;
+        w.u8(self.event_mask)?;
+        w.u8(self.enc_key_size)?;
+        w.u8(self.is_variable as u8)?;
+        Ok(())
+    }
+}
+
+/// Updates characteristic value. If a client enabled notifications or indications,
+/// they are sent to it automatically.
+#[derive(Debug, Copy, Clone)]
+pub struct UpdateCharValue<'a> {
+    pub service: ServiceHandle,
+    pub characteristic: CharacteristicHandle,
+    pub offset: u8,
+    pub value: &'a [u8],
+}
+
+impl<'a> Command for UpdateCharValue<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0106);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.value.len() > u8::MAX as usize {
+            return Err(Error::TooLong);
+        }
+
+        w.u16(self.service.0)?;
+        w.u16(self.characteristic.0)?;
+        w.u8(self.offset)?;
+        w.u8(self.value.len() as u8)?;
+        w.bytes(self.value)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum UpdateType {
+    /// Only update the local value.
+    Local = 0x00,
+    Notification = 0x01,
+    Indication = 0x02,
+}
+
+/// Updates characteristic value and explicitly notifies or indicates it to a single connection.
+#[derive(Debug, Copy, Clone)]
+pub struct UpdateCharValueExt<'a> {
+    /// Connection to notify, `None` for all connections that enabled the CCCD.
+    pub conn_handle: Option<ConnectionHandle>,
+    pub service: ServiceHandle,
+    pub characteristic: CharacteristicHandle,
+    pub update_type: UpdateType,
+    /// Total length of the characteristic value after the update.
+    pub total_len: u16,
+    pub offset: u16,
+    pub value: &'a [u8],
+}
+
+impl<'a> Command for UpdateCharValueExt<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x012c);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.value.len() > u8::MAX as usize {
+            return Err(Error::TooLong);
+        }
+
+        w.u16(self.conn_handle.map(|h| h.0).unwrap_or(0))?;
+        w.u16(self.service.0)?;
+        w.u16(self.characteristic.0)?;
+        w.u8(self.update_type as u8)?;
+        w.u16(self.total_len)?;
+        w.u16(self.offset)?;
+        w.u8(self.value.len() as u8)?;
+        w.bytes(self.value)?;
+        Ok(())
+    }
+}
+
+/// Answers a `WritePermitRequest`.
+#[derive(Debug, Copy, Clone)]
+pub struct WriteResp<'a> {
+    pub conn_handle: ConnectionHandle,
+    pub attr_handle: u16,
+    /// Accept the write. If rejected, `error_code` is sent to the client.
+    pub accept: bool,
+    pub error_code: u8,
+    /// Value to be written if the write is accepted.
+    pub value: &'a [u8],
+}
+
+impl<'a> Command for WriteResp<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0126);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.value.len() > u8::MAX as usize {
+            return Err(Error::TooLong);
+        }
+
+        w.u16(self.conn_handle.0)?;
+        w.u16(self.attr_handle)?;
+        // 0x00 means the write is accepted
+        w.u8(!self.accept as u8)?;
+        w.u8(self.error_code)?;
+        w.u8(self.value.len() as u8)?;
+        w.bytes(self.value)?;
+        Ok(())
+    }
+}
+
+/// Answers a `ReadPermitRequest`, allowing the stack to serve the read.
+#[derive(Debug, Copy, Clone)]
+pub struct AllowRead {
+    pub conn_handle: ConnectionHandle,
+}
+
+impl Command for AllowRead {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0127);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        Ok(())
+    }
+}
+
+/// Answers a `ReadPermitRequest` with an ATT error.
+#[derive(Debug, Copy, Clone)]
+pub struct DenyRead {
+    pub conn_handle: ConnectionHandle,
+    pub error_code: u8,
+}
+
+impl Command for DenyRead {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x012d);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        w.u8(self.error_code)?;
+        Ok(())
+    }
+}
+
+/// Starts ATT MTU exchange. The stack proposes the `att_mtu` given to `shci_ble_init`.
+#[derive(Debug, Copy, Clone)]
+pub struct ExchangeConfig {
+    pub conn_handle: ConnectionHandle,
+}
+
+impl Command for ExchangeConfig {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x010b);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        Ok(())
+    }
+}
+
+/// Discovers all primary services. Reported with `ReadByGroupTypeResp` events.
+#[derive(Debug, Copy, Clone)]
+pub struct DiscAllPrimaryServices {
+    pub conn_handle: ConnectionHandle,
+}
+
+impl Command for DiscAllPrimaryServices {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0112);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        Ok(())
+    }
+}
+
+/// Discovers primary services with the given UUID. Reported with `FindByTypeValueResp` events.
+#[derive(Debug, Copy, Clone)]
+pub struct DiscPrimaryServiceByUuid {
+    pub conn_handle: ConnectionHandle,
+    pub uuid: Uuid,
+}
+
+impl Command for DiscPrimaryServiceByUuid {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0113);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        self.uuid.write_aci(w)?;
+        Ok(())
+    }
+}
+
+/// Discovers all characteristics in the handle range. Reported with `ReadByTypeResp` events.
+#[derive(Debug, Copy, Clone)]
+pub struct DiscAllCharOfService {
+    pub conn_handle: ConnectionHandle,
+    pub start_handle: u16,
+    pub end_handle: u16,
+}
+
+impl Command for DiscAllCharOfService {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0115);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        w.u16(self.start_handle)?;
+        w.u16(self.end_handle)?;
+        Ok(())
+    }
+}
+
+/// Discovers characteristics with the given UUID. Reported with `DiscReadCharByUuidResp` events.
+#[derive(Debug, Copy, Clone)]
+pub struct DiscCharByUuid {
+    pub conn_handle: ConnectionHandle,
+    pub start_handle: u16,
+    pub end_handle: u16,
+    pub uuid: Uuid,
+}
+
+impl Command for DiscCharByUuid {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0116);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        w.u16(self.start_handle)?;
+        w.u16(self.end_handle)?;
+        self.uuid.write_aci(w)?;
+        Ok(())
+    }
+}
+
+/// Discovers descriptors of a characteristic. Reported with `FindInfoResp` events.
+#[derive(Debug, Copy, Clone)]
+pub struct DiscAllCharDesc {
+    pub conn_handle: ConnectionHandle,
+    /// Value handle of the characteristic.
+    pub char_handle: u16,
+    /// End handle of the characteristic.
+    pub end_handle: u16,
+}
+
+impl Command for DiscAllCharDesc {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0117);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        w.u16(self.char_handle)?;
+        w.u16(self.end_handle)?;
+        Ok(())
+    }
+}
+
+/// Reads an attribute value. Reported with a `ReadResp` event.
+#[derive(Debug, Copy, Clone)]
+pub struct ReadCharValue {
+    pub conn_handle: ConnectionHandle,
+    pub attr_handle: u16,
+}
+
+impl Command for ReadCharValue {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0118);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        w.u16(self.attr_handle)?;
+        Ok(())
+    }
+}
+
+/// Reads a long attribute value starting at `offset`. Reported with `ReadBlobResp` events.
+#[derive(Debug, Copy, Clone)]
+pub struct ReadLongCharValue {
+    pub conn_handle: ConnectionHandle,
+    pub attr_handle: u16,
+    pub offset: u16,
+}
+
+impl Command for ReadLongCharValue {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x011a);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        w.u16(self.attr_handle)?;
+        w.u16(self.offset)?;
+        Ok(())
+    }
+}
+
+/// Writes an attribute value with response.
+#[derive(Debug, Copy, Clone)]
+pub struct WriteCharValue<'a> {
+    pub conn_handle: ConnectionHandle,
+    pub attr_handle: u16,
+    pub value: &'a [u8],
+}
+
+impl<'a> Command for WriteCharValue<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x011c);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        write_attribute(w, self.conn_handle, self.attr_handle, self.value)
+    }
+}
+
+/// Writes a long attribute value with prepare/execute write requests.
+#[derive(Debug, Copy, Clone)]
+pub struct WriteLongCharValue<'a> {
+    pub conn_handle: ConnectionHandle,
+    pub attr_handle: u16,
+    pub offset: u16,
+    pub value: &'a [u8],
+}
+
+impl<'a> Command for WriteLongCharValue<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x011d);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.value.len() > u8::MAX as usize {
+            return Err(Error::TooLong);
+        }
+
+        w.u16(self.conn_handle.0)?;
+        w.u16(self.attr_handle)?;
+        w.u16(self.offset)?;
+        w.u8(self.value.len() as u8)?;
+        w.bytes(self.value)?;
+        Ok(())
+    }
+}
+
+/// Writes a characteristic descriptor, e.g. the client characteristic configuration.
+#[derive(Debug, Copy, Clone)]
+pub struct WriteCharDesc<'a> {
+    pub conn_handle: ConnectionHandle,
+    pub attr_handle: u16,
+    pub value: &'a [u8],
+}
+
+impl<'a> Command for WriteCharDesc<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0121);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        write_attribute(w, self.conn_handle, self.attr_handle, self.value)
+    }
+}
+
+/// Writes an attribute value without response. Completes with Command Complete only.
+#[derive(Debug, Copy, Clone)]
+pub struct WriteWithoutResp<'a> {
+    pub conn_handle: ConnectionHandle,
+    pub attr_handle: u16,
+    pub value: &'a [u8],
+}
+
+impl<'a> Command for WriteWithoutResp<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0123);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        write_attribute(w, self.conn_handle, self.attr_handle, self.value)
+    }
+}
+
+/// Confirms a received `Indication`.
+#[derive(Debug, Copy, Clone)]
+pub struct ConfirmIndication {
+    pub conn_handle: ConnectionHandle,
+}
+
+impl Command for ConfirmIndication {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0125);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.conn_handle.0)?;
+        Ok(())
+    }
+}
+
+fn write_attribute(
+    w: &mut Writer,
+    conn_handle: ConnectionHandle,
+    attr_handle: u16,
+    value: &[u8],
+) -> Result<(), Error> {
+    if value.len() > u8::MAX as usize {
+        return Err(Error::TooLong);
+    }
+
+    w.u16(conn_handle.0)?;
+    w.u16(attr_handle)?;
+    w.u8(value.len() as u8)?;
+    w.bytes(value)?;
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Event<'a> {
+    /// A client has written an attribute.
+    AttributeModified {
+        conn_handle: ConnectionHandle,
+        attr_handle: u16,
+        /// Offset of `data` in the attribute value.
+        offset: u16,
+        /// More fragments of this write follow.
+        more_data: bool,
+        data: &'a [u8],
+    },
+
+    /// A GATT procedure timed out, the connection has to be terminated.
+    ProcTimeout { conn_handle: ConnectionHandle },
+
+    /// A client wants to write an attribute, answer with `WriteResp`.
+    WritePermitRequest {
+        conn_handle: ConnectionHandle,
+        attr_handle: u16,
+        data: &'a [u8],
+    },
+
+    /// A client wants to read an attribute, answer with `AllowRead` or `DenyRead`.
+    ReadPermitRequest {
+        conn_handle: ConnectionHandle,
+        attr_handle: u16,
+        offset: u16,
+    },
+
+    /// Buffers are available again after `UpdateCharValue` failed with insufficient resources.
+    TxPoolAvailable {
+        conn_handle: ConnectionHandle,
+        available_buffers: u16,
+    },
+
+    /// A client confirmed an indication.
+    ServerConfirmation { conn_handle: ConnectionHandle },
+
+    /// ATT MTU has been negotiated.
+    ExchangeMtuResp {
+        conn_handle: ConnectionHandle,
+        server_rx_mtu: u16,
+    },
+
+    /// Handle/UUID pairs found by descriptor discovery.
+    FindInfoResp {
+        conn_handle: ConnectionHandle,
+        /// 0x01 for 16-bit UUIDs, 0x02 for 128-bit UUIDs.
+        format: u8,
+        data: &'a [u8],
+    },
+
+    /// Found attribute handle / group end handle pairs of service discovery by UUID.
+    FindByTypeValueResp {
+        conn_handle: ConnectionHandle,
+        data: &'a [u8],
+    },
+
+    /// Characteristic declarations found by characteristic discovery.
+    ReadByTypeResp {
+        conn_handle: ConnectionHandle,
+        /// Length of each handle/value pair in `data`.
+        pair_len: u8,
+        data: &'a [u8],
+    },
+
+    ReadResp {
+        conn_handle: ConnectionHandle,
+        value: &'a [u8],
+    },
+
+    /// Part of a long attribute value.
+    ReadBlobResp {
+        conn_handle: ConnectionHandle,
+        value: &'a [u8],
+    },
+
+    /// Services found by primary service discovery.
+    ReadByGroupTypeResp {
+        conn_handle: ConnectionHandle,
+        /// Length of each attribute data element in `data`.
+        attribute_data_len: u8,
+        data: &'a [u8],
+    },
+
+    /// Server sent an indication, confirm it with `ConfirmIndication`.
+    Indication {
+        conn_handle: ConnectionHandle,
+        attr_handle: u16,
+        value: &'a [u8],
+    },
+
+    Notification {
+        conn_handle: ConnectionHandle,
+        attr_handle: u16,
+        value: &'a [u8],
+    },
+
+    /// A GATT client procedure has completed. Zero `error_code` means success.
+    ProcComplete {
+        conn_handle: ConnectionHandle,
+        error_code: u8,
+    },
+
+    /// Server answered with an ATT error.
+    ErrorResp {
+        conn_handle: ConnectionHandle,
+        req_opcode: u8,
+        attr_handle: u16,
+        error_code: u8,
+    },
+
+    /// Characteristic declaration found by characteristic discovery by UUID.
+    DiscReadCharByUuidResp {
+        conn_handle: ConnectionHandle,
+        attr_handle: u16,
+        value: &'a [u8],
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Returns `None` if the event is malformed and `Some(None)` if the code is unknown.
+    pub(super) fn parse(code: u16, r: &mut Reader<'a>) -> Option<Option<Self>> {
+        Some(Some(match code {
+            EVT_ATTRIBUTE_MODIFIED => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let attr_handle = r.u16()?;
+                let offset = r.u16()?;
+                let len = r.u16()? as usize;
+
+                Event::AttributeModified {
+                    conn_handle,
+                    attr_handle,
+                    offset: offset & 0x7fff,
+                    more_data: offset & 0x8000 != 0,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            EVT_PROC_TIMEOUT => Event::ProcTimeout {
+                conn_handle: ConnectionHandle::from_raw(r.u16()?),
+            },
+
+            EVT_WRITE_PERMIT_REQ => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let attr_handle = r.u16()?;
+                let len = r.u8()? as usize;
+
+                Event::WritePermitRequest {
+                    conn_handle,
+                    attr_handle,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            EVT_READ_PERMIT_REQ => Event::ReadPermitRequest {
+                conn_handle: ConnectionHandle::from_raw(r.u16()?),
+                attr_handle: r.u16()?,
+                offset: r.u16()?,
+            },
+
+            EVT_TX_POOL_AVAILABLE => Event::TxPoolAvailable {
+                conn_handle: ConnectionHandle::from_raw(r.u16()?),
+                available_buffers: r.u16()?,
+            },
+
+            EVT_SERVER_CONFIRMATION => Event::ServerConfirmation {
+                conn_handle: ConnectionHandle::from_raw(r.u16()?),
+            },
+
+            EVT_EXCHANGE_MTU_RESP => Event::ExchangeMtuResp {
+                conn_handle: ConnectionHandle::from_raw(r.u16()?),
+                server_rx_mtu: r.u16()?,
+            },
+
+            EVT_FIND_INFO_RESP => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let format = r.u8()?;
+                let len = r.u8()? as usize;
+
+                Event::FindInfoResp {
+                    conn_handle,
+                    format,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            EVT_FIND_BY_TYPE_VALUE_RESP => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let num_pairs = r.u8()? as usize;
+
+                Event::FindByTypeValueResp {
+                    conn_handle,
+                    data: r.bytes(num_pairs * 4)?,
+                }
+            }
+
+            EVT_READ_BY_TYPE_RESP => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let pair_len = r.u8()?;
+                let len = r.u8()? as usize;
+
+                Event::ReadByTypeResp {
+                    conn_handle,
+                    pair_len,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            EVT_READ_RESP => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let len = r.u8()? as usize;
+
+                Event::ReadResp {
+                    conn_handle,
+                    value: r.bytes(len)?,
+                }
+            }
+
+            EVT_READ_BLOB_RESP => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let len = r.u8()? as usize;
+
+                Event::ReadBlobResp {
+                    conn_handle,
+                    value: r.bytes(len)?,
+                }
+            }
+
+            EVT_READ_BY_GROUP_TYPE_RESP => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let attribute_data_len = r.u8()?;
+                let len = r.u8()? as usize;
+
+                Event::ReadByGroupTypeResp {
+                    conn_handle,
+                    attribute_data_len,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            EVT_INDICATION | EVT_NOTIFICATION => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let attr_handle = r.u16()?;
+                let len = r.u8()? as usize;
+                let value = r.bytes(len)?;
+
+                if code == EVT_INDICATION {
+                    Event::Indication {
+                        conn_handle,
+                        attr_handle,
+                        value,
+                    }
+                } else {
+                    Event::Notification {
+                        conn_handle,
+                        attr_handle,
+                        value,
+                    }
+                }
+            }
+
+            EVT_PROC_COMPLETE => Event::ProcComplete {
+                conn_handle: ConnectionHandle::from_raw(r.u16()?),
+                error_code: r.u8()?,
+            },
+
+            EVT_ERROR_RESP => Event::ErrorResp {
+                conn_handle: ConnectionHandle::from_raw(r.u16()?),
+                req_opcode: r.u8()?,
+                attr_handle: r.u16()?,
+                error_code: r.u8()?,
+            },
+
+            EVT_DISC_READ_CHAR_BY_UUID_RESP => {
+                let conn_handle = ConnectionHandle::from_raw(r.u16()?);
+                let attr_handle = r.u16()?;
+                let len = r.u8()? as usize;
+
+                Event::DiscReadCharByUuidResp {
+                    conn_handle,
+                    attr_handle,
+                    value: r.bytes(len)?,
+                }
+            }
+
+            _ => return Some(None),
+        }))
+    }
+}
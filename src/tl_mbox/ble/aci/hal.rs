@@ -0,0 +1,172 @@
+//! ACI HAL commands and events.
+
+use crate::tl_mbox::ble::command::{opcode, Command, Error, Response, OGF_VENDOR};
+use crate::tl_mbox::ble::types::{AddressType, BdAddr};
+use crate::tl_mbox::bytes::{Reader, Writer};
+use core::convert::TryFrom;
+
+/// Public address, 6 bytes.
+pub const CONFIG_DATA_PUBADDR_OFFSET: u8 = 0x00;
+/// Encryption root key used to derive LTK and CSRK, 16 bytes.
+pub const CONFIG_DATA_ER_OFFSET: u8 = 0x08;
+/// Identity root key used to derive LTK and CSRK, 16 bytes.
+pub const CONFIG_DATA_IR_OFFSET: u8 = 0x18;
+/// Static random address, 6 bytes.
+pub const CONFIG_DATA_RANDOM_ADDRESS_OFFSET: u8 = 0x2e;
+
+pub const EVT_END_OF_RADIO_ACTIVITY: u16 = 0x0004;
+pub const EVT_SCAN_REQ_REPORT: u16 = 0x0005;
+pub const EVT_FW_ERROR: u16 = 0x0006;
+
+/// Writes a value into the configuration data area of the BLE stack.
+#[derive(Debug, Copy, Clone)]
+pub struct WriteConfigData<'a> {
+    pub offset: u8,
+    pub data: &'a [u8],
+}
+
+impl<'a> WriteConfigData<'a> {
+    pub fn public_address(address: &'a BdAddr) -> Self {
+        WriteConfigData {
+            offset: CONFIG_DATA_PUBADDR_OFFSET,
+            data: &address.0,
+        }
+    }
+
+    /// Static random address. Its two most significant bits must be set.
+    pub fn static_random_address(address: &'a BdAddr) -> Self {
+        WriteConfigData {
+            offset: CONFIG_DATA_RANDOM_ADDRESS_OFFSET,
+            data: &address.0,
+        }
+    }
+
+    pub fn identity_root_key(key: &'a [u8; 16]) -> Self {
+        WriteConfigData {
+            offset: CONFIG_DATA_IR_OFFSET,
+            data: key,
+        }
+    }
+
+    pub fn encryption_root_key(key: &'a [u8; 16]) -> Self {
+        WriteConfigData {
+            offset: CONFIG_DATA_ER_OFFSET,
+            data: key,
+        }
+    }
+}
+
+impl<'a> Command for WriteConfigData<'a> {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x000c);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.data.len() > u8::MAX as usize {
+            return Err(Error::TooLong);
+        }
+
+        w.u8(self.offset)?;
+        w.u8(self.data.len() as u8)?;
+        w.bytes(self.data)?;
+        Ok(())
+    }
+}
+
+/// Maximum PA level accepted by `SetTxPowerLevel`.
+pub const PA_LEVEL_MAX: u8 = 0x1f;
+
+/// Sets TX power. With `high_power` disabled, `pa_level` 0 is about -40 dBm and 31 is about +6 dBm.
+#[derive(Debug, Copy, Clone)]
+pub struct SetTxPowerLevel {
+    pub high_power: bool,
+    pub pa_level: u8,
+}
+
+impl Command for SetTxPowerLevel {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x000f);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.pa_level > PA_LEVEL_MAX {
+            return Err(Error::InvalidParameter);
+        }
+
+        w.u8(self.high_power as u8)?;
+        w.u8(self.pa_level)?;
+        Ok(())
+    }
+}
+
+/// Reads the BLE stack firmware build number.
+#[derive(Debug, Copy, Clone)]
+pub struct GetFwBuildNumber;
+
+#[derive(Debug, Copy, Clone)]
+pub struct FwBuildNumber(pub u16);
+
+impl Response for FwBuildNumber {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        Reader::new(params).u16().map(FwBuildNumber)
+    }
+}
+
+impl Command for GetFwBuildNumber {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0000);
+    type Response = FwBuildNumber;
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Event<'a> {
+    /// Radio activity is about to start or has ended.
+    EndOfRadioActivity {
+        last_state: u8,
+        next_state: u8,
+        /// Time of the next radio activity, in system time units (625/256 us).
+        next_state_systime: u32,
+    },
+
+    /// A scan request has been received.
+    ScanReqReport {
+        rssi: i8,
+        peer_address_type: AddressType,
+        peer_address: BdAddr,
+    },
+
+    /// Firmware error reported by the stack.
+    FwError { error_type: u8, data: &'a [u8] },
+}
+
+impl<'a> Event<'a> {
+    /// Returns `None` if the event is malformed and `Some(None)` if the code is unknown.
+    pub(super) fn parse(code: u16, r: &mut Reader<'a>) -> Option<Option<Self>> {
+        Some(Some(match code {
+            EVT_END_OF_RADIO_ACTIVITY => Event::EndOfRadioActivity {
+                last_state: r.u8()?,
+                next_state: r.u8()?,
+                next_state_systime: r.u32()?,
+            },
+
+            EVT_SCAN_REQ_REPORT => Event::ScanReqReport {
+                rssi: r.i8()?,
+                peer_address_type: AddressType::try_from(r.u8()?).ok()?,
+                peer_address: BdAddr(r.array6()?),
+            },
+
+            EVT_FW_ERROR => {
+                let error_type = r.u8()?;
+                let len = r.u8()? as usize;
+
+                Event::FwError {
+                    error_type,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            _ => return Some(None),
+        }))
+    }
+}
@@ -0,0 +1,181 @@
+//! ACI L2CAP commands and events: connection parameter update procedure.
+//!
+//! A slave requests new connection parameters with `ConnectionParameterUpdateReq`. The master
+//! receives `Event::ConnectionUpdateReq` and answers with `ConnectionParameterUpdateResp`, after
+//! which the controller updates the connection and reports it with
+//! `event::LeMetaEvent::ConnectionUpdateComplete`.
+//!
+//! On controllers that support the LL connection parameters request procedure, the master can
+//! use `hci::LeConnectionUpdate` directly instead.
+
+use crate::tl_mbox::ble::command::{opcode, Command, Error, OGF_VENDOR};
+use crate::tl_mbox::ble::hci::ConnectionParameters;
+use crate::tl_mbox::ble::types::ConnectionHandle;
+use crate::tl_mbox::bytes::{Reader, Writer};
+
+pub const EVT_CONNECTION_UPDATE_RESP: u16 = 0x0800;
+pub const EVT_PROC_TIMEOUT: u16 = 0x0801;
+pub const EVT_CONNECTION_UPDATE_REQ: u16 = 0x0802;
+pub const EVT_COMMAND_REJECT: u16 = 0x080a;
+
+/// Result of `Event::ConnectionUpdateResp`: the master has accepted the parameters.
+pub const CONNECTION_PARAMETERS_ACCEPTED: u16 = 0x0000;
+
+/// Result of `Event::ConnectionUpdateResp`: the master has rejected the parameters.
+pub const CONNECTION_PARAMETERS_REJECTED: u16 = 0x0001;
+
+/// Sent by a slave to request new connection parameters. Responds with Command Status, followed
+/// by `Event::ConnectionUpdateResp`.
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectionParameterUpdateReq {
+    pub handle: ConnectionHandle,
+    /// Minimum connection interval, in units of 1.25 ms.
+    pub interval_min: u16,
+    /// Maximum connection interval, in units of 1.25 ms.
+    pub interval_max: u16,
+    pub latency: u16,
+    /// Supervision timeout, in units of 10 ms.
+    pub supervision_timeout: u16,
+}
+
+impl Command for ConnectionParameterUpdateReq {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0181);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        ConnectionParameters {
+            interval_min: self.interval_min,
+            interval_max: self.interval_max,
+            latency: self.latency,
+            supervision_timeout: self.supervision_timeout,
+            min_ce_length: 0,
+            max_ce_length: 0,
+        }
+        .validate()?;
+
+        w.u16(self.handle.0)?;
+        w.u16(self.interval_min)?;
+        w.u16(self.interval_max)?;
+        w.u16(self.latency)?;
+        w.u16(self.supervision_timeout)?;
+        Ok(())
+    }
+}
+
+/// Sent by the master to answer `Event::ConnectionUpdateReq`.
+///
+/// If the request is accepted, the connection is updated with `conn_params`, which are usually
+/// the ones from the request.
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectionParameterUpdateResp {
+    pub handle: ConnectionHandle,
+    pub conn_params: ConnectionParameters,
+    /// Identifier of the request.
+    pub identifier: u8,
+    pub accept: bool,
+}
+
+impl Command for ConnectionParameterUpdateResp {
+    const OPCODE: u16 = opcode(OGF_VENDOR, 0x0182);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        self.conn_params.write(w)?;
+        w.u8(self.identifier)?;
+        w.u8(self.accept as u8)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Event<'a> {
+    /// Master has answered `ConnectionParameterUpdateReq`.
+    ConnectionUpdateResp {
+        handle: ConnectionHandle,
+        /// `CONNECTION_PARAMETERS_ACCEPTED` or `CONNECTION_PARAMETERS_REJECTED`.
+        result: u16,
+    },
+
+    /// Peer didn't answer an L2CAP request in time.
+    ProcTimeout {
+        handle: ConnectionHandle,
+        data: &'a [u8],
+    },
+
+    /// Slave requests new connection parameters, to be answered with
+    /// `ConnectionParameterUpdateResp`.
+    ConnectionUpdateReq {
+        handle: ConnectionHandle,
+        identifier: u8,
+        /// Minimum connection interval, in units of 1.25 ms.
+        interval_min: u16,
+        /// Maximum connection interval, in units of 1.25 ms.
+        interval_max: u16,
+        latency: u16,
+        /// Supervision timeout, in units of 10 ms.
+        supervision_timeout: u16,
+    },
+
+    /// Peer has rejected an L2CAP request.
+    CommandReject {
+        handle: ConnectionHandle,
+        identifier: u8,
+        reason: u16,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Returns `None` if the event is malformed and `Some(None)` if the code is unknown.
+    pub(super) fn parse(code: u16, r: &mut Reader<'a>) -> Option<Option<Self>> {
+        Some(Some(match code {
+            EVT_CONNECTION_UPDATE_RESP => Event::ConnectionUpdateResp {
+                handle: ConnectionHandle::from_raw(r.u16()?),
+                result: r.u16()?,
+            },
+
+            EVT_PROC_TIMEOUT => {
+                let handle = ConnectionHandle::from_raw(r.u16()?);
+                let len = r.u8()? as usize;
+
+                Event::ProcTimeout {
+                    handle,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            EVT_CONNECTION_UPDATE_REQ => {
+                let handle = ConnectionHandle::from_raw(r.u16()?);
+                let identifier = r.u8()?;
+                // L2CAP signaling command length, always 8
+                let _l2cap_len = r.u16()?;
+
+                Event::ConnectionUpdateReq {
+                    handle,
+                    identifier,
+                    interval_min: r.u16()?,
+                    interval_max: r.u16()?,
+                    latency: r.u16()?,
+                    supervision_timeout: r.u16()?,
+                }
+            }
+
+            EVT_COMMAND_REJECT => {
+                let handle = ConnectionHandle::from_raw(r.u16()?);
+                let identifier = r.u8()?;
+                let reason = r.u16()?;
+                let len = r.u8()? as usize;
+
+                Event::CommandReject {
+                    handle,
+                    identifier,
+                    reason,
+                    data: r.bytes(len)?,
+                }
+            }
+
+            _ => return Some(None),
+        }))
+    }
+}
@@ -0,0 +1,212 @@
+//! HCI ACL data packets.
+//!
+//! Outgoing packets are sent with `TlMbox::send_acl_data`. There is a single ACL data buffer
+//! shared with CPU2, so the next packet can only be sent once CPU2 acknowledged the previous
+//! one. Incoming packets are delivered through the event queue as `EvtBox`es of
+//! `TlPacketType::AclData` kind and can be viewed with `AclData::try_from`.
+
+use core::convert::TryFrom;
+
+use crate::tl_mbox::ble::types::ConnectionHandle;
+use crate::tl_mbox::bytes::Reader;
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::EvtBox;
+
+pub use crate::tl_mbox::cmd::MAX_ACL_DATA_LEN;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Data is longer than `MAX_ACL_DATA_LEN`.
+    TooLong,
+
+    /// Packet is shorter than its header says.
+    Malformed,
+
+    /// The packet is not ACL data. Contains raw packet type.
+    NotAclData(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PacketBoundary {
+    /// First fragment of a higher layer (L2CAP) packet, sent from host to controller.
+    FirstNonFlushable = 0x00,
+    /// Continuation fragment of a higher layer packet.
+    Continuing = 0x01,
+    /// First fragment of a higher layer packet, sent from controller to host.
+    FirstFlushable = 0x02,
+}
+
+impl TryFrom<u8> for PacketBoundary {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(PacketBoundary::FirstNonFlushable),
+            0x01 => Ok(PacketBoundary::Continuing),
+            0x02 => Ok(PacketBoundary::FirstFlushable),
+
+            _ => Err(()),
+        }
+    }
+}
+
+/// ACL data packet borrowed from its buffer.
+#[derive(Debug, Copy, Clone)]
+pub struct AclData<'a> {
+    pub handle: ConnectionHandle,
+    pub packet_boundary: PacketBoundary,
+    /// Broadcast flag, always 0 (point-to-point) on LE.
+    pub broadcast: u8,
+    pub data: &'a [u8],
+}
+
+impl<'a> AclData<'a> {
+    /// Creates a point-to-point packet.
+    pub fn new(
+        handle: ConnectionHandle,
+        packet_boundary: PacketBoundary,
+        data: &'a [u8],
+    ) -> Result<Self, Error> {
+        if data.len() > MAX_ACL_DATA_LEN {
+            return Err(Error::TooLong);
+        }
+
+        Ok(AclData {
+            handle,
+            packet_boundary,
+            broadcast: 0,
+            data,
+        })
+    }
+
+    /// Parses ACL data packet (handle with flags, data length and data) without packet type.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(bytes);
+
+        let raw_handle = r.u16().ok_or(Error::Malformed)?;
+        let len = r.u16().ok_or(Error::Malformed)? as usize;
+        let data = r.bytes(len).ok_or(Error::Malformed)?;
+
+        let packet_boundary = PacketBoundary::try_from(((raw_handle >> 12) & 0x03) as u8)
+            .map_err(|_| Error::Malformed)?;
+
+        Ok(AclData {
+            handle: ConnectionHandle::from_raw(raw_handle),
+            packet_boundary,
+            broadcast: ((raw_handle >> 14) & 0x03) as u8,
+            data,
+        })
+    }
+
+    /// Returns handle field with packet boundary and broadcast flags as transferred over HCI.
+    pub(crate) fn raw_handle(&self) -> u16 {
+        (self.handle.0 & 0x0fff)
+            | ((self.packet_boundary as u16) << 12)
+            | ((self.broadcast as u16 & 0x03) << 14)
+    }
+}
+
+impl<'a> TryFrom<&'a EvtBox> for AclData<'a> {
+    type Error = Error;
+
+    fn try_from(evt: &'a EvtBox) -> Result<Self, Self::Error> {
+        let kind = evt.raw_kind();
+        match TlPacketType::try_from(kind) {
+            Ok(TlPacketType::AclData) => AclData::from_bytes(evt.acl_bytes()),
+
+            _ => Err(Error::NotAclData(kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let acl = AclData::new(
+            ConnectionHandle(0x0801),
+            PacketBoundary::Continuing,
+            &[1, 2, 3],
+        )
+        .unwrap();
+        assert_eq!(acl.raw_handle(), 0x1801);
+
+        let raw = acl.raw_handle().to_le_bytes();
+        let bytes = [raw[0], raw[1], 3, 0, 1, 2, 3];
+        let parsed = AclData::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.handle, acl.handle);
+        assert_eq!(parsed.packet_boundary, acl.packet_boundary);
+        assert_eq!(parsed.broadcast, 0);
+        assert_eq!(parsed.data, acl.data);
+    }
+
+    #[test]
+    fn handle_and_flags() {
+        // Handle bits above 12 don't leak into the flags
+        let acl = AclData::new(
+            ConnectionHandle(0xffff),
+            PacketBoundary::FirstFlushable,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(acl.raw_handle(), 0x2fff);
+
+        let acl = AclData {
+            broadcast: 0x03,
+            ..acl
+        };
+        assert_eq!(acl.raw_handle(), 0xefff);
+
+        let parsed = AclData::from_bytes(&[0xff, 0xef, 0, 0]).unwrap();
+        assert_eq!(parsed.handle, ConnectionHandle(0x0fff));
+        assert_eq!(parsed.packet_boundary, PacketBoundary::FirstFlushable);
+        assert_eq!(parsed.broadcast, 0x03);
+        assert!(parsed.data.is_empty());
+
+        // Packet boundary 0b11 is reserved
+        assert_eq!(
+            AclData::from_bytes(&[0x01, 0x30, 0, 0]).err(),
+            Some(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn short_buffer() {
+        assert_eq!(AclData::from_bytes(&[]).err(), Some(Error::Malformed));
+        assert_eq!(
+            AclData::from_bytes(&[0x01, 0x00, 0x02]).err(),
+            Some(Error::Malformed)
+        );
+        assert_eq!(
+            AclData::from_bytes(&[0x01, 0x00, 0x02, 0x00, 0xaa]).err(),
+            Some(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn over_length() {
+        // Data beyond the length in the header is not part of the packet
+        let parsed = AclData::from_bytes(&[0x01, 0x00, 0x01, 0x00, 0xaa, 0xbb]).unwrap();
+        assert_eq!(parsed.data, &[0xaa]);
+
+        let data = [0u8; MAX_ACL_DATA_LEN + 1];
+        assert!(AclData::new(
+            ConnectionHandle(1),
+            PacketBoundary::FirstNonFlushable,
+            &data[..MAX_ACL_DATA_LEN]
+        )
+        .is_ok());
+        assert_eq!(
+            AclData::new(
+                ConnectionHandle(1),
+                PacketBoundary::FirstNonFlushable,
+                &data
+            )
+            .err(),
+            Some(Error::TooLong)
+        );
+    }
+}
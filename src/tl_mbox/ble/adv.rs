@@ -0,0 +1,643 @@
+//! Advertising and scan response data: building and parsing of AD structures.
+//!
+//! `AdvData` packs AD structures into the 31 bytes available for advertising or scan response
+//! data, to be sent with `hci::LeSetAdvertisingData`, `hci::LeSetScanResponseData` or
+//! `aci::gap::UpdateAdvData`:
+//!
+//! ```ignore
+//! let mut adv = AdvData::new();
+//! adv.flags(FLAG_LE_GENERAL_DISCOVERABLE | FLAG_BR_EDR_NOT_SUPPORTED)?
+//!     .uuids16(true, &[0x180d])?
+//!     .local_name("Heart rate sensor")?;
+//! nb::block!(mbox.poll_ble_cmd(&mbox.send_ble_cmd(&mut ipcc, &LeSetAdvertisingData { data: adv.as_bytes() })?))?;
+//! ```
+//!
+//! Received data is decoded with `parse()` or `event::AdvertisingReport::ad_structures()`:
+//!
+//! ```ignore
+//! for ad in report.ad_structures() {
+//!     if let AdStructure::LocalName { name, .. } = ad {
+//!         // ...
+//!     }
+//! }
+//! ```
+//!
+//! See `beacon` for iBeacon and Eddystone payloads.
+
+use super::hci::MAX_ADV_DATA_LEN;
+use crate::tl_mbox::bytes::{BufferFull, Reader, Writer};
+
+pub mod beacon;
+
+pub const AD_TYPE_FLAGS: u8 = 0x01;
+pub const AD_TYPE_INCOMPLETE_UUIDS16: u8 = 0x02;
+pub const AD_TYPE_COMPLETE_UUIDS16: u8 = 0x03;
+pub const AD_TYPE_INCOMPLETE_UUIDS32: u8 = 0x04;
+pub const AD_TYPE_COMPLETE_UUIDS32: u8 = 0x05;
+pub const AD_TYPE_INCOMPLETE_UUIDS128: u8 = 0x06;
+pub const AD_TYPE_COMPLETE_UUIDS128: u8 = 0x07;
+pub const AD_TYPE_SHORTENED_LOCAL_NAME: u8 = 0x08;
+pub const AD_TYPE_COMPLETE_LOCAL_NAME: u8 = 0x09;
+pub const AD_TYPE_TX_POWER_LEVEL: u8 = 0x0a;
+pub const AD_TYPE_SLAVE_CONN_INTERVAL_RANGE: u8 = 0x12;
+pub const AD_TYPE_SERVICE_DATA16: u8 = 0x16;
+pub const AD_TYPE_APPEARANCE: u8 = 0x19;
+pub const AD_TYPE_SERVICE_DATA32: u8 = 0x20;
+pub const AD_TYPE_SERVICE_DATA128: u8 = 0x21;
+pub const AD_TYPE_MANUFACTURER_DATA: u8 = 0xff;
+
+pub const FLAG_LE_LIMITED_DISCOVERABLE: u8 = 0x01;
+pub const FLAG_LE_GENERAL_DISCOVERABLE: u8 = 0x02;
+pub const FLAG_BR_EDR_NOT_SUPPORTED: u8 = 0x04;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// AD structure doesn't fit into the remaining space.
+    TooLong,
+
+    /// A parameter is out of its allowed range.
+    InvalidParameter,
+}
+
+impl From<BufferFull> for Error {
+    fn from(_: BufferFull) -> Self {
+        Error::TooLong
+    }
+}
+
+/// Up to 31 bytes of AD structures.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct AdvData {
+    buf: [u8; MAX_ADV_DATA_LEN],
+    len: usize,
+}
+
+impl AdvData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of bytes left for AD data, not counting the length and type of the next structure.
+    pub fn remaining(&self) -> usize {
+        (MAX_ADV_DATA_LEN - self.len).saturating_sub(2)
+    }
+
+    /// Appends AD structure of any type.
+    pub fn raw(&mut self, ad_type: u8, data: &[u8]) -> Result<&mut Self, Error> {
+        self.push(ad_type, data.len(), |w| w.bytes(data))
+    }
+
+    /// Appends a combination of `FLAG_*` values.
+    pub fn flags(&mut self, flags: u8) -> Result<&mut Self, Error> {
+        self.push(AD_TYPE_FLAGS, 1, |w| w.u8(flags))
+    }
+
+    pub fn complete_local_name(&mut self, name: &str) -> Result<&mut Self, Error> {
+        self.raw(AD_TYPE_COMPLETE_LOCAL_NAME, name.as_bytes())
+    }
+
+    pub fn shortened_local_name(&mut self, name: &str) -> Result<&mut Self, Error> {
+        self.raw(AD_TYPE_SHORTENED_LOCAL_NAME, name.as_bytes())
+    }
+
+    /// Appends the complete local name if it fits, or as much of it as fits as the shortened
+    /// local name. Fails if there's no room for a single character.
+    pub fn local_name(&mut self, name: &str) -> Result<&mut Self, Error> {
+        let room = self.remaining();
+        if name.len() <= room {
+            return self.complete_local_name(name);
+        }
+
+        let end = (1..=room)
+            .rev()
+            .find(|&end| name.is_char_boundary(end))
+            .ok_or(Error::TooLong)?;
+
+        self.shortened_local_name(&name[..end])
+    }
+
+    /// Appends a list of 16-bit service UUIDs, `complete` if it contains all of them.
+    pub fn uuids16(&mut self, complete: bool, uuids: &[u16]) -> Result<&mut Self, Error> {
+        let ad_type = if complete {
+            AD_TYPE_COMPLETE_UUIDS16
+        } else {
+            AD_TYPE_INCOMPLETE_UUIDS16
+        };
+
+        self.push(ad_type, uuids.len() * 2, |w| {
+            uuids.iter().try_for_each(|uuid| w.u16(*uuid))
+        })
+    }
+
+    /// Appends a list of 32-bit service UUIDs, `complete` if it contains all of them.
+    pub fn uuids32(&mut self, complete: bool, uuids: &[u32]) -> Result<&mut Self, Error> {
+        let ad_type = if complete {
+            AD_TYPE_COMPLETE_UUIDS32
+        } else {
+            AD_TYPE_INCOMPLETE_UUIDS32
+        };
+
+        self.push(ad_type, uuids.len() * 4, |w| {
+            uuids.iter().try_for_each(|uuid| w.u32(*uuid))
+        })
+    }
+
+    /// Appends a list of 128-bit service UUIDs (least significant byte first), `complete` if it
+    /// contains all of them.
+    pub fn uuids128(&mut self, complete: bool, uuids: &[[u8; 16]]) -> Result<&mut Self, Error> {
+        let ad_type = if complete {
+            AD_TYPE_COMPLETE_UUIDS128
+        } else {
+            AD_TYPE_INCOMPLETE_UUIDS128
+        };
+
+        self.push(ad_type, uuids.len() * 16, |w| {
+            uuids.iter().try_for_each(|uuid| w.bytes(uuid))
+        })
+    }
+
+    pub fn service_data16(&mut self, uuid: u16, data: &[u8]) -> Result<&mut Self, Error> {
+        self.push(AD_TYPE_SERVICE_DATA16, 2 + data.len(), |w| {
+            w.u16(uuid)?;
+            w.bytes(data)
+        })
+    }
+
+    pub fn service_data32(&mut self, uuid: u32, data: &[u8]) -> Result<&mut Self, Error> {
+        self.push(AD_TYPE_SERVICE_DATA32, 4 + data.len(), |w| {
+            w.u32(uuid)?;
+            w.bytes(data)
+        })
+    }
+
+    /// Appends service data of a 128-bit UUID (least significant byte first).
+    pub fn service_data128(&mut self, uuid: &[u8; 16], data: &[u8]) -> Result<&mut Self, Error> {
+        self.push(AD_TYPE_SERVICE_DATA128, 16 + data.len(), |w| {
+            w.bytes(uuid)?;
+            w.bytes(data)
+        })
+    }
+
+    /// Appends manufacturer specific data of the company with Bluetooth SIG assigned
+    /// `company_id`.
+    pub fn manufacturer_data(&mut self, company_id: u16, data: &[u8]) -> Result<&mut Self, Error> {
+        self.push(AD_TYPE_MANUFACTURER_DATA, 2 + data.len(), |w| {
+            w.u16(company_id)?;
+            w.bytes(data)
+        })
+    }
+
+    /// Appends TX power level in dBm.
+    pub fn tx_power(&mut self, dbm: i8) -> Result<&mut Self, Error> {
+        self.push(AD_TYPE_TX_POWER_LEVEL, 1, |w| w.u8(dbm as u8))
+    }
+
+    /// Appends GAP appearance value.
+    pub fn appearance(&mut self, appearance: u16) -> Result<&mut Self, Error> {
+        self.push(AD_TYPE_APPEARANCE, 2, |w| w.u16(appearance))
+    }
+
+    /// Appends preferred connection interval range, in units of 1.25 ms. `0xffff` leaves either
+    /// bound unspecified.
+    pub fn slave_conn_interval_range(&mut self, min: u16, max: u16) -> Result<&mut Self, Error> {
+        let valid = |interval| (0x0006..=0x0c80).contains(&interval) || interval == 0xffff;
+        if !valid(min) || !valid(max) || (min != 0xffff && max != 0xffff && min > max) {
+            return Err(Error::InvalidParameter);
+        }
+
+        self.push(AD_TYPE_SLAVE_CONN_INTERVAL_RANGE, 4, |w| {
+            w.u16(min)?;
+            w.u16(max)
+        })
+    }
+
+    /// Appends AD structure with `len` bytes of data written by `f`.
+    fn push(
+        &mut self,
+        ad_type: u8,
+        len: usize,
+        f: impl FnOnce(&mut Writer) -> Result<(), BufferFull>,
+    ) -> Result<&mut Self, Error> {
+        let end = self.len + 2 + len;
+        if end > MAX_ADV_DATA_LEN {
+            return Err(Error::TooLong);
+        }
+
+        let mut w = Writer::new(&mut self.buf[self.len..end]);
+        w.u8(len as u8 + 1)?;
+        w.u8(ad_type)?;
+        f(&mut w)?;
+
+        self.len = end;
+        Ok(self)
+    }
+}
+
+/// Decoded AD structure.
+///
+/// Structures of unknown types and structures whose length doesn't match their type are
+/// returned as `Other`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdStructure<'a> {
+    Flags(u8),
+
+    Uuids16 {
+        complete: bool,
+        uuids: Uuids16<'a>,
+    },
+
+    Uuids32 {
+        complete: bool,
+        uuids: Uuids32<'a>,
+    },
+
+    Uuids128 {
+        complete: bool,
+        uuids: Uuids128<'a>,
+    },
+
+    /// Local name, usually but not necessarily UTF-8.
+    LocalName {
+        complete: bool,
+        name: &'a [u8],
+    },
+
+    /// TX power level in dBm.
+    TxPower(i8),
+
+    /// Preferred connection interval range, in units of 1.25 ms.
+    SlaveConnIntervalRange {
+        min: u16,
+        max: u16,
+    },
+
+    ServiceData16 {
+        uuid: u16,
+        data: &'a [u8],
+    },
+
+    ServiceData32 {
+        uuid: u32,
+        data: &'a [u8],
+    },
+
+    ServiceData128 {
+        uuid: [u8; 16],
+        data: &'a [u8],
+    },
+
+    Appearance(u16),
+
+    ManufacturerData {
+        company_id: u16,
+        data: &'a [u8],
+    },
+
+    Other {
+        ad_type: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> AdStructure<'a> {
+    /// Decodes AD data of the given type.
+    pub fn decode(ad_type: u8, data: &'a [u8]) -> Self {
+        Self::try_decode(ad_type, data).unwrap_or(AdStructure::Other { ad_type, data })
+    }
+
+    fn try_decode(ad_type: u8, data: &'a [u8]) -> Option<Self> {
+        let mut r = Reader::new(data);
+
+        let ad = match ad_type {
+            AD_TYPE_FLAGS => AdStructure::Flags(r.u8()?),
+
+            AD_TYPE_INCOMPLETE_UUIDS16 | AD_TYPE_COMPLETE_UUIDS16 => AdStructure::Uuids16 {
+                complete: ad_type == AD_TYPE_COMPLETE_UUIDS16,
+                uuids: Uuids16(uuid_list(&mut r, 2)?),
+            },
+
+            AD_TYPE_INCOMPLETE_UUIDS32 | AD_TYPE_COMPLETE_UUIDS32 => AdStructure::Uuids32 {
+                complete: ad_type == AD_TYPE_COMPLETE_UUIDS32,
+                uuids: Uuids32(uuid_list(&mut r, 4)?),
+            },
+
+            AD_TYPE_INCOMPLETE_UUIDS128 | AD_TYPE_COMPLETE_UUIDS128 => AdStructure::Uuids128 {
+                complete: ad_type == AD_TYPE_COMPLETE_UUIDS128,
+                uuids: Uuids128(uuid_list(&mut r, 16)?),
+            },
+
+            AD_TYPE_SHORTENED_LOCAL_NAME | AD_TYPE_COMPLETE_LOCAL_NAME => AdStructure::LocalName {
+                complete: ad_type == AD_TYPE_COMPLETE_LOCAL_NAME,
+                name: r.rest(),
+            },
+
+            AD_TYPE_TX_POWER_LEVEL => AdStructure::TxPower(r.i8()?),
+
+            AD_TYPE_SLAVE_CONN_INTERVAL_RANGE => AdStructure::SlaveConnIntervalRange {
+                min: r.u16()?,
+                max: r.u16()?,
+            },
+
+            AD_TYPE_SERVICE_DATA16 => AdStructure::ServiceData16 {
+                uuid: r.u16()?,
+                data: r.rest(),
+            },
+
+            AD_TYPE_SERVICE_DATA32 => AdStructure::ServiceData32 {
+                uuid: r.u32()?,
+                data: r.rest(),
+            },
+
+            AD_TYPE_SERVICE_DATA128 => {
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(r.bytes(16)?);
+
+                AdStructure::ServiceData128 {
+                    uuid,
+                    data: r.rest(),
+                }
+            }
+
+            AD_TYPE_APPEARANCE => AdStructure::Appearance(r.u16()?),
+
+            AD_TYPE_MANUFACTURER_DATA => AdStructure::ManufacturerData {
+                company_id: r.u16()?,
+                data: r.rest(),
+            },
+
+            _ => return None,
+        };
+
+        // Fixed-size structures must not have trailing bytes
+        if r.rest().is_empty() {
+            Some(ad)
+        } else {
+            None
+        }
+    }
+}
+
+/// Takes the rest of `r` if it's a whole number of `size` bytes long UUIDs.
+fn uuid_list<'a>(r: &mut Reader<'a>, size: usize) -> Option<&'a [u8]> {
+    let list = r.rest();
+    if list.chunks_exact(size).remainder().is_empty() {
+        Some(list)
+    } else {
+        None
+    }
+}
+
+/// Iterator over a list of 16-bit UUIDs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Uuids16<'a>(&'a [u8]);
+
+impl<'a> Iterator for Uuids16<'a> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut r = Reader::new(self.0);
+        let uuid = r.u16()?;
+        self.0 = r.rest();
+
+        Some(uuid)
+    }
+}
+
+/// Iterator over a list of 32-bit UUIDs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Uuids32<'a>(&'a [u8]);
+
+impl<'a> Iterator for Uuids32<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut r = Reader::new(self.0);
+        let uuid = r.u32()?;
+        self.0 = r.rest();
+
+        Some(uuid)
+    }
+}
+
+/// Iterator over a list of 128-bit UUIDs, least significant byte first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Uuids128<'a>(&'a [u8]);
+
+impl<'a> Iterator for Uuids128<'a> {
+    type Item = [u8; 16];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut r = Reader::new(self.0);
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(r.bytes(16)?);
+        self.0 = r.rest();
+
+        Some(uuid)
+    }
+}
+
+/// Iterator over the AD structures of advertising or scan response data.
+///
+/// Stops at the first zero-length structure (the rest is padding) or at a truncated structure.
+#[derive(Debug, Copy, Clone)]
+pub struct AdStructures<'a> {
+    r: Reader<'a>,
+}
+
+impl<'a> Iterator for AdStructures<'a> {
+    type Item = AdStructure<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = self.r.u8()? as usize;
+        let structure = match self.r.bytes(len) {
+            Some(structure) if len > 0 => structure,
+
+            _ => {
+                self.r.rest();
+                return None;
+            }
+        };
+
+        Some(AdStructure::decode(structure[0], &structure[1..]))
+    }
+}
+
+/// Decodes AD structures of advertising or scan response data.
+pub fn parse<'a>(data: &'a [u8]) -> AdStructures<'a> {
+    AdStructures {
+        r: Reader::new(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_len() {
+        let mut adv = AdvData::new();
+        adv.flags(FLAG_LE_GENERAL_DISCOVERABLE).unwrap();
+        assert_eq!(adv.remaining(), 26);
+
+        adv.raw(AD_TYPE_MANUFACTURER_DATA, &[0x5a; 26]).unwrap();
+        assert_eq!(adv.len(), MAX_ADV_DATA_LEN);
+        assert_eq!(adv.remaining(), 0);
+
+        assert_eq!(adv.tx_power(0).err(), Some(Error::TooLong));
+        assert_eq!(adv.len(), MAX_ADV_DATA_LEN);
+
+        let mut adv = AdvData::new();
+        assert_eq!(
+            adv.raw(AD_TYPE_MANUFACTURER_DATA, &[0x5a; 30]).err(),
+            Some(Error::TooLong)
+        );
+        assert!(adv.is_empty());
+    }
+
+    #[test]
+    fn local_name() {
+        let mut adv = AdvData::new();
+        adv.local_name("Sensor").unwrap();
+        assert_eq!(adv.as_bytes(), b"\x07\x09Sensor");
+
+        // 26 bytes left after the flags
+        let mut adv = AdvData::new();
+        adv.flags(FLAG_LE_GENERAL_DISCOVERABLE)
+            .unwrap()
+            .local_name("A very long name of the heart rate sensor")
+            .unwrap();
+        assert_eq!(&adv.as_bytes()[3..5], &[27, AD_TYPE_SHORTENED_LOCAL_NAME]);
+        assert_eq!(&adv.as_bytes()[5..], b"A very long name of the he");
+
+        // Multi-byte characters are not split
+        let mut adv = AdvData::new();
+        adv.raw(AD_TYPE_MANUFACTURER_DATA, &[0; 25]).unwrap();
+        adv.local_name("\u{e9}\u{e9}").unwrap();
+        assert_eq!(
+            parse(adv.as_bytes()).nth(1),
+            Some(AdStructure::LocalName {
+                complete: false,
+                name: "\u{e9}".as_bytes(),
+            })
+        );
+
+        // No room for a single character
+        let mut adv = AdvData::new();
+        adv.raw(AD_TYPE_MANUFACTURER_DATA, &[0; 27]).unwrap();
+        assert_eq!(adv.local_name("\u{e9}").err(), Some(Error::TooLong));
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let mut adv = AdvData::new();
+        adv.flags(FLAG_LE_GENERAL_DISCOVERABLE | FLAG_BR_EDR_NOT_SUPPORTED)
+            .unwrap()
+            .uuids16(true, &[0x180d, 0x180f])
+            .unwrap()
+            .tx_power(-4)
+            .unwrap()
+            .slave_conn_interval_range(0x0006, 0xffff)
+            .unwrap()
+            .manufacturer_data(0x0030, &[1, 2])
+            .unwrap();
+
+        let mut ads = parse(adv.as_bytes());
+        assert_eq!(ads.next(), Some(AdStructure::Flags(0x06)));
+        match ads.next() {
+            Some(AdStructure::Uuids16 { complete, uuids }) => {
+                assert!(complete);
+                assert!(uuids.eq([0x180d, 0x180f].iter().copied()));
+            }
+            ad => panic!("unexpected {:?}", ad),
+        }
+        assert_eq!(ads.next(), Some(AdStructure::TxPower(-4)));
+        assert_eq!(
+            ads.next(),
+            Some(AdStructure::SlaveConnIntervalRange {
+                min: 0x0006,
+                max: 0xffff,
+            })
+        );
+        assert_eq!(
+            ads.next(),
+            Some(AdStructure::ManufacturerData {
+                company_id: 0x0030,
+                data: &[1, 2],
+            })
+        );
+        assert_eq!(ads.next(), None);
+
+        assert_eq!(
+            AdvData::new()
+                .slave_conn_interval_range(0x0010, 0x0008)
+                .err(),
+            Some(Error::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn parse_malformed() {
+        // Zero-length structure ends the data, the rest is padding
+        let mut ads = parse(&[2, AD_TYPE_FLAGS, 0x06, 0, 2, AD_TYPE_TX_POWER_LEVEL, 0]);
+        assert_eq!(ads.next(), Some(AdStructure::Flags(0x06)));
+        assert_eq!(ads.next(), None);
+        assert_eq!(ads.next(), None);
+
+        // Truncated structure
+        let mut ads = parse(&[2, AD_TYPE_FLAGS, 0x06, 5, AD_TYPE_COMPLETE_LOCAL_NAME, b'a']);
+        assert_eq!(ads.next(), Some(AdStructure::Flags(0x06)));
+        assert_eq!(ads.next(), None);
+
+        // Length doesn't match the type
+        let data = [
+            3,
+            AD_TYPE_FLAGS,
+            0x06,
+            0x00,
+            4,
+            AD_TYPE_COMPLETE_UUIDS16,
+            0x0d,
+            0x18,
+            0x0f,
+            1,
+            AD_TYPE_APPEARANCE,
+        ];
+        let mut ads = parse(&data);
+        assert_eq!(
+            ads.next(),
+            Some(AdStructure::Other {
+                ad_type: AD_TYPE_FLAGS,
+                data: &[0x06, 0x00],
+            })
+        );
+        assert_eq!(
+            ads.next(),
+            Some(AdStructure::Other {
+                ad_type: AD_TYPE_COMPLETE_UUIDS16,
+                data: &[0x0d, 0x18, 0x0f],
+            })
+        );
+        assert_eq!(
+            ads.next(),
+            Some(AdStructure::Other {
+                ad_type: AD_TYPE_APPEARANCE,
+                data: &[],
+            })
+        );
+        assert_eq!(ads.next(), None);
+
+        assert_eq!(parse(&[]).next(), None);
+    }
+}
@@ -0,0 +1,479 @@
+//! iBeacon and Eddystone advertising payloads.
+//!
+//! Each payload takes the whole advertising data, flags included, and is advertised
+//! non-connectable:
+//!
+//! ```ignore
+//! let beacon = IBeacon { uuid: PROXIMITY_UUID, major: 1, minor: 2, tx_power: -59 };
+//! let adv = beacon.adv_data()?;
+//! nb::block!(mbox.poll_ble_cmd(&mbox.send_ble_cmd(&mut ipcc, &LeSetAdvertisingData { data: adv.as_bytes() })?))?;
+//! ```
+
+use core::fmt;
+
+use super::{
+    parse, AdStructure, AdvData, Error, FLAG_BR_EDR_NOT_SUPPORTED, FLAG_LE_GENERAL_DISCOVERABLE,
+};
+use crate::tl_mbox::bytes::{Reader, Writer};
+
+/// Company ID of Apple, Inc.
+pub const APPLE_COMPANY_ID: u16 = 0x004c;
+
+/// 16-bit UUID of the Eddystone service.
+pub const EDDYSTONE_UUID: u16 = 0xfeaa;
+
+const IBEACON_TYPE: u8 = 0x02;
+const IBEACON_LEN: u8 = 0x15;
+
+const EDDYSTONE_UID: u8 = 0x00;
+const EDDYSTONE_URL: u8 = 0x10;
+const EDDYSTONE_TLM: u8 = 0x20;
+
+/// Only unencrypted TLM frames are supported.
+const EDDYSTONE_TLM_VERSION: u8 = 0x00;
+
+/// Flags advertised with beacon payloads.
+const BEACON_FLAGS: u8 = FLAG_LE_GENERAL_DISCOVERABLE | FLAG_BR_EDR_NOT_SUPPORTED;
+
+/// Apple iBeacon.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IBeacon {
+    /// Proximity UUID in the usual (big-endian) byte order.
+    pub uuid: [u8; 16],
+    pub major: u16,
+    pub minor: u16,
+    /// Measured power at 1 m, in dBm.
+    pub tx_power: i8,
+}
+
+impl IBeacon {
+    pub fn adv_data(&self) -> Result<AdvData, Error> {
+        let mut data = [0u8; 23];
+        let mut w = Writer::new(&mut data);
+        w.u8(IBEACON_TYPE)?;
+        w.u8(IBEACON_LEN)?;
+        w.bytes(&self.uuid)?;
+        w.bytes(&self.major.to_be_bytes())?;
+        w.bytes(&self.minor.to_be_bytes())?;
+        w.u8(self.tx_power as u8)?;
+
+        let mut adv = AdvData::new();
+        adv.flags(BEACON_FLAGS)?
+            .manufacturer_data(APPLE_COMPANY_ID, &data)?;
+
+        Ok(adv)
+    }
+
+    /// Finds iBeacon payload in advertising data.
+    pub fn from_adv_data(data: &[u8]) -> Option<Self> {
+        parse(data).find_map(|ad| match ad {
+            AdStructure::ManufacturerData {
+                company_id: APPLE_COMPANY_ID,
+                data,
+            } => Self::from_manufacturer_data(data),
+
+            _ => None,
+        })
+    }
+
+    /// Decodes Apple manufacturer specific data, without the company ID.
+    pub fn from_manufacturer_data(data: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(data);
+        if r.u8()? != IBEACON_TYPE || r.u8()? != IBEACON_LEN {
+            return None;
+        }
+
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(r.bytes(16)?);
+        let major = r.bytes(2)?;
+        let minor = r.bytes(2)?;
+        let tx_power = r.i8()?;
+
+        if !r.rest().is_empty() {
+            return None;
+        }
+
+        Some(IBeacon {
+            uuid,
+            major: u16::from_be_bytes([major[0], major[1]]),
+            minor: u16::from_be_bytes([minor[0], minor[1]]),
+            tx_power,
+        })
+    }
+}
+
+/// Eddystone frame, advertised as service data of `EDDYSTONE_UUID`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Eddystone {
+    Uid(EddystoneUid),
+    Url(EddystoneUrl),
+    Tlm(EddystoneTlm),
+}
+
+impl Eddystone {
+    pub fn adv_data(&self) -> Result<AdvData, Error> {
+        let mut frame = [0u8; 20];
+        let mut w = Writer::new(&mut frame);
+
+        match self {
+            Eddystone::Uid(uid) => {
+                w.u8(EDDYSTONE_UID)?;
+                w.u8(uid.tx_power as u8)?;
+                w.bytes(&uid.namespace)?;
+                w.bytes(&uid.instance)?;
+                // Reserved
+                w.u16(0)?;
+            }
+
+            Eddystone::Url(url) => {
+                w.u8(EDDYSTONE_URL)?;
+                w.u8(url.tx_power as u8)?;
+                w.u8(url.scheme)?;
+                w.bytes(url.encoded())?;
+            }
+
+            Eddystone::Tlm(tlm) => {
+                w.u8(EDDYSTONE_TLM)?;
+                w.u8(EDDYSTONE_TLM_VERSION)?;
+                w.bytes(&tlm.battery_mv.to_be_bytes())?;
+                w.bytes(&tlm.temperature.to_be_bytes())?;
+                w.bytes(&tlm.adv_count.to_be_bytes())?;
+                w.bytes(&tlm.uptime.to_be_bytes())?;
+            }
+        }
+        let len = w.len();
+
+        let mut adv = AdvData::new();
+        adv.flags(BEACON_FLAGS)?
+            .uuids16(true, &[EDDYSTONE_UUID])?
+            .service_data16(EDDYSTONE_UUID, &frame[..len])?;
+
+        Ok(adv)
+    }
+
+    /// Finds Eddystone frame in advertising data.
+    pub fn from_adv_data(data: &[u8]) -> Option<Self> {
+        parse(data).find_map(|ad| match ad {
+            AdStructure::ServiceData16 {
+                uuid: EDDYSTONE_UUID,
+                data,
+            } => Self::from_service_data(data),
+
+            _ => None,
+        })
+    }
+
+    /// Decodes Eddystone service data, without the UUID.
+    pub fn from_service_data(data: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(data);
+
+        match r.u8()? {
+            EDDYSTONE_UID => {
+                let tx_power = r.i8()?;
+                let mut namespace = [0u8; 10];
+                namespace.copy_from_slice(r.bytes(10)?);
+                let mut instance = [0u8; 6];
+                instance.copy_from_slice(r.bytes(6)?);
+
+                Some(Eddystone::Uid(EddystoneUid {
+                    tx_power,
+                    namespace,
+                    instance,
+                }))
+            }
+
+            EDDYSTONE_URL => {
+                let tx_power = r.i8()?;
+                let scheme = r.u8()?;
+                let encoded = r.rest();
+                if scheme as usize >= SCHEMES.len() || encoded.len() > MAX_URL_LEN {
+                    return None;
+                }
+
+                let mut url = [0u8; MAX_URL_LEN];
+                url[..encoded.len()].copy_from_slice(encoded);
+
+                Some(Eddystone::Url(EddystoneUrl {
+                    tx_power,
+                    scheme,
+                    url,
+                    len: encoded.len(),
+                }))
+            }
+
+            EDDYSTONE_TLM => {
+                if r.u8()? != EDDYSTONE_TLM_VERSION {
+                    return None;
+                }
+
+                let b = r.bytes(12)?;
+                Some(Eddystone::Tlm(EddystoneTlm {
+                    battery_mv: u16::from_be_bytes([b[0], b[1]]),
+                    temperature: i16::from_be_bytes([b[2], b[3]]),
+                    adv_count: u32::from_be_bytes([b[4], b[5], b[6], b[7]]),
+                    uptime: u32::from_be_bytes([b[8], b[9], b[10], b[11]]),
+                }))
+            }
+
+            _ => None,
+        }
+    }
+}
+
+/// Eddystone-UID frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EddystoneUid {
+    /// Calibrated TX power at 0 m, in dBm.
+    pub tx_power: i8,
+    pub namespace: [u8; 10],
+    pub instance: [u8; 6],
+}
+
+/// Maximum length of the encoded URL that fits into the advertising data.
+pub const MAX_URL_LEN: usize = 17;
+
+const SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+
+/// Expansions of the codes 0x00..=0x0d. Those with a trailing slash come first, so that they are
+/// preferred when encoding.
+const EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+/// Eddystone-URL frame. The URL is kept encoded and is decoded by its `Display` implementation.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct EddystoneUrl {
+    /// Calibrated TX power at 0 m, in dBm.
+    pub tx_power: i8,
+    scheme: u8,
+    url: [u8; MAX_URL_LEN],
+    len: usize,
+}
+
+impl EddystoneUrl {
+    /// Encodes `url`, which must start with `http://` or `https://` and contain only printable
+    /// ASCII characters.
+    pub fn new(tx_power: i8, url: &str) -> Result<Self, Error> {
+        let (scheme, prefix) = SCHEMES
+            .iter()
+            .enumerate()
+            .find(|(_, prefix)| url.starts_with(*prefix))
+            .ok_or(Error::InvalidParameter)?;
+
+        let mut encoded = EddystoneUrl {
+            tx_power,
+            scheme: scheme as u8,
+            url: [0; MAX_URL_LEN],
+            len: 0,
+        };
+
+        let mut rest = &url[prefix.len()..];
+        while let Some(c) = rest.bytes().next() {
+            let (byte, len) = match EXPANSIONS
+                .iter()
+                .position(|expansion| rest.starts_with(expansion))
+            {
+                Some(code) => (code as u8, EXPANSIONS[code].len()),
+
+                None if c > 0x20 && c < 0x7f => (c, 1),
+                None => return Err(Error::InvalidParameter),
+            };
+
+            if encoded.len == MAX_URL_LEN {
+                return Err(Error::TooLong);
+            }
+
+            encoded.url[encoded.len] = byte;
+            encoded.len += 1;
+            rest = &rest[len..];
+        }
+
+        Ok(encoded)
+    }
+
+    /// URL after the scheme prefix, with expansion codes.
+    pub fn encoded(&self) -> &[u8] {
+        &self.url[..self.len]
+    }
+}
+
+impl fmt::Display for EddystoneUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(SCHEMES[self.scheme as usize])?;
+
+        for &byte in self.encoded() {
+            match EXPANSIONS.get(byte as usize) {
+                Some(expansion) => f.write_str(expansion)?,
+                None if byte > 0x20 && byte < 0x7f => fmt::Write::write_char(f, byte as char)?,
+                // Reserved codes
+                None => f.write_str("\u{fffd}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for EddystoneUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EddystoneUrl")
+            .field("tx_power", &self.tx_power)
+            .field("url", &format_args!("{}", self))
+            .finish()
+    }
+}
+
+/// Unencrypted Eddystone-TLM frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EddystoneTlm {
+    /// Battery voltage in mV, 0 if not supported.
+    pub battery_mv: u16,
+    /// Temperature in degrees Celsius, signed 8.8 fixed point. `-0x8000` if not supported.
+    pub temperature: i16,
+    /// Number of advertising frames sent since power-up or reboot.
+    pub adv_count: u32,
+    /// Time since power-up or reboot, in units of 0.1 s.
+    pub uptime: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    fn display(url: &EddystoneUrl) -> heapless::String<heapless::consts::U64> {
+        let mut s = heapless::String::new();
+        write!(s, "{}", url).unwrap();
+        s
+    }
+
+    #[test]
+    fn ibeacon() {
+        let beacon = IBeacon {
+            uuid: [
+                0xe2, 0xc5, 0x6d, 0xb5, 0xdf, 0xfb, 0x48, 0xd2, 0xb0, 0x60, 0xd0, 0xf5, 0xa7, 0x10,
+                0x96, 0xe0,
+            ],
+            major: 0x0102,
+            minor: 0x0304,
+            tx_power: -59,
+        };
+
+        let adv = beacon.adv_data().unwrap();
+        assert_eq!(adv.len(), 30);
+        assert_eq!(
+            &adv.as_bytes()[..9],
+            &[0x02, 0x01, 0x06, 0x1a, 0xff, 0x4c, 0x00, 0x02, 0x15]
+        );
+        assert_eq!(&adv.as_bytes()[25..], &[0x01, 0x02, 0x03, 0x04, 0xc5]);
+
+        assert_eq!(IBeacon::from_adv_data(adv.as_bytes()), Some(beacon));
+
+        // Trailing bytes
+        let mut data = [0u8; 24];
+        data[..23].copy_from_slice(&adv.as_bytes()[7..]);
+        assert_eq!(IBeacon::from_manufacturer_data(&data), None);
+        assert_eq!(IBeacon::from_manufacturer_data(&data[..22]), None);
+    }
+
+    #[test]
+    fn eddystone_uid() {
+        let uid = Eddystone::Uid(EddystoneUid {
+            tx_power: -20,
+            namespace: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            instance: [10, 11, 12, 13, 14, 15],
+        });
+
+        let adv = uid.adv_data().unwrap();
+        assert_eq!(
+            &adv.as_bytes()[3..11],
+            &[0x03, 0x03, 0xaa, 0xfe, 0x17, 0x16, 0xaa, 0xfe]
+        );
+        assert_eq!(&adv.as_bytes()[11..13], &[EDDYSTONE_UID, 0xec]);
+        assert_eq!(Eddystone::from_adv_data(adv.as_bytes()), Some(uid));
+    }
+
+    #[test]
+    fn eddystone_url() {
+        let url = EddystoneUrl::new(-20, "https://www.example.com/").unwrap();
+        assert_eq!(url.scheme, 1);
+        assert_eq!(url.encoded(), b"example\x00");
+        assert_eq!(display(&url), "https://www.example.com/");
+
+        let adv = Eddystone::Url(url).adv_data().unwrap();
+        assert_eq!(
+            &adv.as_bytes()[11..],
+            &[
+                EDDYSTONE_URL,
+                0xec,
+                0x01,
+                b'e',
+                b'x',
+                b'a',
+                b'm',
+                b'p',
+                b'l',
+                b'e',
+                0x00
+            ]
+        );
+        assert_eq!(
+            Eddystone::from_adv_data(adv.as_bytes()),
+            Some(Eddystone::Url(url))
+        );
+
+        // Expansion without the trailing slash in the middle of the URL
+        let url = EddystoneUrl::new(0, "http://goo.gl.info/a").unwrap();
+        assert_eq!(url.scheme, 2);
+        assert_eq!(url.encoded(), b"goo.gl\x04a");
+        assert_eq!(display(&url), "http://goo.gl.info/a");
+
+        let url = EddystoneUrl::new(0, "http://a.org").unwrap();
+        assert_eq!(url.encoded(), b"a\x08");
+
+        assert_eq!(
+            EddystoneUrl::new(0, "ftp://example.com").err(),
+            Some(Error::InvalidParameter)
+        );
+        assert_eq!(
+            EddystoneUrl::new(0, "http://exa mple.com").err(),
+            Some(Error::InvalidParameter)
+        );
+        assert!(EddystoneUrl::new(0, "https://abcdefghijklmnopq").is_ok());
+        assert_eq!(
+            EddystoneUrl::new(0, "https://abcdefghijklmnopqr").err(),
+            Some(Error::TooLong)
+        );
+
+        // Unknown scheme and reserved codes
+        assert_eq!(Eddystone::from_service_data(&[EDDYSTONE_URL, 0, 4]), None);
+        match Eddystone::from_service_data(&[EDDYSTONE_URL, 0, 3, b'a', 0x0e]) {
+            Some(Eddystone::Url(url)) => assert_eq!(display(&url), "https://a\u{fffd}"),
+            frame => panic!("unexpected {:?}", frame),
+        }
+    }
+
+    #[test]
+    fn eddystone_tlm() {
+        let tlm = Eddystone::Tlm(EddystoneTlm {
+            battery_mv: 3000,
+            temperature: -0x0180,
+            adv_count: 0x0102_0304,
+            uptime: 600,
+        });
+
+        let adv = tlm.adv_data().unwrap();
+        assert_eq!(
+            &adv.as_bytes()[11..17],
+            &[EDDYSTONE_TLM, EDDYSTONE_TLM_VERSION, 0x0b, 0xb8, 0xfe, 0x80]
+        );
+        assert_eq!(Eddystone::from_adv_data(adv.as_bytes()), Some(tlm));
+
+        // Encrypted TLM
+        let mut data = [0u8; 14];
+        data[0] = EDDYSTONE_TLM;
+        data[1] = 0x01;
+        assert_eq!(Eddystone::from_service_data(&data), None);
+    }
+}
@@ -0,0 +1,632 @@
+//! Central role: scanning, white list, connection creation and the table of open connections.
+//!
+//! `Central` builds the HCI LE commands and follows the events fed to `process()`, which are
+//! dequeued with `TlMbox::dequeue_event` like any other BLE event:
+//!
+//! ```ignore
+//! let mut central = Central::<U4>::new();
+//! let (params, enable) = central.start_scan(&scan)?;
+//! nb::block!(mbox.poll_ble_cmd(&mbox.send_ble_cmd(&mut ipcc, &params)?))?;
+//! nb::block!(mbox.poll_ble_cmd(&mbox.send_ble_cmd(&mut ipcc, &enable)?))?;
+//!
+//! // ... for every received event `evt`:
+//! match central.process(&evt) {
+//!     Some(CentralEvent::AdvertisingReports(mut reports)) => {
+//!         if let Some(report) = reports.find(is_wanted) {
+//!             let peer = Peer::Address(report.address_type, report.address);
+//!             let stop = central.stop_scan()?;
+//!             let connect = central.connect(peer, &scan, conn_params)?;
+//!             // ... send both commands
+//!         }
+//!     }
+//!     Some(CentralEvent::Connected(conn)) => {}
+//!     _ => {}
+//! }
+//! ```
+//!
+//! Connections of both roles are tracked. PHY updates and data length changes are reported only
+//! if they are enabled with `hci::LeSetEventMask`.
+
+use heapless::{ArrayLength, Vec};
+
+use crate::tl_mbox::ble::event::{AdvertisingReports, Event, LeMetaEvent};
+use crate::tl_mbox::ble::hci::{
+    ConnectionParameters, DataLength, LeAddDeviceToWhiteList, LeClearWhiteList, LeCreateConnection,
+    LeCreateConnectionCancel, LeRemoveDeviceFromWhiteList, LeSetScanEnable, LeSetScanParameters,
+    ScanType,
+};
+use crate::tl_mbox::ble::types::{AddressType, BdAddr, ConnectionHandle, Phy, Role, Status};
+
+/// Scanning filter policy: accept all advertising packets.
+pub const SCAN_FILTER_ACCEPT_ALL: u8 = 0x00;
+
+/// Scanning filter policy: accept only advertising packets of the devices on the white list.
+pub const SCAN_FILTER_WHITE_LIST_ONLY: u8 = 0x01;
+
+/// Status of LE Connection Complete event after `LeCreateConnectionCancel`.
+pub const UNKNOWN_CONNECTION_IDENTIFIER: Status = Status(0x02);
+
+/// Status of LE Connection Complete event when high duty cycle directed advertising has timed
+/// out in the slave role.
+pub const DIRECTED_ADVERTISING_TIMEOUT: Status = Status(0x3c);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    AlreadyScanning,
+    NotScanning,
+
+    /// A connection is already being created.
+    AlreadyConnecting,
+
+    /// No connection is being created.
+    NotConnecting,
+
+    /// White list is used by scanning or connection creation and can't be modified.
+    WhiteListInUse,
+
+    /// Connection table is full.
+    TooManyConnections,
+}
+
+/// Scanning parameters, also used to scan for the peer when creating a connection.
+#[derive(Debug, Copy, Clone)]
+pub struct ScanParameters {
+    pub scan_type: ScanType,
+    /// Scan interval, in units of 0.625 ms.
+    pub interval: u16,
+    /// Scan window, in units of 0.625 ms. Shall not exceed `interval`.
+    pub window: u16,
+    pub own_address_type: AddressType,
+    /// Report only the devices on the white list.
+    pub white_list_only: bool,
+    /// Let the controller report each device only once while scanning is enabled.
+    pub filter_duplicates: bool,
+}
+
+/// Device to connect to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Peer {
+    Address(AddressType, BdAddr),
+
+    /// Any device on the white list.
+    WhiteList,
+}
+
+/// Open connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub handle: ConnectionHandle,
+    pub role: Role,
+    pub peer_address_type: AddressType,
+    pub peer_address: BdAddr,
+
+    /// Connection interval, in units of 1.25 ms.
+    pub interval: u16,
+    pub latency: u16,
+
+    /// Supervision timeout, in units of 10 ms.
+    pub supervision_timeout: u16,
+
+    pub tx_phy: Phy,
+    pub rx_phy: Phy,
+
+    /// Maximum size and transmission time of sent data packets.
+    pub tx_data_length: DataLength,
+
+    /// Maximum size and transmission time of received data packets.
+    pub rx_data_length: DataLength,
+}
+
+/// Result of `Central::process`.
+#[derive(Debug, Copy, Clone)]
+pub enum CentralEvent<'a> {
+    AdvertisingReports(AdvertisingReports<'a>),
+
+    Connected(Connection),
+
+    /// Connection creation failed or has been cancelled (with status
+    /// `UNKNOWN_CONNECTION_IDENTIFIER`).
+    ConnectFailed(Status),
+
+    /// Connection parameters have been changed.
+    ConnectionUpdated(Connection),
+
+    PhyUpdated(Connection),
+
+    DataLengthChanged(Connection),
+
+    Disconnected {
+        connection: Connection,
+        reason: u8,
+    },
+}
+
+/// Scanning and connection state together with up to `N` open connections.
+pub struct Central<N: ArrayLength<Connection>> {
+    /// Scan parameters while scanning is enabled.
+    scan: Option<ScanParameters>,
+
+    /// Peer of the connection being created.
+    connecting: Option<Peer>,
+
+    connections: Vec<Connection, N>,
+}
+
+impl<N: ArrayLength<Connection>> Central<N> {
+    pub fn new() -> Self {
+        Central {
+            scan: None,
+            connecting: None,
+            connections: Vec::new(),
+        }
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    /// Returns the peer of the connection being created.
+    pub fn connecting(&self) -> Option<Peer> {
+        self.connecting
+    }
+
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    pub fn connection(&self, handle: ConnectionHandle) -> Option<&Connection> {
+        self.connections.iter().find(|conn| conn.handle == handle)
+    }
+
+    /// Returns commands that set scanning parameters and enable scanning, to be sent in this
+    /// order. If either of them fails, `stop_scan` resets the scanning state.
+    pub fn start_scan(
+        &mut self,
+        scan: &ScanParameters,
+    ) -> Result<(LeSetScanParameters, LeSetScanEnable), Error> {
+        if self.scan.is_some() {
+            return Err(Error::AlreadyScanning);
+        }
+        self.scan = Some(*scan);
+
+        let filter_policy = if scan.white_list_only {
+            SCAN_FILTER_WHITE_LIST_ONLY
+        } else {
+            SCAN_FILTER_ACCEPT_ALL
+        };
+
+        Ok((
+            LeSetScanParameters {
+                scan_type: scan.scan_type,
+                interval: scan.interval,
+                window: scan.window,
+                own_address_type: scan.own_address_type,
+                filter_policy,
+            },
+            LeSetScanEnable {
+                enable: true,
+                filter_duplicates: scan.filter_duplicates,
+            },
+        ))
+    }
+
+    pub fn stop_scan(&mut self) -> Result<LeSetScanEnable, Error> {
+        self.scan.take().ok_or(Error::NotScanning)?;
+
+        Ok(LeSetScanEnable {
+            enable: false,
+            filter_duplicates: false,
+        })
+    }
+
+    /// Starts creating a connection to `peer`, scanning for it with the interval, window and own
+    /// address type of `scan`.
+    ///
+    /// Completes with `CentralEvent::Connected` or `CentralEvent::ConnectFailed`.
+    pub fn connect(
+        &mut self,
+        peer: Peer,
+        scan: &ScanParameters,
+        conn_params: ConnectionParameters,
+    ) -> Result<LeCreateConnection, Error> {
+        if self.connecting.is_some() {
+            return Err(Error::AlreadyConnecting);
+        }
+
+        if self.connections.len() == self.connections.capacity() {
+            return Err(Error::TooManyConnections);
+        }
+
+        let (use_white_list, peer_address_type, peer_address) = match peer {
+            Peer::Address(address_type, address) => (false, address_type, address),
+            Peer::WhiteList => (true, AddressType::Public, BdAddr::default()),
+        };
+
+        self.connecting = Some(peer);
+
+        Ok(LeCreateConnection {
+            scan_interval: scan.interval,
+            scan_window: scan.window,
+            use_white_list,
+            peer_address_type,
+            peer_address,
+            own_address_type: scan.own_address_type,
+            conn_params,
+        })
+    }
+
+    /// Cancels connection creation, which then fails with `UNKNOWN_CONNECTION_IDENTIFIER`.
+    ///
+    /// Also clears the connection creation state if the controller has rejected
+    /// `LeCreateConnection`, the returned command can be dropped then.
+    pub fn cancel_connect(&mut self) -> Result<LeCreateConnectionCancel, Error> {
+        self.connecting.take().ok_or(Error::NotConnecting)?;
+
+        Ok(LeCreateConnectionCancel)
+    }
+
+    pub fn add_to_white_list(
+        &mut self,
+        address_type: AddressType,
+        address: BdAddr,
+    ) -> Result<LeAddDeviceToWhiteList, Error> {
+        self.check_white_list_unused()?;
+
+        Ok(LeAddDeviceToWhiteList {
+            address_type,
+            address,
+        })
+    }
+
+    pub fn remove_from_white_list(
+        &mut self,
+        address_type: AddressType,
+        address: BdAddr,
+    ) -> Result<LeRemoveDeviceFromWhiteList, Error> {
+        self.check_white_list_unused()?;
+
+        Ok(LeRemoveDeviceFromWhiteList {
+            address_type,
+            address,
+        })
+    }
+
+    pub fn clear_white_list(&mut self) -> Result<LeClearWhiteList, Error> {
+        self.check_white_list_unused()?;
+
+        Ok(LeClearWhiteList)
+    }
+
+    fn check_white_list_unused(&self) -> Result<(), Error> {
+        let scanning = self.scan.map_or(false, |scan| scan.white_list_only);
+        let connecting = self.connecting == Some(Peer::WhiteList);
+
+        if scanning || connecting {
+            Err(Error::WhiteListInUse)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Updates the state with a received event.
+    ///
+    /// Connections that don't fit into the table are reported, but not tracked.
+    pub fn process<'a>(&mut self, event: &Event<'a>) -> Option<CentralEvent<'a>> {
+        match *event {
+            Event::LeMeta(LeMetaEvent::AdvertisingReport(reports)) => {
+                Some(CentralEvent::AdvertisingReports(reports))
+            }
+
+            Event::LeMeta(LeMetaEvent::ConnectionComplete(evt)) => {
+                if !evt.status.is_success() {
+                    // Advertising has failed in the slave role, the connection being created
+                    // (if any) is still pending
+                    if evt.status == DIRECTED_ADVERTISING_TIMEOUT {
+                        return None;
+                    }
+
+                    // `cancel_connect` has already cleared the state
+                    let pending = self.connecting.take().is_some();
+                    if !pending && evt.status != UNKNOWN_CONNECTION_IDENTIFIER {
+                        return None;
+                    }
+
+                    return Some(CentralEvent::ConnectFailed(evt.status));
+                }
+
+                if evt.role == Role::Master {
+                    self.connecting = None;
+                }
+
+                let conn = Connection {
+                    handle: evt.handle,
+                    role: evt.role,
+                    peer_address_type: evt.peer_address_type,
+                    peer_address: evt.peer_address,
+                    interval: evt.conn_interval,
+                    latency: evt.conn_latency,
+                    supervision_timeout: evt.supervision_timeout,
+                    tx_phy: Phy::Le1M,
+                    rx_phy: Phy::Le1M,
+                    tx_data_length: DataLength::DEFAULT,
+                    rx_data_length: DataLength::DEFAULT,
+                };
+
+                self.remove(evt.handle);
+                self.connections.push(conn).ok();
+
+                Some(CentralEvent::Connected(conn))
+            }
+
+            Event::LeMeta(LeMetaEvent::ConnectionUpdateComplete(evt))
+                if evt.status.is_success() =>
+            {
+                let conn = self.connection_mut(evt.handle)?;
+                conn.interval = evt.conn_interval;
+                conn.latency = evt.conn_latency;
+                conn.supervision_timeout = evt.supervision_timeout;
+
+                Some(CentralEvent::ConnectionUpdated(*conn))
+            }
+
+            Event::LeMeta(LeMetaEvent::PhyUpdateComplete(evt)) if evt.status.is_success() => {
+                let conn = self.connection_mut(evt.handle)?;
+                conn.tx_phy = evt.tx_phy;
+                conn.rx_phy = evt.rx_phy;
+
+                Some(CentralEvent::PhyUpdated(*conn))
+            }
+
+            Event::LeMeta(LeMetaEvent::DataLengthChange(evt)) => {
+                let conn = self.connection_mut(evt.handle)?;
+                conn.tx_data_length = DataLength {
+                    octets: evt.max_tx_octets,
+                    time: evt.max_tx_time,
+                };
+                conn.rx_data_length = DataLength {
+                    octets: evt.max_rx_octets,
+                    time: evt.max_rx_time,
+                };
+
+                Some(CentralEvent::DataLengthChanged(*conn))
+            }
+
+            Event::DisconnectionComplete(evt) if evt.status.is_success() => {
+                let connection = self.remove(evt.handle)?;
+
+                Some(CentralEvent::Disconnected {
+                    connection,
+                    reason: evt.reason,
+                })
+            }
+
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, handle: ConnectionHandle) -> Option<Connection> {
+        let index = self
+            .connections
+            .iter()
+            .position(|conn| conn.handle == handle)?;
+
+        Some(self.connections.swap_remove(index))
+    }
+
+    fn connection_mut(&mut self, handle: ConnectionHandle) -> Option<&mut Connection> {
+        self.connections
+            .iter_mut()
+            .find(|conn| conn.handle == handle)
+    }
+}
+
+impl<N: ArrayLength<Connection>> Default for Central<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::ble::event::{
+        DisconnectionComplete, LeConnectionComplete, LeConnectionUpdateComplete,
+        LePhyUpdateComplete,
+    };
+    use heapless::consts::U2;
+
+    const PEER: BdAddr = BdAddr([1, 2, 3, 4, 5, 6]);
+
+    fn scan() -> ScanParameters {
+        ScanParameters {
+            scan_type: ScanType::Active,
+            interval: 0x0010,
+            window: 0x0010,
+            own_address_type: AddressType::Public,
+            white_list_only: false,
+            filter_duplicates: true,
+        }
+    }
+
+    fn conn_params() -> ConnectionParameters {
+        ConnectionParameters {
+            interval_min: 0x0018,
+            interval_max: 0x0028,
+            latency: 0,
+            supervision_timeout: 0x01f4,
+            min_ce_length: 0,
+            max_ce_length: 0,
+        }
+    }
+
+    fn connection_complete(status: u8, handle: u16, role: Role) -> Event<'static> {
+        Event::LeMeta(LeMetaEvent::ConnectionComplete(LeConnectionComplete {
+            status: Status(status),
+            handle: ConnectionHandle(handle),
+            role,
+            peer_address_type: AddressType::Public,
+            peer_address: PEER,
+            conn_interval: 0x0028,
+            conn_latency: 0,
+            supervision_timeout: 0x01f4,
+            master_clock_accuracy: 0,
+        }))
+    }
+
+    fn connect(central: &mut Central<U2>) {
+        central
+            .connect(
+                Peer::Address(AddressType::Public, PEER),
+                &scan(),
+                conn_params(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn connection_lifecycle() {
+        let mut central = Central::<U2>::new();
+        connect(&mut central);
+
+        let event = central.process(&connection_complete(0x00, 0x0001, Role::Master));
+        assert!(matches!(event, Some(CentralEvent::Connected(_))));
+        assert_eq!(central.connecting(), None);
+        assert_eq!(central.connections().len(), 1);
+
+        let conn = central.connection(ConnectionHandle(0x0001)).unwrap();
+        assert_eq!(conn.role, Role::Master);
+        assert_eq!(conn.peer_address, PEER);
+        assert_eq!(conn.interval, 0x0028);
+        assert_eq!(conn.tx_phy, Phy::Le1M);
+        assert_eq!(conn.tx_data_length, DataLength::DEFAULT);
+
+        let update = Event::LeMeta(LeMetaEvent::ConnectionUpdateComplete(
+            LeConnectionUpdateComplete {
+                status: Status::SUCCESS,
+                handle: ConnectionHandle(0x0001),
+                conn_interval: 0x0050,
+                conn_latency: 4,
+                supervision_timeout: 0x0258,
+            },
+        ));
+        assert!(matches!(
+            central.process(&update),
+            Some(CentralEvent::ConnectionUpdated(_))
+        ));
+        let conn = central.connection(ConnectionHandle(0x0001)).unwrap();
+        assert_eq!(
+            (conn.interval, conn.latency, conn.supervision_timeout),
+            (0x0050, 4, 0x0258)
+        );
+
+        let phy = Event::LeMeta(LeMetaEvent::PhyUpdateComplete(LePhyUpdateComplete {
+            status: Status::SUCCESS,
+            handle: ConnectionHandle(0x0001),
+            tx_phy: Phy::Le2M,
+            rx_phy: Phy::LeCoded,
+        }));
+        assert!(matches!(
+            central.process(&phy),
+            Some(CentralEvent::PhyUpdated(_))
+        ));
+        let conn = central.connection(ConnectionHandle(0x0001)).unwrap();
+        assert_eq!((conn.tx_phy, conn.rx_phy), (Phy::Le2M, Phy::LeCoded));
+
+        // Updates of unknown connections are ignored
+        let phy = Event::LeMeta(LeMetaEvent::PhyUpdateComplete(LePhyUpdateComplete {
+            status: Status::SUCCESS,
+            handle: ConnectionHandle(0x0002),
+            tx_phy: Phy::Le2M,
+            rx_phy: Phy::Le2M,
+        }));
+        assert!(central.process(&phy).is_none());
+
+        let disconnection = Event::DisconnectionComplete(DisconnectionComplete {
+            status: Status::SUCCESS,
+            handle: ConnectionHandle(0x0001),
+            reason: 0x13,
+        });
+        match central.process(&disconnection) {
+            Some(CentralEvent::Disconnected { connection, reason }) => {
+                assert_eq!(connection.handle, ConnectionHandle(0x0001));
+                assert_eq!(connection.tx_phy, Phy::Le2M);
+                assert_eq!(reason, 0x13);
+            }
+            _ => panic!("no disconnection"),
+        }
+        assert!(central.connections().is_empty());
+        assert!(central.process(&disconnection).is_none());
+    }
+
+    #[test]
+    fn slave_connection_keeps_pending_connect() {
+        let mut central = Central::<U2>::new();
+        connect(&mut central);
+
+        central.process(&connection_complete(0x00, 0x0002, Role::Slave));
+        assert!(central.connecting().is_some());
+        assert_eq!(central.connections().len(), 1);
+
+        // Failed directed advertising doesn't affect the connection being created
+        let timeout = connection_complete(DIRECTED_ADVERTISING_TIMEOUT.0, 0, Role::Slave);
+        assert!(central.process(&timeout).is_none());
+        assert!(central.connecting().is_some());
+
+        assert!(central
+            .process(&connection_complete(0x00, 0x0003, Role::Master))
+            .is_some());
+        assert_eq!(central.connecting(), None);
+        assert_eq!(central.connections().len(), 2);
+    }
+
+    #[test]
+    fn connect_failure() {
+        let mut central = Central::<U2>::new();
+
+        // Nothing is pending
+        assert!(central
+            .process(&connection_complete(0x3e, 0, Role::Master))
+            .is_none());
+
+        connect(&mut central);
+        central.cancel_connect().unwrap();
+        let cancelled = connection_complete(UNKNOWN_CONNECTION_IDENTIFIER.0, 0, Role::Master);
+        assert!(matches!(
+            central.process(&cancelled),
+            Some(CentralEvent::ConnectFailed(UNKNOWN_CONNECTION_IDENTIFIER))
+        ));
+
+        connect(&mut central);
+        assert!(matches!(
+            central.process(&connection_complete(0x3e, 0, Role::Master)),
+            Some(CentralEvent::ConnectFailed(Status(0x3e)))
+        ));
+        assert_eq!(central.connecting(), None);
+        assert!(central.connections().is_empty());
+    }
+
+    #[test]
+    fn white_list_in_use() {
+        let mut central = Central::<U2>::new();
+        let scan = ScanParameters {
+            white_list_only: true,
+            ..scan()
+        };
+
+        central.start_scan(&scan).unwrap();
+        assert_eq!(
+            central.clear_white_list().err(),
+            Some(Error::WhiteListInUse)
+        );
+        central.stop_scan().unwrap();
+        assert!(central.clear_white_list().is_ok());
+
+        central
+            .connect(Peer::WhiteList, &scan, conn_params())
+            .unwrap();
+        assert_eq!(
+            central.add_to_white_list(AddressType::Public, PEER).err(),
+            Some(Error::WhiteListInUse)
+        );
+    }
+}
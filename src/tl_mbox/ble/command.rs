@@ -0,0 +1,132 @@
+//! Typed HCI commands sent over the BLE channel.
+//!
+//! A command is serialized straight into the shared `BLE_CMD_BUFFER` and the controller answers
+//! with a Command Complete or Command Status event carrying the same opcode. That event is kept
+//! aside by `TlMbox` instead of being put into the event queue, and can be picked up with
+//! `TlMbox::poll_ble_cmd`:
+//!
+//! ```ignore
+//! let pending = mbox.send_ble_cmd(&mut ipcc, &hci::ReadBdAddr)?;
+//! let bd_addr = nb::block!(mbox.poll_ble_cmd(&pending))?;
+//! ```
+
+use core::marker::PhantomData;
+
+use super::event::Event;
+use super::types::Status;
+use crate::tl_mbox::bytes::{BufferFull, Writer};
+
+/// Builds an HCI opcode from the OpCode Group Field and OpCode Command Field.
+pub const fn opcode(ogf: u16, ocf: u16) -> u16 {
+    (ogf << 10) | ocf
+}
+
+pub const OGF_LINK_CONTROL: u16 = 0x01;
+pub const OGF_CONTROLLER: u16 = 0x03;
+pub const OGF_INFO_PARAM: u16 = 0x04;
+pub const OGF_STATUS_PARAM: u16 = 0x05;
+pub const OGF_LE_CONTROLLER: u16 = 0x08;
+pub const OGF_VENDOR: u16 = 0x3f;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Command parameters (or one of its variable-length fields) are too long.
+    TooLong,
+
+    /// A parameter is out of its allowed range.
+    InvalidParameter,
+
+    /// Previous command didn't receive its Command Complete/Status event yet.
+    Busy,
+
+    /// A newer command has been sent, response to this one is gone.
+    Superseded,
+
+    /// Controller rejected the command.
+    Status(Status),
+
+    /// Return parameters couldn't be decoded.
+    Malformed,
+}
+
+impl From<BufferFull> for Error {
+    fn from(_: BufferFull) -> Self {
+        Error::TooLong
+    }
+}
+
+/// HCI command that can be serialized into the command buffer.
+pub trait Command {
+    const OPCODE: u16;
+
+    /// Return parameters that follow the status byte in Command Complete event.
+    type Response: Response;
+
+    /// Serializes command parameters.
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error>;
+}
+
+/// Decodes command return parameters.
+pub trait Response: Sized {
+    /// Decodes return parameters that follow the status byte.
+    /// Returns `None` if they are malformed.
+    fn from_return_params(params: &[u8]) -> Option<Self>;
+}
+
+impl Response for () {
+    fn from_return_params(_params: &[u8]) -> Option<Self> {
+        Some(())
+    }
+}
+
+/// Handle to a command in flight. Resolved by `TlMbox::poll_ble_cmd`.
+#[derive(Debug)]
+pub struct PendingCommand<C> {
+    pub(crate) seq: u32,
+    _command: PhantomData<C>,
+}
+
+impl<C: Command> PendingCommand<C> {
+    pub(crate) fn new(seq: u32) -> Self {
+        PendingCommand {
+            seq,
+            _command: PhantomData,
+        }
+    }
+}
+
+/// Checks whether the `event` answers the command with given `opcode`.
+pub(crate) fn is_response_to(event: &Event, opcode: u16) -> bool {
+    match event {
+        Event::CommandComplete(cc) => cc.opcode == opcode,
+        Event::CommandStatus(cs) => cs.opcode == opcode,
+
+        _ => false,
+    }
+}
+
+/// Decodes the status and return parameters of a Command Complete/Status event.
+pub(crate) fn decode_response<C: Command>(event: &Event) -> Result<C::Response, Error> {
+    let params: &[u8] = match event {
+        Event::CommandComplete(cc) => {
+            let (status, params) = cc.return_params.split_first().ok_or(Error::Malformed)?;
+            if *status != 0 {
+                return Err(Error::Status(Status(*status)));
+            }
+
+            params
+        }
+
+        Event::CommandStatus(cs) => {
+            if !cs.status.is_success() {
+                return Err(Error::Status(cs.status));
+            }
+
+            &[]
+        }
+
+        _ => return Err(Error::Malformed),
+    };
+
+    C::Response::from_return_params(params).ok_or(Error::Malformed)
+}
@@ -0,0 +1,568 @@
+//! Typed decoding of HCI events received on the BLE channel.
+//!
+//! Events borrow their variable-length parameters from the buffer they were decoded from, so
+//! decoding an `EvtBox` does not copy anything out of the shared RAM.
+
+use core::convert::TryFrom;
+
+use super::adv::{self, AdStructures};
+use super::types::{AddressType, BdAddr, ConnectionHandle, Phy, Role, Status};
+use crate::tl_mbox::bytes::Reader;
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::EvtBox;
+
+pub const EVT_DISCONNECTION_COMPLETE: u8 = 0x05;
+pub const EVT_ENCRYPTION_CHANGE: u8 = 0x08;
+pub const EVT_COMMAND_COMPLETE: u8 = 0x0e;
+pub const EVT_COMMAND_STATUS: u8 = 0x0f;
+pub const EVT_LE_META: u8 = 0x3e;
+pub const EVT_VENDOR: u8 = 0xff;
+
+pub const LE_SUBEVT_CONNECTION_COMPLETE: u8 = 0x01;
+pub const LE_SUBEVT_ADVERTISING_REPORT: u8 = 0x02;
+pub const LE_SUBEVT_CONNECTION_UPDATE_COMPLETE: u8 = 0x03;
+pub const LE_SUBEVT_REMOTE_CONNECTION_PARAMETER_REQUEST: u8 = 0x06;
+pub const LE_SUBEVT_DATA_LENGTH_CHANGE: u8 = 0x07;
+pub const LE_SUBEVT_PHY_UPDATE_COMPLETE: u8 = 0x0c;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Event parameters are truncated or contain out-of-range values.
+    Malformed,
+
+    /// The packet is not an HCI event (e.g. it is ACL data). Contains raw packet type.
+    NotAnEvent(u8),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Event<'a> {
+    CommandComplete(CommandComplete<'a>),
+    CommandStatus(CommandStatus),
+    DisconnectionComplete(DisconnectionComplete),
+    EncryptionChange(EncryptionChange),
+    LeMeta(LeMetaEvent<'a>),
+    Vendor(VendorEvent<'a>),
+
+    /// Event that has no typed representation yet.
+    Unknown {
+        code: u8,
+        params: &'a [u8],
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Decodes an HCI event from its wire representation:
+    /// event code, parameter length and parameters (without the packet type indicator).
+    pub fn from_bytes(buf: &'a [u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(buf);
+        let code = r.u8().ok_or(Error::Malformed)?;
+        let len = r.u8().ok_or(Error::Malformed)? as usize;
+        let params = r.bytes(len).ok_or(Error::Malformed)?;
+
+        Self::parse(code, params).ok_or(Error::Malformed)
+    }
+
+    fn parse(code: u8, params: &'a [u8]) -> Option<Self> {
+        let mut r = Reader::new(params);
+
+        Some(match code {
+            EVT_COMMAND_COMPLETE => Event::CommandComplete(CommandComplete {
+                num_cmd: r.u8()?,
+                opcode: r.u16()?,
+                return_params: r.rest(),
+            }),
+
+            EVT_COMMAND_STATUS => Event::CommandStatus(CommandStatus {
+                status: Status(r.u8()?),
+                num_cmd: r.u8()?,
+                opcode: r.u16()?,
+            }),
+
+            EVT_DISCONNECTION_COMPLETE => Event::DisconnectionComplete(DisconnectionComplete {
+                status: Status(r.u8()?),
+                handle: ConnectionHandle::from_raw(r.u16()?),
+                reason: r.u8()?,
+            }),
+
+            EVT_ENCRYPTION_CHANGE => Event::EncryptionChange(EncryptionChange {
+                status: Status(r.u8()?),
+                handle: ConnectionHandle::from_raw(r.u16()?),
+                enabled: r.u8()? != 0,
+            }),
+
+            EVT_LE_META => Event::LeMeta(LeMetaEvent::parse(&mut r)?),
+
+            EVT_VENDOR => Event::Vendor(VendorEvent {
+                code: r.u16()?,
+                params: r.rest(),
+            }),
+
+            _ => Event::Unknown { code, params },
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a EvtBox> for Event<'a> {
+    type Error = Error;
+
+    fn try_from(evt: &'a EvtBox) -> Result<Self, Self::Error> {
+        let kind = evt.raw_kind();
+        match TlPacketType::try_from(kind) {
+            Ok(TlPacketType::BleEvt) | Ok(TlPacketType::SysEvt) => {}
+
+            _ => return Err(Error::NotAnEvent(kind)),
+        }
+
+        Self::from_bytes(evt.evt_bytes())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CommandComplete<'a> {
+    /// Number of HCI command packets the controller is ready to accept.
+    pub num_cmd: u8,
+    pub opcode: u16,
+
+    /// Command-specific return parameters, usually starting with a status byte.
+    pub return_params: &'a [u8],
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CommandStatus {
+    pub status: Status,
+    pub num_cmd: u8,
+    pub opcode: u16,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DisconnectionComplete {
+    pub status: Status,
+    pub handle: ConnectionHandle,
+    pub reason: u8,
+}
+
+/// Encryption of a connection has been turned on or off, either after pairing or with the keys
+/// of a bonded device.
+#[derive(Debug, Copy, Clone)]
+pub struct EncryptionChange {
+    pub status: Status,
+    pub handle: ConnectionHandle,
+    pub enabled: bool,
+}
+
+/// ST vendor-specific event. `code` is the ACI event code (`ecode`).
+#[derive(Debug, Copy, Clone)]
+pub struct VendorEvent<'a> {
+    pub code: u16,
+    pub params: &'a [u8],
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LeMetaEvent<'a> {
+    ConnectionComplete(LeConnectionComplete),
+    AdvertisingReport(AdvertisingReports<'a>),
+    ConnectionUpdateComplete(LeConnectionUpdateComplete),
+    RemoteConnectionParameterRequest(LeRemoteConnectionParameterRequest),
+    DataLengthChange(LeDataLengthChange),
+    PhyUpdateComplete(LePhyUpdateComplete),
+
+    /// LE sub-event that has no typed representation yet.
+    Unknown {
+        subevent: u8,
+        params: &'a [u8],
+    },
+}
+
+impl<'a> LeMetaEvent<'a> {
+    fn parse(r: &mut Reader<'a>) -> Option<Self> {
+        let subevent = r.u8()?;
+
+        Some(match subevent {
+            LE_SUBEVT_CONNECTION_COMPLETE => {
+                LeMetaEvent::ConnectionComplete(LeConnectionComplete {
+                    status: Status(r.u8()?),
+                    handle: ConnectionHandle::from_raw(r.u16()?),
+                    role: Role::try_from(r.u8()?).ok()?,
+                    peer_address_type: AddressType::try_from(r.u8()?).ok()?,
+                    peer_address: BdAddr(r.array6()?),
+                    conn_interval: r.u16()?,
+                    conn_latency: r.u16()?,
+                    supervision_timeout: r.u16()?,
+                    master_clock_accuracy: r.u8()?,
+                })
+            }
+
+            LE_SUBEVT_ADVERTISING_REPORT => {
+                let num_reports = r.u8()?;
+                let reports = AdvertisingReports {
+                    remaining: num_reports,
+                    r: Reader::new(r.rest()),
+                };
+
+                // Validate all reports up front so that iteration can't fail half-way
+                if reports.count() != num_reports as usize {
+                    return None;
+                }
+
+                LeMetaEvent::AdvertisingReport(reports)
+            }
+
+            LE_SUBEVT_CONNECTION_UPDATE_COMPLETE => {
+                LeMetaEvent::ConnectionUpdateComplete(LeConnectionUpdateComplete {
+                    status: Status(r.u8()?),
+                    handle: ConnectionHandle::from_raw(r.u16()?),
+                    conn_interval: r.u16()?,
+                    conn_latency: r.u16()?,
+                    supervision_timeout: r.u16()?,
+                })
+            }
+
+            LE_SUBEVT_REMOTE_CONNECTION_PARAMETER_REQUEST => {
+                LeMetaEvent::RemoteConnectionParameterRequest(LeRemoteConnectionParameterRequest {
+                    handle: ConnectionHandle::from_raw(r.u16()?),
+                    interval_min: r.u16()?,
+                    interval_max: r.u16()?,
+                    latency: r.u16()?,
+                    supervision_timeout: r.u16()?,
+                })
+            }
+
+            LE_SUBEVT_DATA_LENGTH_CHANGE => LeMetaEvent::DataLengthChange(LeDataLengthChange {
+                handle: ConnectionHandle::from_raw(r.u16()?),
+                max_tx_octets: r.u16()?,
+                max_tx_time: r.u16()?,
+                max_rx_octets: r.u16()?,
+                max_rx_time: r.u16()?,
+            }),
+
+            LE_SUBEVT_PHY_UPDATE_COMPLETE => LeMetaEvent::PhyUpdateComplete(LePhyUpdateComplete {
+                status: Status(r.u8()?),
+                handle: ConnectionHandle::from_raw(r.u16()?),
+                tx_phy: Phy::try_from(r.u8()?).ok()?,
+                rx_phy: Phy::try_from(r.u8()?).ok()?,
+            }),
+
+            _ => LeMetaEvent::Unknown {
+                subevent,
+                params: r.rest(),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeConnectionComplete {
+    pub status: Status,
+    pub handle: ConnectionHandle,
+    pub role: Role,
+    pub peer_address_type: AddressType,
+    pub peer_address: BdAddr,
+
+    /// Connection interval, in units of 1.25 ms.
+    pub conn_interval: u16,
+    pub conn_latency: u16,
+
+    /// Supervision timeout, in units of 10 ms.
+    pub supervision_timeout: u16,
+    pub master_clock_accuracy: u8,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeConnectionUpdateComplete {
+    pub status: Status,
+    pub handle: ConnectionHandle,
+
+    /// Connection interval, in units of 1.25 ms.
+    pub conn_interval: u16,
+    pub conn_latency: u16,
+
+    /// Supervision timeout, in units of 10 ms.
+    pub supervision_timeout: u16,
+}
+
+/// Peer requests new connection parameters, to be answered with
+/// `hci::LeRemoteConnectionParameterRequestReply` or its negative counterpart.
+#[derive(Debug, Copy, Clone)]
+pub struct LeRemoteConnectionParameterRequest {
+    pub handle: ConnectionHandle,
+
+    /// Minimum connection interval, in units of 1.25 ms.
+    pub interval_min: u16,
+
+    /// Maximum connection interval, in units of 1.25 ms.
+    pub interval_max: u16,
+    pub latency: u16,
+
+    /// Supervision timeout, in units of 10 ms.
+    pub supervision_timeout: u16,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeDataLengthChange {
+    pub handle: ConnectionHandle,
+    pub max_tx_octets: u16,
+    pub max_tx_time: u16,
+    pub max_rx_octets: u16,
+    pub max_rx_time: u16,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LePhyUpdateComplete {
+    pub status: Status,
+    pub handle: ConnectionHandle,
+    pub tx_phy: Phy,
+    pub rx_phy: Phy,
+}
+
+/// Iterator over the reports contained in a single LE Advertising Report event.
+#[derive(Debug, Copy, Clone)]
+pub struct AdvertisingReports<'a> {
+    remaining: u8,
+    r: Reader<'a>,
+}
+
+impl<'a> Iterator for AdvertisingReports<'a> {
+    type Item = AdvertisingReport<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let event_type = self.r.u8()?;
+        let address_type = AddressType::try_from(self.r.u8()?).ok()?;
+        let address = BdAddr(self.r.array6()?);
+        let data_len = self.r.u8()? as usize;
+        let data = self.r.bytes(data_len)?;
+        let rssi = self.r.i8()?;
+
+        Some(AdvertisingReport {
+            event_type,
+            address_type,
+            address,
+            data,
+            rssi,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AdvertisingReport<'a> {
+    /// ADV_IND (0x00), ADV_DIRECT_IND (0x01), ADV_SCAN_IND (0x02), ADV_NONCONN_IND (0x03)
+    /// or SCAN_RSP (0x04).
+    pub event_type: u8,
+    pub address_type: AddressType,
+    pub address: BdAddr,
+
+    /// Advertising data (AD structures).
+    pub data: &'a [u8],
+
+    /// RSSI in dBm, 127 if not available.
+    pub rssi: i8,
+}
+
+impl<'a> AdvertisingReport<'a> {
+    /// Decodes AD structures of the advertising data.
+    pub fn ad_structures(&self) -> AdStructures<'a> {
+        adv::parse(self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disconnection_complete() {
+        let buf = [EVT_DISCONNECTION_COMPLETE, 4, 0x00, 0x01, 0x08, 0x13];
+
+        match Event::from_bytes(&buf) {
+            Ok(Event::DisconnectionComplete(evt)) => {
+                assert_eq!(evt.status, Status(0));
+                assert_eq!(evt.handle, ConnectionHandle(0x0801));
+                assert_eq!(evt.reason, 0x13);
+            }
+            evt => panic!("unexpected {:?}", evt),
+        }
+    }
+
+    #[test]
+    fn encryption_change() {
+        let buf = [EVT_ENCRYPTION_CHANGE, 4, 0x00, 0x01, 0x08, 0x01];
+
+        match Event::from_bytes(&buf) {
+            Ok(Event::EncryptionChange(evt)) => {
+                assert_eq!(evt.status, Status(0));
+                assert_eq!(evt.handle, ConnectionHandle(0x0801));
+                assert!(evt.enabled);
+            }
+            evt => panic!("unexpected {:?}", evt),
+        }
+
+        assert_eq!(
+            Event::from_bytes(&[EVT_ENCRYPTION_CHANGE, 3, 0x00, 0x01, 0x08]).err(),
+            Some(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn le_connection_complete() {
+        let buf = [
+            EVT_LE_META,
+            19,
+            LE_SUBEVT_CONNECTION_COMPLETE,
+            0x00,
+            0x01,
+            0x08,
+            0x01,
+            0x01,
+            0x11,
+            0x22,
+            0x33,
+            0x44,
+            0x55,
+            0x66,
+            0x28,
+            0x00,
+            0x00,
+            0x00,
+            0xf4,
+            0x01,
+            0x05,
+        ];
+
+        match Event::from_bytes(&buf) {
+            Ok(Event::LeMeta(LeMetaEvent::ConnectionComplete(evt))) => {
+                assert_eq!(evt.status, Status(0));
+                assert_eq!(evt.handle, ConnectionHandle(0x0801));
+                assert_eq!(evt.role, Role::Slave);
+                assert_eq!(evt.peer_address_type, AddressType::Random);
+                assert_eq!(
+                    evt.peer_address,
+                    BdAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66])
+                );
+                assert_eq!(evt.conn_interval, 0x28);
+                assert_eq!(evt.conn_latency, 0);
+                assert_eq!(evt.supervision_timeout, 500);
+                assert_eq!(evt.master_clock_accuracy, 5);
+            }
+            evt => panic!("unexpected {:?}", evt),
+        }
+    }
+
+    #[test]
+    fn advertising_reports() {
+        let buf = [
+            EVT_LE_META,
+            25,
+            LE_SUBEVT_ADVERTISING_REPORT,
+            2,
+            // ADV_IND from a public address with Flags AD structure
+            0x00,
+            0x00,
+            0x01,
+            0x02,
+            0x03,
+            0x04,
+            0x05,
+            0x06,
+            3,
+            0x02,
+            0x01,
+            0x06,
+            0xc4,
+            // SCAN_RSP from a random address without data
+            0x04,
+            0x01,
+            0xa1,
+            0xa2,
+            0xa3,
+            0xa4,
+            0xa5,
+            0xa6,
+            0,
+            0x7f,
+        ];
+
+        let mut reports = match Event::from_bytes(&buf) {
+            Ok(Event::LeMeta(LeMetaEvent::AdvertisingReport(reports))) => reports,
+            evt => panic!("unexpected {:?}", evt),
+        };
+
+        let first = reports.next().unwrap();
+        assert_eq!(first.event_type, 0x00);
+        assert_eq!(first.address_type, AddressType::Public);
+        assert_eq!(first.address, BdAddr([1, 2, 3, 4, 5, 6]));
+        assert_eq!(first.data, &[0x02, 0x01, 0x06]);
+        assert_eq!(first.rssi, -60);
+
+        let second = reports.next().unwrap();
+        assert_eq!(second.event_type, 0x04);
+        assert_eq!(second.address_type, AddressType::Random);
+        assert_eq!(second.address, BdAddr([0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6]));
+        assert!(second.data.is_empty());
+        assert_eq!(second.rssi, 127);
+
+        assert!(reports.next().is_none());
+    }
+
+    #[test]
+    fn malformed() {
+        // Empty buffer and missing parameter length
+        assert_eq!(Event::from_bytes(&[]).err(), Some(Error::Malformed));
+        assert_eq!(
+            Event::from_bytes(&[EVT_DISCONNECTION_COMPLETE]).err(),
+            Some(Error::Malformed)
+        );
+
+        // Parameter length exceeds the buffer
+        assert_eq!(
+            Event::from_bytes(&[EVT_DISCONNECTION_COMPLETE, 4, 0x00, 0x01]).err(),
+            Some(Error::Malformed)
+        );
+
+        // Parameters are too short for the event
+        assert_eq!(
+            Event::from_bytes(&[EVT_DISCONNECTION_COMPLETE, 2, 0x00, 0x01]).err(),
+            Some(Error::Malformed)
+        );
+
+        // Out-of-range role
+        let mut conn = [0u8; 21];
+        conn[..3].copy_from_slice(&[EVT_LE_META, 19, LE_SUBEVT_CONNECTION_COMPLETE]);
+        conn[6] = 0x02;
+        assert_eq!(Event::from_bytes(&conn).err(), Some(Error::Malformed));
+
+        // Second report claims more data than present
+        let adv = [
+            EVT_LE_META,
+            26,
+            LE_SUBEVT_ADVERTISING_REPORT,
+            2,
+            0x00,
+            0x00,
+            0x01,
+            0x02,
+            0x03,
+            0x04,
+            0x05,
+            0x06,
+            0,
+            0xc4,
+            0x00,
+            0x00,
+            0x01,
+            0x02,
+            0x03,
+            0x04,
+            0x05,
+            0x06,
+            5,
+            0x02,
+            0x01,
+            0x06,
+        ];
+        assert_eq!(Event::from_bytes(&adv).err(), Some(Error::Malformed));
+    }
+}
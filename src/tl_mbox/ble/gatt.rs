@@ -0,0 +1,33 @@
+//! GATT layer on top of ACI GATT commands.
+
+pub mod client;
+pub mod server;
+
+/// Handle of a service declaration in the local GATT database.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ServiceHandle(pub u16);
+
+/// Handle of a characteristic declaration in the local GATT database.
+///
+/// The characteristic value and its client characteristic configuration descriptor (if the
+/// characteristic can be notified or indicated) immediately follow the declaration.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CharacteristicHandle(pub u16);
+
+impl CharacteristicHandle {
+    /// Handle of the characteristic value attribute, `None` if the declaration is the last
+    /// possible handle.
+    pub fn value_handle(self) -> Option<u16> {
+        self.0.checked_add(1)
+    }
+
+    /// Handle of the client characteristic configuration descriptor, `None` if it would be past
+    /// the last possible handle.
+    pub fn cccd_handle(self) -> Option<u16> {
+        self.0.checked_add(2)
+    }
+}
+
+/// Handle of a characteristic descriptor in the local GATT database.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DescriptorHandle(pub u16);
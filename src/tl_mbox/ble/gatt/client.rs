@@ -0,0 +1,806 @@
+//! GATT client: discovery, read and write procedures on a remote GATT server.
+//!
+//! Each procedure is started with the `aci::gatt` command returned by `command()`. Results
+//! arrive as several `aci::gatt::Event`s terminated by `ProcComplete`, which the procedure
+//! collects as they are fed to `process()`:
+//!
+//! ```ignore
+//! let mut discovery = ServiceDiscovery::<U8>::new(conn_handle);
+//! nb::block!(mbox.poll_ble_cmd(&mbox.send_ble_cmd(&mut ipcc, &discovery.command())?))?;
+//! loop {
+//!     // ... for every received `aci::gatt::Event` `evt`:
+//!     if let Some(result) = discovery.process(&evt) {
+//!         let services = result?;
+//!         break;
+//!     }
+//! }
+//! ```
+
+use heapless::{ArrayLength, Vec};
+
+use crate::tl_mbox::ble::aci::gatt::{
+    DiscAllCharDesc, DiscAllCharOfService, DiscAllPrimaryServices, DiscCharByUuid,
+    DiscPrimaryServiceByUuid, Event, ExchangeConfig, ReadCharValue, ReadLongCharValue,
+    WriteCharDesc, WriteCharValue,
+};
+use crate::tl_mbox::ble::types::{ConnectionHandle, Uuid};
+use crate::tl_mbox::bytes::Reader;
+
+/// UUID of the client characteristic configuration descriptor.
+pub const CCCD_UUID: Uuid = Uuid::Uuid16(0x2902);
+
+/// ATT error returned by a server when there are no more attributes in the requested range.
+/// It terminates discovery procedures and is not reported as an error by them.
+pub const ATT_ERR_ATTRIBUTE_NOT_FOUND: u8 = 0x0a;
+
+/// Values of the client characteristic configuration descriptor.
+pub const CCCD_NOTIFY: u16 = 0x0001;
+pub const CCCD_INDICATE: u16 = 0x0002;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    /// `ProcComplete` reported a failure.
+    ProcedureFailed(u8),
+
+    /// Server answered with an ATT error.
+    AttError {
+        req_opcode: u8,
+        attr_handle: u16,
+        error_code: u8,
+    },
+
+    /// Server sent a response that could not be decoded.
+    Malformed,
+
+    /// Results don't fit into the procedure storage.
+    TooManyResults,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DiscoveredService {
+    pub start_handle: u16,
+    pub end_handle: u16,
+    pub uuid: Uuid,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DiscoveredCharacteristic {
+    pub declaration_handle: u16,
+    /// Bitmask of `aci::gatt::PROP_*` constants.
+    pub properties: u8,
+    pub value_handle: u16,
+    pub uuid: Uuid,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DiscoveredDescriptor {
+    pub handle: u16,
+    pub uuid: Uuid,
+}
+
+impl DiscoveredService {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(data);
+
+        Some(DiscoveredService {
+            start_handle: r.u16()?,
+            end_handle: r.u16()?,
+            uuid: Uuid::from_le_bytes(r.rest())?,
+        })
+    }
+}
+
+impl DiscoveredCharacteristic {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(data);
+        let declaration_handle = r.u16()?;
+
+        Self::from_declaration(declaration_handle, r.rest())
+    }
+
+    /// Decodes the value of the characteristic declaration at `declaration_handle`.
+    fn from_declaration(declaration_handle: u16, value: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(value);
+
+        Some(DiscoveredCharacteristic {
+            declaration_handle,
+            properties: r.u8()?,
+            value_handle: r.u16()?,
+            uuid: Uuid::from_le_bytes(r.rest())?,
+        })
+    }
+}
+
+impl DiscoveredDescriptor {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(data);
+
+        Some(DiscoveredDescriptor {
+            handle: r.u16()?,
+            uuid: Uuid::from_le_bytes(r.rest())?,
+        })
+    }
+}
+
+/// State shared by all procedures: tracks completion events of one connection.
+#[derive(Debug)]
+struct Procedure {
+    conn_handle: ConnectionHandle,
+    /// `ATT_ERR_ATTRIBUTE_NOT_FOUND` ends the procedure instead of failing it.
+    discovery: bool,
+    error: Option<ClientError>,
+}
+
+impl Procedure {
+    fn new(conn_handle: ConnectionHandle) -> Self {
+        Procedure {
+            conn_handle,
+            discovery: false,
+            error: None,
+        }
+    }
+
+    fn discovery(conn_handle: ConnectionHandle) -> Self {
+        Procedure {
+            discovery: true,
+            ..Self::new(conn_handle)
+        }
+    }
+
+    fn fail(&mut self, error: ClientError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Handles error and completion events. Returns `Some` once the procedure completed.
+    fn complete(&mut self, event: &Event) -> Option<Result<(), ClientError>> {
+        match *event {
+            Event::ErrorResp {
+                conn_handle,
+                req_opcode,
+                attr_handle,
+                error_code,
+            } if conn_handle == self.conn_handle => {
+                if !(self.discovery && error_code == ATT_ERR_ATTRIBUTE_NOT_FOUND) {
+                    self.fail(ClientError::AttError {
+                        req_opcode,
+                        attr_handle,
+                        error_code,
+                    });
+                }
+
+                None
+            }
+
+            Event::ProcComplete {
+                conn_handle,
+                error_code,
+            } if conn_handle == self.conn_handle => {
+                if error_code != 0 {
+                    self.fail(ClientError::ProcedureFailed(error_code));
+                }
+
+                Some(match self.error.take() {
+                    Some(error) => Err(error),
+                    None => Ok(()),
+                })
+            }
+
+            _ => None,
+        }
+    }
+}
+
+fn push<T, N: ArrayLength<T>>(procedure: &mut Procedure, results: &mut Vec<T, N>, item: Option<T>) {
+    match item {
+        Some(item) => {
+            if results.push(item).is_err() {
+                procedure.fail(ClientError::TooManyResults);
+            }
+        }
+        None => procedure.fail(ClientError::Malformed),
+    }
+}
+
+/// Discovers all primary services of the server.
+pub struct ServiceDiscovery<N: ArrayLength<DiscoveredService>> {
+    procedure: Procedure,
+    services: Vec<DiscoveredService, N>,
+}
+
+impl<N: ArrayLength<DiscoveredService>> ServiceDiscovery<N> {
+    pub fn new(conn_handle: ConnectionHandle) -> Self {
+        ServiceDiscovery {
+            procedure: Procedure::discovery(conn_handle),
+            services: Vec::new(),
+        }
+    }
+
+    pub fn command(&self) -> DiscAllPrimaryServices {
+        DiscAllPrimaryServices {
+            conn_handle: self.procedure.conn_handle,
+        }
+    }
+
+    pub fn process(&mut self, event: &Event) -> Option<Result<&[DiscoveredService], ClientError>> {
+        if let Event::ReadByGroupTypeResp {
+            conn_handle,
+            attribute_data_len,
+            data,
+        } = *event
+        {
+            if conn_handle == self.procedure.conn_handle {
+                let len = attribute_data_len as usize;
+                if len < 4 {
+                    self.procedure.fail(ClientError::Malformed);
+                    return None;
+                }
+
+                for chunk in data.chunks_exact(len) {
+                    let service = DiscoveredService::parse(chunk);
+                    push(&mut self.procedure, &mut self.services, service);
+                }
+
+                return None;
+            }
+        }
+
+        let services = &self.services;
+        self.procedure
+            .complete(event)
+            .map(|r| r.map(|_| &services[..]))
+    }
+}
+
+/// Discovers primary services with the given UUID.
+pub struct ServiceByUuidDiscovery<N: ArrayLength<DiscoveredService>> {
+    procedure: Procedure,
+    uuid: Uuid,
+    services: Vec<DiscoveredService, N>,
+}
+
+impl<N: ArrayLength<DiscoveredService>> ServiceByUuidDiscovery<N> {
+    pub fn new(conn_handle: ConnectionHandle, uuid: Uuid) -> Self {
+        ServiceByUuidDiscovery {
+            procedure: Procedure::discovery(conn_handle),
+            uuid,
+            services: Vec::new(),
+        }
+    }
+
+    pub fn command(&self) -> DiscPrimaryServiceByUuid {
+        DiscPrimaryServiceByUuid {
+            conn_handle: self.procedure.conn_handle,
+            uuid: self.uuid,
+        }
+    }
+
+    pub fn process(&mut self, event: &Event) -> Option<Result<&[DiscoveredService], ClientError>> {
+        if let Event::FindByTypeValueResp { conn_handle, data } = *event {
+            if conn_handle == self.procedure.conn_handle {
+                // Found attribute handle and group end handle pairs
+                for chunk in data.chunks_exact(4) {
+                    let service = DiscoveredService {
+                        start_handle: u16::from_le_bytes([chunk[0], chunk[1]]),
+                        end_handle: u16::from_le_bytes([chunk[2], chunk[3]]),
+                        uuid: self.uuid,
+                    };
+                    push(&mut self.procedure, &mut self.services, Some(service));
+                }
+
+                return None;
+            }
+        }
+
+        let services = &self.services;
+        self.procedure
+            .complete(event)
+            .map(|r| r.map(|_| &services[..]))
+    }
+}
+
+/// Discovers all characteristics of a service.
+pub struct CharacteristicDiscovery<N: ArrayLength<DiscoveredCharacteristic>> {
+    procedure: Procedure,
+    start_handle: u16,
+    end_handle: u16,
+    characteristics: Vec<DiscoveredCharacteristic, N>,
+}
+
+impl<N: ArrayLength<DiscoveredCharacteristic>> CharacteristicDiscovery<N> {
+    pub fn new(conn_handle: ConnectionHandle, service: &DiscoveredService) -> Self {
+        CharacteristicDiscovery {
+            procedure: Procedure::discovery(conn_handle),
+            start_handle: service.start_handle,
+            end_handle: service.end_handle,
+            characteristics: Vec::new(),
+        }
+    }
+
+    pub fn command(&self) -> DiscAllCharOfService {
+        DiscAllCharOfService {
+            conn_handle: self.procedure.conn_handle,
+            start_handle: self.start_handle,
+            end_handle: self.end_handle,
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        event: &Event,
+    ) -> Option<Result<&[DiscoveredCharacteristic], ClientError>> {
+        if let Event::ReadByTypeResp {
+            conn_handle,
+            pair_len,
+            data,
+        } = *event
+        {
+            if conn_handle == self.procedure.conn_handle {
+                let len = pair_len as usize;
+                if len < 5 {
+                    self.procedure.fail(ClientError::Malformed);
+                    return None;
+                }
+
+                for chunk in data.chunks_exact(len) {
+                    let characteristic = DiscoveredCharacteristic::parse(chunk);
+                    push(
+                        &mut self.procedure,
+                        &mut self.characteristics,
+                        characteristic,
+                    );
+                }
+
+                return None;
+            }
+        }
+
+        let characteristics = &self.characteristics;
+        self.procedure
+            .complete(event)
+            .map(|r| r.map(|_| &characteristics[..]))
+    }
+}
+
+/// Discovers characteristics of a service with the given UUID.
+pub struct CharacteristicByUuidDiscovery<N: ArrayLength<DiscoveredCharacteristic>> {
+    procedure: Procedure,
+    start_handle: u16,
+    end_handle: u16,
+    uuid: Uuid,
+    characteristics: Vec<DiscoveredCharacteristic, N>,
+}
+
+impl<N: ArrayLength<DiscoveredCharacteristic>> CharacteristicByUuidDiscovery<N> {
+    pub fn new(conn_handle: ConnectionHandle, service: &DiscoveredService, uuid: Uuid) -> Self {
+        CharacteristicByUuidDiscovery {
+            procedure: Procedure::discovery(conn_handle),
+            start_handle: service.start_handle,
+            end_handle: service.end_handle,
+            uuid,
+            characteristics: Vec::new(),
+        }
+    }
+
+    pub fn command(&self) -> DiscCharByUuid {
+        DiscCharByUuid {
+            conn_handle: self.procedure.conn_handle,
+            start_handle: self.start_handle,
+            end_handle: self.end_handle,
+            uuid: self.uuid,
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        event: &Event,
+    ) -> Option<Result<&[DiscoveredCharacteristic], ClientError>> {
+        if let Event::DiscReadCharByUuidResp {
+            conn_handle,
+            attr_handle,
+            value,
+        } = *event
+        {
+            if conn_handle == self.procedure.conn_handle {
+                let characteristic = DiscoveredCharacteristic::from_declaration(attr_handle, value);
+                push(
+                    &mut self.procedure,
+                    &mut self.characteristics,
+                    characteristic,
+                );
+
+                return None;
+            }
+        }
+
+        let characteristics = &self.characteristics;
+        self.procedure
+            .complete(event)
+            .map(|r| r.map(|_| &characteristics[..]))
+    }
+}
+
+/// Discovers descriptors of a characteristic.
+pub struct DescriptorDiscovery<N: ArrayLength<DiscoveredDescriptor>> {
+    procedure: Procedure,
+    value_handle: u16,
+    end_handle: u16,
+    descriptors: Vec<DiscoveredDescriptor, N>,
+}
+
+impl<N: ArrayLength<DiscoveredDescriptor>> DescriptorDiscovery<N> {
+    /// `end_handle` is the last handle of the characteristic: the handle before the next
+    /// characteristic declaration or the end handle of the service.
+    pub fn new(
+        conn_handle: ConnectionHandle,
+        characteristic: &DiscoveredCharacteristic,
+        end_handle: u16,
+    ) -> Self {
+        DescriptorDiscovery {
+            procedure: Procedure::discovery(conn_handle),
+            value_handle: characteristic.value_handle,
+            end_handle,
+            descriptors: Vec::new(),
+        }
+    }
+
+    pub fn command(&self) -> DiscAllCharDesc {
+        DiscAllCharDesc {
+            conn_handle: self.procedure.conn_handle,
+            char_handle: self.value_handle,
+            end_handle: self.end_handle,
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        event: &Event,
+    ) -> Option<Result<&[DiscoveredDescriptor], ClientError>> {
+        if let Event::FindInfoResp {
+            conn_handle,
+            format,
+            data,
+        } = *event
+        {
+            if conn_handle == self.procedure.conn_handle {
+                let len = match format {
+                    0x01 => 4,
+                    0x02 => 18,
+                    _ => {
+                        self.procedure.fail(ClientError::Malformed);
+                        return None;
+                    }
+                };
+
+                for chunk in data.chunks_exact(len) {
+                    let descriptor = DiscoveredDescriptor::parse(chunk);
+                    push(&mut self.procedure, &mut self.descriptors, descriptor);
+                }
+
+                return None;
+            }
+        }
+
+        let descriptors = &self.descriptors;
+        self.procedure
+            .complete(event)
+            .map(|r| r.map(|_| &descriptors[..]))
+    }
+}
+
+/// Reads a characteristic value. Values longer than `ATT_MTU - 1` are read with
+/// several read blob requests.
+pub struct Read<N: ArrayLength<u8>> {
+    procedure: Procedure,
+    attr_handle: u16,
+    value: Vec<u8, N>,
+}
+
+impl<N: ArrayLength<u8>> Read<N> {
+    pub fn new(conn_handle: ConnectionHandle, attr_handle: u16) -> Self {
+        Read {
+            procedure: Procedure::new(conn_handle),
+            attr_handle,
+            value: Vec::new(),
+        }
+    }
+
+    pub fn command(&self) -> ReadCharValue {
+        ReadCharValue {
+            conn_handle: self.procedure.conn_handle,
+            attr_handle: self.attr_handle,
+        }
+    }
+
+    pub fn long_command(&self) -> ReadLongCharValue {
+        ReadLongCharValue {
+            conn_handle: self.procedure.conn_handle,
+            attr_handle: self.attr_handle,
+            offset: 0,
+        }
+    }
+
+    pub fn process(&mut self, event: &Event) -> Option<Result<&[u8], ClientError>> {
+        match *event {
+            Event::ReadResp { conn_handle, value } | Event::ReadBlobResp { conn_handle, value }
+                if conn_handle == self.procedure.conn_handle =>
+            {
+                if self.value.extend_from_slice(value).is_err() {
+                    self.procedure.fail(ClientError::TooManyResults);
+                }
+
+                None
+            }
+
+            _ => {
+                let value = &self.value;
+                self.procedure
+                    .complete(event)
+                    .map(|r| r.map(|_| &value[..]))
+            }
+        }
+    }
+}
+
+/// Waits for completion of a write procedure started with `WriteCharValue`,
+/// `WriteLongCharValue` or `WriteCharDesc`.
+pub struct Write {
+    procedure: Procedure,
+}
+
+impl Write {
+    pub fn new(conn_handle: ConnectionHandle) -> Self {
+        Write {
+            procedure: Procedure::new(conn_handle),
+        }
+    }
+
+    pub fn command<'a>(&self, attr_handle: u16, value: &'a [u8]) -> WriteCharValue<'a> {
+        WriteCharValue {
+            conn_handle: self.procedure.conn_handle,
+            attr_handle,
+            value,
+        }
+    }
+
+    pub fn process(&mut self, event: &Event) -> Option<Result<(), ClientError>> {
+        self.procedure.complete(event)
+    }
+}
+
+/// Negotiates ATT MTU with the server.
+pub struct MtuExchange {
+    procedure: Procedure,
+    mtu: Option<u16>,
+}
+
+impl MtuExchange {
+    pub fn new(conn_handle: ConnectionHandle) -> Self {
+        MtuExchange {
+            procedure: Procedure::new(conn_handle),
+            mtu: None,
+        }
+    }
+
+    pub fn command(&self) -> ExchangeConfig {
+        ExchangeConfig {
+            conn_handle: self.procedure.conn_handle,
+        }
+    }
+
+    /// Completes with the negotiated MTU, i.e. the server's receive MTU.
+    pub fn process(&mut self, event: &Event) -> Option<Result<u16, ClientError>> {
+        match *event {
+            Event::ExchangeMtuResp {
+                conn_handle,
+                server_rx_mtu,
+            } if conn_handle == self.procedure.conn_handle => {
+                self.mtu = Some(server_rx_mtu);
+                None
+            }
+
+            _ => {
+                let mtu = self.mtu;
+                self.procedure
+                    .complete(event)
+                    .map(|r| r.and_then(|_| mtu.ok_or(ClientError::Malformed)))
+            }
+        }
+    }
+}
+
+/// Enables notifications and/or indications by writing the client characteristic
+/// configuration descriptor. Wait for completion with `Write`.
+pub fn subscribe<'a>(
+    conn_handle: ConnectionHandle,
+    cccd_handle: u16,
+    notify: bool,
+    indicate: bool,
+    buf: &'a mut [u8; 2],
+) -> WriteCharDesc<'a> {
+    let mut value = 0;
+    if notify {
+        value |= CCCD_NOTIFY;
+    }
+    if indicate {
+        value |= CCCD_INDICATE;
+    }
+
+    *buf = value.to_le_bytes();
+
+    WriteCharDesc {
+        conn_handle,
+        attr_handle: cccd_handle,
+        value: &buf[..],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::consts::U4;
+
+    const CONN: ConnectionHandle = ConnectionHandle(0x0801);
+
+    fn not_found() -> Event<'static> {
+        Event::ErrorResp {
+            conn_handle: CONN,
+            req_opcode: 0x0a,
+            attr_handle: 0x0003,
+            error_code: ATT_ERR_ATTRIBUTE_NOT_FOUND,
+        }
+    }
+
+    fn complete(error_code: u8) -> Event<'static> {
+        Event::ProcComplete {
+            conn_handle: CONN,
+            error_code,
+        }
+    }
+
+    #[test]
+    fn discovery_ends_on_attribute_not_found() {
+        let mut discovery = ServiceDiscovery::<U4>::new(CONN);
+
+        let data = [
+            0x01, 0x00, 0x05, 0x00, 0x00, 0x18, 0x06, 0x00, 0x09, 0x00, 0x01, 0x18,
+        ];
+        let resp = Event::ReadByGroupTypeResp {
+            conn_handle: CONN,
+            attribute_data_len: 6,
+            data: &data,
+        };
+        assert_eq!(discovery.process(&resp), None);
+        assert_eq!(discovery.process(&not_found()), None);
+
+        let services = discovery.process(&complete(0)).unwrap().unwrap();
+        assert_eq!(
+            services,
+            &[
+                DiscoveredService {
+                    start_handle: 0x0001,
+                    end_handle: 0x0005,
+                    uuid: Uuid::Uuid16(0x1800),
+                },
+                DiscoveredService {
+                    start_handle: 0x0006,
+                    end_handle: 0x0009,
+                    uuid: Uuid::Uuid16(0x1801),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn read_fails_on_attribute_not_found() {
+        let mut read = Read::<U4>::new(CONN, 0x0003);
+        assert_eq!(read.process(&not_found()), None);
+
+        assert_eq!(
+            read.process(&complete(0)),
+            Some(Err(ClientError::AttError {
+                req_opcode: 0x0a,
+                attr_handle: 0x0003,
+                error_code: ATT_ERR_ATTRIBUTE_NOT_FOUND,
+            }))
+        );
+    }
+
+    #[test]
+    fn write_fails_on_attribute_not_found() {
+        let mut write = Write::new(CONN);
+        assert_eq!(write.process(&not_found()), None);
+
+        assert!(matches!(
+            write.process(&complete(0)),
+            Some(Err(ClientError::AttError { .. }))
+        ));
+    }
+
+    #[test]
+    fn service_by_uuid() {
+        let mut discovery = ServiceByUuidDiscovery::<U4>::new(CONN, Uuid::Uuid16(0x180f));
+        assert_eq!(discovery.command().uuid, Uuid::Uuid16(0x180f));
+
+        let resp = Event::FindByTypeValueResp {
+            conn_handle: CONN,
+            data: &[0x10, 0x00, 0x14, 0x00],
+        };
+        // Events of other connections are ignored
+        let other = Event::FindByTypeValueResp {
+            conn_handle: ConnectionHandle(0x0802),
+            data: &[0x20, 0x00, 0x24, 0x00],
+        };
+        assert_eq!(discovery.process(&resp), None);
+        assert_eq!(discovery.process(&other), None);
+        assert_eq!(discovery.process(&not_found()), None);
+
+        let services = discovery.process(&complete(0)).unwrap().unwrap();
+        assert_eq!(
+            services,
+            &[DiscoveredService {
+                start_handle: 0x0010,
+                end_handle: 0x0014,
+                uuid: Uuid::Uuid16(0x180f),
+            }]
+        );
+    }
+
+    #[test]
+    fn characteristic_by_uuid() {
+        let service = DiscoveredService {
+            start_handle: 0x0010,
+            end_handle: 0x0014,
+            uuid: Uuid::Uuid16(0x180f),
+        };
+        let mut discovery =
+            CharacteristicByUuidDiscovery::<U4>::new(CONN, &service, Uuid::Uuid16(0x2a19));
+
+        let command = discovery.command();
+        assert_eq!((command.start_handle, command.end_handle), (0x0010, 0x0014));
+
+        let resp = Event::DiscReadCharByUuidResp {
+            conn_handle: CONN,
+            attr_handle: 0x0011,
+            value: &[0x12, 0x12, 0x00, 0x19, 0x2a],
+        };
+        assert_eq!(discovery.process(&resp), None);
+        assert_eq!(discovery.process(&not_found()), None);
+
+        let characteristics = discovery.process(&complete(0)).unwrap().unwrap();
+        assert_eq!(
+            characteristics,
+            &[DiscoveredCharacteristic {
+                declaration_handle: 0x0011,
+                properties: 0x12,
+                value_handle: 0x0012,
+                uuid: Uuid::Uuid16(0x2a19),
+            }]
+        );
+    }
+
+    #[test]
+    fn procedure_failure() {
+        let mut discovery = CharacteristicByUuidDiscovery::<U4>::new(
+            CONN,
+            &DiscoveredService {
+                start_handle: 1,
+                end_handle: 5,
+                uuid: Uuid::Uuid16(0x1800),
+            },
+            Uuid::Uuid16(0x2a00),
+        );
+
+        let resp = Event::DiscReadCharByUuidResp {
+            conn_handle: CONN,
+            attr_handle: 0x0002,
+            value: &[0x02],
+        };
+        assert_eq!(discovery.process(&resp), None);
+        assert_eq!(
+            discovery.process(&complete(0x41)),
+            Some(Err(ClientError::Malformed))
+        );
+    }
+}
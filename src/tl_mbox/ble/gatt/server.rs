@@ -0,0 +1,446 @@
+//! GATT server: description of the application GATT database.
+//!
+//! Services are described statically with `ServiceDef`/`CharacteristicDef`/`DescriptorDef`.
+//! The same description is used to build the `aci::gatt` commands that register the database
+//! and to check that `ShciBleInitCmdParam` reserves enough attribute storage on CPU2 for it:
+//!
+//! ```ignore
+//! AttributeBudget::required(&SERVICES, param.num_of_links).check(&param)?;
+//! shci_ble_init(&mut ipcc, param);
+//! // ...
+//! let service = nb::block!(mbox.poll_ble_cmd(&mbox.send_ble_cmd(&mut ipcc, &SERVICES[0].add_service()?)?))?;
+//! ```
+
+use super::{CharacteristicHandle, ServiceHandle};
+use crate::tl_mbox::ble::aci::gatt::{
+    AddChar, AddCharDesc, AddService, ServiceType, UpdateCharValueExt, UpdateType, PROP_EXT,
+    PROP_INDICATE, PROP_NOTIFY,
+};
+use crate::tl_mbox::ble::types::{ConnectionHandle, Uuid};
+use crate::tl_mbox::shci::ShciBleInitCmdParam;
+
+/// Number of services registered by the stack itself (GAP and GATT).
+pub const BUILTIN_SERVICES: u32 = 2;
+
+/// Number of characteristic attribute records registered by the stack itself:
+/// Device Name, Appearance and Peripheral Preferred Connection Parameters in the GAP service
+/// (declaration and value each) and Service Changed in the GATT service (declaration, value and
+/// client characteristic configuration descriptor).
+pub const BUILTIN_ATTRIBUTE_RECORDS: u32 = 3 * 2 + 3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BudgetError {
+    /// A single service has more than 255 attribute records.
+    ServiceTooLarge,
+
+    /// `num_attr_serv` is smaller than the number of services.
+    TooManyServices { required: u32, available: u16 },
+
+    /// `num_attr_record` is smaller than the number of attribute records.
+    TooManyAttributeRecords { required: u32, available: u16 },
+
+    /// `attr_value_arr_size` can't hold all attribute values.
+    ValueArrayTooSmall { required: u32, available: u16 },
+}
+
+/// Storage overhead of an attribute value besides the value itself.
+fn uuid_overhead(uuid: &Uuid) -> u32 {
+    match uuid {
+        Uuid::Uuid16(_) => 5,
+        Uuid::Uuid128(_) => 19,
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DescriptorDef<'a> {
+    pub uuid: Uuid,
+    pub max_len: u8,
+    pub value: &'a [u8],
+    /// Bitmask of `aci::gatt::PERM_*` constants.
+    pub security_permissions: u8,
+    /// One of `aci::gatt::ACCESS_*` constants.
+    pub access_permissions: u8,
+    /// Bitmask of `aci::gatt::NOTIFY_*` constants.
+    pub event_mask: u8,
+    pub enc_key_size: u8,
+    pub is_variable: bool,
+}
+
+impl<'a> DescriptorDef<'a> {
+    pub fn add_char_desc(
+        &self,
+        service: ServiceHandle,
+        characteristic: CharacteristicHandle,
+    ) -> AddCharDesc<'a> {
+        AddCharDesc {
+            service,
+            characteristic,
+            uuid: self.uuid,
+            max_len: self.max_len,
+            value: self.value,
+            security_permissions: self.security_permissions,
+            access_permissions: self.access_permissions,
+            event_mask: self.event_mask,
+            enc_key_size: self.enc_key_size,
+            is_variable: self.is_variable,
+        }
+    }
+
+    fn value_storage(&self) -> u32 {
+        self.max_len as u32 + uuid_overhead(&self.uuid)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CharacteristicDef<'a> {
+    pub uuid: Uuid,
+    pub value_len: u16,
+    pub is_variable: bool,
+    /// Bitmask of `aci::gatt::PROP_*` constants.
+    pub properties: u8,
+    /// Bitmask of `aci::gatt::PERM_*` constants.
+    pub security_permissions: u8,
+    /// Bitmask of `aci::gatt::NOTIFY_*` constants.
+    pub event_mask: u8,
+    pub enc_key_size: u8,
+    pub descriptors: &'a [DescriptorDef<'a>],
+}
+
+impl<'a> CharacteristicDef<'a> {
+    pub fn add_char(&self, service: ServiceHandle) -> AddChar {
+        AddChar {
+            service,
+            uuid: self.uuid,
+            value_len: self.value_len,
+            properties: self.properties,
+            security_permissions: self.security_permissions,
+            event_mask: self.event_mask,
+            enc_key_size: self.enc_key_size,
+            is_variable: self.is_variable,
+        }
+    }
+
+    fn has_cccd(&self) -> bool {
+        self.properties & (PROP_NOTIFY | PROP_INDICATE) != 0
+    }
+
+    fn has_ext_properties(&self) -> bool {
+        self.properties & PROP_EXT != 0
+    }
+
+    /// Number of attribute records: declaration, value, automatically added descriptors
+    /// and user descriptors.
+    pub fn attribute_records(&self) -> u32 {
+        2 + self.has_cccd() as u32
+            + self.has_ext_properties() as u32
+            + self.descriptors.len() as u32
+    }
+
+    /// Bytes of the attribute value array used by this characteristic and its descriptors.
+    pub fn value_storage(&self, num_links: u8) -> u32 {
+        let mut size = self.value_len as u32 + uuid_overhead(&self.uuid);
+
+        if self.has_cccd() {
+            size += 2 * num_links as u32;
+        }
+
+        if self.has_ext_properties() {
+            size += 2;
+        }
+
+        size + self
+            .descriptors
+            .iter()
+            .map(|d| d.value_storage())
+            .sum::<u32>()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ServiceDef<'a> {
+    pub uuid: Uuid,
+    pub service_type: ServiceType,
+    pub characteristics: &'a [CharacteristicDef<'a>],
+}
+
+impl<'a> ServiceDef<'a> {
+    /// Number of attribute records including the service declaration.
+    pub fn attribute_records(&self) -> u32 {
+        1 + self
+            .characteristics
+            .iter()
+            .map(|c| c.attribute_records())
+            .sum::<u32>()
+    }
+
+    pub fn add_service(&self) -> Result<AddService, BudgetError> {
+        let records = self.attribute_records();
+        if records > u8::MAX as u32 {
+            return Err(BudgetError::ServiceTooLarge);
+        }
+
+        Ok(AddService {
+            uuid: self.uuid,
+            service_type: self.service_type,
+            max_attribute_records: records as u8,
+        })
+    }
+}
+
+/// GATT database resources reserved on CPU2 by `shci_ble_init`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AttributeBudget {
+    pub num_attr_serv: u32,
+    pub num_attr_record: u32,
+    pub attr_value_arr_size: u32,
+}
+
+impl AttributeBudget {
+    /// Computes resources needed by the stack services and the application `services`.
+    pub fn required(services: &[ServiceDef], num_links: u8) -> Self {
+        let mut budget = AttributeBudget {
+            num_attr_serv: BUILTIN_SERVICES + services.len() as u32,
+            num_attr_record: BUILTIN_ATTRIBUTE_RECORDS,
+            attr_value_arr_size: 0,
+        };
+
+        for service in services {
+            // Service declarations are not counted in `num_attr_record`
+            budget.num_attr_record += service.attribute_records() - 1;
+            budget.attr_value_arr_size += service
+                .characteristics
+                .iter()
+                .map(|c| c.value_storage(num_links))
+                .sum::<u32>();
+        }
+
+        budget
+    }
+
+    /// Checks that `param` reserves at least this much.
+    pub fn check(&self, param: &ShciBleInitCmdParam) -> Result<(), BudgetError> {
+        let num_attr_serv = param.num_attr_serv;
+        let num_attr_record = param.num_attr_record;
+        let attr_value_arr_size = param.attr_value_arr_size;
+
+        if self.num_attr_serv > num_attr_serv as u32 {
+            return Err(BudgetError::TooManyServices {
+                required: self.num_attr_serv,
+                available: num_attr_serv,
+            });
+        }
+
+        if self.num_attr_record > num_attr_record as u32 {
+            return Err(BudgetError::TooManyAttributeRecords {
+                required: self.num_attr_record,
+                available: num_attr_record,
+            });
+        }
+
+        if self.attr_value_arr_size > attr_value_arr_size as u32 {
+            return Err(BudgetError::ValueArrayTooSmall {
+                required: self.attr_value_arr_size,
+                available: attr_value_arr_size,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Updates the value and sends it as a notification to `conn_handle`.
+pub fn notify<'a>(
+    conn_handle: ConnectionHandle,
+    service: ServiceHandle,
+    characteristic: CharacteristicHandle,
+    value: &'a [u8],
+) -> UpdateCharValueExt<'a> {
+    UpdateCharValueExt {
+        conn_handle: Some(conn_handle),
+        service,
+        characteristic,
+        update_type: UpdateType::Notification,
+        total_len: value.len() as u16,
+        offset: 0,
+        value,
+    }
+}
+
+/// Updates the value and sends it as an indication to `conn_handle`.
+/// The client confirmation is reported with `aci::gatt::Event::ServerConfirmation`.
+pub fn indicate<'a>(
+    conn_handle: ConnectionHandle,
+    service: ServiceHandle,
+    characteristic: CharacteristicHandle,
+    value: &'a [u8],
+) -> UpdateCharValueExt<'a> {
+    UpdateCharValueExt {
+        update_type: UpdateType::Indication,
+        ..notify(conn_handle, service, characteristic, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::ble::aci::gatt::{
+        ACCESS_READ, NOTIFY_NONE, PERM_NONE, PROP_READ, PROP_WRITE,
+    };
+
+    const DESCRIPTORS: [DescriptorDef; 1] = [DescriptorDef {
+        uuid: Uuid::Uuid16(0x2901),
+        max_len: 10,
+        value: b"Setpoint",
+        security_permissions: PERM_NONE,
+        access_permissions: ACCESS_READ,
+        event_mask: NOTIFY_NONE,
+        enc_key_size: 16,
+        is_variable: true,
+    }];
+
+    const CHARACTERISTICS: [CharacteristicDef; 2] = [
+        // Declaration, value and CCCD
+        CharacteristicDef {
+            uuid: Uuid::Uuid16(0x2a6e),
+            value_len: 20,
+            is_variable: true,
+            properties: PROP_READ | PROP_NOTIFY,
+            security_permissions: PERM_NONE,
+            event_mask: NOTIFY_NONE,
+            enc_key_size: 16,
+            descriptors: &[],
+        },
+        // Declaration, value, extended properties and user description
+        CharacteristicDef {
+            uuid: Uuid::Uuid128([0x42; 16]),
+            value_len: 4,
+            is_variable: false,
+            properties: PROP_WRITE | PROP_EXT,
+            security_permissions: PERM_NONE,
+            event_mask: NOTIFY_NONE,
+            enc_key_size: 16,
+            descriptors: &DESCRIPTORS,
+        },
+    ];
+
+    const SERVICES: [ServiceDef; 2] = [
+        ServiceDef {
+            uuid: Uuid::Uuid16(0x181a),
+            service_type: ServiceType::Primary,
+            characteristics: &CHARACTERISTICS,
+        },
+        ServiceDef {
+            uuid: Uuid::Uuid16(0x180f),
+            service_type: ServiceType::Primary,
+            characteristics: &[],
+        },
+    ];
+
+    fn param(
+        num_attr_serv: u16,
+        num_attr_record: u16,
+        attr_value_arr_size: u16,
+    ) -> ShciBleInitCmdParam {
+        ShciBleInitCmdParam {
+            p_ble_buffer_address: 0,
+            ble_buffer_size: 0,
+            num_attr_record,
+            num_attr_serv,
+            attr_value_arr_size,
+            num_of_links: 2,
+            extended_packet_length_enable: 1,
+            pr_write_list_size: 0x3a,
+            mb_lock_count: 0x79,
+            att_mtu: 156,
+            slave_sca: 500,
+            master_sca: 0,
+            ls_source: 1,
+            max_conn_event_length: 0xffff_ffff,
+            hs_startup_time: 0x148,
+            viterbi_enable: 1,
+            ll_only: 0,
+            hw_version: 0,
+        }
+    }
+
+    #[test]
+    fn attribute_records() {
+        assert_eq!(CHARACTERISTICS[0].attribute_records(), 3);
+        assert_eq!(CHARACTERISTICS[1].attribute_records(), 4);
+        assert_eq!(SERVICES[0].attribute_records(), 8);
+        assert_eq!(SERVICES[1].attribute_records(), 1);
+        assert_eq!(
+            SERVICES[0]
+                .add_service()
+                .map(|cmd| cmd.max_attribute_records),
+            Ok(8)
+        );
+    }
+
+    #[test]
+    fn required_budget() {
+        // Value, UUID and a CCCD value per link
+        assert_eq!(CHARACTERISTICS[0].value_storage(2), 20 + 5 + 2 * 2);
+        // Value, UUID, extended properties and the descriptor with its UUID
+        assert_eq!(CHARACTERISTICS[1].value_storage(2), 4 + 19 + 2 + 10 + 5);
+
+        let budget = AttributeBudget::required(&SERVICES, 2);
+        assert_eq!(
+            budget,
+            AttributeBudget {
+                num_attr_serv: BUILTIN_SERVICES + 2,
+                num_attr_record: BUILTIN_ATTRIBUTE_RECORDS + 7,
+                attr_value_arr_size: 29 + 40,
+            }
+        );
+
+        // Only the stack services
+        assert_eq!(
+            AttributeBudget::required(&[], 8),
+            AttributeBudget {
+                num_attr_serv: 2,
+                num_attr_record: 9,
+                attr_value_arr_size: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn check_budget() {
+        let budget = AttributeBudget::required(&SERVICES, 2);
+
+        assert_eq!(budget.check(&param(4, 16, 69)), Ok(()));
+        assert_eq!(
+            budget.check(&param(3, 16, 69)),
+            Err(BudgetError::TooManyServices {
+                required: 4,
+                available: 3,
+            })
+        );
+        assert_eq!(
+            budget.check(&param(4, 15, 69)),
+            Err(BudgetError::TooManyAttributeRecords {
+                required: 16,
+                available: 15,
+            })
+        );
+        assert_eq!(
+            budget.check(&param(4, 16, 68)),
+            Err(BudgetError::ValueArrayTooSmall {
+                required: 69,
+                available: 68,
+            })
+        );
+    }
+
+    #[test]
+    fn characteristic_handles() {
+        let handle = CharacteristicHandle(0x000c);
+        assert_eq!(handle.value_handle(), Some(0x000d));
+        assert_eq!(handle.cccd_handle(), Some(0x000e));
+
+        assert_eq!(CharacteristicHandle(0xfffe).value_handle(), Some(0xffff));
+        assert_eq!(CharacteristicHandle(0xfffe).cccd_handle(), None);
+        assert_eq!(CharacteristicHandle(0xffff).value_handle(), None);
+    }
+}
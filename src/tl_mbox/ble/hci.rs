@@ -0,0 +1,949 @@
+//! Standard HCI commands.
+
+use super::command::{
+    opcode, Command, Error, Response, OGF_CONTROLLER, OGF_INFO_PARAM, OGF_LE_CONTROLLER,
+    OGF_LINK_CONTROL, OGF_STATUS_PARAM,
+};
+use super::types::{AddressType, BdAddr, ConnectionHandle, Phy};
+use crate::tl_mbox::bytes::{Reader, Writer};
+use crate::tl_mbox::shci::ShciBleInitCmdParam;
+use core::convert::TryFrom;
+
+/// Maximum length of advertising and scan response data.
+pub const MAX_ADV_DATA_LEN: usize = 31;
+
+impl Response for BdAddr {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        Reader::new(params).array6().map(BdAddr)
+    }
+}
+
+impl Response for u8 {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        Reader::new(params).u8()
+    }
+}
+
+impl Response for ConnectionHandle {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        Reader::new(params).u16().map(ConnectionHandle::from_raw)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Disconnect {
+    pub handle: ConnectionHandle,
+    pub reason: u8,
+}
+
+impl Command for Disconnect {
+    const OPCODE: u16 = opcode(OGF_LINK_CONTROL, 0x0006);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        w.u8(self.reason)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SetEventMask {
+    pub mask: u64,
+}
+
+impl Command for SetEventMask {
+    const OPCODE: u16 = opcode(OGF_CONTROLLER, 0x0001);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u64(self.mask)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Reset;
+
+impl Command for Reset {
+    const OPCODE: u16 = opcode(OGF_CONTROLLER, 0x0003);
+    type Response = ();
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Reads public device address. Responds with `BdAddr`.
+#[derive(Debug, Copy, Clone)]
+pub struct ReadBdAddr;
+
+impl Command for ReadBdAddr {
+    const OPCODE: u16 = opcode(OGF_INFO_PARAM, 0x0009);
+    type Response = BdAddr;
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ReadRssi {
+    pub handle: ConnectionHandle,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Rssi {
+    pub handle: ConnectionHandle,
+
+    /// RSSI in dBm.
+    pub rssi: i8,
+}
+
+impl Response for Rssi {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(params);
+
+        Some(Rssi {
+            handle: ConnectionHandle::from_raw(r.u16()?),
+            rssi: r.i8()?,
+        })
+    }
+}
+
+impl Command for ReadRssi {
+    const OPCODE: u16 = opcode(OGF_STATUS_PARAM, 0x0005);
+    type Response = Rssi;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetEventMask {
+    pub mask: u64,
+}
+
+impl Command for LeSetEventMask {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0001);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u64(self.mask)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AdvertisingType {
+    /// Connectable and scannable undirected advertising (ADV_IND).
+    ConnectableUndirected = 0x00,
+    /// Connectable high duty cycle directed advertising (ADV_DIRECT_IND).
+    ConnectableDirectedHighDutyCycle = 0x01,
+    /// Scannable undirected advertising (ADV_SCAN_IND).
+    ScannableUndirected = 0x02,
+    /// Non connectable undirected advertising (ADV_NONCONN_IND).
+    NonConnectableUndirected = 0x03,
+    /// Connectable low duty cycle directed advertising (ADV_DIRECT_IND).
+    ConnectableDirectedLowDutyCycle = 0x04,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetAdvertisingParameters {
+    /// Minimum advertising interval, in units of 0.625 ms.
+    pub interval_min: u16,
+    /// Maximum advertising interval, in units of 0.625 ms.
+    pub interval_max: u16,
+    pub advertising_type: AdvertisingType,
+    pub own_address_type: AddressType,
+    pub peer_address_type: AddressType,
+    pub peer_address: BdAddr,
+    /// Bit 0: channel 37, bit 1: channel 38, bit 2: channel 39.
+    pub channel_map: u8,
+    pub filter_policy: u8,
+}
+
+impl Command for LeSetAdvertisingParameters {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0006);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.interval_min)?;
+        w.u16(self.interval_max)?;
+        w.u8(self.advertising_type as u8)?;
+        w.u8(self.own_address_type as u8)?;
+        w.u8(self.peer_address_type as u8)?;
+        w.bytes(&self.peer_address.0)?;
+        w.u8(self.channel_map)?;
+        w.u8(self.filter_policy)?;
+        Ok(())
+    }
+}
+
+/// Writes length-prefixed advertising data padded to the fixed 31-byte field.
+fn write_adv_data(w: &mut Writer, data: &[u8]) -> Result<(), Error> {
+    if data.len() > MAX_ADV_DATA_LEN {
+        return Err(Error::TooLong);
+    }
+
+    w.u8(data.len() as u8)?;
+    w.bytes(data)?;
+    w.bytes(&[0; MAX_ADV_DATA_LEN][data.len()..])?;
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetAdvertisingData<'a> {
+    /// Up to 31 bytes of AD structures.
+    pub data: &'a [u8],
+}
+
+impl<'a> Command for LeSetAdvertisingData<'a> {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0008);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        write_adv_data(w, self.data)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetScanResponseData<'a> {
+    /// Up to 31 bytes of AD structures.
+    pub data: &'a [u8],
+}
+
+impl<'a> Command for LeSetScanResponseData<'a> {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0009);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        write_adv_data(w, self.data)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetAdvertiseEnable {
+    pub enable: bool,
+}
+
+impl Command for LeSetAdvertiseEnable {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x000a);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.enable as u8)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ScanType {
+    Passive = 0x00,
+    Active = 0x01,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetScanParameters {
+    pub scan_type: ScanType,
+    /// Scan interval, in units of 0.625 ms.
+    pub interval: u16,
+    /// Scan window, in units of 0.625 ms. Shall not exceed `interval`.
+    pub window: u16,
+    pub own_address_type: AddressType,
+    pub filter_policy: u8,
+}
+
+/// Checks scan interval and window, in units of 0.625 ms.
+fn check_scan_window(interval: u16, window: u16) -> Result<(), Error> {
+    if !(0x0004..=0x4000).contains(&interval) || !(0x0004..=interval).contains(&window) {
+        return Err(Error::InvalidParameter);
+    }
+
+    Ok(())
+}
+
+impl Command for LeSetScanParameters {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x000b);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        check_scan_window(self.interval, self.window)?;
+
+        w.u8(self.scan_type as u8)?;
+        w.u16(self.interval)?;
+        w.u16(self.window)?;
+        w.u8(self.own_address_type as u8)?;
+        w.u8(self.filter_policy)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetScanEnable {
+    pub enable: bool,
+    pub filter_duplicates: bool,
+}
+
+impl Command for LeSetScanEnable {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x000c);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.enable as u8)?;
+        w.u8(self.filter_duplicates as u8)?;
+        Ok(())
+    }
+}
+
+/// Connection parameters used when creating or updating a connection.
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectionParameters {
+    /// Minimum connection interval, in units of 1.25 ms.
+    pub interval_min: u16,
+    /// Maximum connection interval, in units of 1.25 ms.
+    pub interval_max: u16,
+    pub latency: u16,
+    /// Supervision timeout, in units of 10 ms.
+    pub supervision_timeout: u16,
+    /// Minimum connection event length, in units of 0.625 ms.
+    pub min_ce_length: u16,
+    /// Maximum connection event length, in units of 0.625 ms.
+    pub max_ce_length: u16,
+}
+
+impl ConnectionParameters {
+    /// Checks the ranges of the parameters and that the supervision timeout is longer than
+    /// `2 * interval_max * (1 + latency)`.
+    pub fn validate(&self) -> Result<(), Error> {
+        let valid = (0x0006..=0x0c80).contains(&self.interval_min)
+            && (self.interval_min..=0x0c80).contains(&self.interval_max)
+            && self.latency <= 0x01f3
+            && (0x000a..=0x0c80).contains(&self.supervision_timeout)
+            && self.min_ce_length <= self.max_ce_length
+            // Timeout is in units of 10 ms and the interval is in units of 1.25 ms
+            && self.supervision_timeout as u32 * 4
+                > (1 + self.latency as u32) * self.interval_max as u32;
+
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidParameter)
+        }
+    }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> Result<(), Error> {
+        self.validate()?;
+
+        w.u16(self.interval_min)?;
+        w.u16(self.interval_max)?;
+        w.u16(self.latency)?;
+        w.u16(self.supervision_timeout)?;
+        w.u16(self.min_ce_length)?;
+        w.u16(self.max_ce_length)?;
+        Ok(())
+    }
+}
+
+/// Responds with Command Status, followed by LE Connection Complete event.
+#[derive(Debug, Copy, Clone)]
+pub struct LeCreateConnection {
+    /// Scan interval, in units of 0.625 ms.
+    pub scan_interval: u16,
+    /// Scan window, in units of 0.625 ms.
+    pub scan_window: u16,
+    /// Use white list instead of `peer_address`.
+    pub use_white_list: bool,
+    pub peer_address_type: AddressType,
+    pub peer_address: BdAddr,
+    pub own_address_type: AddressType,
+    pub conn_params: ConnectionParameters,
+}
+
+impl Command for LeCreateConnection {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x000d);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        check_scan_window(self.scan_interval, self.scan_window)?;
+
+        w.u16(self.scan_interval)?;
+        w.u16(self.scan_window)?;
+        w.u8(self.use_white_list as u8)?;
+        w.u8(self.peer_address_type as u8)?;
+        w.bytes(&self.peer_address.0)?;
+        w.u8(self.own_address_type as u8)?;
+        self.conn_params.write(w)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeCreateConnectionCancel;
+
+impl Command for LeCreateConnectionCancel {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x000e);
+    type Response = ();
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Responds with the number of white list entries.
+#[derive(Debug, Copy, Clone)]
+pub struct LeReadWhiteListSize;
+
+impl Command for LeReadWhiteListSize {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x000f);
+    type Response = u8;
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeClearWhiteList;
+
+impl Command for LeClearWhiteList {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0010);
+    type Response = ();
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Writes white list entry. Only public and random addresses can be put on the white list.
+fn write_white_list_entry(
+    w: &mut Writer,
+    address_type: AddressType,
+    address: &BdAddr,
+) -> Result<(), Error> {
+    match address_type {
+        AddressType::Public | AddressType::Random => {}
+
+        _ => return Err(Error::InvalidParameter),
+    }
+
+    w.u8(address_type as u8)?;
+    w.bytes(&address.0)?;
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeAddDeviceToWhiteList {
+    pub address_type: AddressType,
+    pub address: BdAddr,
+}
+
+impl Command for LeAddDeviceToWhiteList {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0011);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        write_white_list_entry(w, self.address_type, &self.address)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LeRemoveDeviceFromWhiteList {
+    pub address_type: AddressType,
+    pub address: BdAddr,
+}
+
+impl Command for LeRemoveDeviceFromWhiteList {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0012);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        write_white_list_entry(w, self.address_type, &self.address)
+    }
+}
+
+/// Sent by the master to change connection parameters. Responds with Command Status, followed by
+/// LE Connection Update Complete event.
+#[derive(Debug, Copy, Clone)]
+pub struct LeConnectionUpdate {
+    pub handle: ConnectionHandle,
+    pub conn_params: ConnectionParameters,
+}
+
+impl Command for LeConnectionUpdate {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0013);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        self.conn_params.write(w)
+    }
+}
+
+/// Accepts LE Remote Connection Parameter Request event with `conn_params`, usually in the
+/// requested range. Responds with the handle of the connection.
+#[derive(Debug, Copy, Clone)]
+pub struct LeRemoteConnectionParameterRequestReply {
+    pub handle: ConnectionHandle,
+    pub conn_params: ConnectionParameters,
+}
+
+impl Command for LeRemoteConnectionParameterRequestReply {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0020);
+    type Response = ConnectionHandle;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        self.conn_params.write(w)
+    }
+}
+
+/// Rejects LE Remote Connection Parameter Request event. Responds with the handle of the
+/// connection.
+#[derive(Debug, Copy, Clone)]
+pub struct LeRemoteConnectionParameterRequestNegativeReply {
+    pub handle: ConnectionHandle,
+    /// Usually 0x3b, unacceptable connection parameters.
+    pub reason: u8,
+}
+
+impl Command for LeRemoteConnectionParameterRequestNegativeReply {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0021);
+    type Response = ConnectionHandle;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        w.u8(self.reason)?;
+        Ok(())
+    }
+}
+
+/// Minimum (and default) number of payload octets of a data packet.
+pub const MIN_DATA_OCTETS: u16 = 27;
+
+/// Maximum number of payload octets of a data packet with data length extension.
+pub const MAX_DATA_OCTETS: u16 = 251;
+
+/// L2CAP header size in a data packet.
+const L2CAP_HEADER_SIZE: u16 = 4;
+
+/// Maximum number of payload octets and transmission time of data packets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DataLength {
+    /// 27..=251.
+    pub octets: u16,
+    /// Transmission time in microseconds, 328..=17040.
+    pub time: u16,
+}
+
+impl DataLength {
+    /// Data length without data length extension.
+    pub const DEFAULT: DataLength = DataLength {
+        octets: MIN_DATA_OCTETS,
+        time: 328,
+    };
+
+    /// Data length for `octets` payload octets sent on LE 1M PHY, clamped to 27..=251.
+    pub fn with_octets(octets: u16) -> Self {
+        let octets = octets.clamp(MIN_DATA_OCTETS, MAX_DATA_OCTETS);
+
+        DataLength {
+            octets,
+            // Preamble, access address, header and MIC are 14 bytes, at 8 us per byte
+            time: (octets + 14) * 8,
+        }
+    }
+
+    /// Data length that carries a whole ATT packet of `att_mtu` bytes in one data packet.
+    ///
+    /// Without `extended_packet_length_enable` in `ShciBleInitCmdParam`, CPU2 doesn't support
+    /// data length extension and `DEFAULT` should be used instead, see `for_init_params`.
+    pub fn for_att_mtu(att_mtu: u16) -> Self {
+        Self::with_octets(att_mtu.saturating_add(L2CAP_HEADER_SIZE))
+    }
+
+    /// Data length suited to the `att_mtu` and `extended_packet_length_enable` given to
+    /// `shci_ble_init`.
+    pub fn for_init_params(param: &ShciBleInitCmdParam) -> Self {
+        if param.extended_packet_length_enable == 0 {
+            Self::DEFAULT
+        } else {
+            Self::for_att_mtu(param.att_mtu)
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if (MIN_DATA_OCTETS..=MAX_DATA_OCTETS).contains(&self.octets)
+            && (328..=17040).contains(&self.time)
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidParameter)
+        }
+    }
+}
+
+impl Response for DataLength {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(params);
+
+        Some(DataLength {
+            octets: r.u16()?,
+            time: r.u16()?,
+        })
+    }
+}
+
+/// Sets the preferred data length of a connection. Responds with the handle of the connection.
+///
+/// The negotiated data length is reported with LE Data Length Change event.
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetDataLength {
+    pub handle: ConnectionHandle,
+    /// Preferred maximum number of payload octets, 27..=251.
+    pub tx_octets: u16,
+    /// Preferred maximum transmission time in microseconds, 328..=17040.
+    pub tx_time: u16,
+}
+
+impl LeSetDataLength {
+    pub fn new(handle: ConnectionHandle, data_length: DataLength) -> Self {
+        LeSetDataLength {
+            handle,
+            tx_octets: data_length.octets,
+            tx_time: data_length.time,
+        }
+    }
+}
+
+impl Command for LeSetDataLength {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0022);
+    type Response = ConnectionHandle;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        DataLength {
+            octets: self.tx_octets,
+            time: self.tx_time,
+        }
+        .validate()?;
+
+        w.u16(self.handle.0)?;
+        w.u16(self.tx_octets)?;
+        w.u16(self.tx_time)?;
+        Ok(())
+    }
+}
+
+/// Responds with `DataLength` used for new connections.
+#[derive(Debug, Copy, Clone)]
+pub struct LeReadSuggestedDefaultDataLength;
+
+impl Command for LeReadSuggestedDefaultDataLength {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0023);
+    type Response = DataLength;
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Sets the data length used for new connections.
+#[derive(Debug, Copy, Clone)]
+pub struct LeWriteSuggestedDefaultDataLength(pub DataLength);
+
+impl Command for LeWriteSuggestedDefaultDataLength {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0024);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        self.0.validate()?;
+
+        w.u16(self.0.octets)?;
+        w.u16(self.0.time)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MaximumDataLength {
+    pub tx: DataLength,
+    pub rx: DataLength,
+}
+
+impl Response for MaximumDataLength {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(params);
+
+        Some(MaximumDataLength {
+            tx: DataLength {
+                octets: r.u16()?,
+                time: r.u16()?,
+            },
+            rx: DataLength {
+                octets: r.u16()?,
+                time: r.u16()?,
+            },
+        })
+    }
+}
+
+/// Responds with `MaximumDataLength` supported by the controller.
+#[derive(Debug, Copy, Clone)]
+pub struct LeReadMaximumDataLength;
+
+impl Command for LeReadMaximumDataLength {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x002f);
+    type Response = MaximumDataLength;
+
+    fn write_params(&self, _w: &mut Writer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub const PHY_MASK_LE_1M: u8 = 0x01;
+pub const PHY_MASK_LE_2M: u8 = 0x02;
+pub const PHY_MASK_LE_CODED: u8 = 0x04;
+
+/// Writes `ALL_PHYS`, `TX_PHYS` and `RX_PHYS` parameters. `None` means no preference.
+fn write_phys(w: &mut Writer, tx_phys: Option<u8>, rx_phys: Option<u8>) -> Result<(), Error> {
+    let valid = |phys: Option<u8>| phys.map_or(true, |phys| phys != 0 && phys & !0x07 == 0);
+    if !valid(tx_phys) || !valid(rx_phys) {
+        return Err(Error::InvalidParameter);
+    }
+
+    w.u8(tx_phys.is_none() as u8 | (rx_phys.is_none() as u8) << 1)?;
+    w.u8(tx_phys.unwrap_or(0))?;
+    w.u8(rx_phys.unwrap_or(0))?;
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LinkPhy {
+    pub handle: ConnectionHandle,
+    pub tx_phy: Phy,
+    pub rx_phy: Phy,
+}
+
+impl Response for LinkPhy {
+    fn from_return_params(params: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(params);
+
+        Some(LinkPhy {
+            handle: ConnectionHandle::from_raw(r.u16()?),
+            tx_phy: Phy::try_from(r.u8()?).ok()?,
+            rx_phy: Phy::try_from(r.u8()?).ok()?,
+        })
+    }
+}
+
+/// Responds with `LinkPhy` of the connection.
+#[derive(Debug, Copy, Clone)]
+pub struct LeReadPhy {
+    pub handle: ConnectionHandle,
+}
+
+impl Command for LeReadPhy {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0030);
+    type Response = LinkPhy;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        Ok(())
+    }
+}
+
+/// Sets PHYs preferred for new connections, as combinations of `PHY_MASK_*`.
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetDefaultPhy {
+    /// `None` if there's no preference.
+    pub tx_phys: Option<u8>,
+    /// `None` if there's no preference.
+    pub rx_phys: Option<u8>,
+}
+
+impl Command for LeSetDefaultPhy {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0031);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        write_phys(w, self.tx_phys, self.rx_phys)
+    }
+}
+
+/// Coding preferred when transmitting on LE Coded PHY.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u16)]
+pub enum CodedPhyOption {
+    NoPreference = 0x0000,
+    S2 = 0x0001,
+    S8 = 0x0002,
+}
+
+/// Sets PHYs preferred for a connection, as combinations of `PHY_MASK_*`. Responds with Command
+/// Status, followed by LE PHY Update Complete event.
+#[derive(Debug, Copy, Clone)]
+pub struct LeSetPhy {
+    pub handle: ConnectionHandle,
+    /// `None` if there's no preference.
+    pub tx_phys: Option<u8>,
+    /// `None` if there's no preference.
+    pub rx_phys: Option<u8>,
+    pub coded_phy_option: CodedPhyOption,
+}
+
+impl Command for LeSetPhy {
+    const OPCODE: u16 = opcode(OGF_LE_CONTROLLER, 0x0032);
+    type Response = ();
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.handle.0)?;
+        write_phys(w, self.tx_phys, self.rx_phys)?;
+        w.u16(self.coded_phy_option as u16)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode<C: Command>(cmd: &C, buf: &mut [u8; 32]) -> Result<usize, Error> {
+        let mut w = Writer::new(buf);
+        cmd.write_params(&mut w)?;
+        Ok(w.len())
+    }
+
+    fn init_params(att_mtu: u16, extended_packet_length_enable: u8) -> ShciBleInitCmdParam {
+        ShciBleInitCmdParam {
+            p_ble_buffer_address: 0,
+            ble_buffer_size: 0,
+            num_attr_record: 68,
+            num_attr_serv: 8,
+            attr_value_arr_size: 1344,
+            num_of_links: 8,
+            extended_packet_length_enable,
+            pr_write_list_size: 0x3a,
+            mb_lock_count: 0x79,
+            att_mtu,
+            slave_sca: 500,
+            master_sca: 0,
+            ls_source: 1,
+            max_conn_event_length: 0xffff_ffff,
+            hs_startup_time: 0x148,
+            viterbi_enable: 1,
+            ll_only: 0,
+            hw_version: 0,
+        }
+    }
+
+    #[test]
+    fn set_default_phy() {
+        let mut buf = [0; 32];
+
+        let cmd = LeSetDefaultPhy {
+            tx_phys: Some(PHY_MASK_LE_1M | PHY_MASK_LE_2M),
+            rx_phys: Some(PHY_MASK_LE_2M),
+        };
+        let len = encode(&cmd, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x00, 0x03, 0x02]);
+
+        // ALL_PHYS bit 0: no TX preference, bit 1: no RX preference
+        let cmd = LeSetDefaultPhy {
+            tx_phys: None,
+            rx_phys: Some(PHY_MASK_LE_CODED),
+        };
+        let len = encode(&cmd, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x01, 0x00, 0x04]);
+
+        let cmd = LeSetDefaultPhy {
+            tx_phys: None,
+            rx_phys: None,
+        };
+        let len = encode(&cmd, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x03, 0x00, 0x00]);
+
+        for phys in &[0x00, 0x08] {
+            let cmd = LeSetDefaultPhy {
+                tx_phys: Some(*phys),
+                rx_phys: None,
+            };
+            assert_eq!(encode(&cmd, &mut buf), Err(Error::InvalidParameter));
+        }
+    }
+
+    #[test]
+    fn set_phy() {
+        let mut buf = [0; 32];
+
+        let cmd = LeSetPhy {
+            handle: ConnectionHandle(0x0801),
+            tx_phys: Some(PHY_MASK_LE_CODED),
+            rx_phys: None,
+            coded_phy_option: CodedPhyOption::S8,
+        };
+        let len = encode(&cmd, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x01, 0x08, 0x02, 0x04, 0x00, 0x02, 0x00]);
+    }
+
+    #[test]
+    fn data_length() {
+        assert_eq!(DataLength::with_octets(27).time, 328);
+        assert_eq!(DataLength::with_octets(251).time, 2120);
+        assert_eq!(DataLength::with_octets(0), DataLength::DEFAULT);
+        assert_eq!(DataLength::with_octets(300), DataLength::with_octets(251));
+
+        // ATT MTU plus L2CAP header
+        assert_eq!(
+            DataLength::for_att_mtu(23),
+            DataLength {
+                octets: 27,
+                time: 328
+            }
+        );
+        assert_eq!(
+            DataLength::for_att_mtu(247),
+            DataLength {
+                octets: 251,
+                time: 2120
+            }
+        );
+        assert_eq!(DataLength::for_att_mtu(u16::MAX).octets, 251);
+
+        assert_eq!(
+            DataLength::for_init_params(&init_params(156, 0)),
+            DataLength::DEFAULT
+        );
+        assert_eq!(
+            DataLength::for_init_params(&init_params(156, 1)),
+            DataLength {
+                octets: 160,
+                time: 1392
+            }
+        );
+        assert_eq!(
+            DataLength::for_init_params(&init_params(251, 1)),
+            DataLength {
+                octets: 251,
+                time: 2120
+            }
+        );
+    }
+
+    #[test]
+    fn set_data_length() {
+        let mut buf = [0; 32];
+
+        let cmd = LeSetDataLength::new(ConnectionHandle(0x0001), DataLength::with_octets(251));
+        let len = encode(&cmd, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x01, 0x00, 0xfb, 0x00, 0x48, 0x08]);
+
+        let cmd = LeWriteSuggestedDefaultDataLength(DataLength {
+            octets: 26,
+            time: 328,
+        });
+        assert_eq!(encode(&cmd, &mut buf), Err(Error::InvalidParameter));
+    }
+}
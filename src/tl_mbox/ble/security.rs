@@ -0,0 +1,603 @@
+//! Security manager: pairing state of each connection on top of ACI GAP security commands.
+//!
+//! Pairing is configured once with `aci::gap::SetIoCapability` and
+//! `aci::gap::SetAuthenticationRequirement`. `SecurityManager` follows connections and pairing
+//! events fed to `process()`, tells the application what it has to do next and builds the
+//! commands that answer the stack. Pairing is only considered successful once the security
+//! level of the connection, read with `aci::gap::GetSecurityLevel`, satisfies the required one.
+//! A bonded device encrypts the connection with its stored keys without pairing again, which is
+//! reported with `SecurityEvent::Encrypted` and verified the same way:
+//!
+//! ```ignore
+//! let mut security = SecurityManager::<U4>::new(SecurityLevel::AuthenticatedSecureConnections);
+//! // ... for every received event `evt`:
+//! match security.process(&evt) {
+//!     Some(SecurityEvent::NumericComparison { handle, value }) => {
+//!         let cmd = security.confirm_numeric_comparison(handle, user_confirms(value))?;
+//!         nb::block!(mbox.poll_ble_cmd(&mbox.send_ble_cmd(&mut ipcc, &cmd)?))?;
+//!     }
+//!     Some(SecurityEvent::Paired(handle)) | Some(SecurityEvent::Encrypted(handle)) => {
+//!         let cmd = GetSecurityLevel { handle };
+//!         let level = nb::block!(mbox.poll_ble_cmd(&mbox.send_ble_cmd(&mut ipcc, &cmd)?))?;
+//!         if security.verify(handle, level).is_err() {
+//!             // Disconnect with `aci::gap::Terminate`
+//!         }
+//!     }
+//!     _ => {}
+//! }
+//! ```
+
+use core::convert::TryFrom;
+
+use heapless::{ArrayLength, Vec};
+
+use crate::tl_mbox::ble::aci::gap::{
+    self, NumericComparisonValueConfirm, PassKeyResp, SecurityLevel, SendPairingReq,
+    SlaveSecurityReq,
+};
+use crate::tl_mbox::ble::aci::AciEvent;
+use crate::tl_mbox::ble::event::{Event, LeMetaEvent};
+use crate::tl_mbox::ble::types::{ConnectionHandle, Status};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Connection is not tracked: it's closed or didn't fit into the manager.
+    UnknownConnection,
+
+    /// The operation is not expected in the current pairing state. Contains the state.
+    InvalidState(PairingState),
+
+    /// Passkey is longer than six decimal digits.
+    InvalidPasskey,
+
+    /// Connection is encrypted with a weaker security level than required.
+    InsufficientSecurity {
+        required: SecurityLevel,
+        actual: SecurityLevel,
+    },
+}
+
+/// Status of `aci::gap::Event::PairingComplete`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PairingStatus {
+    Success,
+    Timeout,
+    Failed,
+    /// Encryption failed because the LTK is missing on the peer.
+    EncryptionFailed,
+    Other(u8),
+}
+
+impl From<u8> for PairingStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => PairingStatus::Success,
+            0x01 => PairingStatus::Timeout,
+            0x02 => PairingStatus::Failed,
+            0x03 => PairingStatus::EncryptionFailed,
+
+            other => PairingStatus::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PairingState {
+    /// Connection is not paired.
+    Unpaired,
+    /// Pairing is in progress.
+    Pairing,
+    /// Waiting for the passkey to be provided with `passkey_response`.
+    PasskeyRequested,
+    /// Waiting for the user to compare the value with `confirm_numeric_comparison`.
+    NumericComparison(u32),
+    /// Pairing has completed or the connection has been encrypted, waiting for the security
+    /// level to be checked with `verify`.
+    Verifying,
+    /// Connection is encrypted with at least the required security level.
+    Secured(SecurityLevel),
+    /// Pairing failed or the security level is below the required one.
+    Failed,
+}
+
+/// What the application has to do after an event, returned by `SecurityManager::process`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SecurityEvent {
+    /// Display a passkey or ask the user for the one displayed by the peer, then answer with
+    /// `passkey_response`.
+    PasskeyRequested(ConnectionHandle),
+
+    /// Display `value` and answer with `confirm_numeric_comparison` once the user has compared it
+    /// with the value displayed by the peer.
+    NumericComparison {
+        handle: ConnectionHandle,
+        value: u32,
+    },
+
+    /// Peer accesses an attribute that requires authorization,
+    /// answer with `aci::gap::AuthorizationResp`.
+    AuthorizationRequested(ConnectionHandle),
+
+    /// Pairing has completed. Read the security level with `aci::gap::GetSecurityLevel` and
+    /// pass it to `verify`.
+    Paired(ConnectionHandle),
+
+    PairingFailed {
+        handle: ConnectionHandle,
+        status: PairingStatus,
+        /// SMP pairing failure reason, valid if `status` is `Failed`.
+        reason: u8,
+    },
+
+    /// Connection has been encrypted, e.g. with the keys of a bonded device. Read the security
+    /// level with `aci::gap::GetSecurityLevel` and pass it to `verify`.
+    ///
+    /// During pairing this is followed by `Paired`.
+    Encrypted(ConnectionHandle),
+
+    /// Encryption couldn't be started, e.g. the peer has lost its bond (PIN or Key Missing).
+    EncryptionFailed {
+        handle: ConnectionHandle,
+        status: Status,
+    },
+
+    /// Previously bonded device tries to pair again, but its bond has been lost. Pairing is
+    /// allowed only after `aci::gap::AllowRebond`.
+    BondLost,
+}
+
+/// Pairing state of a single connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConnectionSecurity {
+    pub handle: ConnectionHandle,
+    pub state: PairingState,
+}
+
+/// Tracks pairing of up to `N` connections.
+pub struct SecurityManager<N: ArrayLength<ConnectionSecurity>> {
+    required: SecurityLevel,
+    connections: Vec<ConnectionSecurity, N>,
+}
+
+impl<N: ArrayLength<ConnectionSecurity>> SecurityManager<N> {
+    /// Creates a manager that accepts pairing only with at least `required` security level.
+    pub fn new(required: SecurityLevel) -> Self {
+        SecurityManager {
+            required,
+            connections: Vec::new(),
+        }
+    }
+
+    pub fn required(&self) -> SecurityLevel {
+        self.required
+    }
+
+    pub fn connections(&self) -> &[ConnectionSecurity] {
+        &self.connections
+    }
+
+    /// Returns pairing state of the connection, if it's tracked.
+    pub fn state(&self, handle: ConnectionHandle) -> Option<PairingState> {
+        self.connection(handle).map(|conn| conn.state)
+    }
+
+    /// Returns `true` if the connection is encrypted with at least the required security level.
+    pub fn is_secured(&self, handle: ConnectionHandle) -> bool {
+        matches!(self.state(handle), Some(PairingState::Secured(_)))
+    }
+
+    /// Updates the state with a received event.
+    ///
+    /// Connections are tracked from LE Connection Complete to Disconnection Complete events.
+    /// Connections that don't fit into the manager are ignored.
+    pub fn process(&mut self, event: &Event) -> Option<SecurityEvent> {
+        match event {
+            Event::LeMeta(LeMetaEvent::ConnectionComplete(evt)) if evt.status.is_success() => {
+                self.remove(evt.handle);
+                self.connections
+                    .push(ConnectionSecurity {
+                        handle: evt.handle,
+                        state: PairingState::Unpaired,
+                    })
+                    .ok();
+
+                None
+            }
+
+            Event::DisconnectionComplete(evt) if evt.status.is_success() => {
+                self.remove(evt.handle);
+
+                None
+            }
+
+            Event::EncryptionChange(evt) => {
+                let conn = self.connection_mut(evt.handle)?;
+
+                if !evt.status.is_success() {
+                    conn.state = PairingState::Failed;
+
+                    return Some(SecurityEvent::EncryptionFailed {
+                        handle: evt.handle,
+                        status: evt.status,
+                    });
+                }
+
+                if !evt.enabled {
+                    conn.state = PairingState::Unpaired;
+
+                    return None;
+                }
+
+                match conn.state {
+                    // Waiting for the user, encryption can't be the outcome of this pairing
+                    PairingState::PasskeyRequested | PairingState::NumericComparison(_) => None,
+
+                    // Encryption restarted with the same keys
+                    PairingState::Secured(_) => None,
+
+                    _ => {
+                        conn.state = PairingState::Verifying;
+
+                        Some(SecurityEvent::Encrypted(evt.handle))
+                    }
+                }
+            }
+
+            Event::Vendor(evt) => match AciEvent::try_from(*evt) {
+                Ok(AciEvent::Gap(evt)) => self.process_gap(&evt),
+
+                _ => None,
+            },
+
+            _ => None,
+        }
+    }
+
+    fn process_gap(&mut self, event: &gap::Event) -> Option<SecurityEvent> {
+        match *event {
+            gap::Event::PassKeyRequest { handle } => {
+                self.connection_mut(handle)?.state = PairingState::PasskeyRequested;
+
+                Some(SecurityEvent::PasskeyRequested(handle))
+            }
+
+            gap::Event::NumericComparisonValue { handle, value } => {
+                self.connection_mut(handle)?.state = PairingState::NumericComparison(value);
+
+                Some(SecurityEvent::NumericComparison { handle, value })
+            }
+
+            gap::Event::AuthorizationRequest { handle } => {
+                self.connection(handle)?;
+
+                Some(SecurityEvent::AuthorizationRequested(handle))
+            }
+
+            gap::Event::PairingComplete {
+                handle,
+                status,
+                reason,
+            } => {
+                let conn = self.connection_mut(handle)?;
+
+                match PairingStatus::from(status) {
+                    PairingStatus::Success => {
+                        conn.state = PairingState::Verifying;
+
+                        Some(SecurityEvent::Paired(handle))
+                    }
+
+                    status => {
+                        conn.state = PairingState::Failed;
+
+                        Some(SecurityEvent::PairingFailed {
+                            handle,
+                            status,
+                            reason,
+                        })
+                    }
+                }
+            }
+
+            gap::Event::BondLost => Some(SecurityEvent::BondLost),
+
+            _ => None,
+        }
+    }
+
+    /// Starts pairing as a master.
+    pub fn start_pairing(
+        &mut self,
+        handle: ConnectionHandle,
+        force_rebond: bool,
+    ) -> Result<SendPairingReq, Error> {
+        self.start(handle)?;
+
+        Ok(SendPairingReq {
+            handle,
+            force_rebond,
+        })
+    }
+
+    /// Asks the master to start pairing.
+    pub fn request_security(
+        &mut self,
+        handle: ConnectionHandle,
+    ) -> Result<SlaveSecurityReq, Error> {
+        self.start(handle)?;
+
+        Ok(SlaveSecurityReq { handle })
+    }
+
+    fn start(&mut self, handle: ConnectionHandle) -> Result<(), Error> {
+        let conn = self
+            .connection_mut(handle)
+            .ok_or(Error::UnknownConnection)?;
+
+        match conn.state {
+            PairingState::Unpaired | PairingState::Secured(_) | PairingState::Failed => {
+                conn.state = PairingState::Pairing;
+                Ok(())
+            }
+
+            state => Err(Error::InvalidState(state)),
+        }
+    }
+
+    /// Answers `SecurityEvent::PasskeyRequested`.
+    pub fn passkey_response(
+        &mut self,
+        handle: ConnectionHandle,
+        pass_key: u32,
+    ) -> Result<PassKeyResp, Error> {
+        if pass_key > gap::MAX_PASS_KEY {
+            return Err(Error::InvalidPasskey);
+        }
+
+        let conn = self
+            .connection_mut(handle)
+            .ok_or(Error::UnknownConnection)?;
+
+        match conn.state {
+            PairingState::PasskeyRequested => {
+                conn.state = PairingState::Pairing;
+                Ok(PassKeyResp { handle, pass_key })
+            }
+
+            state => Err(Error::InvalidState(state)),
+        }
+    }
+
+    /// Answers `SecurityEvent::NumericComparison`. Pairing fails if `confirm` is `false`.
+    pub fn confirm_numeric_comparison(
+        &mut self,
+        handle: ConnectionHandle,
+        confirm: bool,
+    ) -> Result<NumericComparisonValueConfirm, Error> {
+        let conn = self
+            .connection_mut(handle)
+            .ok_or(Error::UnknownConnection)?;
+
+        match conn.state {
+            PairingState::NumericComparison(_) => {
+                conn.state = PairingState::Pairing;
+                Ok(NumericComparisonValueConfirm { handle, confirm })
+            }
+
+            state => Err(Error::InvalidState(state)),
+        }
+    }
+
+    /// Checks the security `level` read after `SecurityEvent::Paired` against the required one.
+    ///
+    /// The connection should be terminated if this fails.
+    pub fn verify(&mut self, handle: ConnectionHandle, level: SecurityLevel) -> Result<(), Error> {
+        let required = self.required;
+        let conn = self
+            .connection_mut(handle)
+            .ok_or(Error::UnknownConnection)?;
+
+        match conn.state {
+            PairingState::Verifying | PairingState::Secured(_) => {}
+
+            state => return Err(Error::InvalidState(state)),
+        }
+
+        if level < required {
+            conn.state = PairingState::Failed;
+
+            return Err(Error::InsufficientSecurity {
+                required,
+                actual: level,
+            });
+        }
+
+        conn.state = PairingState::Secured(level);
+        Ok(())
+    }
+
+    fn remove(&mut self, handle: ConnectionHandle) {
+        if let Some(index) = self
+            .connections
+            .iter()
+            .position(|conn| conn.handle == handle)
+        {
+            self.connections.swap_remove(index);
+        }
+    }
+
+    fn connection(&self, handle: ConnectionHandle) -> Option<&ConnectionSecurity> {
+        self.connections.iter().find(|conn| conn.handle == handle)
+    }
+
+    fn connection_mut(&mut self, handle: ConnectionHandle) -> Option<&mut ConnectionSecurity> {
+        self.connections
+            .iter_mut()
+            .find(|conn| conn.handle == handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::ble::event::{
+        DisconnectionComplete, EncryptionChange, LeConnectionComplete, VendorEvent,
+    };
+    use crate::tl_mbox::ble::types::{AddressType, BdAddr, Role};
+    use heapless::consts::U2;
+
+    const HANDLE: ConnectionHandle = ConnectionHandle(0x0801);
+
+    fn connected() -> SecurityManager<U2> {
+        let mut security = SecurityManager::new(SecurityLevel::Authenticated);
+        let evt = Event::LeMeta(LeMetaEvent::ConnectionComplete(LeConnectionComplete {
+            status: Status::SUCCESS,
+            handle: HANDLE,
+            role: Role::Slave,
+            peer_address_type: AddressType::Public,
+            peer_address: BdAddr([1, 2, 3, 4, 5, 6]),
+            conn_interval: 0x0028,
+            conn_latency: 0,
+            supervision_timeout: 0x01f4,
+            master_clock_accuracy: 0,
+        }));
+
+        assert_eq!(security.process(&evt), None);
+        assert_eq!(security.state(HANDLE), Some(PairingState::Unpaired));
+        security
+    }
+
+    fn gap_event(code: u16, params: &[u8]) -> Event<'_> {
+        Event::Vendor(VendorEvent { code, params })
+    }
+
+    fn encryption_change(status: u8, enabled: bool) -> Event<'static> {
+        Event::EncryptionChange(EncryptionChange {
+            status: Status(status),
+            handle: HANDLE,
+            enabled,
+        })
+    }
+
+    #[test]
+    fn pairing() {
+        let mut security = connected();
+        security.request_security(HANDLE).unwrap();
+        assert_eq!(security.state(HANDLE), Some(PairingState::Pairing));
+
+        assert_eq!(
+            security.process(&gap_event(gap::EVT_PASS_KEY_REQ, &[0x01, 0x08])),
+            Some(SecurityEvent::PasskeyRequested(HANDLE))
+        );
+        assert_eq!(
+            security.passkey_response(HANDLE, 1_000_000).err(),
+            Some(Error::InvalidPasskey)
+        );
+        security.passkey_response(HANDLE, 123_456).unwrap();
+
+        assert_eq!(
+            security.process(&encryption_change(0x00, true)),
+            Some(SecurityEvent::Encrypted(HANDLE))
+        );
+        assert_eq!(
+            security.process(&gap_event(
+                gap::EVT_PAIRING_COMPLETE,
+                &[0x01, 0x08, 0x00, 0x00]
+            )),
+            Some(SecurityEvent::Paired(HANDLE))
+        );
+        assert!(!security.is_secured(HANDLE));
+
+        security
+            .verify(HANDLE, SecurityLevel::Authenticated)
+            .unwrap();
+        assert!(security.is_secured(HANDLE));
+    }
+
+    #[test]
+    fn pairing_failure() {
+        let mut security = connected();
+        security.start_pairing(HANDLE, false).unwrap();
+
+        assert_eq!(
+            security.process(&gap_event(
+                gap::EVT_PAIRING_COMPLETE,
+                &[0x01, 0x08, 0x02, 0x05]
+            )),
+            Some(SecurityEvent::PairingFailed {
+                handle: HANDLE,
+                status: PairingStatus::Failed,
+                reason: 0x05,
+            })
+        );
+        assert_eq!(security.state(HANDLE), Some(PairingState::Failed));
+
+        // Pairing succeeds, but with a weaker security level than required
+        security.start_pairing(HANDLE, true).unwrap();
+        security.process(&gap_event(
+            gap::EVT_PAIRING_COMPLETE,
+            &[0x01, 0x08, 0x00, 0x00],
+        ));
+        assert_eq!(
+            security.verify(HANDLE, SecurityLevel::Unauthenticated),
+            Err(Error::InsufficientSecurity {
+                required: SecurityLevel::Authenticated,
+                actual: SecurityLevel::Unauthenticated,
+            })
+        );
+        assert!(!security.is_secured(HANDLE));
+
+        // Bonded peer has lost its keys
+        security.start_pairing(HANDLE, false).unwrap();
+        assert_eq!(
+            security.process(&encryption_change(0x06, false)),
+            Some(SecurityEvent::EncryptionFailed {
+                handle: HANDLE,
+                status: Status(0x06),
+            })
+        );
+        assert_eq!(security.state(HANDLE), Some(PairingState::Failed));
+    }
+
+    #[test]
+    fn disconnect() {
+        let mut security = connected();
+        security.start_pairing(HANDLE, false).unwrap();
+
+        let evt = Event::DisconnectionComplete(DisconnectionComplete {
+            status: Status::SUCCESS,
+            handle: HANDLE,
+            reason: 0x13,
+        });
+        assert_eq!(security.process(&evt), None);
+        assert_eq!(security.state(HANDLE), None);
+        assert!(security.connections().is_empty());
+
+        assert_eq!(
+            security.start_pairing(HANDLE, false).err(),
+            Some(Error::UnknownConnection)
+        );
+        assert_eq!(security.process(&encryption_change(0x00, true)), None);
+    }
+
+    #[test]
+    fn bonded_reconnect() {
+        let mut security = connected();
+
+        // Master encrypts with the stored keys without pairing again
+        assert_eq!(
+            security.process(&encryption_change(0x00, true)),
+            Some(SecurityEvent::Encrypted(HANDLE))
+        );
+        assert_eq!(security.state(HANDLE), Some(PairingState::Verifying));
+
+        security
+            .verify(HANDLE, SecurityLevel::Authenticated)
+            .unwrap();
+        assert!(security.is_secured(HANDLE));
+
+        // Encryption restarted with the same keys keeps the connection secured
+        assert_eq!(security.process(&encryption_change(0x00, true)), None);
+        assert!(security.is_secured(HANDLE));
+    }
+}
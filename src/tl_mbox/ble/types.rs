@@ -0,0 +1,152 @@
+//! Types shared between HCI commands and events.
+
+use core::convert::TryFrom;
+
+use crate::tl_mbox::bytes::{BufferFull, Writer};
+
+/// Bluetooth device address, least significant byte first (as transferred over HCI).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct BdAddr(pub [u8; 6]);
+
+/// Connection handle assigned by the controller. Only the lower 12 bits are meaningful.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ConnectionHandle(pub u16);
+
+impl ConnectionHandle {
+    pub(crate) fn from_raw(raw: u16) -> Self {
+        ConnectionHandle(raw & 0x0fff)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AddressType {
+    Public = 0x00,
+    Random = 0x01,
+    PublicIdentity = 0x02,
+    RandomIdentity = 0x03,
+}
+
+impl TryFrom<u8> for AddressType {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(AddressType::Public),
+            0x01 => Ok(AddressType::Random),
+            0x02 => Ok(AddressType::PublicIdentity),
+            0x03 => Ok(AddressType::RandomIdentity),
+
+            _ => Err(()),
+        }
+    }
+}
+
+/// Role of the local device in a connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Role {
+    Master = 0x00,
+    Slave = 0x01,
+}
+
+impl TryFrom<u8> for Role {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Role::Master),
+            0x01 => Ok(Role::Slave),
+
+            _ => Err(()),
+        }
+    }
+}
+
+/// LE PHY as reported in PHY update events.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Phy {
+    Le1M = 0x01,
+    Le2M = 0x02,
+    LeCoded = 0x03,
+}
+
+impl TryFrom<u8> for Phy {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Phy::Le1M),
+            0x02 => Ok(Phy::Le2M),
+            0x03 => Ok(Phy::LeCoded),
+
+            _ => Err(()),
+        }
+    }
+}
+
+/// HCI status / error code. Zero means success.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Status(pub u8);
+
+impl Status {
+    pub const SUCCESS: Status = Status(0x00);
+
+    pub fn is_success(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// 16-bit or 128-bit Bluetooth UUID. 128-bit UUIDs are stored least significant byte first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Uuid {
+    Uuid16(u16),
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    /// Size of the UUID on the wire.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+
+    /// Decodes a 2 or 16 bytes long little-endian UUID.
+    pub fn from_le_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.len() {
+            2 => Some(Uuid::Uuid16(u16::from_le_bytes([bytes[0], bytes[1]]))),
+            16 => {
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(bytes);
+                Some(Uuid::Uuid128(uuid))
+            }
+
+            _ => None,
+        }
+    }
+
+    /// UUID type as used by ACI commands: 0x01 for 16-bit and 0x02 for 128-bit UUIDs.
+    pub(crate) fn aci_type(&self) -> u8 {
+        match self {
+            Uuid::Uuid16(_) => 0x01,
+            Uuid::Uuid128(_) => 0x02,
+        }
+    }
+
+    /// Writes the UUID value without its type.
+    pub(crate) fn write(&self, w: &mut Writer) -> Result<(), BufferFull> {
+        match self {
+            Uuid::Uuid16(uuid) => w.u16(*uuid),
+            Uuid::Uuid128(uuid) => w.bytes(uuid),
+        }
+    }
+
+    /// Writes the ACI UUID type followed by the UUID value.
+    pub(crate) fn write_aci(&self, w: &mut Writer) -> Result<(), BufferFull> {
+        w.u8(self.aci_type())?;
+        self.write(w)
+    }
+}
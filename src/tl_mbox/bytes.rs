@@ -0,0 +1,114 @@
+//! Little-endian cursors over HCI parameter buffers.
+
+/// Reads little-endian fields from the front of a byte slice.
+///
+/// Every accessor returns `None` once the slice is exhausted so parsers can bail out with `?`.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < len {
+            return None;
+        }
+
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+
+        Some(head)
+    }
+
+    /// Takes everything that is left.
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = self.buf;
+        self.buf = &[];
+        rest
+    }
+
+    pub fn array6(&mut self) -> Option<[u8; 6]> {
+        let b = self.bytes(6)?;
+        Some([b[0], b[1], b[2], b[3], b[4], b[5]])
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    pub fn i8(&mut self) -> Option<i8> {
+        self.u8().map(|b| b as i8)
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        self.bytes(8)
+            .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+}
+
+/// Returned by `Writer` when the underlying buffer can't hold more data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BufferFull;
+
+/// Writes little-endian fields into a fixed-size buffer, e.g. a command payload.
+#[derive(Debug)]
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Writer { buf, len: 0 }
+    }
+
+    /// Number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<(), BufferFull> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            return Err(BufferFull);
+        }
+
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+
+        Ok(())
+    }
+
+    pub fn u8(&mut self, value: u8) -> Result<(), BufferFull> {
+        self.bytes(&[value])
+    }
+
+    pub fn u16(&mut self, value: u16) -> Result<(), BufferFull> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> Result<(), BufferFull> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn u64(&mut self, value: u64) -> Result<(), BufferFull> {
+        self.bytes(&value.to_le_bytes())
+    }
+}
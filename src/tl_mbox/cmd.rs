@@ -72,13 +72,30 @@ impl CmdPacket {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Maximum length of ACL data in a single packet, `STM32WB_TL_ACL_DATA_LEN` at build time.
+pub const MAX_ACL_DATA_LEN: usize = super::CFG_TL_ACL_DATA_LEN;
+
+#[derive(Copy, Clone)]
 #[repr(C, packed)]
 pub struct AclDataSerial {
     pub ty: u8,
+    /// Connection handle with packet boundary and broadcast flags in the upper 4 bits.
     pub handle: u16,
     pub length: u16,
-    pub acl_data: [u8; 1],
+    pub acl_data: [u8; MAX_ACL_DATA_LEN],
+}
+
+impl core::fmt::Debug for AclDataSerial {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let handle = self.handle;
+        let length = self.length;
+
+        write!(
+            f,
+            "AclDataSerial ({}, {}, {}, [{}...])",
+            self.ty, handle, length, self.acl_data[0]
+        )
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -1,6 +1,8 @@
+use crate::tl_mbox::ble::event::{EVT_COMMAND_COMPLETE, EVT_COMMAND_STATUS, EVT_VENDOR};
+use crate::tl_mbox::bytes::Reader;
 use crate::tl_mbox::cmd::{AclDataPacket, AclDataSerial};
 use crate::tl_mbox::consts::TlPacketType;
-use crate::tl_mbox::{PacketHeader, TL_EVT_HEADER_SIZE};
+use crate::tl_mbox::{PacketHeader, TL_BLE_EVT_ACL_DATA_LEN, TL_EVT_HEADER_SIZE};
 use core::convert::TryFrom;
 use core::mem::MaybeUninit;
 
@@ -47,6 +49,21 @@ pub struct AsynchEvt {
     payload: [u8; 1],
 }
 
+/// Command complete event borrowed from an `EvtBox`, with all its return parameters.
+#[derive(Debug, Copy, Clone)]
+pub struct CcEvtRef<'a> {
+    pub num_cmd: u8,
+    pub cmd_code: u16,
+    pub params: &'a [u8],
+}
+
+/// Asynchronous (vendor specific) event borrowed from an `EvtBox`.
+#[derive(Debug, Copy, Clone)]
+pub struct AsynchEvtRef<'a> {
+    pub sub_evt_code: u16,
+    pub payload: &'a [u8],
+}
+
 #[derive(Debug, Copy, Clone, Default)]
 #[repr(C, packed)]
 pub struct Evt {
@@ -101,7 +118,118 @@ impl EvtBox {
         Self { ptr }
     }
 
+    /// Gives up ownership of the buffer without releasing it.
+    pub(crate) fn into_raw(self) -> *mut EvtPacket {
+        let ptr = self.ptr;
+        core::mem::forget(self);
+
+        ptr
+    }
+
+    /// Returns raw packet type of the underlying packet.
+    pub(crate) fn raw_kind(&self) -> u8 {
+        unsafe { (*self.ptr).evt_serial.kind }
+    }
+
+    /// Borrows the HCI event (event code, parameter length and parameters) from the shared RAM.
+    ///
+    /// Only meaningful for event packets, ACL data has a different layout.
+    pub(crate) fn evt_bytes(&self) -> &[u8] {
+        unsafe {
+            let evt: *const Evt = &(*self.ptr).evt_serial.evt;
+            let len = (*evt).payload_len as usize + 2;
+
+            core::slice::from_raw_parts(evt.cast(), len)
+        }
+    }
+
+    /// Borrows the ACL data packet (handle, data length and data) from the shared RAM.
+    ///
+    /// Only meaningful for ACL data packets.
+    pub(crate) fn acl_bytes(&self) -> &[u8] {
+        unsafe {
+            let acl_data: *const AclDataPacket = self.ptr.cast();
+            let acl_serial: *const AclDataSerial = &(*acl_data).acl_data_serial;
+            // Handle follows the packet type byte
+            let handle: *const u8 = acl_serial.cast::<u8>().add(1);
+
+            // Don't trust the length beyond the size of the event buffer it was received in
+            let len = ((*acl_serial).length as usize).min(TL_BLE_EVT_ACL_DATA_LEN) + 4;
+
+            core::slice::from_raw_parts(handle, len)
+        }
+    }
+
+    /// Returns type of the packet, or the raw packet type if it's unknown.
+    pub fn kind(&self) -> Result<TlPacketType, u8> {
+        let kind = self.raw_kind();
+
+        TlPacketType::try_from(kind).map_err(|_| kind)
+    }
+
+    /// Borrows event parameters, or data of an ACL data packet, from the shared RAM.
+    pub fn payload(&self) -> &[u8] {
+        if let Ok(TlPacketType::AclData) = self.kind() {
+            // Skip handle and data length
+            &self.acl_bytes()[4..]
+        } else {
+            // Skip event code and parameter length
+            &self.evt_bytes()[2..]
+        }
+    }
+
+    /// Borrows the command complete event, if that's what the packet is.
+    pub fn cc_evt(&self) -> Option<CcEvtRef<'_>> {
+        let mut r = Reader::new(self.hci_evt_params(EVT_COMMAND_COMPLETE)?);
+
+        Some(CcEvtRef {
+            num_cmd: r.u8()?,
+            cmd_code: r.u16()?,
+            params: r.rest(),
+        })
+    }
+
+    /// Returns the command status event, if that's what the packet is.
+    pub fn cs_evt(&self) -> Option<CsEvt> {
+        let mut r = Reader::new(self.hci_evt_params(EVT_COMMAND_STATUS)?);
+
+        Some(CsEvt {
+            status: r.u8()?,
+            num_cmd: r.u8()?,
+            cmd_code: r.u16()?,
+        })
+    }
+
+    /// Borrows the asynchronous event with its sub-event code, if that's what the packet is.
+    pub fn asynch_evt(&self) -> Option<AsynchEvtRef<'_>> {
+        let mut r = Reader::new(self.hci_evt_params(EVT_VENDOR)?);
+
+        Some(AsynchEvtRef {
+            sub_evt_code: r.u16()?,
+            payload: r.rest(),
+        })
+    }
+
+    /// Returns parameters of the BLE or system event with event code `code`.
+    fn hci_evt_params(&self, code: u8) -> Option<&[u8]> {
+        match self.kind() {
+            Ok(TlPacketType::BleEvt) | Ok(TlPacketType::SysEvt) => {}
+
+            _ => return None,
+        }
+
+        let evt = self.evt_bytes();
+        if evt[0] == code {
+            Some(&evt[2..])
+        } else {
+            None
+        }
+    }
+
     /// Copies event data from inner pointer and returns an event structure.
+    ///
+    /// Only the first byte of the payload is copied, use `payload` or the typed views
+    /// to borrow the whole payload instead.
     pub fn evt(&self) -> EvtPacket {
         let mut evt = MaybeUninit::uninit();
         unsafe {
@@ -169,11 +297,6 @@ impl EvtBox {
 
 impl Drop for EvtBox {
     fn drop(&mut self) {
-        use crate::ipcc::IpccExt;
-
-        let mut ipcc = unsafe { stm32wb_pac::Peripherals::steal() }
-            .IPCC
-            .constrain();
-        super::mm::evt_drop(self.ptr, &mut ipcc);
+        super::mm::evt_drop(self.ptr, &mut super::steal_ipcc());
     }
 }
@@ -0,0 +1,739 @@
+//! Firmware Upgrade Service (FUS) running on CPU2.
+//!
+//! FUS commands are system commands: they are sent on the SYS channel and answered with
+//! Command Complete events picked up with `TlMbox::poll_shci_cmd`. CPU2 either runs FUS or the
+//! wireless stack, see `TlMbox::cpu2_firmware`. Sending `fus_get_state` while the wireless stack
+//! is running makes CPU2 reboot into FUS.
+//!
+//! FUS resets CPU2 and the whole device while it installs firmware, so `FusUpdate` keeps its
+//! progress in a single `u32` that the application stores in memory surviving a system reset
+//! (e.g. an RTC backup register) and re-creates after each boot:
+//!
+//! ```ignore
+//! let mut update = FusUpdate::from_raw(backup_register).unwrap_or(FusUpdate::new(FusOperation::Upgrade));
+//! let mut state = None;
+//! loop {
+//!     let action = update.next(mbox.cpu2_firmware(), state.take());
+//!     backup_register = update.to_raw();
+//!     match action {
+//!         FusAction::GetState => {
+//!             state = nb::block!(mbox.poll_shci_cmd(&fus_get_state(&mut ipcc))).ok();
+//!         }
+//!         FusAction::WaitCpu2Ready => {
+//!             nb::block!(mbox.poll_cpu2_ready())?;
+//!             update.cpu2_ready();
+//!         }
+//!         FusAction::SendFwUpgrade => {
+//!             let status = nb::block!(mbox.poll_shci_cmd(&fus_fw_upgrade(&mut ipcc)))?;
+//!             update.command_status(status);
+//!         }
+//!         // ...
+//!         FusAction::Done | FusAction::Failed(_) => break,
+//!     }
+//!     backup_register = update.to_raw();
+//! }
+//! ```
+
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::shci::{send_cmd, PendingShciCmd, ShciError, ShciResponse, ShciStatus};
+
+pub const SHCI_OPCODE_FUS_GET_STATE: u16 = 0xfc52;
+pub const SHCI_OPCODE_FUS_FW_UPGRADE: u16 = 0xfc54;
+pub const SHCI_OPCODE_FUS_FW_DELETE: u16 = 0xfc55;
+pub const SHCI_OPCODE_FUS_UPDATE_AUTH_KEY: u16 = 0xfc56;
+pub const SHCI_OPCODE_FUS_LOCK_AUTH_KEY: u16 = 0xfc57;
+pub const SHCI_OPCODE_FUS_STORE_USR_KEY: u16 = 0xfc58;
+pub const SHCI_OPCODE_FUS_LOAD_USR_KEY: u16 = 0xfc59;
+pub const SHCI_OPCODE_FUS_START_WS: u16 = 0xfc5a;
+pub const SHCI_OPCODE_FUS_LOCK_USR_KEY: u16 = 0xfc5d;
+pub const SHCI_OPCODE_FUS_UNLOAD_USR_KEY: u16 = 0xfc5e;
+pub const SHCI_OPCODE_FUS_ACTIVATE_ANTIROLLBACK: u16 = 0xfc5f;
+
+/// Written by FUS at the start of the device information table.
+pub(crate) const FUS_DEVICE_INFO_TABLE_VALIDITY_KEYWORD: u32 = 0xa946_56b9;
+
+/// Maximum size of the authentication key (ECDSA public key).
+pub const AUTH_KEY_MAX_LEN: usize = 64;
+
+/// Maximum size of a user key: 256-bit key with the 96-bit IV of an encrypted key.
+pub const USR_KEY_MAX_LEN: usize = 32 + 12;
+
+/// Firmware currently running on CPU2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cpu2Firmware {
+    Fus,
+    WirelessStack,
+    /// CPU2 hasn't filled the device information table (yet).
+    Unknown,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FusState {
+    Idle,
+    /// Wireless stack upgrade is in progress, contains the raw state.
+    FwUpgradeOngoing(u8),
+    /// FUS upgrade is in progress, contains the raw state.
+    FusUpgradeOngoing(u8),
+    /// A key or service operation is in progress, contains the raw state.
+    ServiceOngoing(u8),
+    /// The last operation has failed, see `FusStateReport::error_code`.
+    Error,
+    Other(u8),
+}
+
+impl From<u8> for FusState {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => FusState::Idle,
+            0x10..=0x1f => FusState::FwUpgradeOngoing(value),
+            0x20..=0x2f => FusState::FusUpgradeOngoing(value),
+            0x30..=0x3f => FusState::ServiceOngoing(value),
+            0xff => FusState::Error,
+
+            other => FusState::Other(other),
+        }
+    }
+}
+
+impl FusState {
+    pub fn is_ongoing(self) -> bool {
+        matches!(
+            self,
+            FusState::FwUpgradeOngoing(_)
+                | FusState::FusUpgradeOngoing(_)
+                | FusState::ServiceOngoing(_)
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FusErrorCode {
+    NoError,
+    ImageNotFound,
+    ImageCorrupt,
+    ImageNotAuthentic,
+    NotEnoughSpace,
+    UserAbort,
+    EraseError,
+    WriteError,
+    StdAuthTagNotFound,
+    CustomAuthTagNotFound,
+    AuthKeyLocked,
+    FwRollbackError,
+    /// FUS is not running, e.g. the state was requested while the wireless stack is running.
+    NotRunning,
+    Unknown,
+    Other(u8),
+}
+
+impl From<u8> for FusErrorCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => FusErrorCode::NoError,
+            0x01 => FusErrorCode::ImageNotFound,
+            0x02 => FusErrorCode::ImageCorrupt,
+            0x03 => FusErrorCode::ImageNotAuthentic,
+            0x04 => FusErrorCode::NotEnoughSpace,
+            0x05 => FusErrorCode::UserAbort,
+            0x06 => FusErrorCode::EraseError,
+            0x07 => FusErrorCode::WriteError,
+            0x08 => FusErrorCode::StdAuthTagNotFound,
+            0x09 => FusErrorCode::CustomAuthTagNotFound,
+            0x0a => FusErrorCode::AuthKeyLocked,
+            0x11 => FusErrorCode::FwRollbackError,
+            0xfe => FusErrorCode::NotRunning,
+            0xff => FusErrorCode::Unknown,
+
+            other => FusErrorCode::Other(other),
+        }
+    }
+}
+
+impl From<FusErrorCode> for u8 {
+    fn from(code: FusErrorCode) -> u8 {
+        match code {
+            FusErrorCode::NoError => 0x00,
+            FusErrorCode::ImageNotFound => 0x01,
+            FusErrorCode::ImageCorrupt => 0x02,
+            FusErrorCode::ImageNotAuthentic => 0x03,
+            FusErrorCode::NotEnoughSpace => 0x04,
+            FusErrorCode::UserAbort => 0x05,
+            FusErrorCode::EraseError => 0x06,
+            FusErrorCode::WriteError => 0x07,
+            FusErrorCode::StdAuthTagNotFound => 0x08,
+            FusErrorCode::CustomAuthTagNotFound => 0x09,
+            FusErrorCode::AuthKeyLocked => 0x0a,
+            FusErrorCode::FwRollbackError => 0x11,
+            FusErrorCode::NotRunning => 0xfe,
+            FusErrorCode::Unknown => 0xff,
+            FusErrorCode::Other(other) => other,
+        }
+    }
+}
+
+/// Response to `fus_get_state`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FusStateReport {
+    pub state: FusState,
+    pub error_code: FusErrorCode,
+}
+
+impl ShciResponse for FusStateReport {
+    fn from_rsp_params(params: &[u8]) -> Option<Self> {
+        let state = FusState::from(*params.first()?);
+
+        // Older FUS versions don't report the error code
+        let error_code = params
+            .get(1)
+            .map(|&code| FusErrorCode::from(code))
+            .unwrap_or(FusErrorCode::NoError);
+
+        Some(FusStateReport { state, error_code })
+    }
+}
+
+/// Response to `fus_store_usr_key`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StoreUsrKeyResponse {
+    pub status: ShciStatus,
+    /// Index to be used with `fus_load_usr_key` and friends, valid on success.
+    pub key_index: u8,
+}
+
+impl ShciResponse for StoreUsrKeyResponse {
+    fn from_rsp_params(params: &[u8]) -> Option<Self> {
+        let status = ShciStatus::from(*params.first()?);
+
+        Some(StoreUsrKeyResponse {
+            status,
+            key_index: if status.is_success() {
+                *params.get(1)?
+            } else {
+                0
+            },
+        })
+    }
+}
+
+/// Requests FUS state. Makes CPU2 reboot into FUS if the wireless stack is running.
+pub fn fus_get_state(ipcc: &mut impl IpccChannels) -> PendingShciCmd<FusStateReport> {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_GET_STATE, &[])
+}
+
+/// Installs the image (wireless stack or FUS) previously written to the free flash area.
+pub fn fus_fw_upgrade(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_FW_UPGRADE, &[])
+}
+
+/// Deletes the installed wireless stack.
+pub fn fus_fw_delete(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_FW_DELETE, &[])
+}
+
+/// Replaces the customer authentication key used to check signed images.
+pub fn fus_update_auth_key(
+    ipcc: &mut impl IpccChannels,
+    key: &[u8],
+) -> Result<PendingShciCmd, ShciError> {
+    if key.len() > AUTH_KEY_MAX_LEN {
+        return Err(ShciError::TooLong);
+    }
+
+    let mut params = [0u8; 1 + AUTH_KEY_MAX_LEN];
+    params[0] = key.len() as u8;
+    params[1..=key.len()].copy_from_slice(key);
+
+    Ok(send_cmd(
+        ipcc,
+        SHCI_OPCODE_FUS_UPDATE_AUTH_KEY,
+        &params[..=key.len()],
+    ))
+}
+
+/// Locks the customer authentication key. This can't be undone.
+pub fn fus_lock_auth_key(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_LOCK_AUTH_KEY, &[])
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum UsrKeyType {
+    Simple = 0x01,
+    Master = 0x02,
+    /// Key encrypted with the master key, followed by its IV.
+    Encrypted = 0x03,
+}
+
+/// Stores a user key in the FUS key storage.
+pub fn fus_store_usr_key(
+    ipcc: &mut impl IpccChannels,
+    key_type: UsrKeyType,
+    key: &[u8],
+) -> Result<PendingShciCmd<StoreUsrKeyResponse>, ShciError> {
+    if key.len() > USR_KEY_MAX_LEN {
+        return Err(ShciError::TooLong);
+    }
+
+    let mut params = [0u8; 2 + USR_KEY_MAX_LEN];
+    params[0] = key_type as u8;
+    params[1] = key.len() as u8;
+    params[2..2 + key.len()].copy_from_slice(key);
+
+    Ok(send_cmd(
+        ipcc,
+        SHCI_OPCODE_FUS_STORE_USR_KEY,
+        &params[..2 + key.len()],
+    ))
+}
+
+/// Loads a user key into the AES1 key register.
+pub fn fus_load_usr_key(ipcc: &mut impl IpccChannels, key_index: u8) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_LOAD_USR_KEY, &[key_index])
+}
+
+/// Locks a user key so that it can't be loaded anymore until next reset.
+pub fn fus_lock_usr_key(ipcc: &mut impl IpccChannels, key_index: u8) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_LOCK_USR_KEY, &[key_index])
+}
+
+/// Removes a loaded user key from the AES1 key register.
+pub fn fus_unload_usr_key(ipcc: &mut impl IpccChannels, key_index: u8) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_UNLOAD_USR_KEY, &[key_index])
+}
+
+/// Starts the installed wireless stack. CPU2 reboots and sends `C2Ready` system event.
+pub fn fus_start_ws(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_START_WS, &[])
+}
+
+/// Forbids installing wireless stack versions older than the installed one. This can't be undone.
+pub fn fus_activate_antirollback(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FUS_ACTIVATE_ANTIROLLBACK, &[])
+}
+
+/// Operation performed by `FusUpdate`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FusOperation {
+    /// Install the image from the free flash area with `fus_fw_upgrade`.
+    Upgrade = 0x01,
+    /// Delete the wireless stack with `fus_fw_delete`.
+    Delete = 0x02,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Step {
+    Start,
+    EnteringFus,
+    /// Operation command has been sent, its status hasn't been received yet.
+    Requested,
+    /// FUS has accepted the operation but hasn't been seen working on it yet.
+    Accepted,
+    /// FUS has reported the operation ongoing or CPU2 has rebooted since it was accepted.
+    Ongoing,
+    StartingWs,
+    Done,
+    Failed(FusErrorCode),
+}
+
+/// What the application has to do next to progress `FusUpdate`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FusAction {
+    /// Send `fus_get_state` (after some delay if an operation is ongoing) and call `next` with
+    /// the response.
+    GetState,
+    /// CPU2 is rebooting: wait for `TlMbox::poll_cpu2_ready` and call `cpu2_ready`.
+    WaitCpu2Ready,
+    /// Send `fus_fw_upgrade` and call `command_status` with its status.
+    SendFwUpgrade,
+    /// Send `fus_fw_delete` and call `command_status` with its status.
+    SendFwDelete,
+    /// Send `fus_start_ws`, wait for `TlMbox::poll_cpu2_ready` and call `cpu2_ready`.
+    StartWs,
+    /// The operation has completed. After an upgrade the wireless stack is running again.
+    Done,
+    Failed(FusErrorCode),
+}
+
+/// Drives firmware upgrade or deletion through FUS across CPU2 and device resets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FusUpdate {
+    operation: FusOperation,
+    step: Step,
+}
+
+/// Upper half of `FusUpdate::to_raw` to tell it from uninitialized memory.
+const RAW_MAGIC: u32 = 0xf05a_0000;
+
+impl FusUpdate {
+    pub fn new(operation: FusOperation) -> Self {
+        FusUpdate {
+            operation,
+            step: Step::Start,
+        }
+    }
+
+    /// Encodes progress to be kept across resets.
+    pub fn to_raw(&self) -> u32 {
+        let (step, error_code) = match self.step {
+            Step::Start => (0, 0),
+            Step::EnteringFus => (1, 0),
+            Step::Requested => (2, 0),
+            Step::StartingWs => (3, 0),
+            Step::Done => (4, 0),
+            Step::Failed(code) => (5, u8::from(code)),
+            Step::Accepted => (6, 0),
+            Step::Ongoing => (7, 0),
+        };
+
+        RAW_MAGIC | ((self.operation as u32) << 12) | (step << 8) | error_code as u32
+    }
+
+    /// Restores progress encoded with `to_raw`. Returns `None` if `raw` is not a valid value.
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        if raw & 0xffff_0000 != RAW_MAGIC {
+            return None;
+        }
+
+        let operation = match (raw >> 12) & 0x0f {
+            0x01 => FusOperation::Upgrade,
+            0x02 => FusOperation::Delete,
+            _ => return None,
+        };
+
+        let step = match (raw >> 8) & 0x0f {
+            0 => Step::Start,
+            1 => Step::EnteringFus,
+            2 => Step::Requested,
+            3 => Step::StartingWs,
+            4 => Step::Done,
+            5 => Step::Failed(FusErrorCode::from(raw as u8)),
+            6 => Step::Accepted,
+            7 => Step::Ongoing,
+            _ => return None,
+        };
+
+        Some(FusUpdate { operation, step })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.step, Step::Done | Step::Failed(_))
+    }
+
+    fn operation_action(&self) -> FusAction {
+        match self.operation {
+            FusOperation::Upgrade => FusAction::SendFwUpgrade,
+            FusOperation::Delete => FusAction::SendFwDelete,
+        }
+    }
+
+    fn fail(&mut self, code: FusErrorCode) -> FusAction {
+        self.step = Step::Failed(code);
+        FusAction::Failed(code)
+    }
+
+    /// Takes the status of the `fus_fw_upgrade`/`fus_fw_delete` command.
+    ///
+    /// If FUS rejects the command the update fails with `FusErrorCode::Unknown`,
+    /// `fus_get_state` may tell why.
+    pub fn command_status(&mut self, status: ShciStatus) -> FusAction {
+        match self.step {
+            Step::Requested if status.is_success() => {
+                self.step = Step::Accepted;
+                FusAction::GetState
+            }
+
+            Step::Requested => self.fail(FusErrorCode::Unknown),
+
+            _ => FusAction::GetState,
+        }
+    }
+
+    /// Notes that CPU2 has (re)booted, i.e. `TlMbox::poll_cpu2_ready` has returned.
+    ///
+    /// FUS reboots CPU2 while it installs or deletes firmware, so a reboot after the operation
+    /// has been accepted means that it has started.
+    pub fn cpu2_ready(&mut self) {
+        if self.step == Step::Accepted {
+            self.step = Step::Ongoing;
+        }
+    }
+
+    /// Advances the update according to the running firmware and the last FUS state
+    /// (if it has been requested), and returns the next action.
+    pub fn next(&mut self, firmware: Cpu2Firmware, state: Option<FusStateReport>) -> FusAction {
+        match (self.step, firmware) {
+            (Step::Done, _) => FusAction::Done,
+            (Step::Failed(code), _) => FusAction::Failed(code),
+
+            // CPU2 hasn't booted yet
+            (_, Cpu2Firmware::Unknown) => FusAction::WaitCpu2Ready,
+
+            // `fus_get_state` makes the wireless stack reboot CPU2 into FUS
+            (Step::Start, Cpu2Firmware::WirelessStack) => {
+                self.step = Step::EnteringFus;
+                FusAction::GetState
+            }
+
+            (Step::EnteringFus, Cpu2Firmware::WirelessStack) => FusAction::WaitCpu2Ready,
+
+            (Step::Start, Cpu2Firmware::Fus) | (Step::EnteringFus, Cpu2Firmware::Fus) => {
+                self.step = Step::Requested;
+                self.operation_action()
+            }
+
+            // The command status was lost to a reset: unless FUS is already working on the
+            // operation, request it again
+            (Step::Requested, Cpu2Firmware::Fus) => match state {
+                Some(report) if report.state.is_ongoing() => {
+                    self.step = Step::Ongoing;
+                    FusAction::GetState
+                }
+
+                _ => self.operation_action(),
+            },
+
+            (Step::Requested, Cpu2Firmware::WirelessStack) => {
+                self.step = Step::EnteringFus;
+                FusAction::GetState
+            }
+
+            (Step::Accepted, Cpu2Firmware::Fus) | (Step::Ongoing, Cpu2Firmware::Fus) => {
+                match state {
+                    Some(report) if report.state.is_ongoing() => {
+                        self.step = Step::Ongoing;
+                        FusAction::GetState
+                    }
+
+                    // Error state may come without an error code
+                    Some(FusStateReport {
+                        state: FusState::Error,
+                        error_code,
+                    }) => self.fail(match error_code {
+                        FusErrorCode::NoError => FusErrorCode::Unknown,
+                        code => code,
+                    }),
+
+                    // Idle FUS hasn't started the operation yet
+                    Some(FusStateReport {
+                        error_code: FusErrorCode::NoError,
+                        ..
+                    })
+                    | None
+                        if self.step == Step::Accepted =>
+                    {
+                        FusAction::GetState
+                    }
+
+                    Some(FusStateReport {
+                        error_code: FusErrorCode::NoError,
+                        ..
+                    }) => match self.operation {
+                        FusOperation::Upgrade => {
+                            self.step = Step::StartingWs;
+                            FusAction::StartWs
+                        }
+
+                        // Nothing to start after deletion
+                        FusOperation::Delete => {
+                            self.step = Step::Done;
+                            FusAction::Done
+                        }
+                    },
+
+                    Some(report) => self.fail(report.error_code),
+
+                    None => FusAction::GetState,
+                }
+            }
+
+            // FUS may start the wireless stack on its own once the upgrade is done, while a
+            // wireless stack still running after deletion means that it hasn't been deleted
+            (Step::Accepted, Cpu2Firmware::WirelessStack)
+            | (Step::Ongoing, Cpu2Firmware::WirelessStack) => match self.operation {
+                FusOperation::Upgrade => {
+                    self.step = Step::Done;
+                    FusAction::Done
+                }
+
+                FusOperation::Delete => self.fail(FusErrorCode::Unknown),
+            },
+
+            (Step::StartingWs, Cpu2Firmware::WirelessStack) => {
+                self.step = Step::Done;
+                FusAction::Done
+            }
+
+            (Step::StartingWs, Cpu2Firmware::Fus) => FusAction::StartWs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_and_error_codes() {
+        assert_eq!(FusState::from(0x00), FusState::Idle);
+        assert_eq!(FusState::from(0x11), FusState::FwUpgradeOngoing(0x11));
+        assert_eq!(FusState::from(0xff), FusState::Error);
+
+        assert_eq!(FusErrorCode::from(0xfe), FusErrorCode::NotRunning);
+        assert_eq!(FusErrorCode::from(0xff), FusErrorCode::Unknown);
+        for code in 0..=0xff {
+            assert_eq!(u8::from(FusErrorCode::from(code)), code);
+        }
+    }
+
+    fn report(state: u8, error_code: u8) -> Option<FusStateReport> {
+        FusStateReport::from_rsp_params(&[state, error_code])
+    }
+
+    #[test]
+    fn upgrade() {
+        let mut update = FusUpdate::new(FusOperation::Upgrade);
+        assert_eq!(
+            update.next(Cpu2Firmware::WirelessStack, None),
+            FusAction::GetState
+        );
+        assert_eq!(
+            update.next(Cpu2Firmware::WirelessStack, None),
+            FusAction::WaitCpu2Ready
+        );
+        update.cpu2_ready();
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x00, 0x00)),
+            FusAction::SendFwUpgrade
+        );
+        assert_eq!(
+            update.command_status(ShciStatus::Success),
+            FusAction::GetState
+        );
+
+        // Idle FUS that hasn't started yet doesn't complete the upgrade
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x00, 0x00)),
+            FusAction::GetState
+        );
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x10, 0x00)),
+            FusAction::GetState
+        );
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x00, 0x00)),
+            FusAction::StartWs
+        );
+        assert_eq!(
+            update.next(Cpu2Firmware::WirelessStack, None),
+            FusAction::Done
+        );
+        assert!(update.is_finished());
+    }
+
+    #[test]
+    fn upgrade_across_reset() {
+        let mut update = FusUpdate::new(FusOperation::Upgrade);
+        update.next(Cpu2Firmware::Fus, None);
+        update.command_status(ShciStatus::Success);
+
+        // FUS resets the device: CPU2 reboot means the upgrade has started
+        let mut update = FusUpdate::from_raw(update.to_raw()).unwrap();
+        assert_eq!(
+            update.next(Cpu2Firmware::Unknown, None),
+            FusAction::WaitCpu2Ready
+        );
+        update.cpu2_ready();
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x00, 0x00)),
+            FusAction::StartWs
+        );
+    }
+
+    #[test]
+    fn lost_command_status_is_resent() {
+        let mut update = FusUpdate::new(FusOperation::Upgrade);
+        update.next(Cpu2Firmware::Fus, None);
+
+        let mut update = FusUpdate::from_raw(update.to_raw()).unwrap();
+        update.cpu2_ready();
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x00, 0x00)),
+            FusAction::SendFwUpgrade
+        );
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x11, 0x00)),
+            FusAction::GetState
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let mut update = FusUpdate::new(FusOperation::Upgrade);
+        update.next(Cpu2Firmware::Fus, report(0x00, 0x00));
+
+        assert_eq!(
+            update.command_status(ShciStatus::FusCmdNotSupported),
+            FusAction::Failed(FusErrorCode::Unknown)
+        );
+        assert!(update.is_finished());
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x00, 0x00)),
+            FusAction::Failed(FusErrorCode::Unknown)
+        );
+    }
+
+    #[test]
+    fn delete() {
+        let mut update = FusUpdate::new(FusOperation::Delete);
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x00, 0x00)),
+            FusAction::SendFwDelete
+        );
+        update.command_status(ShciStatus::Success);
+        update.cpu2_ready();
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0x00, 0x00)),
+            FusAction::Done
+        );
+
+        // Wireless stack still running after the delete has been accepted
+        let mut update = FusUpdate::new(FusOperation::Delete);
+        update.next(Cpu2Firmware::Fus, None);
+        update.command_status(ShciStatus::Success);
+        update.cpu2_ready();
+        assert_eq!(
+            update.next(Cpu2Firmware::WirelessStack, None),
+            FusAction::Failed(FusErrorCode::Unknown)
+        );
+    }
+
+    #[test]
+    fn error_state_fails() {
+        let mut update = FusUpdate::new(FusOperation::Upgrade);
+        update.next(Cpu2Firmware::Fus, report(0x00, 0x00));
+        update.command_status(ShciStatus::Success);
+
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0xff, 0x00)),
+            FusAction::Failed(FusErrorCode::Unknown)
+        );
+        assert!(update.is_finished());
+
+        let mut update = FusUpdate::new(FusOperation::Delete);
+        update.next(Cpu2Firmware::Fus, report(0x00, 0x00));
+        update.command_status(ShciStatus::Success);
+
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, report(0xff, 0x02)),
+            FusAction::Failed(FusErrorCode::ImageCorrupt)
+        );
+
+        // Failure survives a reset
+        let mut update = FusUpdate::from_raw(update.to_raw()).unwrap();
+        assert_eq!(
+            update.next(Cpu2Firmware::Fus, None),
+            FusAction::Failed(FusErrorCode::ImageCorrupt)
+        );
+    }
+}
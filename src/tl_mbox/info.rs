@@ -0,0 +1,358 @@
+//! Decoding of the device information table filled by CPU2.
+//!
+//! Firmware versions and memory footprints of the wireless stack, FUS and safe boot share the
+//! same encoding, see `Version` and `MemorySize`. Boot code can check that the running stack is
+//! usable before initializing it:
+//!
+//! ```ignore
+//! let info = mbox.check_wireless_fw(Version::new(1, 11, 0), StackFeature::Ble)?;
+//! ```
+
+use bit_field::BitField;
+
+/// Type of the build, lowest 4 bits of the version.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReleaseType {
+    Untracked,
+    /// Tracked version, contains its raw number.
+    Tracked(u8),
+    Released,
+}
+
+impl From<u8> for ReleaseType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ReleaseType::Untracked,
+            15 => ReleaseType::Released,
+
+            other => ReleaseType::Tracked(other),
+        }
+    }
+}
+
+/// Firmware version.
+///
+/// ```text
+/// [0:3]   = Build - 0: Untracked - 15:Released - x: Tracked version
+/// [4:7]   = branch - 0: Mass Market - x: ...
+/// [8:15]  = Subversion
+/// [16:23] = Version minor
+/// [24:31] = Version major
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub sub: u8,
+    /// 0 is the mass market branch.
+    pub branch: u8,
+    pub release_type: ReleaseType,
+}
+
+impl Version {
+    /// Version `major.minor.sub` of a released mass market build, e.g. to be used with
+    /// `is_at_least`.
+    pub const fn new(major: u8, minor: u8, sub: u8) -> Self {
+        Version {
+            major,
+            minor,
+            sub,
+            branch: 0,
+            release_type: ReleaseType::Released,
+        }
+    }
+
+    pub fn from_raw(raw: u32) -> Self {
+        Version {
+            major: raw.get_bits(24..32) as u8,
+            minor: raw.get_bits(16..24) as u8,
+            sub: raw.get_bits(8..16) as u8,
+            branch: raw.get_bits(4..8) as u8,
+            release_type: ReleaseType::from(raw.get_bits(0..4) as u8),
+        }
+    }
+
+    /// Returns `true` if `major.minor.sub` of this version is not older than that of `min`.
+    /// Branch and release type are not compared.
+    pub fn is_at_least(&self, min: Version) -> bool {
+        (self.major, self.minor, self.sub) >= (min.major, min.minor, min.sub)
+    }
+}
+
+/// Memory taken by the firmware.
+///
+/// ```text
+/// [0:7]   = Flash ( Number of 4k sector)
+/// [8:15]  = Reserved ( Shall be set to 0 - may be used as flash extension )
+/// [16:23] = SRAM2b ( Number of 1k sector)
+/// [24:31] = SRAM2a ( Number of 1k sector)
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemorySize {
+    /// Number of 4K flash sectors.
+    pub flash_sectors: u8,
+    /// Number of 1K SRAM2a sectors.
+    pub sram2a_sectors: u8,
+    /// Number of 1K SRAM2b sectors.
+    pub sram2b_sectors: u8,
+}
+
+impl MemorySize {
+    pub fn from_raw(raw: u32) -> Self {
+        MemorySize {
+            flash_sectors: raw.get_bits(0..8) as u8,
+            sram2a_sectors: raw.get_bits(24..32) as u8,
+            sram2b_sectors: raw.get_bits(16..24) as u8,
+        }
+    }
+
+    /// Flash size in bytes.
+    pub fn flash_bytes(&self) -> u32 {
+        self.flash_sectors as u32 * 4 * 1024
+    }
+
+    /// SRAM2a size in bytes.
+    pub fn sram2a_bytes(&self) -> u32 {
+        self.sram2a_sectors as u32 * 1024
+    }
+
+    /// SRAM2b size in bytes.
+    pub fn sram2b_bytes(&self) -> u32 {
+        self.sram2b_sectors as u32 * 1024
+    }
+}
+
+/// Wireless stack flavour, lowest byte of the stack information.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StackType {
+    None,
+    BleFull,
+    BleHciOnly,
+    BleLight,
+    BleBeacon,
+    ThreadFtd,
+    ThreadMtd,
+    ZigbeeFfd,
+    ZigbeeRfd,
+    Mac,
+    /// Concurrent BLE and Thread FTD, statically switched.
+    BleThreadFtdStatic,
+    /// Concurrent BLE and Thread FTD, dynamically switched.
+    BleThreadFtdDynamic,
+    /// 802.15.4 link layer tests.
+    Ieee802154Lld,
+    Ieee802154PhyValidation,
+    BlePhyValidation,
+    /// BLE link layer tests.
+    BleLld,
+    BleRlv,
+    Ieee802154Rlv,
+    BleZigbeeFfdStatic,
+    BleZigbeeRfdStatic,
+    BleZigbeeFfdDynamic,
+    BleZigbeeRfdDynamic,
+    Rlv,
+    BleMacStatic,
+    Other(u8),
+}
+
+impl From<u8> for StackType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => StackType::None,
+            0x01 => StackType::BleFull,
+            0x02 => StackType::BleHciOnly,
+            0x03 => StackType::BleLight,
+            0x04 => StackType::BleBeacon,
+            0x10 => StackType::ThreadFtd,
+            0x11 => StackType::ThreadMtd,
+            0x30 => StackType::ZigbeeFfd,
+            0x31 => StackType::ZigbeeRfd,
+            0x40 => StackType::Mac,
+            0x50 => StackType::BleThreadFtdStatic,
+            0x51 => StackType::BleThreadFtdDynamic,
+            0x60 => StackType::Ieee802154Lld,
+            0x61 => StackType::Ieee802154PhyValidation,
+            0x62 => StackType::BlePhyValidation,
+            0x63 => StackType::BleLld,
+            0x64 => StackType::BleRlv,
+            0x65 => StackType::Ieee802154Rlv,
+            0x70 => StackType::BleZigbeeFfdStatic,
+            0x71 => StackType::BleZigbeeRfdStatic,
+            0x78 => StackType::BleZigbeeFfdDynamic,
+            0x79 => StackType::BleZigbeeRfdDynamic,
+            0x80 => StackType::Rlv,
+            0x90 => StackType::BleMacStatic,
+
+            other => StackType::Other(other),
+        }
+    }
+}
+
+impl StackType {
+    /// Returns `true` if the stack can be used through the transport for `feature`.
+    pub fn supports(self, feature: StackFeature) -> bool {
+        use StackType::*;
+
+        match feature {
+            StackFeature::Ble => matches!(
+                self,
+                BleFull
+                    | BleHciOnly
+                    | BleLight
+                    | BleBeacon
+                    | BleThreadFtdStatic
+                    | BleThreadFtdDynamic
+                    | BleZigbeeFfdStatic
+                    | BleZigbeeRfdStatic
+                    | BleZigbeeFfdDynamic
+                    | BleZigbeeRfdDynamic
+                    | BleMacStatic
+            ),
+            StackFeature::BleHost => matches!(
+                self,
+                BleFull
+                    | BleLight
+                    | BleThreadFtdStatic
+                    | BleThreadFtdDynamic
+                    | BleZigbeeFfdStatic
+                    | BleZigbeeRfdStatic
+                    | BleZigbeeFfdDynamic
+                    | BleZigbeeRfdDynamic
+                    | BleMacStatic
+            ),
+            StackFeature::Thread => matches!(
+                self,
+                ThreadFtd | ThreadMtd | BleThreadFtdStatic | BleThreadFtdDynamic
+            ),
+            StackFeature::Zigbee => matches!(
+                self,
+                ZigbeeFfd
+                    | ZigbeeRfd
+                    | BleZigbeeFfdStatic
+                    | BleZigbeeRfdStatic
+                    | BleZigbeeFfdDynamic
+                    | BleZigbeeRfdDynamic
+            ),
+            StackFeature::Mac => matches!(self, Mac | BleMacStatic),
+        }
+    }
+}
+
+/// Capability of the wireless stack required by the application.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StackFeature {
+    /// BLE controller, at least HCI commands.
+    Ble,
+    /// BLE host: GAP, GATT and ACI commands.
+    BleHost,
+    Thread,
+    Zigbee,
+    /// 802.15.4 MAC.
+    Mac,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// CPU2 runs FUS or hasn't filled the device information table.
+    NotRunning,
+
+    /// Wireless stack is older than required. Contains its version.
+    TooOld(Version),
+
+    /// Wireless stack doesn't support the required feature. Contains its type.
+    Unsupported(StackType),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version() {
+        // Stack 1.13.2, tracked build 5 of branch 1
+        let v = Version::from_raw(0x010d_0215);
+        assert_eq!(
+            v,
+            Version {
+                major: 1,
+                minor: 13,
+                sub: 2,
+                branch: 1,
+                release_type: ReleaseType::Tracked(5),
+            }
+        );
+
+        assert_eq!(Version::from_raw(0x010d_020f), Version::new(1, 13, 2));
+        assert_eq!(
+            Version::from_raw(0x010d_0200).release_type,
+            ReleaseType::Untracked
+        );
+
+        assert!(v.is_at_least(Version::new(1, 13, 2)));
+        assert!(v.is_at_least(Version::new(1, 12, 9)));
+        assert!(!v.is_at_least(Version::new(1, 13, 3)));
+        assert!(!v.is_at_least(Version::new(2, 0, 0)));
+    }
+
+    #[test]
+    fn memory_size() {
+        let size = MemorySize::from_raw(0x1c20_0066);
+        assert_eq!(
+            size,
+            MemorySize {
+                flash_sectors: 0x66,
+                sram2a_sectors: 0x1c,
+                sram2b_sectors: 0x20,
+            }
+        );
+        assert_eq!(size.flash_bytes(), 0x66 * 4096);
+        assert_eq!(size.sram2a_bytes(), 28 * 1024);
+        assert_eq!(size.sram2b_bytes(), 32 * 1024);
+
+        // Reserved byte is ignored
+        assert_eq!(MemorySize::from_raw(0x1c20_ff66), size);
+    }
+
+    #[test]
+    fn stack_type() {
+        assert_eq!(StackType::from(0x01), StackType::BleFull);
+        assert_eq!(StackType::from(0x02), StackType::BleHciOnly);
+        assert_eq!(StackType::from(0x11), StackType::ThreadMtd);
+        assert_eq!(StackType::from(0x31), StackType::ZigbeeRfd);
+        assert_eq!(StackType::from(0x40), StackType::Mac);
+        assert_eq!(StackType::from(0x51), StackType::BleThreadFtdDynamic);
+        assert_eq!(StackType::from(0x79), StackType::BleZigbeeRfdDynamic);
+        assert_eq!(StackType::from(0x90), StackType::BleMacStatic);
+        assert_eq!(StackType::from(0x05), StackType::Other(0x05));
+    }
+
+    #[test]
+    fn supports() {
+        use StackFeature::*;
+
+        assert!(StackType::BleFull.supports(Ble));
+        assert!(StackType::BleFull.supports(BleHost));
+        assert!(!StackType::BleFull.supports(Thread));
+
+        // HCI only stack has no host
+        assert!(StackType::BleHciOnly.supports(Ble));
+        assert!(!StackType::BleHciOnly.supports(BleHost));
+
+        assert!(StackType::BleThreadFtdStatic.supports(BleHost));
+        assert!(StackType::BleThreadFtdStatic.supports(Thread));
+        assert!(!StackType::BleThreadFtdStatic.supports(Zigbee));
+
+        assert!(StackType::ZigbeeFfd.supports(Zigbee));
+        assert!(!StackType::ZigbeeFfd.supports(Ble));
+
+        assert!(StackType::BleMacStatic.supports(Mac));
+        assert!(!StackType::ThreadFtd.supports(Mac));
+
+        for feature in [Ble, BleHost, Thread, Zigbee, Mac].iter() {
+            assert!(!StackType::None.supports(*feature));
+            assert!(!StackType::Ieee802154Lld.supports(*feature));
+            assert!(!StackType::Other(0x05).supports(*feature));
+        }
+    }
+}
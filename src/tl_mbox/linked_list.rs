@@ -0,0 +1,264 @@
+//! Intrusive doubly-linked list shared with CPU2.
+//!
+//! Nodes have the layout of ST's `tListNode`, so CPU2 walks the same lists. A list is
+//! identified by its head node, which points to itself when the list is empty. Every operation
+//! runs in a critical section, because lists are modified by IPCC interrupt handlers too.
+
+#[cfg(any(test, feature = "host-sim"))]
+use core::ptr::NonNull;
+
+#[cfg(feature = "host-sim")]
+use crate::tl_mbox::sim::interrupt;
+#[cfg(not(feature = "host-sim"))]
+use cortex_m::interrupt;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, packed(4))]
+pub struct LinkedListNode {
+    pub next: *mut LinkedListNode,
+    pub prev: *mut LinkedListNode,
+}
+
+impl Default for LinkedListNode {
+    fn default() -> Self {
+        LinkedListNode {
+            next: core::ptr::null_mut(),
+            prev: core::ptr::null_mut(),
+        }
+    }
+}
+
+/// Handle to a list with its head in the shared RAM.
+///
+/// Nodes are buffers that are handed over to the list with `push_*` and taken back with
+/// `pop_head`, so the list owns them in between.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct LinkedList {
+    head: *mut LinkedListNode,
+}
+
+impl LinkedList {
+    /// Creates a handle to the list with the head at `head`.
+    ///
+    /// # Safety
+    ///
+    /// `head` must point to a list head that lives for the rest of the program and is modified
+    /// only through `LinkedList` handles or by CPU2. Unless the head has already been
+    /// initialized, `init` must be called before anything else.
+    pub(crate) unsafe fn from_head(head: *mut LinkedListNode) -> Self {
+        LinkedList { head }
+    }
+
+    /// Makes the list empty. Nodes that were in the list are forgotten.
+    pub(crate) fn init(&self) {
+        interrupt::free(|_| unsafe {
+            (*self.head).next = self.head;
+            (*self.head).prev = self.head;
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        interrupt::free(|_| unsafe { (*self.head).next == self.head })
+    }
+
+    /// Number of nodes in the list.
+    #[cfg(any(test, feature = "host-sim"))]
+    pub(crate) fn len(&self) -> usize {
+        interrupt::free(|_| self.iter().count())
+    }
+
+    pub(crate) fn push_head(&self, node: &'static mut LinkedListNode) {
+        let node: *mut LinkedListNode = node;
+
+        interrupt::free(|_| unsafe { insert_after(node, self.head) })
+    }
+
+    pub(crate) fn push_tail(&self, node: &'static mut LinkedListNode) {
+        let node: *mut LinkedListNode = node;
+
+        // Tail is the node before the head
+        interrupt::free(|_| unsafe { insert_after(node, (*self.head).prev) })
+    }
+
+    pub(crate) fn pop_head(&self) -> Option<&'static mut LinkedListNode> {
+        interrupt::free(|_| unsafe {
+            let node = (*self.head).next;
+            if node == self.head {
+                return None;
+            }
+
+            (*(*node).prev).next = (*node).next;
+            (*(*node).next).prev = (*node).prev;
+
+            Some(&mut *node)
+        })
+    }
+
+    /// Iterates over addresses of the nodes, from head to tail.
+    ///
+    /// Nodes must not be removed from the list while it is iterated over.
+    #[cfg(any(test, feature = "host-sim"))]
+    pub(crate) fn iter(&self) -> Iter {
+        Iter {
+            head: self.head,
+            node: self.head,
+        }
+    }
+}
+
+/// Iterator over nodes of `LinkedList`.
+#[cfg(any(test, feature = "host-sim"))]
+pub(crate) struct Iter {
+    head: *mut LinkedListNode,
+    node: *mut LinkedListNode,
+}
+
+#[cfg(any(test, feature = "host-sim"))]
+impl Iterator for Iter {
+    type Item = NonNull<LinkedListNode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = interrupt::free(|_| unsafe { (*self.node).next });
+        if next == self.head {
+            return None;
+        }
+
+        self.node = next;
+        NonNull::new(next)
+    }
+}
+
+/// Links `node` right after `ref_node`.
+unsafe fn insert_after(node: *mut LinkedListNode, ref_node: *mut LinkedListNode) {
+    (*node).next = (*ref_node).next;
+    (*node).prev = ref_node;
+    (*ref_node).next = node;
+    (*(*node).next).prev = node;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::{align_of, size_of};
+    use core::ptr::{addr_of, addr_of_mut};
+
+    /// Nodes of a list under test, the first one is the head.
+    type Nodes = [LinkedListNode; 4];
+
+    const EMPTY: LinkedListNode = LinkedListNode {
+        next: core::ptr::null_mut(),
+        prev: core::ptr::null_mut(),
+    };
+
+    unsafe fn node(nodes: *mut Nodes, i: usize) -> &'static mut LinkedListNode {
+        &mut *addr_of_mut!((*nodes)[i])
+    }
+
+    unsafe fn list(nodes: *mut Nodes) -> LinkedList {
+        let list = LinkedList::from_head(node(nodes, 0));
+        list.init();
+        list
+    }
+
+    fn addrs(list: &LinkedList) -> [usize; 3] {
+        let mut addrs = [0; 3];
+        for (addr, node) in addrs.iter_mut().zip(list.iter()) {
+            *addr = node.as_ptr() as usize;
+        }
+        addrs
+    }
+
+    #[test]
+    fn layout() {
+        // `tListNode`: two pointers, packed
+        let ptr = size_of::<*mut LinkedListNode>();
+        assert_eq!(size_of::<LinkedListNode>(), 2 * ptr);
+        assert_eq!(align_of::<LinkedListNode>(), ptr.min(4));
+
+        let node = LinkedListNode::default();
+        let base = addr_of!(node) as usize;
+        assert_eq!(addr_of!(node.next) as usize - base, 0);
+        assert_eq!(addr_of!(node.prev) as usize - base, ptr);
+    }
+
+    #[test]
+    fn init_head() {
+        static mut NODES: Nodes = [EMPTY; 4];
+
+        unsafe {
+            let list = list(addr_of_mut!(NODES));
+            let head: *mut LinkedListNode = node(addr_of_mut!(NODES), 0);
+
+            // LST_init_head
+            assert_eq!({ NODES[0].next }, head);
+            assert_eq!({ NODES[0].prev }, head);
+            assert!(list.is_empty());
+            assert_eq!(list.len(), 0);
+            assert!(list.pop_head().is_none());
+        }
+    }
+
+    #[test]
+    fn insert_tail_and_remove_head() {
+        static mut NODES: Nodes = [EMPTY; 4];
+
+        unsafe {
+            let nodes = addr_of_mut!(NODES);
+            let list = list(nodes);
+            let a = addr_of!(NODES[1]) as usize;
+            let b = addr_of!(NODES[2]) as usize;
+            let c = addr_of!(NODES[3]) as usize;
+
+            // LST_insert_tail
+            list.push_tail(node(nodes, 1));
+            list.push_tail(node(nodes, 2));
+            list.push_tail(node(nodes, 3));
+            assert!(!list.is_empty());
+            assert_eq!(list.len(), 3);
+            assert_eq!(addrs(&list), [a, b, c]);
+
+            // Tail links back to the head
+            let head: *mut LinkedListNode = node(nodes, 0);
+            assert_eq!({ NODES[3].next }, head);
+            assert_eq!(NODES[0].prev as usize, c);
+
+            // LST_remove_head
+            assert_eq!(list.pop_head().map(|n| n as *mut _ as usize), Some(a));
+            assert_eq!(list.len(), 2);
+            assert_eq!(NODES[0].next as usize, b);
+            assert_eq!({ NODES[2].prev }, head);
+
+            assert_eq!(list.pop_head().map(|n| n as *mut _ as usize), Some(b));
+            assert_eq!(list.pop_head().map(|n| n as *mut _ as usize), Some(c));
+            assert!(list.is_empty());
+            assert!(list.pop_head().is_none());
+        }
+    }
+
+    #[test]
+    fn insert_head() {
+        static mut NODES: Nodes = [EMPTY; 4];
+
+        unsafe {
+            let nodes = addr_of_mut!(NODES);
+            let list = list(nodes);
+            let a = addr_of!(NODES[1]) as usize;
+            let b = addr_of!(NODES[2]) as usize;
+            let c = addr_of!(NODES[3]) as usize;
+
+            // LST_insert_head
+            list.push_head(node(nodes, 1));
+            list.push_head(node(nodes, 2));
+            list.push_tail(node(nodes, 3));
+            assert_eq!(addrs(&list), [b, a, c]);
+
+            assert_eq!(list.pop_head().map(|n| n as *mut _ as usize), Some(b));
+            assert_eq!(addrs(&list), [a, c, 0]);
+
+            // Nodes are forgotten
+            list.init();
+            assert!(list.is_empty());
+            assert_eq!(list.iter().count(), 0);
+        }
+    }
+}
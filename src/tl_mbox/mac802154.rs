@@ -0,0 +1,1017 @@
+//! IPCC 802.15.4 MAC channels of the MAC wireless firmware.
+//!
+//! MLME/MCPS requests are sent with `TlMbox::send_mac_request`. CPU2 accepts or rejects the
+//! request synchronously (`TlMbox::poll_mac_request`), the outcome of the request arrives later
+//! as a confirm notification. Confirms and indications are read with
+//! `TlMbox::poll_mac_notification` and must be acknowledged with `TlMbox::ack_mac_notification`
+//! before the next one can be received:
+//!
+//! ```ignore
+//! mbox.mac_init(&mut ipcc)?;
+//!
+//! mbox.send_mac_request(&mut ipcc, &ResetRequest { set_default_pib: true })?;
+//! nb::block!(mbox.poll_mac_request())?;
+//!
+//! match nb::block!(mbox.poll_mac_notification())? {
+//!     Notification::ResetCnf { status } => { /* ... */ }
+//!     _ => {}
+//! }
+//! mbox.ack_mac_notification(&mut ipcc)?;
+//! ```
+
+use core::mem::MaybeUninit;
+
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::bytes::{BufferFull, Reader, Writer};
+use crate::tl_mbox::channels;
+use crate::tl_mbox::cmd::CmdPacket;
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::EvtPacket;
+use crate::tl_mbox::{
+    Mac802154Table, MAC_802_15_4_CMD_RSP_BUFFER, MAC_802_15_4_NOTIF_ACK_BUFFER,
+    TL_MAC_802_15_4_TABLE,
+};
+
+const ST_VENDOR_OGF: u16 = 0x3f;
+const MAC_802_15_4_CMD_OPCODE_OFFSET: u16 = 0x280;
+
+/// Builds opcode of a request sent to CPU2.
+pub const fn opcode(ocf: u16) -> u16 {
+    (ST_VENDOR_OGF << 9) | (MAC_802_15_4_CMD_OPCODE_OFFSET + ocf)
+}
+
+pub const MLME_ASSOCIATE_REQ: u16 = opcode(0x00);
+pub const MLME_ASSOCIATE_RES: u16 = opcode(0x01);
+pub const MLME_DISASSOCIATE_REQ: u16 = opcode(0x02);
+pub const MLME_GET_REQ: u16 = opcode(0x03);
+pub const MLME_GTS_REQ: u16 = opcode(0x04);
+pub const MLME_ORPHAN_RES: u16 = opcode(0x05);
+pub const MLME_RESET_REQ: u16 = opcode(0x06);
+pub const MLME_RX_ENABLE_REQ: u16 = opcode(0x07);
+pub const MLME_SCAN_REQ: u16 = opcode(0x08);
+pub const MLME_SET_REQ: u16 = opcode(0x09);
+pub const MLME_START_REQ: u16 = opcode(0x0a);
+pub const MLME_SYNC_REQ: u16 = opcode(0x0b);
+pub const MLME_POLL_REQ: u16 = opcode(0x0c);
+pub const MLME_DPS_REQ: u16 = opcode(0x0d);
+pub const MLME_SOUNDING_REQ: u16 = opcode(0x0e);
+pub const MLME_CALIBRATE_REQ: u16 = opcode(0x0f);
+pub const MCPS_DATA_REQ: u16 = opcode(0x10);
+pub const MCPS_PURGE_REQ: u16 = opcode(0x11);
+
+/// IDs of confirms and indications sent by CPU2.
+pub const MLME_ASSOCIATE_CNF: u16 = 0x00;
+pub const MLME_DISASSOCIATE_CNF: u16 = 0x01;
+pub const MLME_GET_CNF: u16 = 0x02;
+pub const MLME_GTS_CNF: u16 = 0x03;
+pub const MLME_RESET_CNF: u16 = 0x04;
+pub const MLME_RX_ENABLE_CNF: u16 = 0x05;
+pub const MLME_SCAN_CNF: u16 = 0x06;
+pub const MLME_SET_CNF: u16 = 0x07;
+pub const MLME_START_CNF: u16 = 0x08;
+pub const MLME_POLL_CNF: u16 = 0x09;
+pub const MLME_DPS_CNF: u16 = 0x0a;
+pub const MLME_SOUNDING_CNF: u16 = 0x0b;
+pub const MLME_CALIBRATE_CNF: u16 = 0x0c;
+pub const MCPS_DATA_CNF: u16 = 0x0d;
+pub const MCPS_PURGE_CNF: u16 = 0x0e;
+pub const MLME_ASSOCIATE_IND: u16 = 0x0f;
+pub const MLME_DISASSOCIATE_IND: u16 = 0x10;
+pub const MLME_BEACON_NOTIFY_IND: u16 = 0x11;
+pub const MLME_COMM_STATUS_IND: u16 = 0x12;
+pub const MLME_GTS_IND: u16 = 0x13;
+pub const MLME_ORPHAN_IND: u16 = 0x14;
+pub const MLME_SYNC_LOSS_IND: u16 = 0x15;
+pub const MLME_DPS_IND: u16 = 0x16;
+pub const MCPS_DATA_IND: u16 = 0x17;
+pub const MLME_POLL_IND: u16 = 0x18;
+
+/// MAC PIB attribute IDs.
+pub mod pib {
+    pub const ACK_WAIT_DURATION: u8 = 0x40;
+    pub const ASSOCIATION_PERMIT: u8 = 0x41;
+    pub const AUTO_REQUEST: u8 = 0x42;
+    pub const BATT_LIFE_EXT: u8 = 0x43;
+    pub const BEACON_PAYLOAD: u8 = 0x45;
+    pub const BEACON_PAYLOAD_LENGTH: u8 = 0x46;
+    pub const BEACON_ORDER: u8 = 0x47;
+    pub const BSN: u8 = 0x49;
+    pub const COORD_EXTENDED_ADDRESS: u8 = 0x4a;
+    pub const COORD_SHORT_ADDRESS: u8 = 0x4b;
+    pub const DSN: u8 = 0x4c;
+    pub const MAX_CSMA_BACKOFFS: u8 = 0x4e;
+    pub const MIN_BE: u8 = 0x4f;
+    pub const PAN_ID: u8 = 0x50;
+    pub const PROMISCUOUS_MODE: u8 = 0x51;
+    pub const RX_ON_WHEN_IDLE: u8 = 0x52;
+    pub const SHORT_ADDRESS: u8 = 0x53;
+    pub const SUPERFRAME_ORDER: u8 = 0x54;
+    pub const TRANSACTION_PERSISTENCE_TIME: u8 = 0x55;
+    pub const MAX_BE: u8 = 0x57;
+    pub const MAX_FRAME_RETRIES: u8 = 0x59;
+    pub const EXTENDED_ADDRESS: u8 = 0x6f;
+}
+
+/// Maximum MSDU length of a data request.
+pub const MAX_MSDU_LEN: usize = 127;
+
+/// Maximum number of PAN descriptors in a scan confirm.
+pub const MAX_PAN_DESC_SUPPORTED: usize = 6;
+
+/// Maximum number of energy detect results in a scan confirm.
+pub const MAX_ED_SCAN_RESULTS_SUPPORTED: usize = 16;
+
+const PAN_DESCRIPTOR_LEN: usize = 20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// MAC transport hasn't been initialized with `TlMbox::mac_init`.
+    NotInitialized,
+
+    /// IPCC channels are already used by Thread or Zigbee transport.
+    ChannelsInUse,
+
+    /// Request doesn't fit into the command buffer.
+    TooLong,
+
+    /// CPU2 rejected the request.
+    Status(MacStatus),
+
+    /// Notification couldn't be decoded.
+    Malformed,
+}
+
+impl From<BufferFull> for Error {
+    fn from(_: BufferFull) -> Self {
+        Error::TooLong
+    }
+}
+
+/// MAC enumeration status as defined by IEEE 802.15.4.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MacStatus {
+    Success,
+    CounterError,
+    ImproperKeyType,
+    ImproperSecurityLevel,
+    UnsupportedLegacy,
+    UnsupportedSecurity,
+    BeaconLoss,
+    ChannelAccessFailure,
+    Denied,
+    DisableTrxFailure,
+    SecurityError,
+    FrameTooLong,
+    InvalidGts,
+    InvalidHandle,
+    InvalidParameter,
+    NoAck,
+    NoBeacon,
+    NoData,
+    NoShortAddress,
+    OutOfCap,
+    PanIdConflict,
+    Realignment,
+    TransactionExpired,
+    TransactionOverflow,
+    TxActive,
+    UnavailableKey,
+    UnsupportedAttribute,
+    InvalidAddress,
+    OnTimeTooLong,
+    PastTime,
+    TrackingOff,
+    InvalidIndex,
+    LimitReached,
+    ReadOnly,
+    ScanInProgress,
+    SuperframeOverlap,
+    Other(u8),
+}
+
+impl From<u8> for MacStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => MacStatus::Success,
+            0xdb => MacStatus::CounterError,
+            0xdc => MacStatus::ImproperKeyType,
+            0xdd => MacStatus::ImproperSecurityLevel,
+            0xde => MacStatus::UnsupportedLegacy,
+            0xdf => MacStatus::UnsupportedSecurity,
+            0xe0 => MacStatus::BeaconLoss,
+            0xe1 => MacStatus::ChannelAccessFailure,
+            0xe2 => MacStatus::Denied,
+            0xe3 => MacStatus::DisableTrxFailure,
+            0xe4 => MacStatus::SecurityError,
+            0xe5 => MacStatus::FrameTooLong,
+            0xe6 => MacStatus::InvalidGts,
+            0xe7 => MacStatus::InvalidHandle,
+            0xe8 => MacStatus::InvalidParameter,
+            0xe9 => MacStatus::NoAck,
+            0xea => MacStatus::NoBeacon,
+            0xeb => MacStatus::NoData,
+            0xec => MacStatus::NoShortAddress,
+            0xed => MacStatus::OutOfCap,
+            0xee => MacStatus::PanIdConflict,
+            0xef => MacStatus::Realignment,
+            0xf0 => MacStatus::TransactionExpired,
+            0xf1 => MacStatus::TransactionOverflow,
+            0xf2 => MacStatus::TxActive,
+            0xf3 => MacStatus::UnavailableKey,
+            0xf4 => MacStatus::UnsupportedAttribute,
+            0xf5 => MacStatus::InvalidAddress,
+            0xf6 => MacStatus::OnTimeTooLong,
+            0xf7 => MacStatus::PastTime,
+            0xf8 => MacStatus::TrackingOff,
+            0xf9 => MacStatus::InvalidIndex,
+            0xfa => MacStatus::LimitReached,
+            0xfb => MacStatus::ReadOnly,
+            0xfc => MacStatus::ScanInProgress,
+            0xfd => MacStatus::SuperframeOverlap,
+
+            other => MacStatus::Other(other),
+        }
+    }
+}
+
+impl MacStatus {
+    pub fn is_success(&self) -> bool {
+        *self == MacStatus::Success
+    }
+}
+
+/// Device address of any addressing mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MacAddress {
+    None,
+    Short(u16),
+    Extended(u64),
+}
+
+impl MacAddress {
+    fn mode(&self) -> u8 {
+        match self {
+            MacAddress::None => 0x00,
+            MacAddress::Short(_) => 0x02,
+            MacAddress::Extended(_) => 0x03,
+        }
+    }
+
+    /// Writes the address as 8-byte field.
+    fn write(&self, w: &mut Writer) -> Result<(), BufferFull> {
+        match *self {
+            MacAddress::None => w.u64(0),
+            MacAddress::Short(addr) => {
+                w.u16(addr)?;
+                w.bytes(&[0; 6])
+            }
+            MacAddress::Extended(addr) => w.u64(addr),
+        }
+    }
+
+    /// Reads 8-byte address field of the given addressing mode.
+    fn read(r: &mut Reader, mode: u8) -> Option<Self> {
+        let bytes = r.bytes(8)?;
+        let mut r = Reader::new(bytes);
+
+        match mode {
+            0x02 => Some(MacAddress::Short(r.u16()?)),
+            0x03 => Some(MacAddress::Extended(r.u64()?)),
+
+            _ => Some(MacAddress::None),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ScanType {
+    EnergyDetect = 0x00,
+    Active = 0x01,
+    Passive = 0x02,
+    Orphan = 0x03,
+}
+
+/// MLME/MCPS request that can be serialized into the command buffer.
+pub trait MacRequest {
+    const OPCODE: u16;
+
+    /// Serializes request parameters.
+    ///
+    /// Requests that carry a variable-length field put a pointer placeholder at the start
+    /// of parameters and return the field from `data`.
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error>;
+
+    /// Variable-length field referenced by the pointer at the start of parameters.
+    ///
+    /// It's copied into the command buffer after parameters and the pointer is set to it.
+    fn data(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Resets the MAC sublayer.
+#[derive(Debug, Copy, Clone)]
+pub struct ResetRequest {
+    /// Reset PIB attributes to their default values.
+    pub set_default_pib: bool,
+}
+
+impl MacRequest for ResetRequest {
+    const OPCODE: u16 = MLME_RESET_REQ;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.set_default_pib as u8)?;
+        w.bytes(&[0; 3])?;
+
+        Ok(())
+    }
+}
+
+/// Sets PIB attribute, see `pib` for attribute IDs.
+#[derive(Debug, Copy, Clone)]
+pub struct SetRequest<'a> {
+    pub pib_attribute: u8,
+    /// Little-endian attribute value.
+    pub value: &'a [u8],
+}
+
+impl<'a> MacRequest for SetRequest<'a> {
+    const OPCODE: u16 = MLME_SET_REQ;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u32(0)?; // Value pointer
+        w.u8(self.pib_attribute)?;
+        w.bytes(&[0; 3])?;
+
+        Ok(())
+    }
+
+    fn data(&self) -> Option<&[u8]> {
+        Some(self.value)
+    }
+}
+
+/// Reads PIB attribute, see `pib` for attribute IDs.
+#[derive(Debug, Copy, Clone)]
+pub struct GetRequest {
+    pub pib_attribute: u8,
+}
+
+impl MacRequest for GetRequest {
+    const OPCODE: u16 = MLME_GET_REQ;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.pib_attribute)?;
+        w.bytes(&[0; 3])?;
+
+        Ok(())
+    }
+}
+
+/// Scans channels, without security.
+#[derive(Debug, Copy, Clone)]
+pub struct ScanRequest {
+    pub scan_type: ScanType,
+    /// Bitmap of channels to scan, bit 11 is channel 11.
+    pub channels: u32,
+    /// Time spent on each channel: `aBaseSuperframeDuration * (2^n + 1)` symbols.
+    pub scan_duration: u8,
+    pub channel_page: u8,
+}
+
+impl MacRequest for ScanRequest {
+    const OPCODE: u16 = MLME_SCAN_REQ;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.scan_type as u8)?;
+        w.u8(self.scan_duration)?;
+        w.u8(self.channel_page)?;
+        // Security level
+        w.u8(0)?;
+        w.u32(self.channels)?;
+
+        // Key source, key ID mode, key index and stuffing
+        w.bytes(&[0; 8 + 1 + 1 + 2])?;
+
+        Ok(())
+    }
+}
+
+/// Starts a PAN or begins using a new superframe configuration, without security.
+#[derive(Debug, Copy, Clone)]
+pub struct StartRequest {
+    pub pan_id: u16,
+    pub channel_number: u8,
+    pub channel_page: u8,
+    pub start_time: u32,
+    /// 15 for a nonbeacon-enabled PAN.
+    pub beacon_order: u8,
+    pub superframe_order: u8,
+    pub pan_coordinator: bool,
+    pub battery_life_extension: bool,
+    pub coord_realignment: bool,
+}
+
+impl MacRequest for StartRequest {
+    const OPCODE: u16 = MLME_START_REQ;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u16(self.pan_id)?;
+        w.u8(self.channel_number)?;
+        w.u8(self.channel_page)?;
+        w.u32(self.start_time)?;
+        w.u8(self.beacon_order)?;
+        w.u8(self.superframe_order)?;
+        w.u8(self.pan_coordinator as u8)?;
+        w.u8(self.battery_life_extension as u8)?;
+        w.u8(self.coord_realignment as u8)?;
+
+        // Coordinator realignment and beacon security parameters and stuffing
+        w.bytes(&[0; 11 + 11 + 1])?;
+
+        Ok(())
+    }
+}
+
+/// Requests association with a coordinator, without security.
+#[derive(Debug, Copy, Clone)]
+pub struct AssociateRequest {
+    pub channel_number: u8,
+    pub channel_page: u8,
+    pub coord_address: MacAddress,
+    pub coord_pan_id: u16,
+    pub capability_information: u8,
+}
+
+impl MacRequest for AssociateRequest {
+    const OPCODE: u16 = MLME_ASSOCIATE_REQ;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        w.u8(self.channel_number)?;
+        w.u8(self.channel_page)?;
+        w.u8(self.coord_address.mode())?;
+        w.u8(self.capability_information)?;
+        w.u16(self.coord_pan_id)?;
+
+        // Security level, key ID mode and key source
+        w.bytes(&[0; 2 + 8])?;
+
+        self.coord_address.write(w)?;
+
+        // Key index and stuffing
+        w.bytes(&[0; 1 + 3])?;
+
+        Ok(())
+    }
+}
+
+/// Transfers MSDU to another device, without security.
+#[derive(Debug, Copy, Clone)]
+pub struct DataRequest<'a> {
+    /// Addressing mode of the source: 0x02 for short, 0x03 for extended address.
+    pub src_addr_mode: u8,
+    pub dst_pan_id: u16,
+    pub dst_address: MacAddress,
+    /// Handle reported back in the data confirm.
+    pub msdu_handle: u8,
+    pub ack_tx: bool,
+    pub indirect_tx: bool,
+    pub msdu: &'a [u8],
+}
+
+impl<'a> MacRequest for DataRequest<'a> {
+    const OPCODE: u16 = MCPS_DATA_REQ;
+
+    fn write_params(&self, w: &mut Writer) -> Result<(), Error> {
+        if self.msdu.len() > MAX_MSDU_LEN {
+            return Err(Error::TooLong);
+        }
+
+        w.u32(0)?; // MSDU pointer
+        w.u8(self.src_addr_mode)?;
+        w.u8(self.dst_address.mode())?;
+        w.u16(self.dst_pan_id)?;
+        self.dst_address.write(w)?;
+        w.u8(self.msdu.len() as u8)?;
+        w.u8(self.msdu_handle)?;
+        w.u8(self.ack_tx as u8)?;
+        w.u8(0)?; // GTS
+        w.u8(self.indirect_tx as u8)?;
+
+        // Security level, key ID mode, key index, key source and UWB/ranging parameters
+        w.bytes(&[0; 3 + 8 + 4])?;
+
+        Ok(())
+    }
+
+    fn data(&self) -> Option<&[u8]> {
+        Some(self.msdu)
+    }
+}
+
+/// PAN descriptor of a beacon received during scan.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PanDescriptor {
+    pub coord_address: MacAddress,
+    pub coord_pan_id: u16,
+    pub logical_channel: u8,
+    pub superframe_spec: u16,
+    pub gts_permit: bool,
+    pub link_quality: u8,
+    pub time_stamp: u32,
+}
+
+impl PanDescriptor {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader::new(bytes);
+
+        let address = r.bytes(8)?;
+        let coord_pan_id = r.u16()?;
+        let coord_addr_mode = r.u8()?;
+
+        Some(PanDescriptor {
+            coord_address: MacAddress::read(&mut Reader::new(address), coord_addr_mode)?,
+            coord_pan_id,
+            logical_channel: r.u8()?,
+            superframe_spec: r.u16()?,
+            gts_permit: r.u8()? != 0,
+            link_quality: r.u8()?,
+            time_stamp: r.u32()?,
+        })
+    }
+}
+
+/// Confirm or indication sent by CPU2, borrowed from the notification buffer.
+#[derive(Debug, Copy, Clone)]
+pub enum Notification<'a> {
+    ResetCnf {
+        status: MacStatus,
+    },
+
+    SetCnf {
+        status: MacStatus,
+        pib_attribute: u8,
+    },
+
+    GetCnf {
+        status: MacStatus,
+        pib_attribute: u8,
+        /// Little-endian attribute value.
+        value: &'a [u8],
+    },
+
+    ScanCnf {
+        status: MacStatus,
+        scan_type: u8,
+        channel_page: u8,
+        unscanned_channels: u32,
+        /// Energy levels for energy detect scan, one per scanned channel.
+        energy_detect_list: &'a [u8],
+        pan_descriptors: PanDescriptors<'a>,
+    },
+
+    StartCnf {
+        status: MacStatus,
+    },
+
+    AssociateCnf {
+        status: MacStatus,
+        assoc_short_address: u16,
+    },
+
+    DataCnf {
+        status: MacStatus,
+        msdu_handle: u8,
+        time_stamp: u32,
+    },
+
+    AssociateInd {
+        device_address: u64,
+        capability_information: u8,
+    },
+
+    DataInd {
+        src_pan_id: u16,
+        src_address: MacAddress,
+        dst_pan_id: u16,
+        dst_address: MacAddress,
+        link_quality: u8,
+        dsn: u8,
+        time_stamp: u32,
+        msdu: &'a [u8],
+    },
+
+    CommStatusInd {
+        pan_id: u16,
+        src_address: MacAddress,
+        dst_address: MacAddress,
+        status: MacStatus,
+    },
+
+    /// Notification that isn't decoded, with its raw parameters.
+    Other {
+        id: u16,
+        params: &'a [u8],
+    },
+}
+
+/// PAN descriptors of a scan confirm.
+#[derive(Debug, Copy, Clone)]
+pub struct PanDescriptors<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for PanDescriptors<'a> {
+    type Item = PanDescriptor;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.len() < PAN_DESCRIPTOR_LEN {
+            return None;
+        }
+
+        let (descriptor, rest) = self.bytes.split_at(PAN_DESCRIPTOR_LEN);
+        self.bytes = rest;
+
+        PanDescriptor::parse(descriptor)
+    }
+}
+
+impl<'a> Notification<'a> {
+    /// Decodes notification ID and its parameters.
+    ///
+    /// # Safety
+    ///
+    /// Get confirm and data indication refer to CPU2 memory by pointers, these must be valid
+    /// while the notification is borrowed.
+    unsafe fn parse(bytes: &'a [u8]) -> Option<Self> {
+        let mut r = Reader::new(bytes);
+        let id = r.u16()?;
+
+        let notification = match id {
+            MLME_RESET_CNF => Notification::ResetCnf {
+                status: r.u8()?.into(),
+            },
+
+            MLME_SET_CNF => Notification::SetCnf {
+                status: r.u8()?.into(),
+                pib_attribute: r.u8()?,
+            },
+
+            MLME_GET_CNF => {
+                let value_ptr = r.u32()? as usize as *const u8;
+                let status = r.u8()?.into();
+                let pib_attribute = r.u8()?;
+                let value_len = r.u8()? as usize;
+
+                let value = if value_ptr.is_null() {
+                    &[]
+                } else {
+                    core::slice::from_raw_parts(value_ptr, value_len)
+                };
+
+                Notification::GetCnf {
+                    status,
+                    pib_attribute,
+                    value,
+                }
+            }
+
+            MLME_SCAN_CNF => {
+                let status = r.u8()?.into();
+                let scan_type = r.u8()?;
+                let channel_page = r.u8()?;
+                let unscanned_channels = r.u32()?;
+                let result_list_size = r.u8()? as usize;
+                let energy_detect_list = r.bytes(MAX_ED_SCAN_RESULTS_SUPPORTED)?;
+                let pan_descriptor_list = r.bytes(PAN_DESCRIPTOR_LEN * MAX_PAN_DESC_SUPPORTED)?;
+
+                // Result list is either energy levels or PAN descriptors, depending on scan type
+                let (energy_detect_list, pan_descriptors) =
+                    if scan_type == ScanType::EnergyDetect as u8 {
+                        (
+                            energy_detect_list.get(..result_list_size)?,
+                            &pan_descriptor_list[..0],
+                        )
+                    } else {
+                        let len = result_list_size.min(MAX_PAN_DESC_SUPPORTED);
+                        (
+                            &energy_detect_list[..0],
+                            &pan_descriptor_list[..len * PAN_DESCRIPTOR_LEN],
+                        )
+                    };
+
+                Notification::ScanCnf {
+                    status,
+                    scan_type,
+                    channel_page,
+                    unscanned_channels,
+                    energy_detect_list,
+                    pan_descriptors: PanDescriptors {
+                        bytes: pan_descriptors,
+                    },
+                }
+            }
+
+            MLME_START_CNF => Notification::StartCnf {
+                status: r.u8()?.into(),
+            },
+
+            MLME_ASSOCIATE_CNF => {
+                let assoc_short_address = r.u16()?;
+
+                Notification::AssociateCnf {
+                    status: r.u8()?.into(),
+                    assoc_short_address,
+                }
+            }
+
+            MCPS_DATA_CNF => {
+                let msdu_handle = r.u8()?;
+                let time_stamp = r.u32()?;
+                let _ranging_received = r.u8()?;
+
+                Notification::DataCnf {
+                    status: r.u8()?.into(),
+                    msdu_handle,
+                    time_stamp,
+                }
+            }
+
+            MLME_ASSOCIATE_IND => Notification::AssociateInd {
+                device_address: r.u64()?,
+                capability_information: r.u8()?,
+            },
+
+            MCPS_DATA_IND => {
+                let msdu_ptr = r.u32()? as usize as *const u8;
+                let src_addr_mode = r.u8()?;
+                let src_pan_id = r.u16()?;
+                let src_address = MacAddress::read(&mut r, src_addr_mode)?;
+                let dst_addr_mode = r.u8()?;
+                let dst_pan_id = r.u16()?;
+                let dst_address = MacAddress::read(&mut r, dst_addr_mode)?;
+                let msdu_len = r.u8()? as usize;
+                let link_quality = r.u8()?;
+                let dsn = r.u8()?;
+                let time_stamp = r.u32()?;
+
+                let msdu = if msdu_ptr.is_null() {
+                    &[]
+                } else {
+                    core::slice::from_raw_parts(msdu_ptr, msdu_len)
+                };
+
+                Notification::DataInd {
+                    src_pan_id,
+                    src_address,
+                    dst_pan_id,
+                    dst_address,
+                    link_quality,
+                    dsn,
+                    time_stamp,
+                    msdu,
+                }
+            }
+
+            MLME_COMM_STATUS_IND => {
+                let pan_id = r.u16()?;
+                let src_addr_mode = r.u8()?;
+                let src_address = MacAddress::read(&mut r, src_addr_mode)?;
+                let dst_addr_mode = r.u8()?;
+                let dst_address = MacAddress::read(&mut r, dst_addr_mode)?;
+
+                Notification::CommStatusInd {
+                    pan_id,
+                    src_address,
+                    dst_address,
+                    status: r.u8()?.into(),
+                }
+            }
+
+            _ => Notification::Other {
+                id,
+                params: r.rest(),
+            },
+        };
+
+        Some(notification)
+    }
+}
+
+pub(super) struct Mac802154 {
+    /// Request waits for CPU2 to accept it.
+    cmd_pending: bool,
+
+    /// Status of the last request is in the command buffer.
+    rsp_ready: bool,
+
+    /// Notification is in the notification buffer and not acknowledged yet.
+    notification_pending: bool,
+}
+
+impl Mac802154 {
+    pub(super) fn new(ipcc: &mut impl IpccChannels) -> Self {
+        unsafe {
+            MAC_802_15_4_CMD_RSP_BUFFER = MaybeUninit::zeroed();
+            MAC_802_15_4_NOTIF_ACK_BUFFER = MaybeUninit::zeroed();
+
+            TL_MAC_802_15_4_TABLE = MaybeUninit::new(Mac802154Table {
+                p_cmdrsp_buffer: MAC_802_15_4_CMD_RSP_BUFFER.as_ptr().cast(),
+                p_notack_buffer: MAC_802_15_4_NOTIF_ACK_BUFFER.as_ptr().cast(),
+                evt_queue: core::ptr::null(),
+            });
+        }
+
+        ipcc.c1_set_rx_channel(
+            channels::cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL,
+            true,
+        );
+
+        Mac802154 {
+            cmd_pending: false,
+            rsp_ready: false,
+            notification_pending: false,
+        }
+    }
+
+    /// Serializes `req` into the command buffer and notifies CPU2.
+    pub(super) fn send_request<R: MacRequest>(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        req: &R,
+    ) -> nb::Result<(), Error> {
+        if self.cmd_pending {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        unsafe {
+            let packet: *mut CmdPacket = MAC_802_15_4_CMD_RSP_BUFFER.as_mut_ptr();
+            let cmd_serial = &mut (*packet).cmdserial;
+
+            let payload = &mut cmd_serial.cmd.payload;
+            let payload_addr = payload.as_ptr() as usize;
+
+            let mut w = Writer::new(&mut payload[..]);
+            req.write_params(&mut w)?;
+
+            // Data follows parameters in the same buffer, CPU2 reads it by the pointer
+            let data_offset = w.len();
+            if let Some(data) = req.data() {
+                w.bytes(data).map_err(Error::from)?;
+            }
+            let payload_len = w.len();
+
+            if req.data().is_some() {
+                let data_ptr = (payload_addr + data_offset) as u32;
+                payload[..4].copy_from_slice(&data_ptr.to_le_bytes());
+            }
+
+            cmd_serial.ty = TlPacketType::OtCmd as u8;
+            cmd_serial.cmd.cmd_code = R::OPCODE;
+            cmd_serial.cmd.payload_len = payload_len as u8;
+        }
+
+        self.cmd_pending = true;
+        self.rsp_ready = false;
+
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_MAC_802_15_4_CMD_RSP_CHANNEL);
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_MAC_802_15_4_CMD_RSP_CHANNEL, true);
+
+        Ok(())
+    }
+
+    /// CPU2 has accepted or rejected the request.
+    pub(super) fn cmd_rsp_handler(&mut self, ipcc: &mut impl IpccChannels) {
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_MAC_802_15_4_CMD_RSP_CHANNEL, false);
+
+        self.cmd_pending = false;
+        self.rsp_ready = true;
+    }
+
+    /// Returns `Ok` once CPU2 has accepted the last request.
+    pub(super) fn poll_request(&mut self) -> nb::Result<(), Error> {
+        if !self.rsp_ready {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.rsp_ready = false;
+
+        let status: MacStatus = unsafe {
+            let packet: *const EvtPacket = MAC_802_15_4_CMD_RSP_BUFFER.as_ptr().cast();
+            (*packet).evt_serial.evt.payload[0].into()
+        };
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(nb::Error::Other(Error::Status(status)))
+        }
+    }
+
+    pub(super) fn notification_handler(&mut self, ipcc: &mut impl IpccChannels) {
+        // Keep the notification in the buffer until the application acknowledges it
+        ipcc.c1_set_rx_channel(
+            channels::cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL,
+            false,
+        );
+
+        self.notification_pending = true;
+    }
+
+    pub(super) fn poll_notification(&self) -> nb::Result<Notification<'_>, Error> {
+        if !self.notification_pending {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        unsafe {
+            let packet: *const EvtPacket = MAC_802_15_4_NOTIF_ACK_BUFFER.as_ptr().cast();
+            let payload: *const u8 = (*packet).evt_serial.evt.payload.as_ptr();
+            let buf = core::slice::from_raw_parts(payload, 255);
+
+            Notification::parse(buf).ok_or(nb::Error::Other(Error::Malformed))
+        }
+    }
+
+    /// Acknowledges the notification so that CPU2 can send the next one.
+    pub(super) fn ack_notification(&mut self, ipcc: &mut impl IpccChannels) {
+        if !self.notification_pending {
+            return;
+        }
+
+        unsafe {
+            let packet: *mut EvtPacket = MAC_802_15_4_NOTIF_ACK_BUFFER.as_mut_ptr().cast();
+            (*packet).evt_serial.kind = TlPacketType::OtAck as u8;
+        }
+
+        self.notification_pending = false;
+
+        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL);
+        ipcc.c1_set_rx_channel(
+            channels::cpu2::IPCC_MAC_802_15_4_NOTIFICATION_ACK_CHANNEL,
+            true,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_opcodes() {
+        // MSG_M4TOM0_MAC_* IDs on top of the vendor MAC opcode base 0x7e80
+        let opcodes = [
+            (MLME_ASSOCIATE_REQ, 0x7e80),
+            (MLME_ASSOCIATE_RES, 0x7e81),
+            (MLME_DISASSOCIATE_REQ, 0x7e82),
+            (MLME_GET_REQ, 0x7e83),
+            (MLME_GTS_REQ, 0x7e84),
+            (MLME_ORPHAN_RES, 0x7e85),
+            (MLME_RESET_REQ, 0x7e86),
+            (MLME_RX_ENABLE_REQ, 0x7e87),
+            (MLME_SCAN_REQ, 0x7e88),
+            (MLME_SET_REQ, 0x7e89),
+            (MLME_START_REQ, 0x7e8a),
+            (MLME_SYNC_REQ, 0x7e8b),
+            (MLME_POLL_REQ, 0x7e8c),
+            (MLME_DPS_REQ, 0x7e8d),
+            (MLME_SOUNDING_REQ, 0x7e8e),
+            (MLME_CALIBRATE_REQ, 0x7e8f),
+            (MCPS_DATA_REQ, 0x7e90),
+            (MCPS_PURGE_REQ, 0x7e91),
+        ];
+        for &(opcode, expected) in opcodes.iter() {
+            assert_eq!(opcode, expected);
+        }
+
+        assert_eq!(ResetRequest::OPCODE, 0x7e86);
+        assert_eq!(SetRequest::OPCODE, 0x7e89);
+        assert_eq!(GetRequest::OPCODE, 0x7e83);
+        assert_eq!(ScanRequest::OPCODE, 0x7e88);
+        assert_eq!(StartRequest::OPCODE, 0x7e8a);
+        assert_eq!(AssociateRequest::OPCODE, 0x7e80);
+        assert_eq!(DataRequest::OPCODE, 0x7e90);
+    }
+
+    #[test]
+    fn scan_request_layout() {
+        let req = ScanRequest {
+            scan_type: ScanType::Active,
+            channels: 0x07ff_f800,
+            scan_duration: 5,
+            channel_page: 0,
+        };
+
+        let mut buf = [0xaa; 32];
+        let mut w = Writer::new(&mut buf);
+        req.write_params(&mut w).unwrap();
+        let len = w.len();
+
+        // MAC_scanReq_t
+        assert_eq!(
+            &buf[..len],
+            &[0x01, 0x05, 0x00, 0x00, 0x00, 0xf8, 0xff, 0x07, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,]
+        );
+    }
+}
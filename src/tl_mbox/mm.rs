@@ -3,15 +3,13 @@
 use core::mem::MaybeUninit;
 
 use super::channels::cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL;
-use super::unsafe_linked_list::{
-    LST_init_head, LST_insert_tail, LST_is_empty, LST_remove_head, LinkedListNode,
-};
+use super::linked_list::LinkedList;
 use super::{
     MemManagerTable, BLE_SPARE_EVT_BUF, EVT_POOL, FREE_BUF_QUEUE, LOCAL_FREE_BUF_QUEUE, POOL_SIZE,
-    SYS_SPARE_EVT_BUF, TL_MEM_MANAGER_TABLE,
+    SYS_SPARE_EVT_BUF, TL_MEM_MANAGER_TABLE, TRACES_EVT_POOL, TRACES_POOL_SIZE,
 };
 
-use crate::ipcc::Ipcc;
+use crate::ipcc::IpccChannels;
 use crate::tl_mbox::evt::EvtPacket;
 use crate::tl_mbox::TL_REF_TABLE;
 
@@ -21,17 +19,19 @@ impl MemoryManager {
     pub fn new() -> Self {
         // Configure MemManager
         unsafe {
-            LST_init_head(FREE_BUF_QUEUE.as_mut_ptr());
-            LST_init_head(LOCAL_FREE_BUF_QUEUE.as_mut_ptr());
+            LinkedList::from_head(FREE_BUF_QUEUE.as_mut_ptr()).init();
+        }
+        local_free_buf_queue().init();
 
+        unsafe {
             TL_MEM_MANAGER_TABLE = MaybeUninit::new(MemManagerTable {
                 spare_ble_buffer: BLE_SPARE_EVT_BUF.as_ptr().cast(),
                 spare_sys_buffer: SYS_SPARE_EVT_BUF.as_ptr().cast(),
                 blepool: EVT_POOL.as_ptr().cast(),
                 blepoolsize: POOL_SIZE as u32,
                 pevt_free_buffer_queue: FREE_BUF_QUEUE.as_mut_ptr(),
-                traces_evt_pool: core::ptr::null(),
-                tracespoolsize: 0,
+                traces_evt_pool: TRACES_EVT_POOL.as_ptr().cast(),
+                tracespoolsize: TRACES_POOL_SIZE as u32,
             });
         }
 
@@ -39,43 +39,44 @@ impl MemoryManager {
     }
 }
 
-pub fn evt_drop(evt: *mut EvtPacket, ipcc: &mut Ipcc) {
-    unsafe {
-        let list_node: *mut _ = evt.cast();
+pub fn evt_drop(evt: *mut EvtPacket, ipcc: &mut impl IpccChannels) {
+    // Packet header is the list node
+    local_free_buf_queue().push_tail(unsafe { &mut *evt.cast() });
 
-        LST_insert_tail(LOCAL_FREE_BUF_QUEUE.as_mut_ptr(), list_node);
+    let channel_is_busy = ipcc.c1_is_active_flag(IPCC_MM_RELEASE_BUFFER_CHANNEL);
 
-        let channel_is_busy = ipcc.c1_is_active_flag(IPCC_MM_RELEASE_BUFFER_CHANNEL);
-
-        // Postpone event buffer freeing to IPCC interrupt handler
-        if channel_is_busy {
-            ipcc.c1_set_tx_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL, true);
-        } else {
-            send_free_buf();
-            ipcc.c1_set_flag_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL);
-        }
+    // Postpone event buffer freeing to IPCC interrupt handler
+    if channel_is_busy {
+        ipcc.c1_set_tx_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL, true);
+    } else {
+        send_free_buf();
+        ipcc.c1_set_flag_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL);
     }
 }
 
 /// Gives free event buffers back to the CPU2 from local buffer queue.
 pub fn send_free_buf() {
-    unsafe {
-        let mut node_ptr: *mut LinkedListNode = core::ptr::null_mut();
-        let node_ptr_ptr: *mut *mut LinkedListNode = &mut node_ptr;
+    let free_buf_queue = unsafe {
+        LinkedList::from_head((&*(*TL_REF_TABLE.as_ptr()).mem_manager_table).pevt_free_buffer_queue)
+    };
 
-        while !LST_is_empty(LOCAL_FREE_BUF_QUEUE.as_mut_ptr()) {
-            LST_remove_head(LOCAL_FREE_BUF_QUEUE.as_mut_ptr(), node_ptr_ptr);
-            LST_insert_tail(
-                (&*(*TL_REF_TABLE.as_ptr()).mem_manager_table).pevt_free_buffer_queue,
-                node_ptr,
-            );
-        }
+    while let Some(node) = local_free_buf_queue().pop_head() {
+        free_buf_queue.push_tail(node);
     }
 }
 
 /// Free buffer channel interrupt handler.
-pub fn free_buf_handler(ipcc: &mut Ipcc) {
+pub fn free_buf_handler(ipcc: &mut impl IpccChannels) {
     ipcc.c1_set_tx_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL, false);
-    send_free_buf();
-    ipcc.c1_set_flag_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL);
+
+    // Buffers may have been given back already when the channel was free
+    if !local_free_buf_queue().is_empty() {
+        send_free_buf();
+        ipcc.c1_set_flag_channel(IPCC_MM_RELEASE_BUFFER_CHANNEL);
+    }
+}
+
+/// Buffers released by CPU1 that CPU2 hasn't been told about yet.
+fn local_free_buf_queue() -> LinkedList {
+    unsafe { LinkedList::from_head(LOCAL_FREE_BUF_QUEUE.as_mut_ptr()) }
 }
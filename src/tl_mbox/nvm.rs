@@ -0,0 +1,525 @@
+//! Persistence of BLE NVM data (the bonding database) in on-chip flash.
+//!
+//! With `shci::CONFIG1_BLE_NVM_DATA_TO_SRAM`, CPU2 keeps BLE NVM data in the `BLE_NVM_SRAM`
+//! buffer in the shared RAM and reports every change with `SysEvent::BleNvmRamUpdate`, instead of
+//! writing flash itself. The buffer size is set in bytes with the `STM32WB_TL_BLE_NVM_SRAM_SIZE`
+//! environment variable at build time, zero (the default) leaves it out.
+//!
+//! `BleNvm` stores the buffer into one of two flash slots in turn, each with a header holding a
+//! sequence number and CRC written after the data. A write interrupted by a reset or power loss
+//! leaves the previous copy intact, and the newest valid copy is restored at boot.
+//!
+//! CPU2 is told about the flash operations with `shci_c2_flash_erase_activity`, but the flash
+//! passed to `store` must follow ST's CPU1/CPU2 flash access protocol as well, which `BleNvm`
+//! doesn't do for lack of a hardware semaphore (HSEM) driver:
+//!
+//! * hardware semaphore 2 is held for the whole `store`, so that CPU2 doesn't access flash
+//!   meanwhile;
+//! * while the CPU2 radio is active, every single page erase and write waits while CPU2 holds
+//!   semaphore 7 or, with `FlashActivityControl::Pes` selected by
+//!   `shci_c2_set_flash_activity_control`, while `FLASH_SR.PESD` is set.
+//!
+//! ```ignore
+//! let mut nvm = BleNvm::new(FlashPage(0xc0), 1)?;
+//!
+//! // Before CPU2 is configured
+//! nvm.restore(&flash);
+//! let mut param = ShciConfigParam { ... };
+//! nvm.configure(&mut param);
+//! nb::block!(mbox.poll_shci_cmd(&shci_c2_config(&mut ipcc, &param)))?;
+//!
+//! // ... for every system event `evt`:
+//! nvm.process(&evt);
+//! if nvm.is_dirty() {
+//!     nb::block!(mbox.poll_shci_cmd(&shci_c2_flash_erase_activity(&mut ipcc, true)))?;
+//!     // `flash` takes the semaphores, see above
+//!     let result = nvm.store(&mut flash);
+//!     nb::block!(mbox.poll_shci_cmd(&shci_c2_flash_erase_activity(&mut ipcc, false)))?;
+//!     result?;
+//! }
+//! ```
+//!
+//! Slots must be in pages that are not used by the application or CPU2 firmware.
+
+use crate::flash::{self, FlashPage, Read, WriteErase};
+use crate::tl_mbox::shci::{
+    ShciConfigParam, SysEvent, CONFIG1_BLE_NVM_DATA_TO_SRAM, EVT_MASK1_SYNCHRO_BLE_NVM_RAM,
+};
+use crate::tl_mbox::{BLE_NVM_SRAM, CFG_BLE_NVM_SRAM_SIZE};
+
+/// Size of BLE NVM data, as configured at build time.
+pub const BLE_NVM_SRAM_SIZE: usize = CFG_BLE_NVM_SRAM_SIZE;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Marks a slot header, "BNV1".
+const SLOT_MAGIC: u32 = 0x3156_4e42;
+
+/// Magic, sequence number, data length and CRC.
+const HEADER_SIZE: usize = 16;
+
+/// Data is copied between the RAM and flash in chunks of this size.
+const CHUNK_SIZE: usize = 64;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Error {
+    /// BLE NVM data buffer is disabled, see `STM32WB_TL_BLE_NVM_SRAM_SIZE`.
+    Disabled,
+
+    /// Slot doesn't fit the header and BLE NVM data.
+    SlotTooSmall,
+
+    Flash(flash::Error),
+}
+
+impl From<flash::Error> for Error {
+    fn from(e: flash::Error) -> Self {
+        Error::Flash(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Header {
+    magic: u32,
+    seq: u32,
+    len: u32,
+    crc: u32,
+}
+
+impl Header {
+    fn from_bytes(buf: &[u8; HEADER_SIZE]) -> Self {
+        let word = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+
+        Header {
+            magic: word(0),
+            seq: word(4),
+            len: word(8),
+            crc: word(12),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0; HEADER_SIZE];
+        buf[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.len.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        buf
+    }
+}
+
+/// BLE NVM data stored in two flash slots.
+#[derive(Debug)]
+pub struct BleNvm {
+    first_page: usize,
+    pages_per_slot: usize,
+
+    /// Slot with the newest copy and its sequence number.
+    current: Option<(usize, u32)>,
+
+    /// CPU2 has updated the data since it was stored.
+    dirty: bool,
+}
+
+impl BleNvm {
+    /// Uses `2 * pages_per_slot` flash pages starting at `first_page` for the two slots.
+    pub fn new(first_page: FlashPage, pages_per_slot: usize) -> Result<Self, Error> {
+        if BLE_NVM_SRAM_SIZE == 0 {
+            return Err(Error::Disabled);
+        }
+
+        if pages_per_slot * PAGE_SIZE < HEADER_SIZE + BLE_NVM_SRAM_SIZE {
+            return Err(Error::SlotTooSmall);
+        }
+
+        Ok(BleNvm {
+            first_page: first_page.0,
+            pages_per_slot,
+            current: None,
+            dirty: false,
+        })
+    }
+
+    /// Sets up `param` of `shci_c2_config` to keep BLE NVM data in the shared RAM buffer and
+    /// report its updates.
+    pub fn configure(&self, param: &mut ShciConfigParam) {
+        param.config1 |= CONFIG1_BLE_NVM_DATA_TO_SRAM;
+        param.evt_mask1 |= EVT_MASK1_SYNCHRO_BLE_NVM_RAM;
+        param.ble_nvm_ram_address = unsafe { BLE_NVM_SRAM.as_ptr() } as u32;
+    }
+
+    /// Copies the newest valid copy from flash into the shared RAM buffer.
+    ///
+    /// Must be called before CPU2 is configured. Returns `false` if neither slot holds a valid
+    /// copy, the buffer is erased then and CPU2 starts with an empty database.
+    pub fn restore(&mut self, flash: &impl Read) -> bool {
+        let ram = ram();
+
+        self.current = (0..2)
+            .filter_map(|slot| self.read_header(flash, slot).map(|seq| (slot, seq)))
+            .fold(None, |newest, (slot, seq)| match newest {
+                Some((_, newest_seq)) if !is_newer(seq, newest_seq) => newest,
+
+                _ => Some((slot, seq)),
+            });
+
+        match self.current {
+            Some((slot, _)) => {
+                flash.read(self.slot_address(slot) + HEADER_SIZE, ram);
+                true
+            }
+
+            None => {
+                ram.iter_mut().for_each(|b| *b = 0xff);
+                false
+            }
+        }
+    }
+
+    /// Marks the data to be stored if `event` reports its update.
+    pub fn process(&mut self, event: &SysEvent) {
+        if let SysEvent::BleNvmRamUpdate { .. } = event {
+            self.dirty = true;
+        }
+    }
+
+    /// Returns `true` if CPU2 has updated the data since it was stored or restored.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Writes the shared RAM buffer into the older slot.
+    ///
+    /// CPU2 must be told about the flash erase with `shci_c2_flash_erase_activity` beforehand,
+    /// and `flash` must take the hardware semaphores of the flash access protocol described in
+    /// the module documentation. Updates reported while storing mark the data dirty again.
+    pub fn store<F>(&mut self, flash: &mut F) -> Result<(), Error>
+    where
+        F: WriteErase<NativeType = u64>,
+    {
+        self.dirty = false;
+
+        let result = self.write_slot(flash);
+        if result.is_err() {
+            self.dirty = true;
+        }
+
+        result
+    }
+
+    fn write_slot<F>(&mut self, flash: &mut F) -> Result<(), Error>
+    where
+        F: WriteErase<NativeType = u64>,
+    {
+        let (slot, seq) = match self.current {
+            Some((slot, seq)) => (1 - slot, seq.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        let first_page = self.first_page + slot * self.pages_per_slot;
+        for page in first_page..first_page + self.pages_per_slot {
+            flash.erase_page(FlashPage(page))?;
+        }
+
+        // The slot is invalid until its header is written after the data
+        let address = self.slot_address(slot);
+        let mut crc = CRC_INIT;
+        let mut chunk = [0u8; CHUNK_SIZE];
+        for (i, data) in ram().chunks(CHUNK_SIZE).enumerate() {
+            // CPU2 may update the data meanwhile, so the CRC is taken over the written copy
+            let chunk = &mut chunk[..data.len()];
+            chunk.copy_from_slice(data);
+            crc = crc32(crc, chunk);
+
+            flash.write(address + HEADER_SIZE + i * CHUNK_SIZE, chunk)?;
+        }
+
+        let header = Header {
+            magic: SLOT_MAGIC,
+            seq,
+            len: BLE_NVM_SRAM_SIZE as u32,
+            crc: !crc,
+        };
+        flash.write(address, &header.to_bytes())?;
+
+        self.current = Some((slot, seq));
+        Ok(())
+    }
+
+    /// Returns the sequence number of a valid copy in `slot`.
+    fn read_header(&self, flash: &impl Read, slot: usize) -> Option<u32> {
+        let address = self.slot_address(slot);
+
+        let mut buf = [0u8; HEADER_SIZE];
+        flash.read(address, &mut buf);
+        let header = Header::from_bytes(&buf);
+
+        if header.magic != SLOT_MAGIC || header.len as usize != BLE_NVM_SRAM_SIZE {
+            return None;
+        }
+
+        let mut crc = CRC_INIT;
+        let mut chunk = [0u8; CHUNK_SIZE];
+        for offset in (0..BLE_NVM_SRAM_SIZE).step_by(CHUNK_SIZE) {
+            let chunk = &mut chunk[..CHUNK_SIZE.min(BLE_NVM_SRAM_SIZE - offset)];
+            flash.read(address + HEADER_SIZE + offset, chunk);
+            crc = crc32(crc, chunk);
+        }
+
+        if !crc == header.crc {
+            Some(header.seq)
+        } else {
+            None
+        }
+    }
+
+    fn slot_address(&self, slot: usize) -> usize {
+        FlashPage(self.first_page + slot * self.pages_per_slot).to_address()
+    }
+}
+
+/// Shared RAM buffer with BLE NVM data.
+fn ram() -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(BLE_NVM_SRAM.as_mut_ptr().cast(), BLE_NVM_SRAM_SIZE) }
+}
+
+/// Compares sequence numbers that may have wrapped around.
+fn is_newer(seq: u32, than: u32) -> bool {
+    (seq.wrapping_sub(than) as i32) > 0
+}
+
+const CRC_INIT: u32 = 0xffff_ffff;
+
+/// Updates CRC-32 (IEEE 802.3) with `data`. The final value is inverted.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configure_enables_nvm_ram_update() {
+        // `new` fails unless the buffer is enabled at build time
+        let nvm = BleNvm {
+            first_page: 0xc0,
+            pages_per_slot: 1,
+            current: None,
+            dirty: false,
+        };
+
+        let mut param = ShciConfigParam {
+            config1: 0,
+            evt_mask1: 0,
+            ble_nvm_ram_address: 0,
+            thread_nvm_ram_address: 0,
+            revision_id: 0,
+            device_id: 0,
+        };
+        nvm.configure(&mut param);
+
+        assert_eq!(param.config1, CONFIG1_BLE_NVM_DATA_TO_SRAM);
+        // Bit 1 of SHCI_C2_CONFIG_EVTMASK1, bit 0 is the error notification
+        assert_eq!(param.evt_mask1, 0x02);
+        assert_eq!(param.ble_nvm_ram_address, unsafe { BLE_NVM_SRAM.as_ptr() }
+            as u32);
+    }
+
+    #[test]
+    fn process_marks_dirty() {
+        let mut nvm = BleNvm {
+            first_page: 0xc0,
+            pages_per_slot: 1,
+            current: None,
+            dirty: false,
+        };
+
+        nvm.process(&SysEvent::BleNvmRamUpdate {
+            start_address: 0,
+            size: 0,
+        });
+        assert!(nvm.is_dirty());
+    }
+
+    const FIRST_PAGE: usize = 0xc0;
+
+    /// Two flash pages in RAM, one per slot.
+    struct MockFlash {
+        mem: [u8; 2 * PAGE_SIZE],
+
+        /// Number of writes that succeed before the next one fails, simulating a reset.
+        writes_left: Option<usize>,
+    }
+
+    impl MockFlash {
+        fn new() -> Self {
+            MockFlash {
+                mem: [0xff; 2 * PAGE_SIZE],
+                writes_left: None,
+            }
+        }
+
+        fn offset(address: usize) -> usize {
+            address - FlashPage(FIRST_PAGE).to_address()
+        }
+    }
+
+    impl Read for MockFlash {
+        type NativeType = u8;
+
+        fn read_native(&self, address: usize, array: &mut [u8]) {
+            self.read(address, array);
+        }
+
+        fn read(&self, address: usize, buf: &mut [u8]) {
+            let offset = Self::offset(address);
+            buf.copy_from_slice(&self.mem[offset..offset + buf.len()]);
+        }
+    }
+
+    impl WriteErase for MockFlash {
+        type NativeType = u64;
+
+        fn status(&self) -> flash::Result {
+            Ok(())
+        }
+
+        fn erase_page(&mut self, page: FlashPage) -> flash::Result {
+            let offset = Self::offset(page.to_address());
+            self.mem[offset..offset + PAGE_SIZE]
+                .iter_mut()
+                .for_each(|b| *b = 0xff);
+
+            Ok(())
+        }
+
+        fn write_native(&mut self, _address: usize, _array: &[u64]) -> flash::Result {
+            unimplemented!()
+        }
+
+        fn write(&mut self, address: usize, data: &[u8]) -> flash::Result {
+            if let Some(writes_left) = &mut self.writes_left {
+                if *writes_left == 0 {
+                    return Err(flash::Error::Failure);
+                }
+                *writes_left -= 1;
+            }
+
+            let offset = Self::offset(address);
+            self.mem[offset..offset + data.len()].copy_from_slice(data);
+
+            Ok(())
+        }
+    }
+
+    fn ble_nvm() -> BleNvm {
+        BleNvm {
+            first_page: FIRST_PAGE,
+            pages_per_slot: 1,
+            current: None,
+            dirty: false,
+        }
+    }
+
+    fn fill_ram(value: u8) {
+        ram().iter_mut().for_each(|b| *b = value);
+    }
+
+    fn restored(flash: &MockFlash) -> Option<((usize, u32), bool)> {
+        fill_ram(0);
+        let mut nvm = ble_nvm();
+
+        if !nvm.restore(flash) {
+            assert!(ram().iter().all(|&b| b == 0xff));
+            return None;
+        }
+
+        let value = ram().first().copied();
+        let same = ram().iter().all(|&b| Some(b) == value);
+        Some((nvm.current.unwrap(), same))
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(!crc32(CRC_INIT, b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn sequence_wraparound() {
+        assert!(is_newer(1, 0));
+        assert!(!is_newer(0, 1));
+        assert!(!is_newer(5, 5));
+        assert!(is_newer(0, u32::MAX));
+        assert!(!is_newer(u32::MAX, 0));
+    }
+
+    // Slots share the BLE NVM buffer, so every scenario runs in a single test
+    #[test]
+    fn store_and_restore() {
+        let mut flash = MockFlash::new();
+        assert_eq!(restored(&flash), None);
+
+        // Slots are used in turn
+        let mut nvm = ble_nvm();
+        nvm.restore(&flash);
+        fill_ram(0xa1);
+        nvm.dirty = true;
+        nvm.store(&mut flash).unwrap();
+        assert!(!nvm.is_dirty());
+        assert_eq!(restored(&flash), Some(((0, 0), true)));
+
+        fill_ram(0xb2);
+        nvm.store(&mut flash).unwrap();
+        assert_eq!(restored(&flash), Some(((1, 1), true)));
+        if !ram().is_empty() {
+            assert_eq!(ram()[0], 0xb2);
+        }
+
+        // Corrupted header CRC or data make the older copy the newest valid one
+        let crc = HEADER_SIZE - 4 + PAGE_SIZE;
+        flash.mem[crc] ^= 0x01;
+        assert_eq!(restored(&flash), Some(((0, 0), true)));
+        flash.mem[crc] ^= 0x01;
+
+        if !ram().is_empty() {
+            let data = HEADER_SIZE + PAGE_SIZE + BLE_NVM_SRAM_SIZE - 1;
+            flash.mem[data] ^= 0x80;
+            assert_eq!(restored(&flash), Some(((0, 0), true)));
+            assert_eq!(ram()[0], 0xa1);
+            flash.mem[data] ^= 0x80;
+        }
+
+        // Reset before the header is written keeps the previous copy
+        let mut nvm = ble_nvm();
+        nvm.restore(&flash);
+        fill_ram(0xc3);
+        flash.writes_left = Some((BLE_NVM_SRAM_SIZE + CHUNK_SIZE - 1) / CHUNK_SIZE);
+        assert!(nvm.store(&mut flash).is_err());
+        assert!(nvm.is_dirty());
+        flash.writes_left = None;
+        assert_eq!(restored(&flash), Some(((1, 1), true)));
+        if !ram().is_empty() {
+            assert_eq!(ram()[0], 0xb2);
+        }
+
+        // Sequence number wraps around
+        let mut nvm = ble_nvm();
+        nvm.current = Some((0, u32::MAX - 1));
+        nvm.store(&mut flash).unwrap();
+        assert_eq!(restored(&flash), Some(((1, u32::MAX), true)));
+        nvm.store(&mut flash).unwrap();
+        assert_eq!(restored(&flash), Some(((0, 0), true)));
+    }
+}
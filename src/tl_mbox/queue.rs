@@ -0,0 +1,194 @@
+//! Queues of events received from CPU2, filled in IPCC RX IRQ handler.
+
+use heapless::spsc;
+use heapless::ArrayLength;
+
+use crate::tl_mbox::evt::EvtBox;
+
+/// What to do with an event received while its queue is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the received event, returning its buffer to the memory manager, and count it.
+    DropNewest,
+
+    /// Leave events in the CPU2 queue and stop receiving on the channel until the application
+    /// dequeues an event. CPU2 can't send more events on the channel meanwhile.
+    Backpressure,
+}
+
+pub type HeaplessEvtQueue<N = heapless::consts::U32> =
+    spsc::Queue<EvtBox, N, usize, spsc::SingleCore>;
+
+/// Event queue of a single IPCC channel.
+pub struct EvtQueue<N: ArrayLength<EvtBox>> {
+    queue: HeaplessEvtQueue<N>,
+    policy: OverflowPolicy,
+
+    /// Number of events dropped because the queue was full.
+    dropped: u32,
+
+    /// Receiving is stopped because the queue was full.
+    paused: bool,
+}
+
+impl<N: ArrayLength<EvtBox>> EvtQueue<N> {
+    pub(crate) fn new(policy: OverflowPolicy) -> Self {
+        EvtQueue {
+            queue: unsafe { spsc::Queue::usize_sc() },
+            policy,
+            dropped: 0,
+            paused: false,
+        }
+    }
+
+    /// Enqueues `event`. Returns it back if it has to be left in the CPU2 queue.
+    pub(crate) fn push(&mut self, event: EvtBox) -> Result<(), EvtBox> {
+        match self.queue.enqueue(event) {
+            Ok(()) => Ok(()),
+
+            Err(event) => match self.policy {
+                OverflowPolicy::DropNewest => {
+                    self.dropped = self.dropped.wrapping_add(1);
+
+                    // Buffer is released to the memory manager here
+                    drop(event);
+
+                    Ok(())
+                }
+
+                OverflowPolicy::Backpressure => {
+                    self.paused = true;
+                    Err(event)
+                }
+            },
+        }
+    }
+
+    /// Dequeues an event. Returns `true` as the second value if receiving has been
+    /// stopped and must be resumed.
+    pub(crate) fn pop(&mut self) -> (Option<EvtBox>, bool) {
+        let event = self.queue.dequeue();
+        let resume = event.is_some() && self.paused;
+        if resume {
+            self.paused = false;
+        }
+
+        (event, resume)
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// Number of events dropped because the queue was full (wraps around).
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+}
+
+#[cfg(all(test, feature = "host-sim"))]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::shci::ReadyState;
+    use crate::tl_mbox::sim::{Cpu2Sim, SimIpcc};
+    use crate::tl_mbox::TlMbox;
+    use heapless::consts::U2;
+
+    const DISCONNECTION_COMPLETE: [u8; 6] = [0x05, 4, 0x00, 0x01, 0x08, 0x13];
+
+    fn init(sys_policy: OverflowPolicy, ble_policy: OverflowPolicy) -> (Cpu2Sim, TlMbox<U2, U2>) {
+        let mut sim = Cpu2Sim::new();
+        let mut mbox = TlMbox::tl_init_channels(&mut SimIpcc, sys_policy, ble_policy);
+
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(mbox.poll_cpu2_ready(), Ok(ReadyState::WirelessStack));
+        drop(mbox.dequeue_sys_event());
+        sim.service(&mut mbox).unwrap();
+
+        (sim, mbox)
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (mut sim, mut mbox) = init(OverflowPolicy::DropNewest, OverflowPolicy::DropNewest);
+        let free = sim.free_evt_buffers();
+        let capacity = mbox.ble_queue().capacity();
+
+        for _ in 0..=capacity {
+            sim.send_ble_event(&DISCONNECTION_COMPLETE).unwrap();
+        }
+        sim.service(&mut mbox).unwrap();
+
+        assert_eq!(mbox.ble_queue().len(), capacity);
+        assert_eq!(mbox.dropped_ble_events(), 1);
+        assert_eq!(sim.queued_events(), 0);
+
+        // Dropped event buffer is returned to CPU2
+        assert_eq!(sim.free_evt_buffers(), free - capacity);
+    }
+
+    #[test]
+    fn backpressure() {
+        let (mut sim, mut mbox) = init(OverflowPolicy::DropNewest, OverflowPolicy::Backpressure);
+        let capacity = mbox.ble_queue().capacity();
+
+        for _ in 0..=capacity {
+            sim.send_ble_event(&DISCONNECTION_COMPLETE).unwrap();
+        }
+        sim.service(&mut mbox).unwrap();
+
+        assert_eq!(mbox.ble_queue().len(), capacity);
+        assert_eq!(mbox.dropped_ble_events(), 0);
+        assert_eq!(sim.queued_events(), 1);
+
+        // The channel stays masked until an event is dequeued
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(sim.queued_events(), 1);
+
+        drop(mbox.dequeue_ble_event().unwrap());
+        sim.service(&mut mbox).unwrap();
+
+        assert_eq!(sim.queued_events(), 0);
+        assert_eq!(mbox.ble_queue().len(), capacity);
+        assert_eq!(mbox.dropped_ble_events(), 0);
+    }
+
+    #[test]
+    fn c2_ready_reported_once_after_backpressure() {
+        let (mut sim, mut mbox) = init(OverflowPolicy::Backpressure, OverflowPolicy::DropNewest);
+        let capacity = mbox.sys_queue().capacity();
+
+        for _ in 0..capacity {
+            sim.send_sys_event(&[0xff, 2, 0x10, 0x92]).unwrap();
+        }
+        sim.send_sys_event(&[0xff, 3, 0x00, 0x92, 0x00]).unwrap();
+        sim.service(&mut mbox).unwrap();
+
+        // C2Ready is left in the CPU2 queue
+        assert_eq!(sim.queued_events(), 1);
+        assert_eq!(mbox.poll_cpu2_ready(), Err(nb::Error::WouldBlock));
+
+        drop(mbox.dequeue_sys_event().unwrap());
+        sim.service(&mut mbox).unwrap();
+
+        assert_eq!(sim.queued_events(), 0);
+        assert_eq!(mbox.poll_cpu2_ready(), Ok(ReadyState::WirelessStack));
+
+        drop(mbox.dequeue_sys_event().unwrap());
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(mbox.poll_cpu2_ready(), Err(nb::Error::WouldBlock));
+    }
+}
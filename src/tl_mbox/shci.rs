@@ -1,10 +1,136 @@
-use crate::ipcc::Ipcc;
+use core::convert::TryFrom;
+use core::marker::PhantomData;
+
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::bytes::{Reader, Writer};
 use crate::tl_mbox::cmd::CmdPacket;
 use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::{CcEvt, EvtBox};
 use crate::tl_mbox::sys;
 use crate::tl_mbox::{TL_CS_EVT_SIZE, TL_EVT_HEADER_SIZE, TL_PACKET_HEADER_SIZE, TL_SYS_TABLE};
 
 pub const SHCI_OPCODE_BLE_INIT: u16 = 0xfc66;
+pub const SHCI_OPCODE_THREAD_INIT: u16 = 0xfc67;
+pub const SHCI_OPCODE_FLASH_ERASE_ACTIVITY: u16 = 0xfc69;
+pub const SHCI_OPCODE_CONCURRENT_SET_MODE: u16 = 0xfc6a;
+pub const SHCI_OPCODE_FLASH_STORE_DATA: u16 = 0xfc6b;
+pub const SHCI_OPCODE_FLASH_ERASE_DATA: u16 = 0xfc6c;
+pub const SHCI_OPCODE_RADIO_ALLOW_LOW_POWER: u16 = 0xfc6d;
+pub const SHCI_OPCODE_MAC_802_15_4_INIT: u16 = 0xfc6e;
+pub const SHCI_OPCODE_REINIT: u16 = 0xfc6f;
+pub const SHCI_OPCODE_ZIGBEE_INIT: u16 = 0xfc70;
+pub const SHCI_OPCODE_EXTPA_CONFIG: u16 = 0xfc72;
+pub const SHCI_OPCODE_SET_FLASH_ACTIVITY_CONTROL: u16 = 0xfc73;
+pub const SHCI_OPCODE_BLE_LLD_INIT: u16 = 0xfc74;
+pub const SHCI_OPCODE_CONFIG: u16 = 0xfc75;
+
+pub const SHCI_SUB_EVT_CODE_READY: u16 = 0x9200;
+pub const SHCI_SUB_EVT_ERROR_NOTIF: u16 = 0x9201;
+pub const SHCI_SUB_EVT_BLE_NVM_RAM_UPDATE: u16 = 0x9202;
+pub const SHCI_SUB_EVT_OT_NVM_RAM_UPDATE: u16 = 0x9203;
+pub const SHCI_SUB_EVT_NVM_START_WRITE: u16 = 0x9204;
+pub const SHCI_SUB_EVT_NVM_END_WRITE: u16 = 0x9205;
+pub const SHCI_SUB_EVT_NVM_START_ERASE: u16 = 0x9206;
+pub const SHCI_SUB_EVT_NVM_END_ERASE: u16 = 0x9207;
+
+/// Event code of system asynchronous events.
+const SHCI_EVT_CODE: u8 = 0xff;
+
+/// `ShciConfigParam::config1`: BLE NVM data is kept in SRAM instead of flash.
+pub const CONFIG1_BLE_NVM_DATA_TO_SRAM: u8 = 0x01;
+/// `ShciConfigParam::config1`: Thread NVM data is kept in SRAM instead of flash.
+pub const CONFIG1_THREAD_NVM_DATA_TO_SRAM: u8 = 0x02;
+
+/// `ShciConfigParam::evt_mask1` bits enabling the corresponding system events.
+pub const EVT_MASK1_ERROR_NOTIF: u8 = 0x01;
+pub const EVT_MASK1_SYNCHRO_BLE_NVM_RAM: u8 = 0x02;
+pub const EVT_MASK1_SYNCHRO_THREAD_NVM_RAM: u8 = 0x04;
+pub const EVT_MASK1_NVM_START_WRITE: u8 = 0x08;
+pub const EVT_MASK1_NVM_END_WRITE: u8 = 0x10;
+pub const EVT_MASK1_NVM_START_ERASE: u8 = 0x20;
+pub const EVT_MASK1_NVM_END_ERASE: u8 = 0x40;
+
+/// Status of a system command, returned in its Command Complete event.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShciStatus {
+    Success,
+    UnknownCmd,
+    MemoryCapacityExceeded,
+    UnsupportedFeature,
+    InvalidHciCmdParams,
+    InvalidParams,
+    FusCmdNotSupported,
+    Other(u8),
+}
+
+impl From<u8> for ShciStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => ShciStatus::Success,
+            0x01 => ShciStatus::UnknownCmd,
+            0x07 => ShciStatus::MemoryCapacityExceeded,
+            0x11 => ShciStatus::UnsupportedFeature,
+            0x12 => ShciStatus::InvalidHciCmdParams,
+            0x42 => ShciStatus::InvalidParams,
+            0xff => ShciStatus::FusCmdNotSupported,
+
+            other => ShciStatus::Other(other),
+        }
+    }
+}
+
+impl ShciStatus {
+    /// Decodes status from the first byte of the Command Complete event payload.
+    pub fn from_cc_evt(cc: &CcEvt) -> Self {
+        ShciStatus::from(cc.payload[0])
+    }
+
+    pub fn is_success(self) -> bool {
+        self == ShciStatus::Success
+    }
+}
+
+/// Response of a system command, decoded from Command Complete event return parameters.
+pub trait ShciResponse: Sized {
+    /// Returns `None` if `params` are too short.
+    fn from_rsp_params(params: &[u8]) -> Option<Self>;
+}
+
+impl ShciResponse for ShciStatus {
+    fn from_rsp_params(params: &[u8]) -> Option<Self> {
+        params.first().map(|&status| ShciStatus::from(status))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShciError {
+    /// Parameters don't fit into the system command buffer.
+    TooLong,
+
+    /// Command Complete event of another command has been received. Contains its opcode.
+    UnexpectedResponse(u16),
+
+    /// Response parameters are too short.
+    Malformed,
+
+    /// Previous command didn't receive its Command Complete event yet.
+    Busy,
+}
+
+/// System command that has been sent to CPU2 and waits for its Command Complete event.
+///
+/// The response is picked up with `TlMbox::poll_shci_cmd`.
+#[derive(Debug)]
+pub struct PendingShciCmd<R = ShciStatus> {
+    pub(crate) opcode: u16,
+    _response: PhantomData<R>,
+}
+
+impl<R> PendingShciCmd<R> {
+    pub fn opcode(&self) -> u16 {
+        self.opcode
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 #[repr(C, packed)]
@@ -51,25 +177,436 @@ pub const TL_BLEEVT_CS_PACKET_SIZE: usize = TL_EVT_HEADER_SIZE + TL_CS_EVT_SIZE;
 #[allow(dead_code)] // Not used currently but reserved
 const TL_BLEEVT_CS_BUFFER_SIZE: usize = TL_PACKET_HEADER_SIZE + TL_BLEEVT_CS_PACKET_SIZE;
 
-pub fn shci_ble_init(ipcc: &mut Ipcc, param: ShciBleInitCmdParam) {
-    let mut packet = ShciBleInitCmdPacket {
-        header: ShciHeader::default(),
-        param,
+/// Writes system command with `params` into the system command buffer and notifies CPU2.
+///
+/// `params` must fit into the command payload.
+pub(crate) fn send_cmd<R>(
+    ipcc: &mut impl IpccChannels,
+    opcode: u16,
+    params: &[u8],
+) -> PendingShciCmd<R> {
+    unsafe {
+        let p_cmd_buffer: *mut CmdPacket = (*TL_SYS_TABLE.as_mut_ptr()).pcmd_buffer;
+        let cmd_serial = &mut (*p_cmd_buffer).cmdserial;
+
+        cmd_serial.ty = TlPacketType::SysCmd as u8;
+        cmd_serial.cmd.cmd_code = opcode;
+        cmd_serial.cmd.payload_len = params.len() as u8;
+        cmd_serial.cmd.payload[..params.len()].copy_from_slice(params);
+    }
+
+    sys::send_cmd(ipcc);
+
+    PendingShciCmd {
+        opcode,
+        _response: PhantomData,
+    }
+}
+
+pub fn shci_ble_init(ipcc: &mut impl IpccChannels, param: ShciBleInitCmdParam) -> PendingShciCmd {
+    let param_ptr: *const ShciBleInitCmdParam = &param;
+    let params = unsafe {
+        core::slice::from_raw_parts(
+            param_ptr.cast::<u8>(),
+            core::mem::size_of::<ShciBleInitCmdParam>(),
+        )
     };
 
-    let packet_ptr: *mut _ = &mut packet;
+    send_cmd(ipcc, SHCI_OPCODE_BLE_INIT, params)
+}
 
-    unsafe {
-        let cmd_ptr: *mut CmdPacket = packet_ptr.cast();
+/// Restarts CPU2 wireless stack. CPU2 sends `C2Ready` system event once it's up again.
+pub fn shci_c2_reinit(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_REINIT, &[])
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ShciConfigParam {
+    /// Bitmask of `CONFIG1_*` constants.
+    pub config1: u8,
+    /// Bitmask of `EVT_MASK1_*` constants.
+    pub evt_mask1: u8,
+    /// SRAM address of BLE NVM data, if `CONFIG1_BLE_NVM_DATA_TO_SRAM` is set.
+    pub ble_nvm_ram_address: u32,
+    /// SRAM address of Thread NVM data, if `CONFIG1_THREAD_NVM_DATA_TO_SRAM` is set.
+    pub thread_nvm_ram_address: u32,
+    /// Revision ID of the device, as read from `DBGMCU_IDCODE`.
+    pub revision_id: u16,
+    /// Device ID of the device, as read from `DBGMCU_IDCODE`.
+    pub device_id: u16,
+}
+
+/// Size of `SHCI_C2_CONFIG` parameters.
+const SHCI_CONFIG_PARAM_SIZE: usize = 16;
+
+pub fn shci_c2_config(ipcc: &mut impl IpccChannels, param: &ShciConfigParam) -> PendingShciCmd {
+    let mut buf = [0u8; SHCI_CONFIG_PARAM_SIZE];
+    let mut w = Writer::new(&mut buf);
+
+    // Buffer is sized for exactly these fields
+    let _ = w.u8(SHCI_CONFIG_PARAM_SIZE as u8);
+    let _ = w.u8(param.config1);
+    let _ = w.u8(param.evt_mask1);
+    let _ = w.u8(0); // Spare
+    let _ = w.u32(param.ble_nvm_ram_address);
+    let _ = w.u32(param.thread_nvm_ram_address);
+    let _ = w.u16(param.revision_id);
+    let _ = w.u16(param.device_id);
+
+    send_cmd(ipcc, SHCI_OPCODE_CONFIG, &buf)
+}
+
+/// Tells CPU2 whether CPU1 is about to erase flash, so that CPU2 can protect its radio timing.
+pub fn shci_c2_flash_erase_activity(
+    ipcc: &mut impl IpccChannels,
+    erase_on: bool,
+) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FLASH_ERASE_ACTIVITY, &[erase_on as u8])
+}
 
-        (*cmd_ptr).cmdserial.cmd.cmd_code = SHCI_OPCODE_BLE_INIT;
-        (*cmd_ptr).cmdserial.cmd.payload_len = core::mem::size_of::<ShciBleInitCmdParam>() as u8;
+/// Wireless stack that owns NVM data on CPU2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FlashIp {
+    Ble = 0x00,
+    Thread = 0x01,
+    Zigbee = 0x02,
+}
+
+/// Requests CPU2 to write NVM data of the given stack to flash.
+pub fn shci_c2_flash_store_data(ipcc: &mut impl IpccChannels, ip: FlashIp) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FLASH_STORE_DATA, &[ip as u8])
+}
+
+/// Requests CPU2 to erase NVM data of the given stack from flash.
+pub fn shci_c2_flash_erase_data(ipcc: &mut impl IpccChannels, ip: FlashIp) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_FLASH_ERASE_DATA, &[ip as u8])
+}
+
+/// Mechanism used to arbitrate flash access between CPU1 and CPU2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FlashActivityControl {
+    /// `PESD` bit of `FLASH_ACR`.
+    Pes = 0x00,
+    /// Hardware semaphore 7.
+    Sem7 = 0x01,
+}
+
+pub fn shci_c2_set_flash_activity_control(
+    ipcc: &mut impl IpccChannels,
+    control: FlashActivityControl,
+) -> PendingShciCmd {
+    send_cmd(
+        ipcc,
+        SHCI_OPCODE_SET_FLASH_ACTIVITY_CONTROL,
+        &[control as u8],
+    )
+}
 
-        let mut p_cmd_buffer = &mut *(*TL_SYS_TABLE.as_mut_ptr()).pcmd_buffer;
-        core::ptr::write(p_cmd_buffer, *cmd_ptr);
+/// Allows or forbids low power mode of the radio of the given stack.
+pub fn shci_c2_radio_allow_low_power(
+    ipcc: &mut impl IpccChannels,
+    ip: FlashIp,
+    allow: bool,
+) -> PendingShciCmd {
+    send_cmd(
+        ipcc,
+        SHCI_OPCODE_RADIO_ALLOW_LOW_POWER,
+        &[ip as u8, allow as u8],
+    )
+}
+
+/// External power amplifier controlled by CPU2 through a GPIO.
+#[derive(Debug, Copy, Clone)]
+pub struct ExtpaConfig {
+    /// Base address of the GPIO port.
+    pub gpio_port_base: u32,
+    /// Pin mask of the GPIO (`1 << pin`).
+    pub gpio_pin_number: u16,
+    /// GPIO level enabling the amplifier.
+    pub active_high: bool,
+    pub enabled: bool,
+}
+
+pub fn shci_c2_extpa_config(ipcc: &mut impl IpccChannels, config: &ExtpaConfig) -> PendingShciCmd {
+    let mut buf = [0u8; 8];
+    let mut w = Writer::new(&mut buf);
+
+    // Buffer is sized for exactly these fields
+    let _ = w.u32(config.gpio_port_base);
+    let _ = w.u16(config.gpio_pin_number);
+    let _ = w.u8(config.active_high as u8);
+    let _ = w.u8(config.enabled as u8);
+
+    send_cmd(ipcc, SHCI_OPCODE_EXTPA_CONFIG, &buf)
+}
+
+/// Starts BLE LLD (link layer only radio driver) firmware with raw init parameters.
+pub fn shci_c2_ble_lld_init(
+    ipcc: &mut impl IpccChannels,
+    params: &[u8],
+) -> Result<PendingShciCmd, ShciError> {
+    let mut buf = [0u8; 255];
+    let mut w = Writer::new(&mut buf);
+
+    // Parameter size byte followed by the parameters
+    w.u8(params.len() as u8).map_err(|_| ShciError::TooLong)?;
+    w.bytes(params).map_err(|_| ShciError::TooLong)?;
+    let len = w.len();
+
+    Ok(send_cmd(ipcc, SHCI_OPCODE_BLE_LLD_INIT, &buf[..len]))
+}
+
+pub fn shci_c2_zigbee_init(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_ZIGBEE_INIT, &[])
+}
+
+pub fn shci_c2_thread_init(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_THREAD_INIT, &[])
+}
+
+pub fn shci_c2_802_15_4_init(ipcc: &mut impl IpccChannels) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_MAC_802_15_4_INIT, &[])
+}
+
+/// Stack that gets the radio in concurrent mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ConcurrentMode {
+    Ble = 0x00,
+    Thread = 0x01,
+    Zigbee = 0x02,
+    Mac = 0x03,
+}
+
+pub fn shci_c2_concurrent_set_mode(
+    ipcc: &mut impl IpccChannels,
+    mode: ConcurrentMode,
+) -> PendingShciCmd {
+    send_cmd(ipcc, SHCI_OPCODE_CONCURRENT_SET_MODE, &[mode as u8])
+}
+
+/// Firmware CPU2 has started, reported with `SysEvent::C2Ready`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReadyState {
+    WirelessStack,
+    Fus,
+    NvmBackup,
+    NvmRestore,
+    Other(u8),
+}
+
+impl From<u8> for ReadyState {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => ReadyState::WirelessStack,
+            0x01 => ReadyState::Fus,
+            0x10 => ReadyState::NvmBackup,
+            0x11 => ReadyState::NvmRestore,
+
+            other => ReadyState::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SysErrorCode {
+    BleInit,
+    ThreadLldFatalError,
+    ThreadUnknownCmd,
+    ZigbeeUnknownCmd,
+    Other(u8),
+}
+
+impl From<u8> for SysErrorCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => SysErrorCode::BleInit,
+            0x7d => SysErrorCode::ThreadLldFatalError,
+            0x7e => SysErrorCode::ThreadUnknownCmd,
+            0xc8 => SysErrorCode::ZigbeeUnknownCmd,
+
+            other => SysErrorCode::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SysEventError {
+    /// Event parameters are truncated.
+    Malformed,
+
+    /// The packet is not a system event. Contains raw packet type.
+    NotSysEvent(u8),
+}
+
+/// Asynchronous event received on the system channel.
+#[derive(Debug, Copy, Clone)]
+pub enum SysEvent<'a> {
+    /// CPU2 has booted and is ready to accept system commands.
+    C2Ready(ReadyState),
+
+    ErrorNotification(SysErrorCode),
+
+    /// BLE NVM data in SRAM has been updated (see `CONFIG1_BLE_NVM_DATA_TO_SRAM`).
+    BleNvmRamUpdate {
+        start_address: u32,
+        size: u32,
+    },
+
+    /// Thread NVM data in SRAM has been updated (see `CONFIG1_THREAD_NVM_DATA_TO_SRAM`).
+    OtNvmRamUpdate {
+        start_address: u32,
+        size: u32,
+    },
+
+    /// CPU2 is about to write flash.
+    NvmStartWrite {
+        number_of_words: u32,
+    },
+
+    NvmEndWrite,
+
+    /// CPU2 is about to erase flash.
+    NvmStartErase {
+        number_of_sectors: u32,
+    },
+
+    NvmEndErase,
+
+    /// Event that has no typed representation yet.
+    Unknown {
+        code: u16,
+        params: &'a [u8],
+    },
+}
+
+impl<'a> SysEvent<'a> {
+    /// Decodes a system event from its wire representation: event code (0xff), parameter length,
+    /// sub-event code and parameters.
+    pub fn from_bytes(buf: &'a [u8]) -> Result<Self, SysEventError> {
+        Self::parse(buf).ok_or(SysEventError::Malformed)
+    }
+
+    fn parse(buf: &'a [u8]) -> Option<Self> {
+        let mut r = Reader::new(buf);
+        if r.u8()? != SHCI_EVT_CODE {
+            return None;
+        }
+
+        let len = r.u8()? as usize;
+        let mut r = Reader::new(r.bytes(len)?);
+        let code = r.u16()?;
+
+        Some(match code {
+            SHCI_SUB_EVT_CODE_READY => SysEvent::C2Ready(ReadyState::from(r.u8()?)),
+
+            SHCI_SUB_EVT_ERROR_NOTIF => SysEvent::ErrorNotification(SysErrorCode::from(r.u8()?)),
+
+            SHCI_SUB_EVT_BLE_NVM_RAM_UPDATE => SysEvent::BleNvmRamUpdate {
+                start_address: r.u32()?,
+                size: r.u32()?,
+            },
+
+            SHCI_SUB_EVT_OT_NVM_RAM_UPDATE => SysEvent::OtNvmRamUpdate {
+                start_address: r.u32()?,
+                size: r.u32()?,
+            },
+
+            SHCI_SUB_EVT_NVM_START_WRITE => SysEvent::NvmStartWrite {
+                number_of_words: r.u32()?,
+            },
+
+            SHCI_SUB_EVT_NVM_END_WRITE => SysEvent::NvmEndWrite,
+
+            SHCI_SUB_EVT_NVM_START_ERASE => SysEvent::NvmStartErase {
+                number_of_sectors: r.u32()?,
+            },
+
+            SHCI_SUB_EVT_NVM_END_ERASE => SysEvent::NvmEndErase,
+
+            _ => SysEvent::Unknown {
+                code,
+                params: r.rest(),
+            },
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a EvtBox> for SysEvent<'a> {
+    type Error = SysEventError;
+
+    fn try_from(evt: &'a EvtBox) -> Result<Self, Self::Error> {
+        let kind = evt.raw_kind();
+        match TlPacketType::try_from(kind) {
+            Ok(TlPacketType::SysEvt) => SysEvent::from_bytes(evt.evt_bytes()),
+
+            _ => Err(SysEventError::NotSysEvent(kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_notification(code: u8) -> Option<SysErrorCode> {
+        let [lo, hi] = SHCI_SUB_EVT_ERROR_NOTIF.to_le_bytes();
+        match SysEvent::from_bytes(&[SHCI_EVT_CODE, 3, lo, hi, code]) {
+            Ok(SysEvent::ErrorNotification(code)) => Some(code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn error_notification_codes() {
+        assert_eq!(error_notification(0x00), Some(SysErrorCode::BleInit));
+        assert_eq!(
+            error_notification(0x7d),
+            Some(SysErrorCode::ThreadLldFatalError)
+        );
+        assert_eq!(
+            error_notification(0x7e),
+            Some(SysErrorCode::ThreadUnknownCmd)
+        );
+        assert_eq!(
+            error_notification(0xc8),
+            Some(SysErrorCode::ZigbeeUnknownCmd)
+        );
+        assert_eq!(error_notification(0x7f), Some(SysErrorCode::Other(0x7f)));
+    }
+
+    #[test]
+    fn c2_ready() {
+        let [lo, hi] = SHCI_SUB_EVT_CODE_READY.to_le_bytes();
+        let ready = |state| match SysEvent::from_bytes(&[SHCI_EVT_CODE, 3, lo, hi, state]) {
+            Ok(SysEvent::C2Ready(state)) => Some(state),
+            _ => None,
+        };
+
+        assert_eq!(ready(0x00), Some(ReadyState::WirelessStack));
+        assert_eq!(ready(0x01), Some(ReadyState::Fus));
+        assert_eq!(ready(0x10), Some(ReadyState::NvmBackup));
+        assert_eq!(ready(0x11), Some(ReadyState::NvmRestore));
+        assert_eq!(ready(0x02), Some(ReadyState::Other(0x02)));
+    }
 
-        (*p_cmd_buffer).cmdserial.ty = TlPacketType::SysCmd as u8;
+    #[test]
+    fn truncated() {
+        let [lo, hi] = SHCI_SUB_EVT_ERROR_NOTIF.to_le_bytes();
 
-        sys::send_cmd(ipcc);
+        assert_eq!(
+            SysEvent::from_bytes(&[SHCI_EVT_CODE, 2, lo, hi]).err(),
+            Some(SysEventError::Malformed)
+        );
+        assert_eq!(
+            SysEvent::from_bytes(&[SHCI_EVT_CODE, 3, lo, hi]).err(),
+            Some(SysEventError::Malformed)
+        );
+        assert_eq!(
+            SysEvent::from_bytes(&[0x0e, 3, lo, hi, 0]).err(),
+            Some(SysEventError::Malformed)
+        );
     }
 }
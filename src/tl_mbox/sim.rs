@@ -0,0 +1,854 @@
+//! Host-side simulation of CPU2 for testing `tl_mbox` without the hardware.
+//!
+//! Enabled with the `host-sim` feature. IPCC registers are replaced with global flags behind
+//! `SimIpcc`, and `Cpu2Sim` plays the part of the wireless coprocessor on the shared RAM tables
+//! populated by `TlMbox`: it consumes system and BLE commands, answers them with command complete
+//! events, queues asynchronous events into the linked-list queues and takes event buffers back
+//! through the memory manager release channel. Thread, 802.15.4 MAC and Zigbee channels are not
+//! simulated, tests fill their buffers and signal them with `Cpu2Sim::set_flag_channel` and
+//! `clear_flag_channel`.
+//!
+//! IPCC interrupt handlers are called by `Cpu2Sim::service` on the calling thread:
+//!
+//! ```ignore
+//! let mut sim = Cpu2Sim::new();
+//! let mut ipcc = SimIpcc;
+//! let mut mbox: TlMbox = TlMbox::tl_init_channels(
+//!     &mut ipcc,
+//!     OverflowPolicy::DropNewest,
+//!     OverflowPolicy::DropNewest,
+//! );
+//!
+//! sim.boot(ReadyState::WirelessStack)?;
+//! sim.service(&mut mbox)?;
+//! assert_eq!(mbox.poll_cpu2_ready(), Ok(ReadyState::WirelessStack));
+//! ```
+//!
+//! Tests run on the host target, e.g.
+//! `cargo test --target x86_64-unknown-linux-gnu --features host-sim,xG-package`.
+//! The feature can't be enabled when building for the target.
+//! The shared RAM tables are global, so `Cpu2Sim::new` waits until the previous simulator is
+//! dropped, serializing tests that run in parallel.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use heapless::consts::U64;
+use heapless::Vec;
+
+use crate::ipcc::{IpccChannel, IpccChannels};
+use crate::tl_mbox::asynch::AsyncMbox;
+use crate::tl_mbox::channels;
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::EvtBox;
+use crate::tl_mbox::linked_list::{LinkedList, LinkedListNode};
+use crate::tl_mbox::shci::{ReadyState, SHCI_SUB_EVT_CODE_READY};
+use crate::tl_mbox::{
+    divc, TlMbox, TL_BLE_EVENT_FRAME_SIZE, TL_EVT_HEADER_SIZE, TL_PACKET_HEADER_SIZE, TL_REF_TABLE,
+};
+use heapless::ArrayLength;
+
+/// Critical sections are not needed, simulated interrupt handlers run on the calling thread.
+pub(crate) mod interrupt {
+    pub fn free<F, R>(f: F) -> R
+    where
+        F: FnOnce(&()) -> R,
+    {
+        f(&())
+    }
+}
+
+/// Flags set by CPU1 (`C1TOC2SR`).
+static C1_FLAGS: AtomicU8 = AtomicU8::new(0);
+
+/// Flags set by CPU2 (`C2TOC1SR`).
+static C2_FLAGS: AtomicU8 = AtomicU8::new(0);
+
+/// CPU1 RX channels that are unmasked.
+static C1_RX_ENABLED: AtomicU8 = AtomicU8::new(0);
+
+/// CPU1 TX channels that are unmasked.
+static C1_TX_ENABLED: AtomicU8 = AtomicU8::new(0);
+
+/// Held by the live `Cpu2Sim`.
+static SIM_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Size of an event buffer in the event pool, as CPU2 carves them.
+const EVT_BUF_SIZE: usize = 4 * divc(TL_PACKET_HEADER_SIZE + TL_BLE_EVENT_FRAME_SIZE, 4);
+
+/// Size of a buffer in the traces pool.
+const TRACE_BUF_SIZE: usize = 4 * divc(TL_PACKET_HEADER_SIZE + TL_EVT_HEADER_SIZE + 255, 4);
+
+/// Event code of a command complete event.
+const EVT_CODE_CC: u8 = 0x0e;
+
+/// Event code of a system (vendor specific) event.
+const EVT_CODE_SYS: u8 = 0xff;
+
+fn bit(channel: IpccChannel) -> u8 {
+    channel as u8
+}
+
+fn set_bits(reg: &AtomicU8, channel: IpccChannel, enabled: bool) {
+    if enabled {
+        reg.fetch_or(bit(channel), Ordering::SeqCst);
+    } else {
+        reg.fetch_and(!bit(channel), Ordering::SeqCst);
+    }
+}
+
+fn get_bits(reg: &AtomicU8, channel: IpccChannel) -> bool {
+    reg.load(Ordering::SeqCst) & bit(channel) != 0
+}
+
+/// Simulated IPCC as seen by CPU1.
+///
+/// All handles share the same registers, so they can be created at will.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SimIpcc;
+
+impl IpccChannels for SimIpcc {
+    fn c1_set_rx_channel(&mut self, channel: IpccChannel, enabled: bool) {
+        set_bits(&C1_RX_ENABLED, channel, enabled);
+    }
+
+    fn c1_get_rx_channel(&self, channel: IpccChannel) -> bool {
+        get_bits(&C1_RX_ENABLED, channel)
+    }
+
+    fn c1_set_tx_channel(&mut self, channel: IpccChannel, enabled: bool) {
+        set_bits(&C1_TX_ENABLED, channel, enabled);
+    }
+
+    fn c1_get_tx_channel(&self, channel: IpccChannel) -> bool {
+        get_bits(&C1_TX_ENABLED, channel)
+    }
+
+    fn c1_clear_flag_channel(&mut self, channel: IpccChannel) {
+        set_bits(&C2_FLAGS, channel, false);
+    }
+
+    fn c1_set_flag_channel(&mut self, channel: IpccChannel) {
+        set_bits(&C1_FLAGS, channel, true);
+    }
+
+    fn c1_is_active_flag(&self, channel: IpccChannel) -> bool {
+        get_bits(&C1_FLAGS, channel)
+    }
+
+    fn c2_is_active_flag(&self, channel: IpccChannel) -> bool {
+        get_bits(&C2_FLAGS, channel)
+    }
+}
+
+/// Computes the response to a command: gets opcode and parameters, writes return parameters
+/// into the buffer and returns their length.
+pub type Responder = fn(opcode: u16, params: &[u8], rsp: &mut [u8]) -> usize;
+
+/// Default responder: every command succeeds with no return parameters besides the status.
+fn respond_success(_opcode: u16, _params: &[u8], rsp: &mut [u8]) -> usize {
+    rsp[0] = 0x00;
+    1
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// All event buffers are owned by CPU1.
+    NoFreeBuffer,
+
+    /// Event doesn't fit into an event buffer.
+    TooLong,
+
+    /// Pool holds more buffers than the simulator can keep track of.
+    PoolTooLarge,
+
+    /// CPU1 has released a buffer that CPU2 already owns.
+    ReleasedTwice,
+
+    /// CPU1 has released a buffer that doesn't belong to any pool.
+    UnknownBuffer,
+}
+
+/// Last command received on a channel.
+#[derive(Debug, Clone, Default)]
+struct LastCmd {
+    opcode: u16,
+    params: Vec<u8, heapless::consts::U255>,
+}
+
+/// Simulated CPU2.
+pub struct Cpu2Sim {
+    evt_pool: Pool,
+    traces_pool: Pool,
+
+    sys_responder: Responder,
+    ble_responder: Responder,
+
+    last_sys_cmd: Option<LastCmd>,
+    last_ble_cmd: Option<LastCmd>,
+    last_acl_data: Option<Vec<u8, heapless::consts::U256>>,
+
+    /// Number of buffers released by CPU1.
+    released: u32,
+}
+
+impl Cpu2Sim {
+    /// Takes the simulator and resets IPCC registers.
+    ///
+    /// Waits until the previous `Cpu2Sim` is dropped.
+    pub fn new() -> Self {
+        while SIM_LOCK
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+
+        C1_FLAGS.store(0, Ordering::SeqCst);
+        C2_FLAGS.store(0, Ordering::SeqCst);
+        C1_RX_ENABLED.store(0, Ordering::SeqCst);
+        C1_TX_ENABLED.store(0, Ordering::SeqCst);
+
+        Cpu2Sim {
+            evt_pool: Pool::new(EVT_BUF_SIZE),
+            traces_pool: Pool::new(TRACE_BUF_SIZE),
+            sys_responder: respond_success,
+            ble_responder: respond_success,
+            last_sys_cmd: None,
+            last_ble_cmd: None,
+            last_acl_data: None,
+            released: 0,
+        }
+    }
+
+    /// Sets the responder for system commands.
+    pub fn set_sys_responder(&mut self, responder: Responder) {
+        self.sys_responder = responder;
+    }
+
+    /// Sets the responder for BLE commands.
+    pub fn set_ble_responder(&mut self, responder: Responder) {
+        self.ble_responder = responder;
+    }
+
+    /// Starts CPU2 on the tables populated by `TlMbox`: takes the event pools from the memory
+    /// manager table and reports `C2Ready` with `state`.
+    pub fn boot(&mut self, state: ReadyState) -> Result<(), Error> {
+        unsafe {
+            let mm = &*(*TL_REF_TABLE.as_ptr()).mem_manager_table;
+
+            self.evt_pool
+                .carve(mm.blepool as usize, mm.blepoolsize as usize)?;
+            self.traces_pool
+                .carve(mm.traces_evt_pool as usize, mm.tracespoolsize as usize)?;
+        }
+
+        let state = match state {
+            ReadyState::WirelessStack => 0x00,
+            ReadyState::Fus => 0x01,
+            ReadyState::NvmBackup => 0x10,
+            ReadyState::NvmRestore => 0x11,
+            ReadyState::Other(state) => state,
+        };
+
+        let code = SHCI_SUB_EVT_CODE_READY.to_le_bytes();
+        self.send_sys_event(&[EVT_CODE_SYS, 3, code[0], code[1], state])
+    }
+
+    /// Queues a system event (event code, parameter length and parameters) and notifies CPU1.
+    pub fn send_sys_event(&mut self, evt: &[u8]) -> Result<(), Error> {
+        let queue = unsafe { (*(*TL_REF_TABLE.as_ptr()).sys_table).sys_queue as *mut _ };
+        let buf = self.evt_pool.alloc(TlPacketType::SysEvt, evt)?;
+
+        unsafe { LinkedList::from_head(queue).push_tail(buf) };
+        set_bits(&C2_FLAGS, channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL, true);
+
+        Ok(())
+    }
+
+    /// Queues a BLE event (event code, parameter length and parameters) and notifies CPU1.
+    pub fn send_ble_event(&mut self, evt: &[u8]) -> Result<(), Error> {
+        let queue = unsafe { (*(*TL_REF_TABLE.as_ptr()).ble_table).pevt_queue as *mut _ };
+        let buf = self.evt_pool.alloc(TlPacketType::BleEvt, evt)?;
+
+        unsafe { LinkedList::from_head(queue).push_tail(buf) };
+        set_bits(&C2_FLAGS, channels::cpu2::IPCC_BLE_EVENT_CHANNEL, true);
+
+        Ok(())
+    }
+
+    /// Queues a received ACL data packet (handle, data length and data) and notifies CPU1.
+    pub fn send_acl_data(&mut self, acl: &[u8]) -> Result<(), Error> {
+        let queue = unsafe { (*(*TL_REF_TABLE.as_ptr()).ble_table).pevt_queue as *mut _ };
+        let buf = self.evt_pool.alloc(TlPacketType::AclData, acl)?;
+
+        unsafe { LinkedList::from_head(queue).push_tail(buf) };
+        set_bits(&C2_FLAGS, channels::cpu2::IPCC_BLE_EVENT_CHANNEL, true);
+
+        Ok(())
+    }
+
+    /// Queues a trace packet (event code, parameter length and parameters) and notifies CPU1.
+    pub fn send_trace(&mut self, kind: TlPacketType, evt: &[u8]) -> Result<(), Error> {
+        let queue = unsafe { (*(*TL_REF_TABLE.as_ptr()).traces_table).traces_queue as *mut _ };
+        let buf = self.traces_pool.alloc(kind, evt)?;
+
+        unsafe { LinkedList::from_head(queue).push_tail(buf) };
+        set_bits(&C2_FLAGS, channels::cpu2::IPCC_TRACES_CHANNEL, true);
+
+        Ok(())
+    }
+
+    /// Notifies CPU1 of a message on `channel` that is not simulated. The message must be
+    /// in the channel buffer already.
+    pub fn set_flag_channel(&mut self, channel: IpccChannel) {
+        set_bits(&C2_FLAGS, channel, true);
+    }
+
+    /// Completes the command or acknowledges the message that CPU1 has sent on `channel` that
+    /// is not simulated. The response must be in the channel buffer already.
+    pub fn clear_flag_channel(&mut self, channel: IpccChannel) {
+        set_bits(&C1_FLAGS, channel, false);
+    }
+
+    /// Handles everything CPU1 has signalled: answers system and BLE commands, takes ACL data
+    /// and released event buffers.
+    pub fn step(&mut self) -> Result<(), Error> {
+        let ipcc = SimIpcc;
+
+        if ipcc.c1_is_active_flag(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL) {
+            self.sys_cmd();
+            set_bits(
+                &C1_FLAGS,
+                channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL,
+                false,
+            );
+        }
+
+        if ipcc.c1_is_active_flag(channels::cpu1::IPCC_BLE_CMD_CHANNEL) {
+            // Command stays in the buffer if there's nowhere to put the response
+            if self.ble_cmd().is_ok() {
+                set_bits(&C1_FLAGS, channels::cpu1::IPCC_BLE_CMD_CHANNEL, false);
+            }
+        }
+
+        if ipcc.c1_is_active_flag(channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL) {
+            self.acl_data();
+            set_bits(&C1_FLAGS, channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL, false);
+        }
+
+        if ipcc.c1_is_active_flag(channels::cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL) {
+            self.release_bufs()?;
+            set_bits(
+                &C1_FLAGS,
+                channels::cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL,
+                false,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Steps CPU2 and runs IPCC interrupt handlers of `mbox` until neither side has anything
+    /// left to do.
+    pub fn service<SQ, BQ>(&mut self, mbox: &mut TlMbox<SQ, BQ>) -> Result<(), Error>
+    where
+        SQ: ArrayLength<EvtBox>,
+        BQ: ArrayLength<EvtBox>,
+    {
+        let mut ipcc = SimIpcc;
+
+        self.run(|rx, tx| {
+            if rx {
+                mbox.interrupt_ipcc_rx_handler(&mut ipcc);
+            }
+
+            if tx {
+                mbox.interrupt_ipcc_tx_handler(&mut ipcc);
+            }
+        })
+    }
+
+    /// Same as `service`, for the mailbox owned by `AsyncMbox`. Waiting tasks are woken.
+    pub fn service_async<SQ, BQ>(&mut self, mbox: &AsyncMbox<SimIpcc, SQ, BQ>) -> Result<(), Error>
+    where
+        SQ: ArrayLength<EvtBox>,
+        BQ: ArrayLength<EvtBox>,
+    {
+        self.run(|rx, tx| {
+            if rx {
+                mbox.interrupt_ipcc_rx_handler();
+            }
+
+            if tx {
+                mbox.interrupt_ipcc_tx_handler();
+            }
+        })
+    }
+
+    /// Steps CPU2 and calls `handlers` with pending RX and TX interrupts until there are none.
+    fn run(&mut self, mut handlers: impl FnMut(bool, bool)) -> Result<(), Error> {
+        loop {
+            self.step()?;
+
+            let rx = rx_irq_pending();
+            let tx = tx_irq_pending();
+            if !rx && !tx {
+                return Ok(());
+            }
+
+            handlers(rx, tx);
+        }
+    }
+
+    /// Returns opcode and parameters of the last system command.
+    pub fn last_sys_cmd(&self) -> Option<(u16, &[u8])> {
+        self.last_sys_cmd
+            .as_ref()
+            .map(|cmd| (cmd.opcode, &cmd.params[..]))
+    }
+
+    /// Returns opcode and parameters of the last BLE command.
+    pub fn last_ble_cmd(&self) -> Option<(u16, &[u8])> {
+        self.last_ble_cmd
+            .as_ref()
+            .map(|cmd| (cmd.opcode, &cmd.params[..]))
+    }
+
+    /// Returns the last ACL data packet: handle, data length and data.
+    pub fn last_acl_data(&self) -> Option<&[u8]> {
+        self.last_acl_data.as_ref().map(|data| &data[..])
+    }
+
+    /// Number of event buffers owned by CPU2.
+    pub fn free_evt_buffers(&self) -> usize {
+        self.evt_pool.free.len()
+    }
+
+    /// Number of system and BLE events that CPU1 hasn't taken from the queues yet.
+    pub fn queued_events(&self) -> usize {
+        unsafe {
+            let table = &*TL_REF_TABLE.as_ptr();
+            let sys_queue = (*table.sys_table).sys_queue as *mut _;
+            let ble_queue = (*table.ble_table).pevt_queue as *mut _;
+
+            LinkedList::from_head(sys_queue).len() + LinkedList::from_head(ble_queue).len()
+        }
+    }
+
+    /// Number of buffers released by CPU1 so far.
+    pub fn released_buffers(&self) -> u32 {
+        self.released
+    }
+
+    fn sys_cmd(&mut self) {
+        unsafe {
+            let buf: *mut u8 = (*(*TL_REF_TABLE.as_ptr()).sys_table).pcmd_buffer.cast();
+            let cmd = read_cmd(buf);
+
+            // Response replaces the command in the same buffer, without the packet header
+            let mut rsp = [0u8; 255 - 3];
+            let len = (self.sys_responder)(cmd.opcode, &cmd.params, &mut rsp);
+            let serial = buf.add(TL_PACKET_HEADER_SIZE);
+            write_cc(serial, TlPacketType::SysRsp, cmd.opcode, &rsp[..len]);
+
+            self.last_sys_cmd = Some(cmd);
+        }
+    }
+
+    fn ble_cmd(&mut self) -> Result<(), Error> {
+        let (queue, cmd) = unsafe {
+            let table = &*(*TL_REF_TABLE.as_ptr()).ble_table;
+            (
+                table.pevt_queue as *mut _,
+                read_cmd(table.pcmd_buffer.cast()),
+            )
+        };
+
+        let buf = match self.evt_pool.free.pop() {
+            Some(buf) => buf as *mut u8,
+            None => return Err(Error::NoFreeBuffer),
+        };
+
+        unsafe {
+            // Return parameters follow packet type, event header, number of commands and opcode
+            let max_len = self.evt_pool.buf_size - TL_PACKET_HEADER_SIZE - 6;
+            let mut rsp = [0u8; 255 - 3];
+            let rsp = &mut rsp[..max_len.min(255 - 3)];
+            let len = (self.ble_responder)(cmd.opcode, &cmd.params, rsp);
+            write_cc(
+                buf.add(TL_PACKET_HEADER_SIZE),
+                TlPacketType::BleEvt,
+                cmd.opcode,
+                &rsp[..len],
+            );
+
+            LinkedList::from_head(queue).push_tail(&mut *buf.cast());
+        }
+
+        self.last_ble_cmd = Some(cmd);
+        set_bits(&C2_FLAGS, channels::cpu2::IPCC_BLE_EVENT_CHANNEL, true);
+
+        Ok(())
+    }
+
+    fn acl_data(&mut self) {
+        unsafe {
+            let packet: *const u8 = (*(*TL_REF_TABLE.as_ptr()).ble_table)
+                .phci_acl_data_buffer
+                .cast();
+            // Packet type, handle and data length follow the packet header
+            let serial = packet.add(TL_PACKET_HEADER_SIZE);
+            let len = u16::from_le_bytes([*serial.add(3), *serial.add(4)]) as usize;
+            let bytes = core::slice::from_raw_parts(serial.add(1), (len + 4).min(256));
+
+            let mut data = Vec::new();
+            data.extend_from_slice(bytes).ok();
+            self.last_acl_data = Some(data);
+        }
+    }
+
+    fn release_bufs(&mut self) -> Result<(), Error> {
+        let queue = unsafe {
+            LinkedList::from_head(
+                (*(*TL_REF_TABLE.as_ptr()).mem_manager_table).pevt_free_buffer_queue,
+            )
+        };
+
+        while let Some(node) = queue.pop_head() {
+            let addr = node as *mut LinkedListNode as usize;
+            let pool = if self.traces_pool.contains(addr) {
+                &mut self.traces_pool
+            } else if self.evt_pool.contains(addr) {
+                &mut self.evt_pool
+            } else {
+                return Err(Error::UnknownBuffer);
+            };
+
+            if pool.free.contains(&addr) {
+                return Err(Error::ReleasedTwice);
+            }
+            // Can't overflow, the pool was carved into at most this many buffers
+            pool.free.push(addr).map_err(|_| Error::ReleasedTwice)?;
+
+            self.released += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Cpu2Sim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Cpu2Sim {
+    fn drop(&mut self) {
+        SIM_LOCK.store(false, Ordering::Release);
+    }
+}
+
+/// Returns `true` if CPU1 IPCC RX interrupt is pending.
+pub fn rx_irq_pending() -> bool {
+    C2_FLAGS.load(Ordering::SeqCst) & C1_RX_ENABLED.load(Ordering::SeqCst) != 0
+}
+
+/// Returns `true` if CPU1 IPCC TX interrupt is pending.
+pub fn tx_irq_pending() -> bool {
+    !C1_FLAGS.load(Ordering::SeqCst) & C1_TX_ENABLED.load(Ordering::SeqCst) != 0
+}
+
+/// Event pool that CPU2 allocates buffers from.
+struct Pool {
+    /// Buffers owned by CPU2.
+    free: Vec<usize, U64>,
+
+    buf_size: usize,
+
+    /// Address range of the pool, to tell the released buffers apart.
+    range: (usize, usize),
+}
+
+impl Pool {
+    fn new(buf_size: usize) -> Self {
+        Pool {
+            free: Vec::new(),
+            buf_size,
+            range: (0, 0),
+        }
+    }
+
+    /// Splits the pool at `addr` into buffers.
+    fn carve(&mut self, addr: usize, size: usize) -> Result<(), Error> {
+        self.free.clear();
+        self.range = (addr, addr + size);
+
+        // Hand out lower addresses first
+        for i in (0..size / self.buf_size).rev() {
+            self.free
+                .push(addr + i * self.buf_size)
+                .map_err(|_| Error::PoolTooLarge)?;
+        }
+
+        Ok(())
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        addr >= self.range.0 && addr < self.range.1
+    }
+
+    /// Takes a buffer and writes a packet of type `kind` with `evt` into it.
+    fn alloc(
+        &mut self,
+        kind: TlPacketType,
+        evt: &[u8],
+    ) -> Result<&'static mut LinkedListNode, Error> {
+        if TL_PACKET_HEADER_SIZE + 1 + evt.len() > self.buf_size {
+            return Err(Error::TooLong);
+        }
+
+        let buf = self.free.pop().ok_or(Error::NoFreeBuffer)? as *mut u8;
+
+        unsafe {
+            let serial = buf.add(TL_PACKET_HEADER_SIZE);
+            *serial = kind as u8;
+            core::ptr::copy_nonoverlapping(evt.as_ptr(), serial.add(1), evt.len());
+        }
+
+        // Packet header is the list node
+        Ok(unsafe { &mut *buf.cast() })
+    }
+}
+
+/// Reads opcode and parameters of the command in `packet`.
+unsafe fn read_cmd(packet: *const u8) -> LastCmd {
+    // Packet type, opcode, parameter length and parameters follow the packet header
+    let serial = packet.add(TL_PACKET_HEADER_SIZE);
+    let opcode = u16::from_le_bytes([*serial.add(1), *serial.add(2)]);
+    let len = *serial.add(3) as usize;
+    let params = core::slice::from_raw_parts(serial.add(4), len);
+
+    // Parameter length can't exceed the buffer
+    let mut cmd = LastCmd {
+        opcode,
+        params: Vec::new(),
+    };
+    cmd.params.extend_from_slice(params).ok();
+
+    cmd
+}
+
+/// Writes command complete event for `opcode` with return parameters `rsp` into `serial`.
+unsafe fn write_cc(serial: *mut u8, kind: TlPacketType, opcode: u16, rsp: &[u8]) {
+    let opcode = opcode.to_le_bytes();
+    let header = [
+        kind as u8,
+        EVT_CODE_CC,
+        (3 + rsp.len()) as u8,
+        1,
+        opcode[0],
+        opcode[1],
+    ];
+
+    core::ptr::copy_nonoverlapping(header.as_ptr(), serial, header.len());
+    core::ptr::copy_nonoverlapping(rsp.as_ptr(), serial.add(header.len()), rsp.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::ble::acl::{AclData, PacketBoundary};
+    use crate::tl_mbox::ble::command::Command;
+    use crate::tl_mbox::ble::hci::{ReadBdAddr, Reset};
+    use crate::tl_mbox::queue::OverflowPolicy;
+    use crate::tl_mbox::shci::SHCI_OPCODE_REINIT;
+    use crate::tl_mbox::{ble, shci, TlMbox, TL_BLE_EVT_ACL_DATA_LEN};
+    use core::convert::TryFrom;
+
+    fn init() -> (Cpu2Sim, TlMbox) {
+        let sim = Cpu2Sim::new();
+        let mbox = TlMbox::tl_init_channels(
+            &mut SimIpcc,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::DropNewest,
+        );
+
+        (sim, mbox)
+    }
+
+    #[test]
+    fn boot_reports_c2_ready() {
+        let (mut sim, mut mbox) = init();
+        assert_eq!(mbox.poll_cpu2_ready(), Err(nb::Error::WouldBlock));
+
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service(&mut mbox).unwrap();
+
+        assert_eq!(mbox.poll_cpu2_ready(), Ok(ReadyState::WirelessStack));
+    }
+
+    #[test]
+    fn sys_command_complete() {
+        let (mut sim, mut mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service(&mut mbox).unwrap();
+
+        let pending = shci::shci_c2_reinit(&mut SimIpcc);
+        sim.service(&mut mbox).unwrap();
+
+        assert_eq!(mbox.poll_shci_cmd(&pending), Ok(shci::ShciStatus::Success));
+        assert_eq!(sim.last_sys_cmd(), Some((SHCI_OPCODE_REINIT, &[][..])));
+    }
+
+    #[test]
+    fn ble_command_complete() {
+        fn bd_addr(_opcode: u16, _params: &[u8], rsp: &mut [u8]) -> usize {
+            rsp[..7].copy_from_slice(&[0x00, 1, 2, 3, 4, 5, 6]);
+            7
+        }
+
+        let (mut sim, mut mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service(&mut mbox).unwrap();
+        let ready = mbox.dequeue_event();
+        drop(ready);
+
+        let pending = mbox.send_ble_cmd(&mut SimIpcc, &Reset).unwrap();
+        assert_eq!(mbox.poll_ble_cmd(&pending), Err(nb::Error::WouldBlock));
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(mbox.poll_ble_cmd(&pending), Ok(()));
+        assert_eq!(sim.last_ble_cmd(), Some((Reset::OPCODE, &[][..])));
+
+        sim.set_ble_responder(bd_addr);
+        let pending = mbox.send_ble_cmd(&mut SimIpcc, &ReadBdAddr).unwrap();
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(
+            mbox.poll_ble_cmd(&pending).map(|addr| addr.0),
+            Ok([1, 2, 3, 4, 5, 6])
+        );
+
+        // Command complete events are released once they are polled
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(sim.queued_events(), 0);
+        assert_eq!(sim.released_buffers(), 3);
+    }
+
+    #[test]
+    fn lost_ble_command_response() {
+        let (mut sim, mut mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service(&mut mbox).unwrap();
+        drop(mbox.dequeue_event());
+
+        // CPU2 takes the command but never answers it
+        let lost = mbox.send_ble_cmd(&mut SimIpcc, &Reset).unwrap();
+        set_bits(&C1_FLAGS, channels::cpu1::IPCC_BLE_CMD_CHANNEL, false);
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(
+            mbox.send_ble_cmd(&mut SimIpcc, &Reset).err(),
+            Some(ble::command::Error::Busy)
+        );
+
+        mbox.cancel_ble_cmd();
+        assert_eq!(
+            mbox.poll_ble_cmd(&lost),
+            Err(nb::Error::Other(ble::command::Error::Superseded))
+        );
+
+        let pending = mbox.send_ble_cmd(&mut SimIpcc, &Reset).unwrap();
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(mbox.poll_ble_cmd(&pending), Ok(()));
+
+        // CPU2 restarts with a command in flight
+        let lost = mbox.send_ble_cmd(&mut SimIpcc, &Reset).unwrap();
+        set_bits(&C1_FLAGS, channels::cpu1::IPCC_BLE_CMD_CHANNEL, false);
+        let code = SHCI_SUB_EVT_CODE_READY.to_le_bytes();
+        sim.send_sys_event(&[EVT_CODE_SYS, 3, code[0], code[1], 0x00])
+            .unwrap();
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(mbox.poll_cpu2_ready(), Ok(ReadyState::WirelessStack));
+        assert_eq!(
+            mbox.poll_ble_cmd(&lost),
+            Err(nb::Error::Other(ble::command::Error::Superseded))
+        );
+
+        let pending = mbox.send_ble_cmd(&mut SimIpcc, &Reset).unwrap();
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(mbox.poll_ble_cmd(&pending), Ok(()));
+    }
+
+    #[test]
+    fn dropped_event_returns_to_pool() {
+        let (mut sim, mut mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        let free = sim.free_evt_buffers();
+        sim.service(&mut mbox).unwrap();
+
+        let evt = mbox.dequeue_event().unwrap();
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(sim.free_evt_buffers(), free);
+        assert_eq!(sim.released_buffers(), 0);
+
+        drop(evt);
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(sim.free_evt_buffers(), free + 1);
+        assert_eq!(sim.released_buffers(), 1);
+    }
+
+    #[test]
+    fn received_acl_data() {
+        let (mut sim, mut mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service(&mut mbox).unwrap();
+        drop(mbox.dequeue_event());
+
+        sim.send_acl_data(&[0x01, 0x20, 3, 0, 0xaa, 0xbb, 0xcc])
+            .unwrap();
+        sim.service(&mut mbox).unwrap();
+        let evt = mbox.dequeue_event().unwrap();
+        let acl = AclData::try_from(&evt).unwrap();
+        assert_eq!(acl.handle.0, 0x0001);
+        assert_eq!(acl.packet_boundary, PacketBoundary::FirstFlushable);
+        assert_eq!(acl.data, &[0xaa, 0xbb, 0xcc]);
+        drop(evt);
+
+        // Length beyond the event buffer isn't read past the buffer
+        sim.send_acl_data(&[0x01, 0x20, 0xff, 0xff, 0xaa]).unwrap();
+        sim.service(&mut mbox).unwrap();
+        let evt = mbox.dequeue_event().unwrap();
+        assert_eq!(evt.payload().len(), TL_BLE_EVT_ACL_DATA_LEN);
+        assert_eq!(
+            AclData::try_from(&evt).err(),
+            Some(ble::acl::Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn event_too_long() {
+        let (mut sim, _mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+
+        let evt = [0u8; EVT_BUF_SIZE];
+        assert_eq!(sim.send_ble_event(&evt), Err(Error::TooLong));
+    }
+
+    #[test]
+    fn release_of_unknown_buffer() {
+        static mut NODE: LinkedListNode = LinkedListNode {
+            next: core::ptr::null_mut(),
+            prev: core::ptr::null_mut(),
+        };
+
+        let (mut sim, _mbox) = init();
+        sim.boot(ReadyState::WirelessStack).unwrap();
+
+        unsafe {
+            let queue = (*(*TL_REF_TABLE.as_ptr()).mem_manager_table).pevt_free_buffer_queue;
+            LinkedList::from_head(queue).push_tail(&mut *core::ptr::addr_of_mut!(NODE));
+        }
+        SimIpcc.c1_set_flag_channel(channels::cpu1::IPCC_MM_RELEASE_BUFFER_CHANNEL);
+
+        assert_eq!(sim.step(), Err(Error::UnknownBuffer));
+    }
+}
@@ -1,26 +1,29 @@
 //! IPCC SYS (System) channel routines.
+use core::convert::TryFrom;
 use core::mem::MaybeUninit;
 
 use super::channels;
-use crate::ipcc::Ipcc;
+use crate::ipcc::IpccChannels;
 use crate::tl_mbox::cmd::{CmdPacket, CmdSerial};
 use crate::tl_mbox::evt::{CcEvt, EvtBox, EvtSerial};
-use crate::tl_mbox::unsafe_linked_list::{
-    LST_init_head, LST_is_empty, LST_remove_head, LinkedListNode,
-};
-use crate::tl_mbox::{
-    evt, HeaplessEvtQueue, SysTable, SYSTEM_EVT_QUEUE, SYS_CMD_BUF, TL_SYS_TABLE,
-};
+use crate::tl_mbox::linked_list::{LinkedList, LinkedListNode};
+use crate::tl_mbox::queue::EvtQueue;
+use crate::tl_mbox::shci::{ReadyState, SysEvent};
+use crate::tl_mbox::{evt, SysTable, SYSTEM_EVT_QUEUE, SYS_CMD_BUF, TL_SYS_TABLE};
+use heapless::ArrayLength;
 
 pub type SysCallback = fn();
 
-pub struct Sys {}
+pub struct Sys {
+    /// State reported by the last `C2Ready` event that hasn't been picked up yet.
+    ready: Option<ReadyState>,
+}
 
 impl Sys {
-    pub fn new(ipcc: &mut Ipcc) -> Self {
-        unsafe {
-            LST_init_head(SYSTEM_EVT_QUEUE.as_mut_ptr());
+    pub fn new(ipcc: &mut impl IpccChannels) -> Self {
+        evt_queue().init();
 
+        unsafe {
             TL_SYS_TABLE = MaybeUninit::new(SysTable {
                 pcmd_buffer: SYS_CMD_BUF.as_mut_ptr(),
                 sys_queue: SYSTEM_EVT_QUEUE.as_ptr(),
@@ -29,10 +32,10 @@ impl Sys {
 
         ipcc.c1_set_rx_channel(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL, true);
 
-        Sys {}
+        Sys { ready: None }
     }
 
-    pub fn cmd_evt_handler(&self, ipcc: &mut Ipcc) -> CcEvt {
+    pub fn cmd_evt_handler(&self, ipcc: &mut impl IpccChannels) -> CcEvt {
         ipcc.c1_set_tx_channel(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL, false);
 
         // ST's command response data structure is really convoluted.
@@ -52,26 +55,85 @@ impl Sys {
         }
     }
 
-    pub fn evt_handler(&self, ipcc: &mut Ipcc, queue: &mut HeaplessEvtQueue) {
+    /// Calls `f` with return parameters of the last system command response.
+    ///
+    /// They stay in the command buffer until the next command is sent.
+    pub(crate) fn with_cmd_rsp_params<R>(f: impl FnOnce(&[u8]) -> R) -> R {
         unsafe {
-            let mut node_ptr: *mut LinkedListNode = core::ptr::null_mut();
-            let node_ptr_ptr: *mut *mut LinkedListNode = &mut node_ptr;
+            let pcmd: *const CmdPacket = (&*TL_SYS_TABLE.as_ptr()).pcmd_buffer;
+            let cmd_serial: *const CmdSerial = &(*pcmd).cmdserial;
+            let evt_serial: *const EvtSerial = cmd_serial.cast();
+            let payload_len = (*evt_serial).evt.payload_len as usize;
 
-            while !LST_is_empty(SYSTEM_EVT_QUEUE.as_mut_ptr()) {
-                LST_remove_head(SYSTEM_EVT_QUEUE.as_mut_ptr(), node_ptr_ptr);
+            // Return parameters follow number of commands and opcode
+            let cc: *const CcEvt = (*evt_serial).evt.payload.as_ptr().cast();
+            let params: *const u8 = (*cc).payload.as_ptr();
+            let len = payload_len.saturating_sub(3);
+
+            f(core::slice::from_raw_parts(params, len))
+        }
+    }
+
+    /// Takes the state reported by the last `C2Ready` event.
+    pub(crate) fn take_ready(&mut self) -> Option<ReadyState> {
+        self.ready.take()
+    }
 
-                let event: *mut evt::EvtPacket = node_ptr.cast();
-                let event = EvtBox::new(event);
+    /// Moves events from the CPU2 queue into `queue`.
+    ///
+    /// If `queue` is full with `OverflowPolicy::Backpressure`, the channel is masked and left
+    /// pending until `resume_rx` is called.
+    ///
+    /// Returns `true` if a `C2Ready` event has been queued, i.e. CPU2 has (re)started.
+    pub fn evt_handler<N: ArrayLength<EvtBox>>(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        queue: &mut EvtQueue<N>,
+    ) -> bool {
+        let mut restarted = false;
+        let evt_queue = evt_queue();
 
-                queue.enqueue(event).unwrap();
+        while let Some(node) = evt_queue.pop_head() {
+            let node: *mut LinkedListNode = node;
+            let event: *mut evt::EvtPacket = node.cast();
+            let event = EvtBox::new(event);
+
+            let ready = match SysEvent::try_from(&event) {
+                Ok(SysEvent::C2Ready(state)) => Some(state),
+                _ => None,
+            };
+
+            if let Err(event) = queue.push(event) {
+                // Leave the event to CPU2 until there's room for it, it's decoded again then
+                evt_queue.push_head(unsafe { &mut *event.into_raw().cast() });
+                ipcc.c1_set_rx_channel(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL, false);
+
+                return restarted;
+            }
+
+            if ready.is_some() {
+                self.ready = ready;
+                restarted = true;
             }
         }
 
         ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL);
+
+        restarted
+    }
+
+    /// Unmasks the channel after it has been stopped by `OverflowPolicy::Backpressure`.
+    pub fn resume_rx(&mut self, ipcc: &mut impl IpccChannels) {
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_SYSTEM_EVENT_CHANNEL, true);
     }
 }
 
-pub fn send_cmd(ipcc: &mut Ipcc) {
+pub fn send_cmd(ipcc: &mut impl IpccChannels) {
     ipcc.c1_set_flag_channel(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL);
     ipcc.c1_set_tx_channel(channels::cpu1::IPCC_SYSTEM_CMD_RSP_CHANNEL, true);
 }
+
+/// System event queue that CPU2 fills.
+fn evt_queue() -> LinkedList {
+    unsafe { LinkedList::from_head(SYSTEM_EVT_QUEUE.as_mut_ptr()) }
+}
@@ -0,0 +1,401 @@
+//! IPCC Thread channels: OpenThread API calls, notifications and CLI.
+//!
+//! OpenThread API calls are serialized as `OtMessage`s: API function ID followed by 32-bit
+//! arguments. CPU2 answers each call in the same buffer once it's done. Notifications (calls
+//! from the stack to the application) and CLI output arrive on separate channels and must be
+//! acknowledged before the next one can be received.
+
+use core::mem::MaybeUninit;
+
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::bytes::{BufferFull, Reader, Writer};
+use crate::tl_mbox::channels;
+use crate::tl_mbox::cmd::CmdPacket;
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::EvtPacket;
+use crate::tl_mbox::{
+    ThreadTable, THREAD_CLI_CMD_RSP_BUFFER, THREAD_NOTIF_ACK_BUFFER, THREAD_OT_CMD_RSP_BUFFER,
+    TL_THREAD_TABLE,
+};
+
+/// Maximum number of 32-bit arguments of an OpenThread API call.
+pub const OT_MAX_ARGS: usize = (255 - 8) / 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Thread transport hasn't been initialized with `TlMbox::thread_init`.
+    NotInitialized,
+
+    /// IPCC channels are already used by 802.15.4 MAC or Zigbee transport.
+    ChannelsInUse,
+
+    /// Command doesn't fit into the command buffer.
+    TooLong,
+}
+
+/// OpenThread API call, its response or a notification, borrowed from the shared RAM.
+#[derive(Debug, Copy, Clone)]
+pub struct OtMessage<'a> {
+    /// OpenThread API function ID.
+    pub id: u32,
+    /// Arguments, 4 bytes each.
+    args: &'a [u8],
+}
+
+impl<'a> OtMessage<'a> {
+    /// Parses API function ID, number of arguments and arguments.
+    fn parse(buf: &'a [u8]) -> Option<Self> {
+        let mut r = Reader::new(buf);
+        let id = r.u32()?;
+        let size = r.u32()? as usize;
+
+        Some(OtMessage {
+            id,
+            args: r.bytes(size.checked_mul(4)?)?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.args.len() / 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Returns argument at `index`. For responses the first argument is the return value.
+    pub fn arg(&self, index: usize) -> Option<u32> {
+        Reader::new(self.args.get(index * 4..)?).u32()
+    }
+}
+
+pub(super) struct Thread {
+    /// OpenThread API call waits for CPU2 to complete.
+    ot_cmd_pending: bool,
+
+    /// Response to the last OpenThread API call is in the command buffer.
+    ot_rsp_ready: bool,
+
+    /// Notification is in the notification buffer and not acknowledged yet.
+    notification_pending: bool,
+
+    /// CLI output is in the CLI buffer and not acknowledged yet.
+    cli_notification_pending: bool,
+}
+
+impl Thread {
+    pub(super) fn new(ipcc: &mut impl IpccChannels) -> Self {
+        unsafe {
+            THREAD_OT_CMD_RSP_BUFFER = MaybeUninit::zeroed();
+            THREAD_NOTIF_ACK_BUFFER = MaybeUninit::zeroed();
+            THREAD_CLI_CMD_RSP_BUFFER = MaybeUninit::zeroed();
+
+            TL_THREAD_TABLE = MaybeUninit::new(ThreadTable {
+                nostack_buffer: THREAD_NOTIF_ACK_BUFFER.as_ptr().cast(),
+                clicmdrsp_buffer: THREAD_CLI_CMD_RSP_BUFFER.as_ptr().cast(),
+                otcmdrsp_buffer: THREAD_OT_CMD_RSP_BUFFER.as_ptr().cast(),
+            });
+        }
+
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL, true);
+        ipcc.c1_set_rx_channel(
+            channels::cpu2::IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL,
+            true,
+        );
+
+        Thread {
+            ot_cmd_pending: false,
+            ot_rsp_ready: false,
+            notification_pending: false,
+            cli_notification_pending: false,
+        }
+    }
+
+    /// Serializes OpenThread API call into the command buffer and notifies CPU2.
+    ///
+    /// Returns `WouldBlock` while the previous call hasn't completed.
+    pub(super) fn send_ot_cmd(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        id: u32,
+        args: &[u32],
+    ) -> nb::Result<(), Error> {
+        if args.len() > OT_MAX_ARGS {
+            return Err(nb::Error::Other(Error::TooLong));
+        }
+
+        if self.ot_cmd_pending {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        unsafe { write_cmd_message(THREAD_OT_CMD_RSP_BUFFER.as_mut_ptr(), id, args) }
+            .map_err(|_| Error::TooLong)?;
+
+        self.ot_cmd_pending = true;
+        self.ot_rsp_ready = false;
+
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL);
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL, true);
+
+        Ok(())
+    }
+
+    /// CPU2 has completed the OpenThread API call.
+    pub(super) fn ot_cmd_rsp_handler(&mut self, ipcc: &mut impl IpccChannels) {
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_THREAD_OT_CMD_RSP_CHANNEL, false);
+
+        self.ot_cmd_pending = false;
+        self.ot_rsp_ready = true;
+    }
+
+    /// Returns response to the last OpenThread API call once it's completed.
+    pub(super) fn poll_ot_rsp(&mut self) -> nb::Result<OtMessage<'_>, Error> {
+        if !self.ot_rsp_ready {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        // Response is kept in the buffer until the next call
+        Ok(unsafe { evt_message(THREAD_OT_CMD_RSP_BUFFER.as_ptr().cast()) })
+    }
+
+    pub(super) fn notification_handler(&mut self, ipcc: &mut impl IpccChannels) {
+        // Keep the notification in the buffer until the application acknowledges it
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL, false);
+
+        self.notification_pending = true;
+    }
+
+    pub(super) fn notification(&self) -> Option<OtMessage<'_>> {
+        if self.notification_pending {
+            Some(unsafe { evt_message(THREAD_NOTIF_ACK_BUFFER.as_ptr().cast()) })
+        } else {
+            None
+        }
+    }
+
+    /// Acknowledges the notification so that CPU2 can send the next one.
+    /// The notification buffer carries the response (if any) back to CPU2.
+    pub(super) fn ack_notification(&mut self, ipcc: &mut impl IpccChannels, ret: Option<u32>) {
+        if !self.notification_pending {
+            return;
+        }
+
+        unsafe { ack_message(THREAD_NOTIF_ACK_BUFFER.as_mut_ptr().cast(), ret) };
+
+        self.notification_pending = false;
+
+        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL);
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_THREAD_NOTIFICATION_ACK_CHANNEL, true);
+    }
+
+    /// Sends a CLI command line. Returns `WouldBlock` while CPU2 hasn't taken the previous one.
+    pub(super) fn send_cli_cmd(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        line: &[u8],
+    ) -> nb::Result<(), Error> {
+        if line.len() > u8::MAX as usize {
+            return Err(nb::Error::Other(Error::TooLong));
+        }
+
+        if ipcc.c1_is_active_flag(channels::cpu1::IPCC_THREAD_CLI_CMD_CHANNEL) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        unsafe {
+            let packet: *mut CmdPacket = THREAD_CLI_CMD_RSP_BUFFER.as_mut_ptr();
+            let cmd_serial = &mut (*packet).cmdserial;
+
+            cmd_serial.ty = TlPacketType::CliCmd as u8;
+            cmd_serial.cmd.payload_len = line.len() as u8;
+            cmd_serial.cmd.payload[..line.len()].copy_from_slice(line);
+        }
+
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_THREAD_CLI_CMD_CHANNEL);
+
+        Ok(())
+    }
+
+    pub(super) fn cli_notification_handler(&mut self, ipcc: &mut impl IpccChannels) {
+        ipcc.c1_set_rx_channel(
+            channels::cpu2::IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL,
+            false,
+        );
+
+        self.cli_notification_pending = true;
+    }
+
+    pub(super) fn cli_notification(&self) -> Option<&[u8]> {
+        if !self.cli_notification_pending {
+            return None;
+        }
+
+        unsafe {
+            let packet: *const EvtPacket = THREAD_CLI_CMD_RSP_BUFFER.as_ptr().cast();
+            let evt = &(*packet).evt_serial.evt;
+            let payload: *const u8 = evt.payload.as_ptr();
+
+            Some(core::slice::from_raw_parts(
+                payload,
+                evt.payload_len as usize,
+            ))
+        }
+    }
+
+    pub(super) fn ack_cli_notification(&mut self, ipcc: &mut impl IpccChannels) {
+        if !self.cli_notification_pending {
+            return;
+        }
+
+        unsafe {
+            let packet: *mut CmdPacket = THREAD_CLI_CMD_RSP_BUFFER.as_mut_ptr();
+            (*packet).cmdserial.ty = TlPacketType::CliAck as u8;
+        }
+
+        self.cli_notification_pending = false;
+
+        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL);
+        ipcc.c1_set_rx_channel(
+            channels::cpu2::IPCC_THREAD_CLI_NOTIFICATION_ACK_CHANNEL,
+            true,
+        );
+    }
+}
+
+/// Serializes API call into the command payload of `packet`.
+///
+/// Zigbee stack uses the same serialization, so it's shared with `zigbee`.
+pub(super) unsafe fn write_cmd_message(
+    packet: *mut CmdPacket,
+    id: u32,
+    args: &[u32],
+) -> Result<(), BufferFull> {
+    let cmd_serial = &mut (*packet).cmdserial;
+
+    let mut w = Writer::new(&mut cmd_serial.cmd.payload);
+    w.u32(id)?;
+    w.u32(args.len() as u32)?;
+    for &arg in args {
+        w.u32(arg)?;
+    }
+
+    cmd_serial.ty = TlPacketType::OtCmd as u8;
+
+    Ok(())
+}
+
+/// Marks the message in `packet` acknowledged, optionally with a return value.
+pub(super) unsafe fn ack_message(packet: *mut EvtPacket, ret: Option<u32>) {
+    if let Some(ret) = ret {
+        // Return value replaces the first argument, ID and size are kept
+        let payload: *mut u8 = (*packet).evt_serial.evt.payload.as_mut_ptr();
+        let ret = ret.to_le_bytes();
+        core::ptr::copy_nonoverlapping(ret.as_ptr(), payload.add(8), ret.len());
+    }
+
+    (*packet).evt_serial.kind = TlPacketType::OtAck as u8;
+}
+
+/// Borrows `OtMessage` from the event payload of a Thread buffer.
+///
+/// Malformed messages are returned with ID and no arguments.
+pub(super) unsafe fn evt_message<'a>(packet: *const EvtPacket) -> OtMessage<'a> {
+    // Message size is not limited by the event header, but by the buffer
+    let payload: *const u8 = (*packet).evt_serial.evt.payload.as_ptr();
+    let buf = core::slice::from_raw_parts(payload, 255);
+
+    OtMessage::parse(buf).unwrap_or(OtMessage {
+        id: Reader::new(buf).u32().unwrap_or(0),
+        args: &[],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::TL_PACKET_HEADER_SIZE;
+
+    #[test]
+    fn parse() {
+        let buf = [
+            0x34, 0x12, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xef, 0xbe,
+            0xad, 0xde, 0xff,
+        ];
+
+        let msg = OtMessage::parse(&buf).unwrap();
+        assert_eq!(msg.id, 0x1234);
+        assert_eq!(msg.len(), 2);
+        assert_eq!(msg.arg(0), Some(1));
+        assert_eq!(msg.arg(1), Some(0xdead_beef));
+        assert_eq!(msg.arg(2), None);
+
+        let msg = OtMessage::parse(&[0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        assert!(msg.is_empty());
+        assert_eq!(msg.arg(0), None);
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let mut buf = [0u8; 16];
+        buf[4] = 3;
+
+        // Two arguments out of three
+        assert!(OtMessage::parse(&buf).is_none());
+        // Size without arguments
+        assert!(OtMessage::parse(&buf[..7]).is_none());
+
+        buf[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(OtMessage::parse(&buf).is_none());
+    }
+
+    #[test]
+    fn write_cmd() {
+        let mut packet = CmdPacket::default();
+
+        unsafe { write_cmd_message(&mut packet, 0x0102, &[7, 0xdead_beef]) }.unwrap();
+        let ty = packet.cmdserial.ty;
+        assert_eq!(ty, TlPacketType::OtCmd as u8);
+
+        let payload = packet.cmdserial.cmd.payload;
+        assert_eq!(
+            &payload[..16],
+            &[2, 1, 0, 0, 2, 0, 0, 0, 7, 0, 0, 0, 0xef, 0xbe, 0xad, 0xde]
+        );
+
+        let msg = OtMessage::parse(&payload).unwrap();
+        assert_eq!((msg.id, msg.len()), (0x0102, 2));
+        assert_eq!(msg.arg(1), Some(0xdead_beef));
+
+        let args = [0u32; OT_MAX_ARGS + 1];
+        assert!(unsafe { write_cmd_message(&mut packet, 0, &args[..OT_MAX_ARGS]) }.is_ok());
+        assert_eq!(
+            unsafe { write_cmd_message(&mut packet, 0, &args) },
+            Err(BufferFull)
+        );
+    }
+
+    #[test]
+    fn notification() {
+        // Message follows packet header, packet type, event code and length
+        const MSG: usize = TL_PACKET_HEADER_SIZE + 3;
+
+        let mut buf = [0u8; MSG + 255];
+        buf[MSG..MSG + 12].copy_from_slice(&[5, 0, 0, 0, 1, 0, 0, 0, 9, 0, 0, 0]);
+
+        let msg = unsafe { evt_message(buf.as_ptr().cast()) };
+        assert_eq!((msg.id, msg.len(), msg.arg(0)), (5, 1, Some(9)));
+
+        unsafe { ack_message(buf.as_mut_ptr().cast(), Some(0xaabb_ccdd)) };
+        assert_eq!(buf[TL_PACKET_HEADER_SIZE], TlPacketType::OtAck as u8);
+        assert_eq!(
+            &buf[MSG..MSG + 12],
+            &[5, 0, 0, 0, 1, 0, 0, 0, 0xdd, 0xcc, 0xbb, 0xaa]
+        );
+
+        // Size beyond the buffer
+        buf[MSG + 7] = 0x01;
+        let msg = unsafe { evt_message(buf.as_ptr().cast()) };
+        assert_eq!(msg.id, 5);
+        assert!(msg.is_empty());
+    }
+}
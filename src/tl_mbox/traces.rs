@@ -0,0 +1,177 @@
+//! IPCC traces channel routines.
+//!
+//! CPU2 allocates trace packets from the traces event pool and sends them on the traces channel.
+//! They're queued as `EvtBox`es, dequeued with `TlMbox::dequeue_trace_event` and viewed with
+//! `Trace::try_from`. Dropping the `EvtBox` gives the buffer back to CPU2.
+
+use core::convert::TryFrom;
+use core::mem::MaybeUninit;
+
+use heapless::ArrayLength;
+
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::channels;
+use crate::tl_mbox::consts::TlPacketType;
+use crate::tl_mbox::evt::{EvtBox, EvtPacket};
+use crate::tl_mbox::linked_list::{LinkedList, LinkedListNode};
+use crate::tl_mbox::queue::EvtQueue;
+use crate::tl_mbox::{TracesTable, TL_TRACES_TABLE, TRACES_EVT_QUEUE};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TraceKind {
+    /// Trace of the application running on CPU2.
+    App,
+    /// Trace of the wireless stack.
+    Wireless,
+}
+
+/// Trace packet borrowed from its buffer.
+#[derive(Debug, Copy, Clone)]
+pub struct Trace<'a> {
+    pub kind: TraceKind,
+    pub data: &'a [u8],
+}
+
+impl<'a> TryFrom<&'a EvtBox> for Trace<'a> {
+    /// Raw packet type of a packet that is not a trace.
+    type Error = u8;
+
+    fn try_from(evt: &'a EvtBox) -> Result<Self, Self::Error> {
+        let kind = evt.raw_kind();
+        let kind = match TlPacketType::try_from(kind) {
+            Ok(TlPacketType::TracesApp) => TraceKind::App,
+            Ok(TlPacketType::TracesWl) => TraceKind::Wireless,
+
+            _ => return Err(kind),
+        };
+
+        Ok(Trace {
+            kind,
+            data: evt.payload(),
+        })
+    }
+}
+
+pub(super) struct Traces {}
+
+impl Traces {
+    pub(super) fn new(ipcc: &mut impl IpccChannels) -> Self {
+        evt_queue().init();
+
+        unsafe {
+            TL_TRACES_TABLE = MaybeUninit::new(TracesTable {
+                traces_queue: TRACES_EVT_QUEUE.as_ptr().cast(),
+            });
+        }
+
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_TRACES_CHANNEL, true);
+
+        Traces {}
+    }
+
+    /// Moves trace packets from the CPU2 queue into `queue`.
+    pub(super) fn evt_handler<N: ArrayLength<EvtBox>>(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        queue: &mut EvtQueue<N>,
+    ) {
+        while let Some(node) = evt_queue().pop_head() {
+            let node: *mut LinkedListNode = node;
+            let event: *mut EvtPacket = node.cast();
+
+            // Traces queue always drops the newest trace when full
+            let _ = queue.push(EvtBox::new(event));
+        }
+
+        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_TRACES_CHANNEL);
+    }
+}
+
+/// Trace queue that CPU2 fills.
+fn evt_queue() -> LinkedList {
+    unsafe { LinkedList::from_head(TRACES_EVT_QUEUE.as_mut_ptr()) }
+}
+
+#[cfg(all(test, feature = "host-sim"))]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::queue::OverflowPolicy;
+    use crate::tl_mbox::shci::ReadyState;
+    use crate::tl_mbox::sim::{Cpu2Sim, SimIpcc};
+    use crate::tl_mbox::{TlMbox, CFG_TL_TRACES_EVT_POOL_LEN};
+
+    fn init() -> (Cpu2Sim, TlMbox) {
+        let mut sim = Cpu2Sim::new();
+        let mut mbox = TlMbox::tl_init_channels(
+            &mut SimIpcc,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::DropNewest,
+        );
+
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service(&mut mbox).unwrap();
+        drop(mbox.dequeue_event());
+
+        (sim, mbox)
+    }
+
+    #[test]
+    fn trace() {
+        let (mut sim, mut mbox) = init();
+
+        sim.send_trace(TlPacketType::TracesWl, &[0x00, 2, b'o', b'k'])
+            .unwrap();
+        sim.service(&mut mbox).unwrap();
+
+        // Traces are kept apart from the events
+        assert!(mbox.dequeue_event().is_none());
+
+        let evt = mbox.dequeue_trace_event().unwrap();
+        let trace = Trace::try_from(&evt).unwrap();
+        assert_eq!(trace.kind, TraceKind::Wireless);
+        assert_eq!(trace.data, b"ok");
+        assert!(mbox.dequeue_trace_event().is_none());
+
+        drop(evt);
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(sim.released_buffers(), 2);
+
+        sim.send_ble_event(&[0x0e, 0]).unwrap();
+        sim.service(&mut mbox).unwrap();
+        let evt = mbox.dequeue_event().unwrap();
+        assert_eq!(
+            Trace::try_from(&evt).err(),
+            Some(TlPacketType::BleEvt as u8)
+        );
+    }
+
+    #[test]
+    fn full_queue_drops_newest() {
+        let (mut sim, mut mbox) = init();
+
+        // Queue only overflows if the pool has more buffers than the queue can hold, e.g. with
+        // `STM32WB_TL_TRACES_EVT_POOL_LENGTH=12`
+        let capacity = mbox.traces_queue.capacity();
+        let queued = CFG_TL_TRACES_EVT_POOL_LEN.min(capacity);
+        let dropped = CFG_TL_TRACES_EVT_POOL_LEN - queued;
+
+        for i in 0..CFG_TL_TRACES_EVT_POOL_LEN {
+            sim.send_trace(TlPacketType::TracesApp, &[0x00, 1, i as u8])
+                .unwrap();
+        }
+        sim.service(&mut mbox).unwrap();
+
+        assert_eq!(mbox.dropped_trace_events(), dropped as u32);
+        // Dropped traces are given back to CPU2 right away
+        sim.service(&mut mbox).unwrap();
+        assert_eq!(sim.released_buffers(), 1 + dropped as u32);
+
+        for i in 0..queued {
+            let evt = mbox.dequeue_trace_event().unwrap();
+            let trace = Trace::try_from(&evt).unwrap();
+            assert_eq!(trace.kind, TraceKind::App);
+            assert_eq!(trace.data, &[i as u8]);
+        }
+        assert!(mbox.dequeue_trace_event().is_none());
+    }
+}
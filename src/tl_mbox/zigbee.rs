@@ -0,0 +1,333 @@
+//! IPCC Zigbee channels: stack API calls, notifications and requests from CPU2.
+//!
+//! Zigbee stack uses the same serialization as OpenThread: API function ID, number of arguments
+//! and 32-bit arguments, see `ZigbeeMessage`. CPU2 answers each call in the same buffer once
+//! it's done. Notifications and requests from CPU2 arrive on separate channels and must be
+//! acknowledged before the next one can be received.
+
+use core::mem::MaybeUninit;
+
+use crate::ipcc::IpccChannels;
+use crate::tl_mbox::channels;
+use crate::tl_mbox::thread::{self, OtMessage, OT_MAX_ARGS};
+use crate::tl_mbox::{
+    ZigbeeTable, TL_ZIGBEE_TABLE, ZIGBEE_APPLI_CMD_BUFFER, ZIGBEE_NOTIF_ACK_BUFFER,
+    ZIGBEE_REQUEST_BUFFER,
+};
+
+/// Zigbee stack API call, its response, a notification or a request from CPU2.
+pub type ZigbeeMessage<'a> = OtMessage<'a>;
+
+/// Maximum number of 32-bit arguments of a Zigbee API call.
+pub const ZIGBEE_MAX_ARGS: usize = OT_MAX_ARGS;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Zigbee transport hasn't been initialized with `TlMbox::zigbee_init`.
+    NotInitialized,
+
+    /// IPCC channels are already used by Thread or 802.15.4 MAC transport.
+    ChannelsInUse,
+
+    /// Command doesn't fit into the command buffer.
+    TooLong,
+}
+
+pub(super) struct Zigbee {
+    /// API call waits for CPU2 to complete.
+    cmd_pending: bool,
+
+    /// Response to the last API call is in the command buffer.
+    rsp_ready: bool,
+
+    /// Notification is in the notification buffer and not acknowledged yet.
+    notification_pending: bool,
+
+    /// Request from CPU2 is in the request buffer and not acknowledged yet.
+    request_pending: bool,
+}
+
+impl Zigbee {
+    pub(super) fn new(ipcc: &mut impl IpccChannels) -> Self {
+        unsafe {
+            ZIGBEE_APPLI_CMD_BUFFER = MaybeUninit::zeroed();
+            ZIGBEE_NOTIF_ACK_BUFFER = MaybeUninit::zeroed();
+            ZIGBEE_REQUEST_BUFFER = MaybeUninit::zeroed();
+
+            TL_ZIGBEE_TABLE = MaybeUninit::new(ZigbeeTable {
+                notack_buffer: ZIGBEE_NOTIF_ACK_BUFFER.as_ptr().cast(),
+                appli_cmd_m4_to_m0_buffer: ZIGBEE_APPLI_CMD_BUFFER.as_ptr().cast(),
+                request_m0_to_m4_buffer: ZIGBEE_REQUEST_BUFFER.as_ptr().cast(),
+            });
+        }
+
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_ZIGBEE_APPLI_NOTIF_ACK_CHANNEL, true);
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_ZIGBEE_M0_REQUEST_CHANNEL, true);
+
+        Zigbee {
+            cmd_pending: false,
+            rsp_ready: false,
+            notification_pending: false,
+            request_pending: false,
+        }
+    }
+
+    /// Serializes API call into the command buffer and notifies CPU2.
+    ///
+    /// Returns `WouldBlock` while the previous call hasn't completed.
+    pub(super) fn send_cmd(
+        &mut self,
+        ipcc: &mut impl IpccChannels,
+        id: u32,
+        args: &[u32],
+    ) -> nb::Result<(), Error> {
+        if args.len() > ZIGBEE_MAX_ARGS {
+            return Err(nb::Error::Other(Error::TooLong));
+        }
+
+        if self.cmd_pending {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        unsafe { thread::write_cmd_message(ZIGBEE_APPLI_CMD_BUFFER.as_mut_ptr(), id, args) }
+            .map_err(|_| Error::TooLong)?;
+
+        self.cmd_pending = true;
+        self.rsp_ready = false;
+
+        ipcc.c1_set_flag_channel(channels::cpu1::IPCC_ZIGBEE_CMD_APPLI_CHANNEL);
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_ZIGBEE_CMD_APPLI_CHANNEL, true);
+
+        Ok(())
+    }
+
+    /// CPU2 has completed the API call.
+    pub(super) fn cmd_rsp_handler(&mut self, ipcc: &mut impl IpccChannels) {
+        ipcc.c1_set_tx_channel(channels::cpu1::IPCC_ZIGBEE_CMD_APPLI_CHANNEL, false);
+
+        self.cmd_pending = false;
+        self.rsp_ready = true;
+    }
+
+    /// Returns response to the last API call once it's completed.
+    pub(super) fn poll_rsp(&mut self) -> nb::Result<ZigbeeMessage<'_>, Error> {
+        if !self.rsp_ready {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        // Response is kept in the buffer until the next call
+        Ok(unsafe { thread::evt_message(ZIGBEE_APPLI_CMD_BUFFER.as_ptr().cast()) })
+    }
+
+    pub(super) fn notification_handler(&mut self, ipcc: &mut impl IpccChannels) {
+        // Keep the notification in the buffer until the application acknowledges it
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_ZIGBEE_APPLI_NOTIF_ACK_CHANNEL, false);
+
+        self.notification_pending = true;
+    }
+
+    pub(super) fn notification(&self) -> Option<ZigbeeMessage<'_>> {
+        if self.notification_pending {
+            Some(unsafe { thread::evt_message(ZIGBEE_NOTIF_ACK_BUFFER.as_ptr().cast()) })
+        } else {
+            None
+        }
+    }
+
+    /// Acknowledges the notification so that CPU2 can send the next one.
+    pub(super) fn ack_notification(&mut self, ipcc: &mut impl IpccChannels, ret: Option<u32>) {
+        if !self.notification_pending {
+            return;
+        }
+
+        unsafe { thread::ack_message(ZIGBEE_NOTIF_ACK_BUFFER.as_mut_ptr().cast(), ret) };
+
+        self.notification_pending = false;
+
+        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_ZIGBEE_APPLI_NOTIF_ACK_CHANNEL);
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_ZIGBEE_APPLI_NOTIF_ACK_CHANNEL, true);
+    }
+
+    pub(super) fn request_handler(&mut self, ipcc: &mut impl IpccChannels) {
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_ZIGBEE_M0_REQUEST_CHANNEL, false);
+
+        self.request_pending = true;
+    }
+
+    pub(super) fn request(&self) -> Option<ZigbeeMessage<'_>> {
+        if self.request_pending {
+            Some(unsafe { thread::evt_message(ZIGBEE_REQUEST_BUFFER.as_ptr().cast()) })
+        } else {
+            None
+        }
+    }
+
+    /// Acknowledges the request so that CPU2 can send the next one.
+    pub(super) fn ack_request(&mut self, ipcc: &mut impl IpccChannels, ret: Option<u32>) {
+        if !self.request_pending {
+            return;
+        }
+
+        unsafe { thread::ack_message(ZIGBEE_REQUEST_BUFFER.as_mut_ptr().cast(), ret) };
+
+        self.request_pending = false;
+
+        ipcc.c1_clear_flag_channel(channels::cpu2::IPCC_ZIGBEE_M0_REQUEST_CHANNEL);
+        ipcc.c1_set_rx_channel(channels::cpu2::IPCC_ZIGBEE_M0_REQUEST_CHANNEL, true);
+    }
+}
+
+#[cfg(all(test, feature = "host-sim"))]
+mod tests {
+    use super::*;
+    use crate::tl_mbox::consts::TlPacketType;
+    use crate::tl_mbox::queue::OverflowPolicy;
+    use crate::tl_mbox::shci::ReadyState;
+    use crate::tl_mbox::sim::{self, Cpu2Sim, SimIpcc};
+    use crate::tl_mbox::{TlMbox, TL_PACKET_HEADER_SIZE};
+
+    /// Offset of the message in a buffer written by CPU2: packet header, packet type,
+    /// event code and length come first.
+    const MSG: usize = TL_PACKET_HEADER_SIZE + 3;
+
+    fn init() -> (Cpu2Sim, TlMbox) {
+        let mut sim = Cpu2Sim::new();
+        let mut mbox = TlMbox::tl_init_channels(
+            &mut SimIpcc,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::DropNewest,
+        );
+
+        sim.boot(ReadyState::WirelessStack).unwrap();
+        sim.service(&mut mbox).unwrap();
+        drop(mbox.dequeue_event());
+
+        (sim, mbox)
+    }
+
+    /// Writes a message into `buf` the way CPU2 does.
+    unsafe fn write_message(buf: *mut u8, id: u32, args: &[u32]) {
+        let buf = buf.add(MSG);
+        buf.cast::<[u8; 4]>().write(id.to_le_bytes());
+        buf.add(4)
+            .cast::<[u8; 4]>()
+            .write((args.len() as u32).to_le_bytes());
+
+        for (i, arg) in args.iter().enumerate() {
+            buf.add(8 + i * 4)
+                .cast::<[u8; 4]>()
+                .write(arg.to_le_bytes());
+        }
+    }
+
+    /// Reads the packet type and the `n`th argument of the message in `buf`.
+    unsafe fn read_ack(buf: *const u8, n: usize) -> (u8, u32) {
+        let kind = *buf.add(TL_PACKET_HEADER_SIZE);
+        let arg = buf.add(MSG + 8 + n * 4).cast::<[u8; 4]>().read();
+
+        (kind, u32::from_le_bytes(arg))
+    }
+
+    #[test]
+    fn not_initialized() {
+        let (_sim, mut mbox) = init();
+
+        assert_eq!(
+            mbox.send_zigbee_cmd(&mut SimIpcc, 0x0010, &[]),
+            Err(nb::Error::Other(Error::NotInitialized))
+        );
+
+        mbox.zigbee_init(&mut SimIpcc).unwrap();
+        assert_eq!(mbox.zigbee_init(&mut SimIpcc), Err(Error::ChannelsInUse));
+    }
+
+    #[test]
+    fn command() {
+        let (mut sim, mut mbox) = init();
+        mbox.zigbee_init(&mut SimIpcc).unwrap();
+
+        mbox.send_zigbee_cmd(&mut SimIpcc, 0x0010, &[1, 0xdead_beef])
+            .unwrap();
+        assert!(SimIpcc.c1_is_active_flag(channels::cpu1::IPCC_ZIGBEE_CMD_APPLI_CHANNEL));
+
+        let cmd = unsafe { (*ZIGBEE_APPLI_CMD_BUFFER.as_ptr()).cmdserial };
+        assert_eq!(cmd.ty, TlPacketType::OtCmd as u8);
+        assert_eq!(
+            &cmd.cmd.payload[..16],
+            &[0x10, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0xef, 0xbe, 0xad, 0xde]
+        );
+
+        // One call at a time
+        assert!(matches!(mbox.poll_zigbee_rsp(), Err(nb::Error::WouldBlock)));
+        assert_eq!(
+            mbox.send_zigbee_cmd(&mut SimIpcc, 0x0011, &[]),
+            Err(nb::Error::WouldBlock)
+        );
+
+        unsafe { write_message(ZIGBEE_APPLI_CMD_BUFFER.as_mut_ptr().cast(), 0x0010, &[0]) };
+        sim.clear_flag_channel(channels::cpu1::IPCC_ZIGBEE_CMD_APPLI_CHANNEL);
+        sim.service(&mut mbox).unwrap();
+
+        let rsp = mbox.poll_zigbee_rsp().unwrap();
+        assert_eq!((rsp.id, rsp.len(), rsp.arg(0)), (0x0010, 1, Some(0)));
+
+        let args = [0u32; ZIGBEE_MAX_ARGS + 1];
+        assert_eq!(
+            mbox.send_zigbee_cmd(&mut SimIpcc, 0x0012, &args),
+            Err(nb::Error::Other(Error::TooLong))
+        );
+        assert!(mbox
+            .send_zigbee_cmd(&mut SimIpcc, 0x0012, &args[..ZIGBEE_MAX_ARGS])
+            .is_ok());
+    }
+
+    #[test]
+    fn notification() {
+        let (mut sim, mut mbox) = init();
+        mbox.zigbee_init(&mut SimIpcc).unwrap();
+        let channel = channels::cpu2::IPCC_ZIGBEE_APPLI_NOTIF_ACK_CHANNEL;
+        let buf: *mut u8 = unsafe { ZIGBEE_NOTIF_ACK_BUFFER.as_mut_ptr().cast() };
+
+        assert!(mbox.zigbee_notification().is_none());
+
+        unsafe { write_message(buf, 0x0020, &[7]) };
+        sim.set_flag_channel(channel);
+        sim.service(&mut mbox).unwrap();
+
+        let msg = mbox.zigbee_notification().unwrap();
+        assert_eq!((msg.id, msg.arg(0)), (0x0020, Some(7)));
+
+        // Channel is masked until the notification is acknowledged
+        assert!(SimIpcc.c2_is_active_flag(channel));
+        assert!(!sim::rx_irq_pending());
+
+        mbox.ack_zigbee_notification(&mut SimIpcc, Some(5)).unwrap();
+        assert!(mbox.zigbee_notification().is_none());
+        assert!(!SimIpcc.c2_is_active_flag(channel));
+        assert!(SimIpcc.c1_get_rx_channel(channel));
+        assert_eq!(unsafe { read_ack(buf, 0) }, (TlPacketType::OtAck as u8, 5));
+    }
+
+    #[test]
+    fn request() {
+        let (mut sim, mut mbox) = init();
+        mbox.zigbee_init(&mut SimIpcc).unwrap();
+        let channel = channels::cpu2::IPCC_ZIGBEE_M0_REQUEST_CHANNEL;
+        let buf: *mut u8 = unsafe { ZIGBEE_REQUEST_BUFFER.as_mut_ptr().cast() };
+
+        unsafe { write_message(buf, 0x0030, &[1, 2]) };
+        sim.set_flag_channel(channel);
+        sim.service(&mut mbox).unwrap();
+
+        let msg = mbox.zigbee_request().unwrap();
+        assert_eq!((msg.id, msg.len(), msg.arg(1)), (0x0030, 2, Some(2)));
+        assert!(mbox.zigbee_notification().is_none());
+        assert!(!sim::rx_irq_pending());
+
+        // Acknowledged without a return value, arguments are kept
+        mbox.ack_zigbee_request(&mut SimIpcc, None).unwrap();
+        assert!(mbox.zigbee_request().is_none());
+        assert!(!SimIpcc.c2_is_active_flag(channel));
+        assert_eq!(unsafe { read_ack(buf, 0) }, (TlPacketType::OtAck as u8, 1));
+    }
+}